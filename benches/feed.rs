@@ -0,0 +1,178 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use nyc_subway::entities::{
+    CollectibleEntity, EntityCollection, GTFSData, Route, ShapeIndex, ShapeRoutes, ShapeSeq, Stop, TripRow,
+};
+use nyc_subway::feed::{Feed, FeedManager, SharedFeedState, Transport, FEEDS};
+use nyc_subway::ids::{RouteId, StopId};
+use nyc_subway::nyct::Extensions;
+use nyc_subway::proto::gtfs::realtime::FeedMessage;
+use nyc_subway::proto::json;
+use nyc_subway::util::{self, clock::ManualClock, latest};
+use nyc_subway::webhook::Webhooks;
+use prost::bytes::Bytes;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// a directory of recorded messages named by feed, e.g. ACE.pb, in place of the feed cache
+const FEEDS_VAR: &str = "NYC_SUBWAY_FEEDS";
+
+// a feed's message as it was fetched
+struct Recorded {
+    feed: String,
+    endpoint: String,
+    body: Bytes,
+}
+
+struct Schedule {
+    stops: EntityCollection<BTreeMap<StopId, Stop>>,
+    routes: EntityCollection<HashMap<RouteId, Route>>,
+    shapes: ShapeIndex,
+}
+
+// each feed's recorded message, in place of its endpoint
+struct Replay(HashMap<String, Bytes>);
+
+impl Transport for Replay {
+    fn get(&self, url: &str) -> anyhow::Result<Bytes> {
+        self.0
+            .get(url)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No recorded message for {url}"))
+    }
+}
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// messages from $NYC_SUBWAY_FEEDS, or the feed cache, which has the last message of each feed the map fetched
+fn recorded() -> Vec<Recorded> {
+    let dir = env::var_os(FEEDS_VAR)
+        .map(PathBuf::from)
+        .or_else(|| util::get_xdg().ok()?.find_cache_file("feeds"));
+    let Some(dir) = dir else {
+        return Vec::new();
+    };
+    FEEDS
+        .iter()
+        .filter_map(|feed| {
+            let body = fs::read(dir.join(format!("{feed:?}.pb"))).ok()?;
+            Some(Recorded {
+                feed: format!("{feed:?}"),
+                endpoint: feed.endpoint().to_owned(),
+                body: Bytes::from(body),
+            })
+        })
+        .collect()
+}
+
+// the downloaded schedule, that recorded messages are matched against
+fn downloaded_schedule() -> anyhow::Result<Schedule> {
+    let shapes = ShapeSeq::load_collection()?;
+    let shape_routes = <ShapeRoutes as GTFSData<TripRow>>::load()?;
+    Ok(Schedule {
+        stops: Stop::load_collection()?,
+        routes: Route::load_collection()?,
+        shapes: ShapeIndex::new(&shapes, &shape_routes),
+    })
+}
+
+// ace.json and the schedule the tests use, for checkouts that have never run the map
+fn fixture_schedule() -> (Vec<Recorded>, Schedule) {
+    env::set_var("XDG_DATA_HOME", fixtures().join("data"));
+    let value = json::parse(&fs::read(fixtures().join("feeds/ace.json")).unwrap()).unwrap();
+    let body = FeedMessage::deserialize(&value).unwrap().encode_to_vec();
+    let recorded = vec![Recorded {
+        feed: format!("{:?}", Feed::ACE),
+        endpoint: Feed::ACE.endpoint().to_owned(),
+        body: Bytes::from(body),
+    }];
+    let schedule = Schedule {
+        stops: Stop::load_collection().unwrap(),
+        routes: Route::load_collection().unwrap(),
+        shapes: ShapeIndex::new(&ShapeSeq::collection(), &ShapeRoutes::default()),
+    };
+    (recorded, schedule)
+}
+
+fn decode(body: &Bytes) -> (FeedMessage, Extensions) {
+    if json::is_json(body) {
+        let value = json::parse(body).unwrap();
+        (FeedMessage::deserialize(&value).unwrap(), Extensions::from_json(&value).unwrap())
+    } else {
+        (FeedMessage::decode(body.clone()).unwrap(), Extensions::decode(body.clone()).unwrap())
+    }
+}
+
+fn feeds(c: &mut Criterion) {
+    let recorded = recorded();
+    let (recorded, schedule) = match downloaded_schedule() {
+        Ok(schedule) if !recorded.is_empty() => (recorded, schedule),
+        _ => {
+            eprintln!("No recorded feeds or downloaded schedule, benchmarking the test fixtures");
+            fixture_schedule()
+        }
+    };
+    // processing caches each newer message it fetches, this keeps the recordings from being rewritten
+    env::set_var("XDG_CACHE_HOME", env::temp_dir().join("nyc_subway_rs-bench"));
+
+    let mut group = c.benchmark_group("decode");
+    for recorded in &recorded {
+        group.throughput(Throughput::Bytes(recorded.body.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(&recorded.feed), &recorded.body, |b, body| {
+            b.iter(|| decode(body))
+        });
+    }
+    group.finish();
+
+    // everything a manager does with a fetched message: decoding, queueing arrivals, applying the queue and
+    // publishing, and building the renderer's frame. the clock is stopped, so each update drains its whole queue
+    let published = recorded
+        .iter()
+        .map(|recorded| decode(&recorded.body).0.header.timestamp())
+        .max()
+        .unwrap_or_default();
+    let replay = Arc::new(Replay(
+        recorded
+            .iter()
+            .map(|recorded| (recorded.endpoint.clone(), recorded.body.clone()))
+            .collect(),
+    ));
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Bytes(recorded.iter().map(|recorded| recorded.body.len() as u64).sum()));
+    group.bench_function("all", |b| {
+        b.iter_batched(
+            || {
+                let (tx, rx) = latest::channel();
+                let manager = FeedManager::new(
+                    &schedule.stops,
+                    &schedule.routes,
+                    &schedule.shapes,
+                    SharedFeedState::default(),
+                    Webhooks::default(),
+                )
+                .with_transport(replay.clone())
+                .with_clock(Arc::new(ManualClock::new(published as i64)))
+                .with_frames(tx);
+                (manager, rx)
+            },
+            // fetching applies the first queued op, the second round applies the rest
+            |(mut manager, rx)| {
+                for _ in 0..FEEDS.len() * 2 {
+                    manager.update();
+                }
+                (manager, rx)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, feeds);
+criterion_main!(benches);
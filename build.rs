@@ -1,11 +1,57 @@
 use std::io::Result;
 use prost_build::Config;
 
+// GTFS-realtime fields the proto3 JSON mapping writes other than serde would, with the function
+// in proto::json that reads them: 64-bit integers as strings and enums by name
+const JSON_FIELDS: &[(&str, &str)] = &[
+    (".transit_realtime.FeedHeader.timestamp", "uint64"),
+    (".transit_realtime.FeedHeader.incrementality", "incrementality"),
+    (".transit_realtime.TripUpdate.timestamp", "uint64"),
+    (".transit_realtime.TripUpdate.StopTimeEvent.time", "int64"),
+    (".transit_realtime.TripUpdate.StopTimeUpdate.schedule_relationship", "stop_schedule_relationship"),
+    (".transit_realtime.TripUpdate.StopTimeUpdate.departure_occupancy_status", "occupancy_status"),
+    (".transit_realtime.VehiclePosition.timestamp", "uint64"),
+    (".transit_realtime.VehiclePosition.current_status", "vehicle_stop_status"),
+    (".transit_realtime.VehiclePosition.congestion_level", "congestion_level"),
+    (".transit_realtime.VehiclePosition.occupancy_status", "occupancy_status"),
+    (".transit_realtime.VehiclePosition.CarriageDetails.occupancy_status", "occupancy_status"),
+    (".transit_realtime.Alert.cause", "cause"),
+    (".transit_realtime.Alert.effect", "effect"),
+    (".transit_realtime.Alert.severity_level", "severity_level"),
+    (".transit_realtime.TimeRange.start", "uint64"),
+    (".transit_realtime.TimeRange.end", "uint64"),
+    (".transit_realtime.TripDescriptor.schedule_relationship", "trip_schedule_relationship"),
+    (".transit_realtime.VehicleDescriptor.wheelchair_accessible", "wheelchair_accessible"),
+    (".transit_realtime.TripModifications.Modification.last_modified_time", "uint64"),
+    (".transit_realtime.NyctTripDescriptor.direction", "nyct_direction"),
+];
+
+// messages whose upstream docs have markdown lists clippy reads as continuations or overindented,
+// prost matches by prefix so TripUpdate covers its StopTimeEvent
+const LOOSE_DOC_LISTS: &[&str] = &[
+    ".transit_realtime.TripUpdate",
+    ".transit_realtime.TripDescriptor",
+    ".transit_realtime.TranslatedString",
+    ".transit_realtime.TranslatedImage",
+];
+
 fn main() -> Result<()> {
     let mut config = Config::new();
 
-    config.type_attribute(".", "#[derive(serde::Serialize)]");
+    config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+    // fields missing from JSON messages are left unset, as they are decoding protobuf
+    config.message_attribute(".", "#[serde(default)]");
+    for (path, function) in JSON_FIELDS {
+        config.field_attribute(path, format!("#[serde(deserialize_with = \"crate::proto::json::{function}\")]"));
+    }
+
+    for path in LOOSE_DOC_LISTS {
+        config.type_attribute(path, "#[allow(clippy::doc_lazy_continuation, clippy::doc_overindented_list_items)]");
+    }
 
-    config.compile_protos(&["proto/gtfs/gtfs-realtime-NYCT.proto"], &["proto/"])?;
+    config.compile_protos(
+        &["proto/gtfs/gtfs-realtime-NYCT.proto", "proto/mvt/vector_tile.proto"],
+        &["proto/"],
+    )?;
     Ok(())
 }
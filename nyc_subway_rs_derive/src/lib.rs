@@ -1,11 +1,13 @@
 use proc_macro::TokenStream;
 use proc_macro_error::{proc_macro_error, Diagnostic, Level};
 use quote::{quote, format_ident};
-use syn::Data;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Ident, Lit};
+
+const WIDTHS: [&str; 4] = ["u8", "u16", "u32", "u64"];
 
 #[proc_macro_derive(Deserialize_enum_or, attributes(fallback))]
 pub fn deserialize_enum_or_macro_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).unwrap();
+    let ast = parse_macro_input!(input as DeriveInput);
 
     impl_deserialize_enum(&ast)
 }
@@ -14,21 +16,67 @@ pub fn deserialize_enum_or_macro_derive(input: TokenStream) -> TokenStream {
 fn impl_deserialize_enum(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
     if let Data::Enum(data) = &ast.data {
-        let fallback_variant = &data
+        let Some(first_variant) = data.variants.first().map(|variant| &variant.ident) else {
+            Diagnostic::spanned(
+                name.span().unwrap().into(),
+                Level::Error,
+                "expected at least one variant".to_owned(),
+            )
+            .abort();
+        };
+        let mut fallbacks = data.variants.iter().filter_map(|variant| {
+            variant
+                .attrs
+                .iter()
+                .find(|attr| attr.path().is_ident("fallback"))
+                .map(|attr| (variant, attr))
+        });
+        let fallback = fallbacks.next().map(|(variant, _)| variant);
+        if let Some((_, attr)) = fallbacks.next() {
+            Diagnostic::spanned(
+                attr.path().get_ident().unwrap().span().unwrap().into(),
+                Level::Error,
+                "only one variant can be the #[fallback]".to_owned(),
+            )
+            .abort();
+        }
+        // a fallback with a field, e.g. `Other(u8)`, keeps the unrecognized value
+        let unknown = match fallback.map(|variant| (&variant.ident, &variant.fields)) {
+            Some((ident, Fields::Unit)) => quote! { Self::Value::#ident },
+            Some((ident, Fields::Unnamed(fields))) if fields.unnamed.len() == 1 => {
+                quote! { Self::Value::#ident(v) }
+            }
+            Some((ident, _)) => Diagnostic::spanned(
+                ident.span().unwrap().into(),
+                Level::Error,
+                "fallback variant must be a unit variant or have a single unnamed integer field".to_owned(),
+            )
+            .abort(),
+            None => quote! { Self::Value::#first_variant },
+        };
+        // a missing value can't be captured, it's the fallback if that's a unit variant, otherwise the first variant
+        let missing = match fallback {
+            Some(variant) if matches!(variant.fields, Fields::Unit) => &variant.ident,
+            _ => first_variant,
+        };
+        let known: Vec<_> = data
             .variants
             .iter()
-            .find(|variant| {
-                variant
-                    .attrs
-                    .iter()
-                    .find(|attr| attr.path().is_ident("fallback"))
-                    .is_some()
+            .filter(|variant| matches!(variant.fields, Fields::Unit))
+            .map(|variant| match &variant.discriminant {
+                Some((_, disc)) => (&variant.ident, disc),
+                None => Diagnostic::spanned(
+                    variant.ident.span().unwrap().into(),
+                    Level::Error,
+                    "expected an explicit discriminant".to_owned(),
+                )
+                .abort(),
             })
-            .or_else(|| data.variants.first())
-            .unwrap()
-            .ident;
-        let variants = data.variants.iter().map(|variant| &variant.ident);
-        let disc = data.variants.iter().map(|variant| &variant.discriminant.as_ref().unwrap().1);
+            .collect();
+        let width = repr_width(ast).unwrap_or_else(|| inferred_width(known.iter().map(|(_, disc)| *disc)));
+        let deserialize_width = format_ident!("deserialize_{}", width);
+        let variants = known.iter().map(|(ident, _)| ident);
+        let disc = known.iter().map(|(_, disc)| disc);
         let visitor_name = format_ident!("My{}Visitor", name);
         let gen = quote! {
             impl<'de> Deserialize<'de> for #name {
@@ -41,28 +89,42 @@ fn impl_deserialize_enum(ast: &syn::DeriveInput) -> TokenStream {
                         type Value = #name;
 
                         fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-                            write!(formatter, "an integer between 0 and {}", u8::MAX)
+                            write!(formatter, "an integer between 0 and {}", #width::MAX)
                         }
 
-                        fn visit_u8<E>(self, v: u8) -> std::result::Result<Self::Value, E>
+                        // narrower unsigned integers are forwarded here by serde
+                        fn visit_u64<E>(self, v: u64) -> std::result::Result<Self::Value, E>
                             where
                                 E: serde::de::Error, {
+                            let Ok(v) = #width::try_from(v) else {
+                                return Err(E::invalid_value(serde::de::Unexpected::Unsigned(v), &self));
+                            };
                             match v {
                                 #(#disc => Ok(Self::Value::#variants)),*,
-                                _ => Ok(Self::Value::#fallback_variant),
+                                _ => Ok(#unknown),
+                            }
+                        }
+
+                        // self-describing formats may hand over small numbers as signed
+                        fn visit_i64<E>(self, v: i64) -> std::result::Result<Self::Value, E>
+                            where
+                                E: serde::de::Error, {
+                            match u64::try_from(v) {
+                                Ok(v) => self.visit_u64(v),
+                                Err(_) => Err(E::invalid_value(serde::de::Unexpected::Signed(v), &self)),
                             }
                         }
 
                         fn visit_none<E>(self) -> std::result::Result<Self::Value, E>
                             where
                                 E: serde::de::Error, {
-                            Ok(Self::Value::#fallback_variant)
+                            Ok(Self::Value::#missing)
                         }
 
                         fn visit_some<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
                             where
                                 D: Deserializer<'de>, {
-                            deserializer.deserialize_u8(self)
+                            deserializer.#deserialize_width(self)
                         }
                     }
 
@@ -80,3 +142,42 @@ fn impl_deserialize_enum(ast: &syn::DeriveInput) -> TokenStream {
         .abort();
     }
 }
+
+// the unsigned integer type named by `#[repr(..)]`, if any
+fn repr_width(ast: &syn::DeriveInput) -> Option<Ident> {
+    let mut width = None;
+    for attr in ast.attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        // other hints like `C` are ignored
+        let _ = attr.parse_nested_meta(|meta| {
+            if let Some(ident) = meta.path.get_ident().filter(|ident| WIDTHS.contains(&ident.to_string().as_str())) {
+                width = Some(ident.clone());
+            }
+            Ok(())
+        });
+    }
+    width
+}
+
+// the narrowest unsigned integer type holding every discriminant
+fn inferred_width<'a>(discriminants: impl Iterator<Item = &'a Expr>) -> Ident {
+    let max = discriminants
+        .filter_map(|disc| match disc {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Int(int) => int.base10_parse::<u64>().ok(),
+                _ => None,
+            },
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let width = if max <= u8::MAX as u64 {
+        "u8"
+    } else if max <= u16::MAX as u64 {
+        "u16"
+    } else if max <= u32::MAX as u64 {
+        "u32"
+    } else {
+        "u64"
+    };
+    format_ident!("{}", width)
+}
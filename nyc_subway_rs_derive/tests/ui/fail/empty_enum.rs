@@ -0,0 +1,6 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+
+#[derive(Deserialize_enum_or)]
+enum LocationKind {}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+
+#[derive(Deserialize_enum_or)]
+#[repr(u8)]
+enum LocationKind {
+    Platform = 0,
+    #[fallback]
+    Other(u8, u8),
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+
+#[derive(Deserialize_enum_or)]
+enum LocationKind {
+    Platform = 0,
+    Station,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+
+#[derive(Deserialize_enum_or)]
+enum LocationKind {
+    #[fallback]
+    Platform = 0,
+    #[fallback]
+    Station = 1,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+
+#[derive(Deserialize_enum_or)]
+struct LocationKind {
+    location_type: u8,
+}
+
+fn main() {}
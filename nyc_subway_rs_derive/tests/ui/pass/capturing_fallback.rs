@@ -0,0 +1,28 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+use serde::de::value::Error;
+use serde::de::{Deserialize, Deserializer, IntoDeserializer, Visitor};
+use std::fmt::Formatter;
+
+#[derive(Debug, PartialEq, Deserialize_enum_or)]
+#[repr(u8)]
+enum LocationKind {
+    Platform = 0,
+    Station = 1,
+    #[fallback]
+    Other(u8),
+}
+
+fn main() {
+    assert_eq!(kind(0), Ok(LocationKind::Platform));
+    assert_eq!(kind(1), Ok(LocationKind::Station));
+    // unknown values are kept
+    assert_eq!(kind(7), Ok(LocationKind::Other(7)));
+    // wider than the repr
+    assert!(kind(256).is_err());
+    // there's no value to capture, so the first variant
+    assert_eq!(LocationKind::deserialize(().into_deserializer()), Ok::<_, Error>(LocationKind::Platform));
+}
+
+fn kind(value: u64) -> Result<LocationKind, Error> {
+    LocationKind::deserialize(value.into_deserializer())
+}
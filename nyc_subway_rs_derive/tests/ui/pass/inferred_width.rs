@@ -0,0 +1,24 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+use serde::de::value::Error;
+use serde::de::{Deserialize, Deserializer, IntoDeserializer, Visitor};
+use std::fmt::Formatter;
+
+// inferred to be u16
+#[derive(Debug, PartialEq, Deserialize_enum_or)]
+enum Wide {
+    Small = 0,
+    Large = 300,
+}
+
+fn main() {
+    assert_eq!(wide(0), Ok(Wide::Small));
+    assert_eq!(wide(300), Ok(Wide::Large));
+    // without a fallback, unknown values are the first variant
+    assert_eq!(wide(5), Ok(Wide::Small));
+    assert!(wide(u16::MAX as u64 + 1).is_err());
+    assert_eq!(Wide::deserialize(().into_deserializer()), Ok::<_, Error>(Wide::Small));
+}
+
+fn wide(value: u64) -> Result<Wide, Error> {
+    Wide::deserialize(value.into_deserializer())
+}
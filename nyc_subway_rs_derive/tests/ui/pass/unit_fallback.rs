@@ -0,0 +1,26 @@
+use nyc_subway_rs_derive::Deserialize_enum_or;
+use serde::de::value::Error;
+use serde::de::{Deserialize, Deserializer, IntoDeserializer, Visitor};
+use std::fmt::Formatter;
+
+#[derive(Debug, PartialEq, Deserialize_enum_or)]
+enum LocationKind {
+    #[fallback]
+    Platform = 0,
+    Station = 1,
+}
+
+fn main() {
+    assert_eq!(kind(0), Ok(LocationKind::Platform));
+    assert_eq!(kind(1), Ok(LocationKind::Station));
+    assert_eq!(kind(9), Ok(LocationKind::Platform));
+    // missing values are the fallback too
+    assert_eq!(LocationKind::deserialize(().into_deserializer()), Ok::<_, Error>(LocationKind::Platform));
+    // small numbers may come signed, negative ones are never valid
+    assert_eq!(LocationKind::deserialize(1i64.into_deserializer()), Ok::<_, Error>(LocationKind::Station));
+    assert!(LocationKind::deserialize(IntoDeserializer::<Error>::into_deserializer(-1i64)).is_err());
+}
+
+fn kind(value: u64) -> Result<LocationKind, Error> {
+    LocationKind::deserialize(value.into_deserializer())
+}
@@ -0,0 +1,351 @@
+use anyhow::{anyhow, Context, Result};
+use geo::Coord;
+use tracing::{info, warn};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::entities::{EntityCollection, Stop};
+use crate::feed;
+use crate::ids::StopId;
+use crate::render::stop::StopInstance;
+use crate::util::{self, static_data::STATIONS_STATIC};
+
+const OUTAGES_ENDPOINT: &str =
+    "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fnyct_ene.json";
+const EQUIPMENT_ENDPOINT: &str =
+    "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fnyct_ene_equipments.json";
+// the outage list is refreshed every few minutes upstream
+const POLLING_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// outage markers sit below and right of the station, annotation markers above it
+const MARKER_OFFSET: f32 = 150.;
+const MARKER_COLOR: [f32; 3] = [0.0, 0.3, 0.8];
+// accessibility markers sit below and left of the station
+const ACCESSIBLE_COLOR: [f32; 3] = [0.0, 0.55, 0.95];
+const PARTIAL_COLOR: [f32; 3] = [0.45, 0.75, 1.0];
+// accessible on paper, but an elevator there is out of service
+const UNAVAILABLE_COLOR: [f32; 3] = [0.4, 0.4, 0.4];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EquipmentKind {
+    Elevator,
+    Escalator,
+}
+
+impl EquipmentKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Elevator => "Elevator",
+            Self::Escalator => "Escalator",
+        }
+    }
+}
+
+// an elevator or escalator out of service, as reported by the MTA
+#[derive(Debug, Clone, Deserialize)]
+pub struct Outage {
+    pub equipment: String,
+    #[serde(rename = "equipmenttype", deserialize_with = "equipment_kind")]
+    pub kind: EquipmentKind,
+    // what the equipment connects, e.g. "street to mezzanine"
+    pub serving: String,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(rename = "outagedate", default)]
+    pub since: String,
+    #[serde(rename = "estimatedreturntoservice", default)]
+    pub estimated_return: String,
+    #[serde(rename = "isupcomingoutage", deserialize_with = "flag", default)]
+    upcoming: bool,
+}
+
+impl Outage {
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} {} ({})", self.kind.label(), self.equipment, self.serving);
+        if !self.reason.is_empty() {
+            summary.push_str(&format!(", {}", self.reason));
+        }
+        if !self.since.is_empty() {
+            summary.push_str(&format!(", out since {}", self.since));
+        }
+        if !self.estimated_return.is_empty() {
+            summary.push_str(&format!(", back {}", self.estimated_return));
+        }
+        summary
+    }
+}
+
+#[derive(Deserialize)]
+struct Equipment {
+    #[serde(rename = "equipmentno")]
+    equipment: String,
+    // stations the equipment serves, separated by '/'
+    #[serde(rename = "elevatorsgtfsstopid", default)]
+    stop_ids: String,
+}
+
+fn equipment_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<EquipmentKind, D::Error> {
+    let kind: String = Deserialize::deserialize(deserializer)?;
+    Ok(match kind.as_str() {
+        "ES" => EquipmentKind::Escalator,
+        _ => EquipmentKind::Elevator,
+    })
+}
+
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let flag: String = Deserialize::deserialize(deserializer)?;
+    Ok(flag == "Y")
+}
+
+#[derive(Default)]
+struct OutageState {
+    // current outages by parent stop_id
+    stations: HashMap<StopId, Vec<Outage>>,
+    markers: Vec<StopInstance>,
+    // bumped on every publish, so the renderer only rewrites markers when they change
+    generation: u64,
+}
+
+// elevator and escalator outages, refreshed on a background thread
+#[derive(Clone, Default)]
+pub struct Outages(Arc<RwLock<OutageState>>);
+
+impl Outages {
+    pub fn spawn(&self, stops: Arc<EntityCollection<BTreeMap<StopId, Stop>>>) {
+        let outages = self.clone();
+        thread::spawn(move || {
+            let client = feed::client();
+            // the equipment list rarely changes, it's fetched until it succeeds once
+            let mut equipment: Option<HashMap<String, Vec<StopId>>> = None;
+            loop {
+                if equipment.is_none() {
+                    equipment = fetch_equipment(&client, &stops)
+                        .inspect_err(|err| warn!("Failed to fetch elevator and escalator list: {}", err))
+                        .ok();
+                }
+                if let Some(equipment) = &equipment {
+                    match fetch_outages(&client) {
+                        Ok(current) => outages.publish(current, equipment, &stops),
+                        Err(err) => warn!("Failed to fetch elevator and escalator outages: {}", err),
+                    }
+                }
+                thread::sleep(POLLING_INTERVAL);
+            }
+        });
+    }
+
+    fn publish(
+        &self,
+        current: Vec<Outage>,
+        equipment: &HashMap<String, Vec<StopId>>,
+        stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+    ) {
+        let mut stations: HashMap<StopId, Vec<Outage>> = HashMap::new();
+        for outage in current.into_iter().filter(|outage| !outage.upcoming) {
+            for stop_id in equipment.get(&outage.equipment).into_iter().flatten() {
+                stations.entry(stop_id.clone()).or_default().push(outage.clone());
+            }
+        }
+        let markers = stations
+            .keys()
+            .filter_map(|stop_id| stops.get(stop_id))
+            .map(|stop| StopInstance {
+                position: [
+                    stop.coord.x - MARKER_OFFSET,
+                    stop.coord.y + MARKER_OFFSET,
+                    0.0,
+                ],
+                color: MARKER_COLOR,
+                scale: -0.6,
+            })
+            .collect();
+        info!("{} station(s) with elevator or escalator outages", stations.len());
+
+        let mut state = self.0.write().unwrap();
+        state.stations = stations;
+        state.markers = markers;
+        state.generation += 1;
+    }
+
+    pub fn station(&self, stop_id: &str) -> Vec<Outage> {
+        self.0
+            .read()
+            .unwrap()
+            .stations
+            .get(stop_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // the markers, if they changed since `generation`
+    pub fn markers_since(&self, generation: u64) -> Option<(u64, Vec<StopInstance>)> {
+        let state = self.0.read().unwrap();
+        (state.generation != generation).then(|| (state.generation, state.markers.clone()))
+    }
+
+    // an elevator at the station is out of service, which can leave it inaccessible
+    pub fn elevator_out(&self, stop_id: &str) -> bool {
+        self.0
+            .read()
+            .unwrap()
+            .stations
+            .get(stop_id)
+            .is_some_and(|outages| outages.iter().any(|outage| outage.kind == EquipmentKind::Elevator))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Full,
+    // e.g. in one direction only
+    Partial,
+}
+
+impl Access {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Full => "Accessible",
+            Self::Partial => "Partially accessible",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessibleStation {
+    pub access: Access,
+    // the MTA's notes on what is accessible at partially accessible stations
+    pub notes: String,
+    coord: Coord<f32>,
+}
+
+#[derive(Deserialize)]
+struct StationRow {
+    // the parent station stop_id
+    #[serde(rename = "GTFS Stop ID")]
+    stop_id: StopId,
+    // 0 not accessible, 1 fully, 2 partially
+    #[serde(rename = "ADA")]
+    ada: u8,
+    #[serde(rename = "ADA Notes", default)]
+    notes: String,
+}
+
+// stations that can be used from a wheelchair, as built. from stops.txt's wheelchair_boarding and the MTA's
+// subway stations dataset, which is more complete and wins where they disagree
+#[derive(Default)]
+pub struct Accessibility {
+    // by parent stop_id
+    stations: BTreeMap<StopId, AccessibleStation>,
+}
+
+impl Accessibility {
+    pub fn load(stops: &EntityCollection<BTreeMap<StopId, Stop>>) -> Self {
+        let stations = stops
+            .values()
+            .filter(|stop| stop.parent.is_none() && stop.wheelchair_boarding == Some(true))
+            .map(|stop| {
+                let station = AccessibleStation {
+                    access: Access::Full,
+                    notes: String::new(),
+                    coord: stop.coord,
+                };
+                (stop.id.clone(), station)
+            })
+            .collect();
+        let mut accessibility = Self { stations };
+        if let Err(err) = accessibility.load_stations(stops) {
+            warn!("Station accessibility limited to stops.txt: {}", err);
+        }
+        info!("{} accessible station(s)", accessibility.stations.len());
+        accessibility
+    }
+
+    fn load_stations(&mut self, stops: &EntityCollection<BTreeMap<StopId, Stop>>) -> Result<()> {
+        let xdg = util::get_xdg()?;
+        let path = xdg
+            .find_data_file(STATIONS_STATIC.1)
+            .ok_or_else(|| anyhow!("Could not find {} data", STATIONS_STATIC.1))?;
+        let unreadable = || format!("Failed to read '{}'", path.display());
+        for rec in csv::Reader::from_path(&path).with_context(unreadable)?.deserialize() {
+            let row: StationRow = rec.with_context(|| format!("Malformed row in '{}'", path.display()))?;
+            let Some(stop) = stops.get(row.stop_id.as_str()) else {
+                continue;
+            };
+            let access = match row.ada {
+                1 => Access::Full,
+                2 => Access::Partial,
+                _ => {
+                    self.stations.remove(&row.stop_id);
+                    continue;
+                }
+            };
+            let station = AccessibleStation {
+                access,
+                notes: row.notes,
+                coord: stop.coord,
+            };
+            self.stations.insert(row.stop_id, station);
+        }
+        Ok(())
+    }
+
+    pub fn station(&self, stop_id: &str) -> Option<&AccessibleStation> {
+        self.stations.get(stop_id)
+    }
+
+    // a marker beside each accessible station, greyed out while an elevator there is out of service.
+    // always one per station in stop_id order, so only their colors change as outages do
+    pub fn markers(&self, outages: &Outages) -> Vec<StopInstance> {
+        self.stations
+            .iter()
+            .map(|(stop_id, station)| {
+                let color = match station.access {
+                    _ if outages.elevator_out(stop_id.as_str()) => UNAVAILABLE_COLOR,
+                    Access::Full => ACCESSIBLE_COLOR,
+                    Access::Partial => PARTIAL_COLOR,
+                };
+                StopInstance {
+                    position: [
+                        station.coord.x - MARKER_OFFSET,
+                        station.coord.y - MARKER_OFFSET,
+                        0.0,
+                    ],
+                    color,
+                    scale: -0.6,
+                }
+            })
+            .collect()
+    }
+}
+
+// parent stop_ids by equipment number
+fn fetch_equipment(
+    client: &Client,
+    stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+) -> Result<HashMap<String, Vec<StopId>>> {
+    let failed = || format!("Failed to fetch '{EQUIPMENT_ENDPOINT}'");
+    let response = client.get(EQUIPMENT_ENDPOINT).send().and_then(|res| res.error_for_status()).with_context(failed)?;
+    let equipment: Vec<Equipment> =
+        serde_json::from_slice(&response.bytes().with_context(failed)?).with_context(failed)?;
+    Ok(equipment
+        .into_iter()
+        .map(|row| {
+            let stop_ids = row
+                .stop_ids
+                .split('/')
+                .filter_map(|stop_id| stops.station(stop_id.trim()))
+                .map(|stop| stop.id.clone())
+                .collect();
+            (row.equipment, stop_ids)
+        })
+        .collect())
+}
+
+fn fetch_outages(client: &Client) -> Result<Vec<Outage>> {
+    let failed = || format!("Failed to fetch '{OUTAGES_ENDPOINT}'");
+    let response = client.get(OUTAGES_ENDPOINT).send().and_then(|res| res.error_for_status()).with_context(failed)?;
+    serde_json::from_slice(&response.bytes().with_context(failed)?).with_context(failed)
+}
@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use tracing::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::util;
+
+pub const ANNOTATIONS_FILE: &str = "annotations.toml";
+
+// user notes and tags, read from $XDG_CONFIG_HOME/nyc_subway_rs/annotations.toml
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Annotations {
+    // by parent station stop_id
+    pub stations: HashMap<String, Annotation>,
+    // by route_id
+    pub routes: HashMap<String, Annotation>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Annotation {
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Annotations {
+    pub fn load() -> Result<Self> {
+        let xdg = util::get_xdg()?;
+        let Some(path) = xdg.find_config_file(ANNOTATIONS_FILE) else {
+            return Ok(Self::default());
+        };
+        info!("Loading annotations: '{}'", path.display());
+        let annotations = toml::from_str(
+            &fs::read_to_string(&path).with_context(|| format!("Failed to read '{}'", path.display()))?,
+        )
+        .with_context(|| format!("Invalid annotations in '{}'", path.display()))?;
+        Ok(annotations)
+    }
+
+    pub fn station(&self, stop_id: &str) -> Option<&Annotation> {
+        self.stations.get(stop_id)
+    }
+
+    pub fn route(&self, route_id: &str) -> Option<&Annotation> {
+        self.routes.get(route_id)
+    }
+}
+
+impl Annotation {
+    // note and tags on a single line, e.g. `my stop [home, work]`
+    pub fn summary(&self) -> String {
+        let note = self.note.as_deref().unwrap_or_default();
+        if self.tags.is_empty() {
+            note.to_owned()
+        } else {
+            format!("{} [{}]", note, self.tags.join(", ")).trim().to_owned()
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{bail, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::entities::{EntityCollection, Route, Schedule, ShapeIndex, Stop};
+use crate::feed::{FeedManager, FeedSource, SharedFeedState};
+use crate::ids::{RouteId, StopId};
+use crate::recorder;
+use crate::timetable::AGENCY_TIMEZONE;
+use crate::util::{self, format::Formatter};
+use crate::validate::ScheduleValidator;
+use crate::webhook::Webhooks;
+
+// how often the board is redrawn when no key is pressed, countdowns only change by the minute
+const REFRESH: Duration = Duration::from_secs(1);
+// arrivals listed per station
+const DEPARTURES: usize = 8;
+
+type Stops = EntityCollection<BTreeMap<StopId, Stop>>;
+type Routes = EntityCollection<HashMap<RouteId, Route>>;
+
+// run the feeds without a window and show the upcoming trains at `stations` in the terminal, until q is pressed.
+// `plain` prints the boards as lines of text instead, each time they change, until interrupted
+pub fn run(
+    stations: &[String],
+    schedule: Schedule,
+    config: Config,
+    validator: Option<ScheduleValidator>,
+    source: FeedSource,
+    plain: bool,
+) -> Result<()> {
+    let Schedule {
+        stops,
+        shapes,
+        routes,
+        shape_routes,
+        timetable,
+        ..
+    } = schedule;
+    let boards = resolve(stations, &stops)?;
+
+    let shared = SharedFeedState::default();
+    if config.history.enabled {
+        recorder::spawn(&config.history, &shared.updates)?;
+    }
+    let (stops, routes) = (Arc::new(stops), Arc::new(routes));
+    {
+        let shared = shared.clone();
+        let (stops, routes) = (stops.clone(), routes.clone());
+        let motion = config.motion;
+        let feeds = config.feeds;
+        let webhooks = Webhooks::spawn(config.webhooks);
+        thread::spawn(move || {
+            let shape_index = ShapeIndex::new(&shapes, &shape_routes);
+            let mut feed_manager = FeedManager::new(&stops, &routes, &shape_index, shared, webhooks);
+            if let Some(validator) = &validator {
+                feed_manager = feed_manager.with_validator(validator);
+            }
+            feed_manager = feed_manager
+                .with_source(source)
+                .with_feeds(&feeds)
+                .with_timetable(&timetable)
+                .with_motion(motion);
+
+            loop {
+                feed_manager.update();
+                thread::sleep(feed_manager.polling_interval());
+            }
+        });
+    }
+
+    if plain {
+        let mut printed = Vec::new();
+        loop {
+            let lines = plain_lines(&boards, &stops, &shared, &config.format);
+            if lines != printed {
+                println!("{}\n", lines.join("\n"));
+                printed = lines;
+            }
+            thread::sleep(REFRESH);
+        }
+    }
+
+    let mut terminal = ratatui::init();
+    let shown = loop {
+        if let Err(err) = terminal.draw(|frame| draw(frame, &boards, &stops, &routes, &shared, &config.format)) {
+            break Err(err);
+        }
+        match event::poll(REFRESH) {
+            Ok(false) => continue,
+            Ok(true) => {}
+            Err(err) => break Err(err),
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    break Ok(());
+                }
+            }
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+    };
+    ratatui::restore();
+    Ok(shown?)
+}
+
+// each station as given by stop_id, platforms resolve to their station, or by a case-insensitive part of its name
+fn resolve(stations: &[String], stops: &Stops) -> Result<Vec<StopId>> {
+    let mut boards: Vec<StopId> = Vec::new();
+    for station in stations {
+        let found: Vec<&Stop> = match stops.station(station.as_str()) {
+            Some(stop) => vec![stop],
+            None => {
+                let needle = station.to_lowercase();
+                stops
+                    .values()
+                    .filter(|stop| stop.parent.is_none())
+                    .filter(|stop| stop.name.to_lowercase().contains(&needle))
+                    .collect()
+            }
+        };
+        if found.is_empty() {
+            bail!("No station matches '{}'", station);
+        }
+        for stop in found {
+            if !boards.contains(&stop.id) {
+                boards.push(stop.id.clone());
+            }
+        }
+    }
+    Ok(boards)
+}
+
+fn draw(
+    frame: &mut Frame,
+    boards: &[StopId],
+    stops: &Stops,
+    routes: &Routes,
+    shared: &SharedFeedState,
+    formatter: &Formatter,
+) {
+    let now = util::posix_now();
+    let [header, body] = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(frame.area());
+
+    let feeds = shared.snapshot.feeds();
+    let newest = feeds.iter().filter_map(|feed| feed.updated).max();
+    let stale = feeds.iter().filter(|feed| feed.stale).count();
+    let mut status = vec![Span::styled(
+        format!("{} ET", formatter.clock(now, AGENCY_TIMEZONE)),
+        Style::new().add_modifier(Modifier::BOLD),
+    )];
+    match newest {
+        Some(updated) => status.push(Span::raw(format!("  data as of {}", formatter.clock(updated as i64, AGENCY_TIMEZONE)))),
+        None => status.push(Span::raw("  waiting for feeds")),
+    }
+    if stale > 0 {
+        status.push(Span::styled(format!("  {stale} stale"), Style::new().fg(Color::Yellow)));
+    }
+    status.push(Span::styled("  q to quit", Style::new().fg(Color::DarkGray)));
+    frame.render_widget(Paragraph::new(Line::from(status)), header);
+
+    let areas = Layout::vertical(boards.iter().map(|_| Constraint::Fill(1))).split(body);
+    for (stop_id, area) in boards.iter().zip(areas.iter()) {
+        let name = stops.get(stop_id).map_or(stop_id.as_str(), |stop| stop.name.as_str());
+        let rows = shared
+            .arrivals
+            .upcoming(stop_id.as_str(), now)
+            .into_iter()
+            .take(DEPARTURES)
+            .map(|arrival| {
+                let color = routes.get(&arrival.route_id).map_or([1.0; 3], |route| route.color());
+                let [r, g, b] = srgb::gamma::u8_from_linear(color);
+                let minutes = (arrival.smoothed - now).max(0) / 60;
+                Row::new([
+                    Span::styled(
+                        arrival.route_id.to_string(),
+                        Style::new().fg(Color::Rgb(r, g, b)).add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(
+                        arrival.service_pattern.map_or("", |pattern| pattern.label()),
+                        Style::new().fg(Color::DarkGray),
+                    ),
+                    Span::raw(formatter.time(arrival.time, AGENCY_TIMEZONE)),
+                    Span::raw(if minutes == 0 { "now".to_string() } else { format!("{minutes} min") }),
+                ])
+            });
+        let widths = [Constraint::Length(4), Constraint::Length(8), Constraint::Length(10), Constraint::Fill(1)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["", "", "Arrives", "In"]).style(Style::new().fg(Color::DarkGray)))
+            .block(Block::bordered().title(format!(" {name} ")));
+        frame.render_widget(table, *area);
+    }
+}
+
+// the boards as lines of text, for screen readers and scripts: the time and how current the feeds are, then each
+// station's name followed by one line per train
+fn plain_lines(boards: &[StopId], stops: &Stops, shared: &SharedFeedState, formatter: &Formatter) -> Vec<String> {
+    let now = util::posix_now();
+    let feeds = shared.snapshot.feeds();
+    // to the minute, so the boards are only printed again when something a listener cares about has changed
+    let mut status = format!("{} ET", formatter.time(now, AGENCY_TIMEZONE));
+    match feeds.iter().filter_map(|feed| feed.updated).max() {
+        Some(updated) => status.push_str(&format!(", data as of {}", formatter.time(updated as i64, AGENCY_TIMEZONE))),
+        None => status.push_str(", waiting for feeds"),
+    }
+    let stale = feeds.iter().filter(|feed| feed.stale).count();
+    if stale > 0 {
+        status.push_str(&format!(", {stale} stale"));
+    }
+    let mut lines = vec![status];
+    for stop_id in boards {
+        let name = stops.get(stop_id).map_or(stop_id.as_str(), |stop| stop.name.as_str());
+        lines.push(name.to_owned());
+        let arrivals = shared.arrivals.upcoming(stop_id.as_str(), now);
+        if arrivals.is_empty() {
+            lines.push("  no trains due".to_owned());
+        }
+        for arrival in arrivals.into_iter().take(DEPARTURES) {
+            let minutes = (arrival.smoothed - now).max(0) / 60;
+            let pattern = arrival.service_pattern.map_or(String::new(), |pattern| format!(" {}", pattern.label()));
+            let due = if minutes == 0 { "now".to_string() } else { format!("in {minutes} min") };
+            let time = formatter.time(arrival.time, AGENCY_TIMEZONE);
+            lines.push(format!("  {}{pattern} train, arrives {time}, {due}", arrival.route_id));
+        }
+    }
+    lines
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use crate::util;
+
+pub const SCHEDULE_CACHE: &str = "schedule.bincode";
+pub const ATLAS_CACHE: &str = "atlas.bincode";
+// bump when the schedule is parsed into something different from the same zip, so cached ones are rebuilt
+const SCHEDULE_REVISION: u32 = 1;
+
+// hex sha256 of everything a cached value was derived from
+pub fn key<'a>(inputs: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = Sha256::new();
+    // entries written by another version may not deserialize
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    for input in inputs {
+        hasher.update(input);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// the key a schedule parsed from `zip` is cached under
+pub fn schedule_key(zip: &[u8]) -> String {
+    key([SCHEDULE_REVISION.to_le_bytes().as_slice(), zip])
+}
+
+// the cached value, if it was stored under the same key
+pub fn load<T: DeserializeOwned>(file: &str, key: &str) -> Option<T> {
+    let xdg = util::get_xdg().ok()?;
+    let path = xdg.find_cache_file(file)?;
+    let mut reader = BufReader::new(File::open(&path).ok()?);
+    let cached_key: String = bincode::deserialize_from(&mut reader).ok()?;
+    if cached_key != key {
+        info!("Stale cache: '{}'", path.display());
+        return None;
+    }
+    match bincode::deserialize_from(&mut reader) {
+        Ok(value) => {
+            info!("Loaded cache: '{}'", path.display());
+            Some(value)
+        }
+        Err(err) => {
+            warn!("Failed to read cache '{}': {}", path.display(), err);
+            None
+        }
+    }
+}
+
+pub fn store<T: Serialize>(file: &str, key: &str, value: &T) -> Result<()> {
+    let xdg = util::get_xdg()?;
+    let path = xdg.place_cache_file(file)?;
+    let failed = || format!("Failed to write the cache '{}'", path.display());
+    let mut writer = BufWriter::new(File::create(&path).with_context(failed)?);
+    bincode::serialize_into(&mut writer, key).with_context(failed)?;
+    bincode::serialize_into(&mut writer, value).with_context(failed)?;
+    info!("Stored cache: '{}'", path.display());
+    Ok(())
+}
+
+// load from cache, or build and cache the value, when there's nothing to key it by it's just built
+pub fn load_or_build<T: Serialize + DeserializeOwned>(
+    file: &str,
+    key: Option<&str>,
+    build: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(key) = key else {
+        return build();
+    };
+    if let Some(value) = load(file, key) {
+        return Ok(value);
+    }
+    let value = build()?;
+    if let Err(err) = store(file, key, &value) {
+        warn!("Failed to write cache '{}': {}", file, err);
+    }
+    Ok(value)
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Context, Result};
+use geo::Coord;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::entities::{EntityCollection, Stop};
+use crate::ids::StopId;
+use crate::util::{self, static_data::STATIONS_STATIC};
+
+#[derive(Deserialize)]
+struct StationRow {
+    #[serde(rename = "Complex ID")]
+    complex_id: u32,
+    // the parent station stop_id
+    #[serde(rename = "GTFS Stop ID")]
+    stop_id: StopId,
+}
+
+// physically connected stations, from the MTA's subway stations dataset
+#[derive(Default)]
+pub struct Complexes {
+    complexes: HashMap<StopId, u32>,
+    // stations of each complex, in stop_id order
+    members: HashMap<u32, Vec<StopId>>,
+}
+
+impl Complexes {
+    pub fn load() -> Result<Self> {
+        let xdg = util::get_xdg()?;
+        let path = xdg
+            .find_data_file(STATIONS_STATIC.1)
+            .ok_or_else(|| anyhow!("Could not find {} data", STATIONS_STATIC.1))?;
+        let mut complexes = Self::default();
+        let unreadable = || format!("Failed to read '{}'", path.display());
+        for rec in csv::Reader::from_path(&path).with_context(unreadable)?.deserialize() {
+            let row: StationRow = rec.with_context(|| format!("Malformed row in '{}'", path.display()))?;
+            complexes.complexes.insert(row.stop_id.clone(), row.complex_id);
+            complexes.members.entry(row.complex_id).or_default().push(row.stop_id);
+        }
+        for members in complexes.members.values_mut() {
+            members.sort();
+            members.dedup();
+        }
+        Ok(complexes)
+    }
+
+    // the complex_id of a station
+    pub fn complex(&self, stop_id: &StopId) -> Option<u32> {
+        self.complexes.get(stop_id).copied()
+    }
+
+    // the stations in the same complex as `stop_id`, including itself
+    pub fn members<'a>(&'a self, stop_id: &'a StopId) -> &'a [StopId] {
+        self.complexes
+            .get(stop_id)
+            .and_then(|complex_id| self.members.get(complex_id))
+            .map_or(std::slice::from_ref(stop_id), Vec::as_slice)
+    }
+
+    // where a complex's stations are drawn when merged, the middle of its stations
+    pub fn centers(&self, stops: &EntityCollection<BTreeMap<StopId, Stop>>) -> HashMap<StopId, Coord<f32>> {
+        let mut centers = HashMap::new();
+        for members in self.members.values().filter(|members| members.len() > 1) {
+            let coords: Vec<_> = members
+                .iter()
+                .filter_map(|stop_id| stops.get(stop_id.as_str()))
+                .map(|stop| stop.coord)
+                .collect();
+            if coords.is_empty() {
+                continue;
+            }
+            let center = coords.iter().fold(Coord::zero(), |sum, coord| sum + *coord) / coords.len() as f32;
+            for stop_id in members {
+                centers.insert(stop_id.clone(), center);
+            }
+        }
+        centers
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use tracing::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::feed::FeedFormat;
+use crate::style::StyleRule;
+use crate::ids::StopId;
+use crate::motion::Motion;
+use crate::palette::PaletteConfig;
+use crate::util::{self, format::Formatter, projection::Projection};
+use crate::webhook::EventKind;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+// user configuration, read from $XDG_CONFIG_HOME/nyc_subway_rs/config.toml
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub webhooks: Vec<WebhookConfig>,
+    pub format: Formatter,
+    pub styles: Vec<StyleRule>,
+    pub session: SessionConfig,
+    pub export: ExportConfig,
+    pub capture: CaptureConfig,
+    pub motion: Motion,
+    pub palette: PaletteConfig,
+    pub history: HistoryConfig,
+    pub map: MapConfig,
+    pub feeds: FeedsConfig,
+    pub serve: ServeConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub events: Vec<EventKind>,
+    // signs each payload with HMAC-SHA256 when set
+    pub secret: Option<String>,
+    // parent station stop_ids to notify station_arrival events for
+    #[serde(default)]
+    pub stations: Vec<StopId>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    // append a summary of each session to $XDG_STATE_HOME/nyc_subway_rs/sessions.log
+    pub save_summary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MapConfig {
+    pub projection: Projection,
+    // how strongly lit stations and trains glow, 0 turns it off
+    pub bloom: f32,
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self {
+            projection: Projection::default(),
+            bloom: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FeedsConfig {
+    pub format: FeedFormat,
+    // fetched in place of the MTA's endpoints, by feed name, e.g. ACE or S1234567
+    pub endpoints: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ServeConfig {
+    // requests a second each client may make to the JSON endpoints, 0 doesn't limit them
+    pub rate_limit: u32,
+    // requests a client may make at once before it's held to the rate limit
+    pub burst: u32,
+    // milliseconds a response is served again to requests for the same path and query, 0 doesn't cache them
+    pub cache_ms: u64,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            rate_limit: 10,
+            burst: 20,
+            cache_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // record each train's stops to a SQLite database
+    pub enabled: bool,
+    // defaults to $XDG_DATA_HOME/nyc_subway_rs/history.sqlite
+    pub path: Option<PathBuf>,
+    // observations older than this are deleted, 0 keeps them forever
+    pub retention_days: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    // exports are rendered at this multiple of the window size, then downsampled
+    pub scale: u32,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self { scale: 3 }
+    }
+}
+
+// where captured frames go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureSink {
+    // numbered PNGs in their own directory
+    #[default]
+    Png,
+    // raw frames piped to ffmpeg, encoded to a video
+    Ffmpeg,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub sink: CaptureSink,
+    // capture every nth rendered frame
+    pub every: u32,
+    // frame rate of the encoded video
+    pub framerate: u32,
+    // ffmpeg arguments between its raw frame input and the output file
+    pub ffmpeg_args: Vec<String>,
+    // extension of the encoded video, which ffmpeg picks the container from
+    pub extension: String,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            sink: CaptureSink::default(),
+            every: 1,
+            framerate: 30,
+            ffmpeg_args: ["-c:v", "libx264", "-pix_fmt", "yuv420p"]
+                .map(str::to_owned)
+                .to_vec(),
+            extension: "mp4".to_owned(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let xdg = util::get_xdg()?;
+        let Some(path) = xdg.find_config_file(CONFIG_FILE) else {
+            return Ok(Self::default());
+        };
+        info!("Loading config: '{}'", path.display());
+        let config = toml::from_str(
+            &fs::read_to_string(&path).with_context(|| format!("Failed to read '{}'", path.display()))?,
+        )
+        .with_context(|| format!("Invalid config in '{}'", path.display()))?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Result;
+use reqwest::StatusCode;
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::annotations::Annotations;
+use crate::config::Config;
+use crate::feed::{self, FEEDS};
+use crate::util::{
+    self,
+    static_data::{
+        self, StaticDataEndpoint, BOROUGH_BOUNDARIES_STATIC, COASTLINE_STATIC, GTFS_STATIC,
+        PARKS_STATIC, RIDERSHIP_STATIC, STATIONS_STATIC,
+    },
+};
+
+const STATIC_ENDPOINTS: [StaticDataEndpoint; 6] = [
+    GTFS_STATIC,
+    COASTLINE_STATIC,
+    BOROUGH_BOUNDARIES_STATIC,
+    PARKS_STATIC,
+    STATIONS_STATIC,
+    RIDERSHIP_STATIC,
+];
+// unpacked from the GTFS archive
+const GTFS_FILES: [&str; 6] = [
+    "stops.txt",
+    "shapes.txt",
+    "routes.txt",
+    "trips.txt",
+    "stop_times.txt",
+    "calendar.txt",
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+// collects the outcome of each check as it's printed
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, status: Status, subject: &str, detail: impl Display) {
+        let label = match status {
+            Status::Ok => "ok  ",
+            Status::Warn => {
+                self.warnings += 1;
+                "warn"
+            }
+            Status::Fail => {
+                self.failures += 1;
+                "FAIL"
+            }
+        };
+        println!("[{label}] {subject}: {detail}");
+    }
+}
+
+// check everything the viewer needs to start, returns false if anything failed
+pub async fn run() -> Result<bool> {
+    let mut report = Report::default();
+
+    println!("Configuration");
+    check_config(&mut report);
+    println!("\nStatic data");
+    check_static_data(&mut report)?;
+    println!("\nNetwork");
+    check_network(&mut report).await?;
+    println!("\nGraphics");
+    check_adapter(&mut report).await;
+
+    println!(
+        "\n{} failure(s), {} warning(s)",
+        report.failures, report.warnings
+    );
+    Ok(report.failures == 0)
+}
+
+fn check_config(report: &mut Report) {
+    match Config::load() {
+        Ok(config) => report.check(
+            Status::Ok,
+            "config.toml",
+            format!(
+                "{} webhook(s), {} style rule(s)",
+                config.webhooks.len(),
+                config.styles.len()
+            ),
+        ),
+        Err(err) => report.check(
+            Status::Fail,
+            "config.toml",
+            format!("{err:#}, fix or remove the file to use the defaults"),
+        ),
+    }
+    match Annotations::load() {
+        Ok(annotations) => report.check(
+            Status::Ok,
+            "annotations.toml",
+            format!(
+                "{} station(s), {} route(s)",
+                annotations.stations.len(),
+                annotations.routes.len()
+            ),
+        ),
+        Err(err) => report.check(
+            Status::Fail,
+            "annotations.toml",
+            format!("{err:#}, fix or remove the file to start without annotations"),
+        ),
+    }
+}
+
+fn check_static_data(report: &mut Report) -> Result<()> {
+    let xdg = util::get_xdg()?;
+    let (_, _, gtfs_max_age) = GTFS_STATIC;
+    let files = STATIC_ENDPOINTS
+        .iter()
+        .map(|(_, file, max_age)| (*file, *max_age))
+        .chain(GTFS_FILES.map(|file| (file, gtfs_max_age)));
+    for (file, max_age) in files {
+        // GTFS files are in the newest schedule's directory, the rest straight in the data directory
+        let Some(path) = xdg.find_cache_file(file).or_else(|| static_data::gtfs_file(file)) else {
+            report.check(
+                Status::Warn,
+                file,
+                "missing, it will be downloaded on the next start",
+            );
+            continue;
+        };
+        match static_data::since_validated(&path) {
+            Some(age) if age > max_age => report.check(
+                Status::Warn,
+                file,
+                format!(
+                    "checked {} days ago, it will be revalidated on the next start",
+                    age.as_secs() / 86400
+                ),
+            ),
+            Some(age) => report.check(
+                Status::Ok,
+                file,
+                format!("checked {} days ago", age.as_secs() / 86400),
+            ),
+            None => report.check(Status::Ok, file, path.display()),
+        }
+    }
+    Ok(())
+}
+
+async fn check_network(report: &mut Report) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    for (url, file, _) in STATIC_ENDPOINTS {
+        match client.head(url).send().await {
+            Ok(res) if res.status().is_success() => {
+                report.check(Status::Ok, file, res.status())
+            }
+            // some hosts don't answer HEAD, but the server is reachable
+            Ok(res) if res.status() == StatusCode::METHOD_NOT_ALLOWED => {
+                report.check(Status::Ok, file, "reachable")
+            }
+            Ok(res) => report.check(
+                Status::Warn,
+                file,
+                format!("{} from {url}", res.status()),
+            ),
+            Err(err) => report.check(
+                Status::Warn,
+                file,
+                format!("{err}, only needed when the file is missing"),
+            ),
+        }
+    }
+    // feeds pointed at mirrors or recordings are checked there
+    let endpoints = Config::load().map(|config| config.feeds.endpoints).unwrap_or_default();
+    for feed in FEEDS {
+        let subject = format!("{feed:?} feed");
+        let endpoint = endpoints
+            .get(&format!("{feed:?}"))
+            .map_or(feed.endpoint(), String::as_str);
+        match feed::local_path(endpoint) {
+            Ok(Some(path)) if path.is_file() => {
+                report.check(Status::Ok, &subject, path.display());
+                continue;
+            }
+            Ok(Some(path)) => {
+                report.check(Status::Fail, &subject, format!("{} doesn't exist", path.display()));
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                report.check(Status::Fail, &subject, err);
+                continue;
+            }
+        }
+        match client.get(endpoint).send().await {
+            Ok(res) if res.status().is_success() => {
+                report.check(Status::Ok, &subject, res.status())
+            }
+            Ok(res)
+                if matches!(
+                    res.status(),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+                ) =>
+            {
+                report.check(
+                    Status::Fail,
+                    &subject,
+                    format!(
+                        "{}, the feeds no longer require an API key, check for a proxy rewriting requests",
+                        res.status()
+                    ),
+                )
+            }
+            Ok(res) => report.check(Status::Fail, &subject, res.status()),
+            Err(err) => report.check(
+                Status::Fail,
+                &subject,
+                format!("{err}, check your connection to api-endpoint.mta.info"),
+            ),
+        }
+    }
+    Ok(())
+}
+
+async fn check_adapter(report: &mut Report) {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await;
+    match adapter {
+        Some(adapter) => {
+            let info = adapter.get_info();
+            report.check(
+                Status::Ok,
+                "adapter",
+                format!("{} ({:?}, {:?})", info.name, info.backend, info.device_type),
+            )
+        }
+        None => report.check(
+            Status::Fail,
+            "adapter",
+            "no compatible GPU adapter, install Vulkan, Metal or DX12 drivers",
+        ),
+    }
+}
@@ -1,31 +1,124 @@
+use crate::ids::{RouteId, StopId};
+use crate::palette::Palette;
+use crate::timetable::Timetable;
 use crate::util;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
 use geo::{self, BoundingRect, GeometryCollection, MapCoords, Translate};
+use rstar::{
+    primitives::{GeomWithData, Line},
+    RTree,
+};
 use serde::de::DeserializeOwned;
-use serde::{de::Visitor, Deserialize, Deserializer};
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Formatter;
 use std::ops::{Deref, DerefMut};
-use std::path::{Path, PathBuf};
-use util::static_data::{BOROUGH_BOUNDARIES_STATIC, PARKS_STATIC};
+use std::path::PathBuf;
+use util::projection::Projection;
+use util::static_data::{BOROUGH_BOUNDARIES_STATIC, COASTLINE_STATIC, PARKS_STATIC};
 
 type Coord = geo::Coord<f32>;
+
+// why static data couldn't be loaded, with the file and where in it
+#[derive(Debug, thiserror::Error)]
+pub enum EntityError {
+    #[error("Could not find {0}, run with --force-refresh to download it again")]
+    MissingFile(&'static str),
+    #[error("Failed to read {file}: {source}")]
+    Io {
+        file: &'static str,
+        source: std::io::Error,
+    },
+    #[error("{file} line {line}: {message}")]
+    ParseError {
+        file: &'static str,
+        line: u64,
+        message: String,
+    },
+    #[error("{file} feature {index}: {message}")]
+    BadGeometry {
+        file: &'static str,
+        index: usize,
+        message: String,
+    },
+}
+
+impl EntityError {
+    // `first_line` is the line the CSV being read starts on in `file`, for files read in pieces
+    fn csv(file: &'static str, first_line: u64, err: csv::Error) -> Self {
+        let line = err.position().map_or(0, |pos| pos.line() + first_line - 1);
+        // the row's position is already in `line`, the deserializer's own message names the field
+        let message = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => err.to_string(),
+        };
+        match err.into_kind() {
+            csv::ErrorKind::Io(source) => Self::Io { file, source },
+            _ => Self::ParseError {
+                file,
+                line,
+                message,
+            },
+        }
+    }
+}
+
+fn data_file(file: &'static str) -> Result<PathBuf, EntityError> {
+    util::static_data::gtfs_file(file).ok_or(EntityError::MissingFile(file))
+}
+
+// bytes of shapes.txt parsed per task
+const SHAPE_CHUNK_SIZE: usize = 1 << 20;
 type Point = geo::Point<f32>;
 
-#[derive(Debug, nyc_subway_rs_derive::Deserialize_enum_or)]
-enum LocationKind {
-    #[fallback]
+// a missing location_type is a platform
+#[derive(Debug, Clone, Copy, nyc_subway_rs_derive::Deserialize_enum_or)]
+#[repr(u8)]
+pub enum LocationKind {
     Platform = 0,
     Station = 1,
+    // entrances, nodes and any types added later
+    #[fallback]
+    Other(u8),
+}
+
+impl LocationKind {
+    fn location_type(&self) -> u8 {
+        match self {
+            Self::Platform => 0,
+            Self::Station => 1,
+            Self::Other(location_type) => *location_type,
+        }
+    }
+}
+
+// mirrors the optional integer location_type the derived Deserialize expects
+impl Serialize for LocationKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_some(&self.location_type())
+    }
 }
 
 #[derive(Deserialize)]
 pub struct StopRow {
-    stop_id: String,
+    stop_id: StopId,
+    stop_name: String,
     stop_lat: f32,
     stop_lon: f32,
     location_type: LocationKind,
-    parent_station: Option<String>,
+    parent_station: Option<StopId>,
+    // 0 or empty for no information, 1 accessible, 2 not. the MTA's schedule usually leaves it out
+    #[serde(default)]
+    wheelchair_boarding: Option<u8>,
+}
+
+fn wheelchair_boarding(value: Option<u8>) -> Option<bool> {
+    match value? {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    }
 }
 
 #[derive(Deserialize)]
@@ -36,70 +129,333 @@ struct ShapeRow {
     shape_pt_lon: f32,
 }
 
+#[derive(Deserialize)]
+pub struct TripRow {
+    route_id: RouteId,
+    shape_id: Option<String>,
+}
+
+// route_id by shape_id, joined through trips.txt
+pub type ShapeRoutes = HashMap<String, RouteId>;
+
+#[derive(Deserialize)]
+struct TransferRow {
+    from_stop_id: StopId,
+    to_stop_id: StopId,
+    min_transfer_time: Option<u32>,
+}
+
+// a walking connection to another station
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub to: StopId,
+    // seconds, when given
+    pub min_time: Option<u32>,
+}
+
+// transfers between stations by the stop_id they start from, transfers.txt is optional
+#[derive(Default, Serialize, Deserialize)]
+pub struct Transfers(HashMap<StopId, Vec<Transfer>>);
+
+impl Transfers {
+    pub fn load() -> Result<Self> {
+        let mut transfers = Self::default();
+        let Some(path) = util::static_data::gtfs_file("transfers.txt") else {
+            return Ok(transfers);
+        };
+        let reader = csv::Reader::from_path(&path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+        for rec in reader.into_deserialize() {
+            let row: TransferRow = rec.with_context(|| format!("Malformed row in '{}'", path.display()))?;
+            // the MTA lists every station as a transfer to itself
+            if row.from_stop_id != row.to_stop_id {
+                transfers.0.entry(row.from_stop_id).or_default().push(Transfer {
+                    to: row.to_stop_id,
+                    min_time: row.min_transfer_time,
+                });
+            }
+        }
+        Ok(transfers)
+    }
+
+    pub fn from(&self, stop_id: &str) -> &[Transfer] {
+        self.0.get(stop_id).map_or(&[], Vec::as_slice)
+    }
+
+    // each connected pair of stations once
+    pub fn pairs(&self) -> impl Iterator<Item = (&StopId, &StopId)> {
+        self.0.iter().flat_map(move |(from, transfers)| {
+            transfers
+                .iter()
+                .filter(move |transfer| {
+                    *from < transfer.to || !self.from(transfer.to.as_str()).iter().any(|back| back.to == *from)
+                })
+                .map(move |transfer| (from, &transfer.to))
+        })
+    }
+}
+
+// GeoJSON feature properties, e.g. boro_name
+pub type Properties = BTreeMap<String, serde_json::Value>;
+
 #[derive(Debug, Deserialize)]
 pub struct Boro {
     #[serde(deserialize_with = "geojson::de::deserialize_geometry")]
     geometry: geo::geometry::Geometry<f32>,
+    #[serde(flatten)]
+    properties: Properties,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Park {
     #[serde(deserialize_with = "geojson::de::deserialize_geometry")]
     geometry: geo::geometry::Geometry<f32>,
+    #[serde(flatten)]
+    properties: Properties,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Coastline {
+    #[serde(deserialize_with = "geojson::de::deserialize_geometry")]
+    geometry: geo::geometry::Geometry<f32>,
+    #[serde(flatten)]
+    properties: Properties,
+}
+
+// geometries and the properties of the features they came from, in the same order
+#[derive(Default)]
+pub struct Features {
+    geometries: GeometryCollection<f32>,
+    properties: Vec<Properties>,
+}
+
+impl Features {
+    pub fn iter_features(&self) -> impl Iterator<Item = (&geo::Geometry<f32>, &Properties)> {
+        self.geometries.iter().zip(&self.properties)
+    }
 }
 
-#[derive(Debug)]
+impl Deref for Features {
+    type Target = GeometryCollection<f32>;
+    fn deref(&self) -> &Self::Target {
+        &self.geometries
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Stop {
-    pub id: String,
+    pub id: StopId,
+    pub name: String,
     pub kind: LocationKind,
     pub coord: Coord,
-    pub parent: Option<String>,
+    pub parent: Option<StopId>,
     pub status: StationStatus,
     pub index: usize,
+    // whether the stop can be boarded from a wheelchair, None when stops.txt doesn't say
+    pub wheelchair_boarding: Option<bool>,
 }
 
-#[derive(Debug)]
-enum StationStatus {
-    Active(Vec<String>),
+#[derive(Debug, Serialize, Deserialize)]
+pub enum StationStatus {
+    Active(Vec<RouteId>),
     Inactive,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ShapeSeq {
     seq: usize,
     coord: Coord,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Route {
     #[serde(rename = "route_id")]
-    id: String,
+    id: RouteId,
     #[serde(rename = "route_color")]
-    #[serde(deserialize_with = "hex_to_srgb")]
+    #[serde(deserialize_with = "hex_to_srgb", serialize_with = "srgb_to_hex")]
     color: [f32; 3],
 }
 
 impl Route {
+    pub fn id(&self) -> &RouteId {
+        &self.id
+    }
+
     pub fn color(&self) -> [f32; 3] {
         self.color
     }
+
+    // recolor from routes.txt to the palette, only once
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.color = palette.route_color(self.id.as_str(), self.color);
+    }
 }
 
-fn hex_to_srgb<'de, D>(deserializer: D) -> Result<[f32; 3], D::Error>
+fn srgb_to_hex<S: Serializer>(color: &[f32; 3], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(srgb::gamma::u8_from_linear(*color)))
+}
+
+// linear color from a hex sRGB color, with or without a leading #
+pub fn parse_hex(hex_str: &str) -> Option<[f32; 3]> {
+    let mut color = [0; 3];
+    hex::decode_to_slice(hex_str.trim_start_matches('#'), &mut color).ok()?;
+    Some(srgb::gamma::linear_from_u8(color))
+}
+
+pub fn hex_to_srgb<'de, D>(deserializer: D) -> Result<[f32; 3], D::Error>
 where
     D: Deserializer<'de>,
 {
     let mut color = [0; 3];
-    let mut hex_str: &str = serde::Deserialize::deserialize(deserializer)?;
+    let hex_str: &str = serde::Deserialize::deserialize(deserializer)?;
+    let mut hex_str = hex_str.trim_start_matches('#');
 
     if hex_str.len() != 6 {
         hex_str = "FFFFFF";
     }
-    hex::decode_to_slice(hex_str, &mut color).unwrap();
+    hex::decode_to_slice(hex_str, &mut color).map_err(serde::de::Error::custom)?;
     let linear_color = srgb::gamma::linear_from_u8(color);
     Ok(linear_color)
 }
 
+// the parsed GTFS schedule, in untranslated coordinates
+#[derive(Serialize, Deserialize)]
+pub struct Schedule {
+    pub stops: EntityCollection<BTreeMap<StopId, Stop>>,
+    pub shapes: EntityCollection<BTreeMap<String, Vec<ShapeSeq>>>,
+    pub routes: EntityCollection<HashMap<RouteId, Route>>,
+    pub shape_routes: ShapeRoutes,
+    pub timetable: Timetable,
+    pub transfers: Transfers,
+}
+
+impl Schedule {
+    // tables are loaded concurrently
+    pub fn load() -> Result<Self> {
+        let ((stops, shapes), ((routes, shape_routes), (timetable, transfers))) = rayon::join(
+            || rayon::join(Stop::load_collection, ShapeSeq::load_collection),
+            || {
+                rayon::join(
+                    || rayon::join(Route::load_collection, ShapeRoutes::load),
+                    || rayon::join(Timetable::load, Transfers::load),
+                )
+            },
+        );
+        Ok(Self {
+            stops: stops?,
+            shapes: shapes?,
+            routes: routes?,
+            shape_routes: shape_routes?,
+            timetable: timetable?,
+            transfers: transfers?,
+        })
+    }
+}
+
+// nearest-neighbor lookups over rendered (parent) stops, in translated map coordinates
+pub struct StopIndex {
+    tree: RTree<GeomWithData<[f32; 2], StopId>>,
+}
+
+impl StopIndex {
+    pub fn new(stops: &EntityCollection<BTreeMap<StopId, Stop>>) -> Self {
+        Self::with_positions(stops, &HashMap::new())
+    }
+
+    // stops found where `positions` has them instead, e.g. merged complexes drawn at their center
+    pub fn with_positions(
+        stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+        positions: &HashMap<StopId, Coord>,
+    ) -> Self {
+        let points = stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .map(|stop| {
+                let coord = positions.get(&stop.id).copied().unwrap_or(stop.coord);
+                GeomWithData::new([coord.x, coord.y], stop.id.clone())
+            })
+            .collect();
+        Self {
+            tree: RTree::bulk_load(points),
+        }
+    }
+
+    pub fn nearest(&self, coord: Coord, max_distance: f32) -> Option<&StopId> {
+        self.tree
+            .nearest_neighbor(&[coord.x, coord.y])
+            .filter(|stop| {
+                let [x, y] = *stop.geom();
+                (x - coord.x).hypot(y - coord.y) <= max_distance
+            })
+            .map(|stop| &stop.data)
+    }
+}
+
+// shape segment, with the shape_id and route_id it belongs to
+type ShapeSegment = GeomWithData<Line<[f32; 2]>, (String, Option<RouteId>)>;
+
+// nearest-segment lookups over route shapes, for the direction of track at a point
+pub struct ShapeIndex {
+    tree: RTree<ShapeSegment>,
+}
+
+impl ShapeIndex {
+    pub fn new(
+        shapes: &EntityCollection<BTreeMap<String, Vec<ShapeSeq>>>,
+        shape_routes: &ShapeRoutes,
+    ) -> Self {
+        let segments = shapes
+            .iter()
+            .flat_map(|(shape_id, shape)| {
+                let route_id = shape_routes.get(shape_id);
+                shape.windows(2).map(move |pair| {
+                    let (from, to) = (pair[0].coord, pair[1].coord);
+                    GeomWithData::new(
+                        Line::new([from.x, from.y], [to.x, to.y]),
+                        (shape_id.clone(), route_id.cloned()),
+                    )
+                })
+            })
+            .collect();
+        Self {
+            tree: RTree::bulk_load(segments),
+        }
+    }
+
+    // unit direction of the nearest segment of `shape_id`, falling back to any shape of `route_id`
+    // shapes run in the direction of travel, but a route's shapes run both ways
+    pub fn direction(
+        &self,
+        coord: Coord,
+        max_distance: f32,
+        shape_id: Option<&str>,
+        route_id: &RouteId,
+    ) -> Option<Coord> {
+        let nearby: Vec<_> = self
+            .tree
+            .nearest_neighbor_iter_with_distance_2(&[coord.x, coord.y])
+            .take_while(|(_, distance_2)| *distance_2 <= max_distance * max_distance)
+            .map(|(segment, _)| segment)
+            .collect();
+        let segment = nearby
+            .iter()
+            .find(|segment| shape_id.is_some_and(|id| segment.data.0 == id))
+            .or_else(|| {
+                nearby
+                    .iter()
+                    .find(|segment| segment.data.1.as_ref() == Some(route_id))
+            })?;
+
+        let line = segment.geom();
+        let (dx, dy) = (line.to[0] - line.from[0], line.to[1] - line.from[1]);
+        let length = dx.hypot(dy);
+        (length > 0.0).then(|| Coord {
+            x: dx / length,
+            y: dy / length,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct EntityCollection<T> {
     collection: T,
 }
@@ -108,10 +464,10 @@ impl<K, V> EntityCollection<HashMap<K, V>>
 where
     V: CollectibleEntity,
 {
-    pub fn translate_origin_from(&mut self, point: &Point) {
+    pub fn translate_origin_from(&mut self, point: &Point, projection: Projection) {
         for val in self.collection.values_mut() {
-            let mut coord = val.coord().clone();
-            coord = util::geo::coord_to_xy(coord, point);
+            let mut coord = val.coord();
+            coord = projection.project(coord, point);
             val.set_coord(coord);
         }
     }
@@ -121,30 +477,67 @@ impl<K, V> EntityCollection<BTreeMap<K, V>>
 where
     V: CollectibleEntity,
 {
-    pub fn translate_origin_from(&mut self, point: &Point) {
+    pub fn translate_origin_from(&mut self, point: &Point, projection: Projection) {
         for val in self.collection.values_mut() {
-            let mut coord = val.coord().clone();
-            coord = util::geo::coord_to_xy(coord, point);
+            let mut coord = val.coord();
+            coord = projection.project(coord, point);
             val.set_coord(coord);
         }
     }
 }
 
+impl EntityCollection<BTreeMap<StopId, Stop>> {
+    // the station a stop_id is part of, platforms resolve to their station and stations to themselves
+    pub fn station(&self, stop_id: &str) -> Option<&Stop> {
+        let stop = self.get(stop_id)?;
+        Some(stop.parent.as_ref().and_then(|parent| self.get(parent.as_str())).unwrap_or(stop))
+    }
+}
+
+// each stop_id's station by its position among the stops in stop_id order, resolved once when the stops are
+// loaded so the feeds look up a platform's station in one step instead of through parent_station
+pub struct ParentTable(HashMap<StopId, usize>);
+
+impl ParentTable {
+    pub fn new(stops: &EntityCollection<BTreeMap<StopId, Stop>>) -> Self {
+        let positions: HashMap<&str, usize> =
+            stops.keys().enumerate().map(|(idx, stop_id)| (stop_id.as_str(), idx)).collect();
+        let parents = stops
+            .values()
+            .enumerate()
+            .map(|(idx, stop)| {
+                let parent = stop
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| positions.get(parent.as_str()).copied())
+                    .unwrap_or(idx);
+                (stop.id.clone(), parent)
+            })
+            .collect();
+        Self(parents)
+    }
+
+    // the position of the stop's station, None for stops missing from stops.txt
+    pub fn get(&self, stop_id: &str) -> Option<usize> {
+        self.0.get(stop_id).copied()
+    }
+}
+
 impl EntityCollection<BTreeMap<String, Vec<ShapeSeq>>> {
-    pub fn translate_origin_from(&mut self, point: &Point) {
+    pub fn translate_origin_from(&mut self, point: &Point, projection: Projection) {
         for shape in self.collection.values_mut() {
             for seq in shape.iter_mut() {
-                let mut coord = seq.coord().clone();
-                coord = util::geo::coord_to_xy(coord, point);
+                let mut coord = seq.coord();
+                coord = projection.project(coord, point);
                 seq.set_coord(coord);
             }
         }
     }
 }
 
-impl EntityCollection<GeometryCollection<f32>> {
-    pub fn translate_origin_from(&mut self, point: &Point) {
-        self.collection = self.map_coords(|c| util::geo::coord_to_xy(c, &point));
+impl EntityCollection<Features> {
+    pub fn translate_origin_from(&mut self, point: &Point, projection: Projection) {
+        self.collection.geometries = self.map_coords(|c| projection.project(c, point));
     }
 }
 
@@ -166,11 +559,11 @@ pub trait CollectibleEntity {
     fn coord(&self) -> Coord;
     fn set_coord(&mut self, coord: Coord);
     fn collection() -> Self::Collection;
-    fn load_collection() -> Result<Self::Collection>;
+    fn load_collection() -> Result<Self::Collection, EntityError>;
 }
 
 impl CollectibleEntity for Stop {
-    type Collection = EntityCollection<BTreeMap<String, Self>>;
+    type Collection = EntityCollection<BTreeMap<StopId, Self>>;
     fn coord(&self) -> geo::Coord<f32> {
         self.coord
     }
@@ -185,16 +578,24 @@ impl CollectibleEntity for Stop {
         }
     }
 
-    fn load_collection() -> Result<Self::Collection> {
-        let xdg = util::get_xdg()?;
-        let stops_path = xdg.find_data_file("stops.txt").unwrap();
-        let mut rdr = csv::Reader::from_path(stops_path)?;
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        const FILE: &str = "stops.txt";
+        let mut rdr = csv::Reader::from_path(data_file(FILE)?).map_err(|err| EntityError::csv(FILE, 1, err))?;
+        let headers = rdr.headers().map_err(|err| EntityError::csv(FILE, 1, err))?.clone();
         let mut collection = Self::collection();
         let mut parent_idxs = HashMap::new();
+        // where each platform is listed, in case its parent station isn't
+        let mut child_lines = HashMap::new();
         let mut idx = 0;
-        for rec in rdr.deserialize() {
-            let row: StopRow = rec?;
-            let index = if let None = row.parent_station {
+        for rec in rdr.records() {
+            let record = rec.map_err(|err| EntityError::csv(FILE, 1, err))?;
+            let row: StopRow = record
+                .deserialize(Some(&headers))
+                .map_err(|err| EntityError::csv(FILE, 1, err))?;
+            if row.parent_station.is_some() {
+                child_lines.insert(row.stop_id.clone(), record.position().map_or(0, csv::Position::line));
+            }
+            let index = if row.parent_station.is_none() {
                 let ndx = idx;
                 parent_idxs.insert(row.stop_id.to_owned(), ndx);
                 idx += 1;
@@ -205,11 +606,13 @@ impl CollectibleEntity for Stop {
 
             let stop = Stop {
                 id: row.stop_id,
+                name: row.stop_name,
                 kind: row.location_type,
                 coord: geo::coord! { x: row.stop_lon, y: row.stop_lat },
                 parent: row.parent_station,
                 status: StationStatus::Inactive,
                 index,
+                wheelchair_boarding: wheelchair_boarding(row.wheelchair_boarding),
             };
             collection.insert(stop.id.clone(), stop);
         }
@@ -217,7 +620,11 @@ impl CollectibleEntity for Stop {
         // only parent stops are rendered, but we still want to look up the rendered stop instances by child stop_id
         for stop in collection.values_mut() {
             if let Some(parent_id) = &stop.parent {
-                stop.index = parent_idxs.get(parent_id).unwrap().to_owned();
+                stop.index = *parent_idxs.get(parent_id).ok_or_else(|| EntityError::ParseError {
+                    file: FILE,
+                    line: child_lines[&stop.id],
+                    message: format!("parent_station {parent_id} isn't a station"),
+                })?;
             }
         }
 
@@ -241,32 +648,66 @@ impl CollectibleEntity for ShapeSeq {
         }
     }
 
-    fn load_collection() -> Result<Self::Collection> {
-        let xdg = util::get_xdg()?;
-        let stops_path = xdg.find_data_file("shapes.txt").unwrap();
-        let mut rdr = csv::Reader::from_path(stops_path)?;
+    // shapes.txt is the largest table, its lines are parsed in parallel chunks
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        const FILE: &str = "shapes.txt";
+        let data = std::fs::read(data_file(FILE)?).map_err(|source| EntityError::Io { file: FILE, source })?;
+        let header_end = data
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(data.len(), |idx| idx + 1);
+        let (header, body) = data.split_at(header_end);
+        let headers = csv::Reader::from_reader(header)
+            .headers()
+            .map_err(|err| EntityError::csv(FILE, 1, err))?
+            .clone();
+
+        // errors are reported at their line in the file, chunks start after the header
+        let chunks = line_chunks(body, SHAPE_CHUNK_SIZE);
+        let first_lines = chunks.iter().scan(2, |line, chunk| {
+            let first = *line;
+            *line += chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+            Some(first)
+        });
+        let partials = chunks
+            .iter()
+            .zip(first_lines)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(chunk, first_line)| {
+                let mut rdr = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(*chunk);
+                let mut partial: BTreeMap<String, Vec<ShapeSeq>> = BTreeMap::new();
+                for rec in rdr.records() {
+                    let row: ShapeRow = rec
+                        .and_then(|record| record.deserialize(Some(&headers)))
+                        .map_err(|err| EntityError::csv(FILE, first_line, err))?;
+                    partial.entry(row.shape_id).or_default().push(ShapeSeq {
+                        coord: geo::coord! { x: row.shape_pt_lon, y: row.shape_pt_lat },
+                        seq: row.shape_pt_sequence,
+                    });
+                }
+                Ok(partial)
+            })
+            .collect::<Result<Vec<_>, EntityError>>()?;
+
         let mut collection = Self::collection();
-        for rec in rdr.deserialize() {
-            let row: ShapeRow = rec?;
-            let shape = ShapeSeq {
-                coord: geo::coord! { x: row.shape_pt_lon, y: row.shape_pt_lat },
-                seq: row.shape_pt_sequence,
-            };
-            let seq = collection
-                .entry(row.shape_id.clone())
-                .or_insert_with(|| Vec::new());
-            seq.push(shape);
-        }
-        for seq in collection.values_mut() {
-            seq.sort_by(|a, b| a.seq.cmp(&b.seq));
+        for partial in partials {
+            for (shape_id, seqs) in partial {
+                collection.entry(shape_id).or_default().extend(seqs);
+            }
         }
+        collection
+            .par_iter_mut()
+            .for_each(|(_, seq)| seq.sort_by_key(|shape_seq| shape_seq.seq));
 
         Ok(collection)
     }
 }
 
 impl CollectibleEntity for Route {
-    type Collection = EntityCollection<HashMap<String, Route>>;
+    type Collection = EntityCollection<HashMap<RouteId, Route>>;
     fn coord(&self) -> Coord {
         Coord::zero()
     }
@@ -279,13 +720,12 @@ impl CollectibleEntity for Route {
         }
     }
 
-    fn load_collection() -> Result<Self::Collection> {
-        let xdg = util::get_xdg()?;
-        let path = xdg.find_data_file("routes.txt").unwrap();
-        let mut rdr = csv::Reader::from_path(path)?;
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        const FILE: &str = "routes.txt";
+        let mut rdr = csv::Reader::from_path(data_file(FILE)?).map_err(|err| EntityError::csv(FILE, 1, err))?;
         let mut collection = Self::collection();
         for rec in rdr.deserialize() {
-            let row: Route = rec?;
+            let row: Route = rec.map_err(|err| EntityError::csv(FILE, 1, err))?;
             collection.insert(row.id.clone(), row);
         }
         Ok(collection)
@@ -293,7 +733,8 @@ impl CollectibleEntity for Route {
 }
 
 impl CollectibleEntity for Boro {
-    type Collection = EntityCollection<GeometryCollection<f32>>;
+    type Collection = EntityCollection<Features>;
+    // loading rejects features without a bounding rect
     fn coord(&self) -> Coord {
         self.geometry.bounding_rect().unwrap().center()
     }
@@ -306,32 +747,24 @@ impl CollectibleEntity for Boro {
     }
     fn collection() -> Self::Collection {
         EntityCollection {
-            collection: GeometryCollection::default(),
+            collection: Features::default(),
         }
     }
-    fn load_collection() -> Result<Self::Collection> {
-        let xdg = util::get_xdg()?;
-        let feature_reader = {
-            use std::fs::File;
-            let file =
-                File::open(xdg.find_data_file(BOROUGH_BOUNDARIES_STATIC.1).unwrap()).unwrap();
-            geojson::FeatureReader::from_reader(file)
-        };
-
-        let mut geos = Vec::new();
-        for rec in feature_reader.deserialize().unwrap() {
-            let boro: Boro = rec?;
-            geos.push(boro.geometry);
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        let mut features = Features::default();
+        for boro in read_features(BOROUGH_BOUNDARIES_STATIC.1, |boro: &Boro| &boro.geometry)? {
+            features.geometries.0.push(boro.geometry);
+            features.properties.push(boro.properties);
         }
 
         Ok(EntityCollection {
-            collection: GeometryCollection(geos),
+            collection: features,
         })
     }
 }
 
 impl CollectibleEntity for Park {
-    type Collection = EntityCollection<GeometryCollection<f32>>;
+    type Collection = EntityCollection<Features>;
     fn coord(&self) -> Coord {
         self.geometry.bounding_rect().unwrap().center()
     }
@@ -344,37 +777,94 @@ impl CollectibleEntity for Park {
     }
     fn collection() -> Self::Collection {
         EntityCollection {
-            collection: GeometryCollection::default(),
+            collection: Features::default(),
         }
     }
-    fn load_collection() -> Result<Self::Collection> {
-        let xdg = util::get_xdg()?;
-        let feature_reader = {
-            use std::fs::File;
-            let file = File::open(xdg.find_data_file(PARKS_STATIC.1).unwrap()).unwrap();
-            geojson::FeatureReader::from_reader(file)
-        };
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        let mut features = Features::default();
+        for park in read_features(PARKS_STATIC.1, |park: &Park| &park.geometry)? {
+            features.geometries.0.push(park.geometry);
+            features.properties.push(park.properties);
+        }
+
+        Ok(EntityCollection {
+            collection: features,
+        })
+    }
+}
+
+impl CollectibleEntity for Coastline {
+    type Collection = EntityCollection<Features>;
+    fn coord(&self) -> Coord {
+        self.geometry.bounding_rect().unwrap().center()
+    }
 
-        let mut geos = Vec::new();
-        for rec in feature_reader.deserialize().unwrap() {
-            let park: Park = rec?;
-            geos.push(park.geometry);
+    fn set_coord(&mut self, coord: Coord) {
+        let center = self.coord();
+        self.geometry = self
+            .geometry
+            .translate(coord.x - center.x, coord.y - center.y);
+    }
+    fn collection() -> Self::Collection {
+        EntityCollection {
+            collection: Features::default(),
+        }
+    }
+    fn load_collection() -> Result<Self::Collection, EntityError> {
+        let mut features = Features::default();
+        for coastline in read_features(COASTLINE_STATIC.1, |coastline: &Coastline| &coastline.geometry)? {
+            features.geometries.0.push(coastline.geometry);
+            features.properties.push(coastline.properties);
         }
 
         Ok(EntityCollection {
-            collection: GeometryCollection(geos),
+            collection: features,
         })
     }
 }
 
-trait StaticData {
+// a GeoJSON file's features, each must have a geometry with some extent to be drawn and placed
+fn read_features<T: DeserializeOwned>(
+    file: &'static str,
+    geometry: impl Fn(&T) -> &geo::Geometry<f32>,
+) -> Result<Vec<T>, EntityError> {
+    let reader = std::fs::File::open(data_file(file)?).map_err(|source| EntityError::Io { file, source })?;
+    let bad_geometry = |index, message| EntityError::BadGeometry { file, index, message };
+    geojson::FeatureReader::from_reader(reader)
+        .deserialize()
+        .map_err(|err| bad_geometry(0, err.to_string()))?
+        .enumerate()
+        .map(|(index, rec)| {
+            let feature = rec.map_err(|err| bad_geometry(index, err.to_string()))?;
+            if geometry(&feature).bounding_rect().is_none() {
+                return Err(bad_geometry(index, "empty geometry".to_owned()));
+            }
+            Ok(feature)
+        })
+        .collect()
+}
+
+// split CSV rows into chunks of roughly `size` bytes, on line boundaries
+// GTFS tables don't quote newlines, so every line is a row
+fn line_chunks(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let end = match rest.iter().skip(size).position(|byte| *byte == b'\n') {
+            Some(idx) => size + idx + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+pub trait StaticData {
     const FILENAME: &'static str;
-    fn filepath() -> PathBuf {
-        let xdg = util::get_xdg().unwrap();
-        let path = xdg
-            .find_data_file(Self::FILENAME)
-            .expect(format!("Could not find load {} data", Self::FILENAME).as_str());
-        path
+    fn filepath() -> Result<PathBuf, EntityError> {
+        data_file(Self::FILENAME)
     }
 }
 
@@ -382,15 +872,15 @@ pub trait GTFSData<T>: Default
 where
     T: StaticData + DeserializeOwned,
 {
-    fn load() -> Self {
-        let path = T::filepath();
-        let mut rdr = csv::Reader::from_path(path).unwrap();
+    fn load() -> Result<Self, EntityError> {
+        let csv_error = |err| EntityError::csv(T::FILENAME, 1, err);
+        let mut rdr = csv::Reader::from_path(T::filepath()?).map_err(csv_error)?;
         let mut collection = Self::default();
         for rec in rdr.deserialize() {
-            let row: T = rec.unwrap();
+            let row: T = rec.map_err(csv_error)?;
             collection.insert_row(row);
         }
-        collection
+        Ok(collection)
     }
 
     fn insert_row(&mut self, value: T);
@@ -400,17 +890,19 @@ impl StaticData for StopRow {
     const FILENAME: &'static str = "stops.txt";
 }
 
-impl GTFSData<StopRow> for HashMap<String, Stop> {
+impl GTFSData<StopRow> for HashMap<StopId, Stop> {
     fn insert_row(&mut self, value: StopRow) {
         self.insert(
             value.stop_id.clone(),
             Stop {
                 id: value.stop_id,
+                name: value.stop_name,
                 kind: value.location_type,
                 coord: geo::coord! { x: value.stop_lon, y: value.stop_lat },
                 parent: value.parent_station,
                 status: StationStatus::Inactive,
                 index: 0,
+                wheelchair_boarding: wheelchair_boarding(value.wheelchair_boarding),
             },
         );
     }
@@ -428,7 +920,7 @@ impl GTFSData<ShapeRow> for BTreeMap<String, Vec<ShapeSeq>> {
         };
         let seq = self
             .entry(value.shape_id.clone())
-            .or_insert_with(|| Vec::new());
+            .or_default();
         seq.push(shape);
     }
 }
@@ -437,8 +929,20 @@ impl StaticData for Route {
     const FILENAME: &'static str = "routes.txt";
 }
 
-impl GTFSData<Route> for HashMap<String, Route> {
+impl GTFSData<Route> for HashMap<RouteId, Route> {
     fn insert_row(&mut self, value: Route) {
         self.insert(value.id.clone(), value);
     }
 }
+
+impl StaticData for TripRow {
+    const FILENAME: &'static str = "trips.txt";
+}
+
+impl GTFSData<TripRow> for ShapeRoutes {
+    fn insert_row(&mut self, value: TripRow) {
+        if let Some(shape_id) = value.shape_id {
+            self.entry(shape_id).or_insert(value.route_id);
+        }
+    }
+}
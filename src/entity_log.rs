@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use tracing::{info, warn};
+
+use crate::ids::{RouteId, StopId, TripId};
+
+// how processed feed entities are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    // appended to as they're processed
+    Csv,
+    // written once each hour is over, needs the `parquet` feature
+    Parquet,
+}
+
+impl LogFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+// a GTFS-realtime entity of a feed message, as FeedManager processes it
+#[derive(Debug, Clone, Serialize)]
+pub struct EntityRow {
+    pub feed: String,
+    pub entity_id: String,
+    // trip_update, vehicle or alert, or deleted for an entity a differential message removes
+    pub kind: &'static str,
+    pub trip_id: Option<TripId>,
+    pub route_id: Option<RouteId>,
+    // the stop a vehicle is at or approaching, or a trip update's next stop
+    pub stop_id: Option<StopId>,
+    // a vehicle's STOPPED_AT, INCOMING_AT or IN_TRANSIT_TO, as named in the GTFS-realtime spec
+    pub status: Option<String>,
+    // POSIX timestamp the entity was reported, or its message's when it has none
+    pub timestamp: u64,
+    // reported delay of the trip, in seconds
+    pub delay: Option<i32>,
+    // e.g. MANY_SEATS_AVAILABLE, as named in the GTFS-realtime spec
+    pub occupancy: Option<String>,
+    // the track the train is on at `stop_id`, and whether it differs from the one it was scheduled on
+    pub track: Option<String>,
+    pub track_changed: bool,
+    // POSIX timestamp the entity was processed
+    pub processed_at: i64,
+}
+
+enum Message {
+    Row(Box<EntityRow>),
+    // write out the hour in progress, then stop
+    Close(Sender<()>),
+}
+
+// handle for logging rows, writing happens on its own thread so the feed loop never waits on the disk
+#[derive(Clone, Default)]
+pub struct EntityLog {
+    tx: Option<Sender<Message>>,
+}
+
+impl EntityLog {
+    // rows go to `dir`/date=YYYY-MM-DD/hour=HH/entities.{csv,parquet}, partitioned by the UTC hour they were processed in
+    pub fn spawn(dir: PathBuf, format: LogFormat) -> Result<Self> {
+        #[cfg(not(feature = "parquet"))]
+        if format == LogFormat::Parquet {
+            anyhow::bail!("Parquet logging needs a build with the parquet feature");
+        }
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create '{}'", dir.display()))?;
+        info!("Logging feed entities as {:?} to '{}'", format, dir.display());
+        let (tx, rx) = channel::<Message>();
+        thread::spawn(move || {
+            let mut hour: Option<Hour> = None;
+            // rows come in a message at a time, the CSV is flushed once the channel is drained
+            while let Ok(message) = rx.recv() {
+                for message in iter::once(message).chain(rx.try_iter()) {
+                    let row = match message {
+                        Message::Row(row) => *row,
+                        Message::Close(done) => {
+                            if let Some(hour) = hour.take() {
+                                hour.finish();
+                            }
+                            let _ = done.send(());
+                            return;
+                        }
+                    };
+                    let path = partition(&dir, row.processed_at, format);
+                    // a new hour, the last one's file is complete
+                    if hour.as_ref().is_some_and(|current| current.path != path) {
+                        hour.take().unwrap().finish();
+                    }
+                    let current = hour.get_or_insert_with(|| Hour::new(path));
+                    current.push(row, format);
+                }
+                if let Some(current) = &mut hour {
+                    current.flush();
+                }
+            }
+            if let Some(hour) = hour {
+                hour.finish();
+            }
+        });
+        Ok(Self { tx: Some(tx) })
+    }
+
+    // whether rows are written anywhere, so they aren't built for nothing
+    pub fn enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    pub fn log(&self, row: EntityRow) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(Message::Row(Box::new(row)));
+        }
+    }
+
+    // writes out the hour in progress and waits for it, for shutting down.
+    // Parquet files are only written once their hour is over, otherwise the rows of the last one are lost
+    pub fn close(&self) {
+        if let Some(tx) = &self.tx {
+            let (done, finished) = channel();
+            if tx.send(Message::Close(done)).is_ok() {
+                let _ = finished.recv();
+            }
+        }
+    }
+}
+
+// the file of the hour being logged. CSV rows are written as they come, Parquet rows are kept until the hour is over
+struct Hour {
+    path: PathBuf,
+    csv: Option<csv::Writer<File>>,
+    rows: Vec<EntityRow>,
+}
+
+impl Hour {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            csv: None,
+            rows: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, row: EntityRow, format: LogFormat) {
+        match format {
+            LogFormat::Csv => {
+                if self.csv.is_none() {
+                    match open_csv(&self.path) {
+                        Ok(writer) => self.csv = Some(writer),
+                        Err(err) => {
+                            warn!("Failed to log feed entities to '{}': {}", self.path.display(), err);
+                            return;
+                        }
+                    }
+                }
+                if let Some(Err(err)) = self.csv.as_mut().map(|writer| writer.serialize(&row)) {
+                    warn!("Failed to log feed entity to '{}': {}", self.path.display(), err);
+                }
+            }
+            LogFormat::Parquet => self.rows.push(row),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(Err(err)) = self.csv.as_mut().map(|writer| writer.flush()) {
+            warn!("Failed to log feed entities to '{}': {}", self.path.display(), err);
+        }
+    }
+
+    // only Parquet keeps rows, they're written out with the hour
+    fn finish(mut self) {
+        self.flush();
+        #[cfg(feature = "parquet")]
+        if !self.rows.is_empty() {
+            match write_parquet(&self.path, &self.rows) {
+                Ok(()) => info!("Logged {} feed entities to '{}'", self.rows.len(), self.path.display()),
+                Err(err) => warn!("Failed to log feed entities to '{}': {}", self.path.display(), err),
+            }
+        }
+    }
+}
+
+fn partition(dir: &Path, timestamp: i64, format: LogFormat) -> PathBuf {
+    let at = DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap_or_default();
+    dir.join(at.format("date=%Y-%m-%d").to_string())
+        .join(at.format("hour=%H").to_string())
+        .join(format!("entities.{}", format.extension()))
+}
+
+// the header is only written to a new file, so restarts within an hour keep appending to it
+fn open_csv(path: &Path) -> Result<csv::Writer<File>> {
+    let new = !path.exists();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create '{}'", parent.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open '{}'", path.display()))?;
+    Ok(csv::WriterBuilder::new().has_headers(new).from_writer(file))
+}
+
+// an existing file for the hour, from an earlier run, is kept alongside rather than overwritten
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, rows: &[EntityRow]) -> Result<()> {
+    use arrow_array::{ArrayRef, BooleanArray, Int32Array, Int64Array, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let strings = |column: fn(&EntityRow) -> Option<&str>| -> ArrayRef {
+        Arc::new(rows.iter().map(column).collect::<StringArray>())
+    };
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("feed", DataType::Utf8, false),
+        Field::new("entity_id", DataType::Utf8, false),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("trip_id", DataType::Utf8, true),
+        Field::new("route_id", DataType::Utf8, true),
+        Field::new("stop_id", DataType::Utf8, true),
+        Field::new("status", DataType::Utf8, true),
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("delay", DataType::Int32, true),
+        Field::new("occupancy", DataType::Utf8, true),
+        Field::new("track", DataType::Utf8, true),
+        Field::new("track_changed", DataType::Boolean, false),
+        Field::new("processed_at", DataType::Int64, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            strings(|row| Some(row.feed.as_str())),
+            strings(|row| Some(row.entity_id.as_str())),
+            strings(|row| Some(row.kind)),
+            strings(|row| row.trip_id.as_ref().map(TripId::as_str)),
+            strings(|row| row.route_id.as_ref().map(RouteId::as_str)),
+            strings(|row| row.stop_id.as_ref().map(StopId::as_str)),
+            strings(|row| row.status.as_deref()),
+            Arc::new(rows.iter().map(|row| row.timestamp).collect::<UInt64Array>()),
+            Arc::new(rows.iter().map(|row| row.delay).collect::<Int32Array>()),
+            strings(|row| row.occupancy.as_deref()),
+            strings(|row| row.track.as_deref()),
+            Arc::new(rows.iter().map(|row| Some(row.track_changed)).collect::<BooleanArray>()),
+            Arc::new(rows.iter().map(|row| row.processed_at).collect::<Int64Array>()),
+        ],
+    )?;
+
+    let mut path = path.to_path_buf();
+    let mut attempt = 1;
+    while path.exists() {
+        path.set_file_name(format!("entities-{attempt}.parquet"));
+        attempt += 1;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create '{}'", parent.display()))?;
+    }
+    let failed = || format!("Failed to write '{}'", path.display());
+    let mut writer = ArrowWriter::try_new(fs::File::create(&path).with_context(failed)?, schema, None)?;
+    writer.write(&batch).with_context(failed)?;
+    writer.close().with_context(failed)?;
+    Ok(())
+}
@@ -1,16 +1,129 @@
+use anyhow::{anyhow, Context, Result};
+use geo::Coord;
+use prost::bytes::Bytes;
 use prost::Message;
 use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap, VecDeque},
-    sync::mpsc::Sender,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug_span, field, info_span, instrument, warn};
 
 use crate::{
-    entities::{EntityCollection, Route, Stop},
-    proto::gtfs::realtime::{vehicle_position::VehicleStopStatus, FeedMessage},
+    accessibility::Outages,
+    complexes::Complexes,
+    config::FeedsConfig,
+    entity_log::{EntityLog, EntityRow},
+    entities::{EntityCollection, Route, ShapeIndex, Stop},
+    headway::{Direction, HeadwayTracker, Headways},
+    ids::{RouteId, StopId, TripId},
+    intern::{Interner, RouteHandle, ScheduleIds, StopHandle, TripHandle},
+    motion::Motion,
+    nyct::{Extensions, NyctTrip, Track},
+    proto::gtfs::realtime::{
+        self,
+        alert::Effect,
+        feed_header::Incrementality,
+        vehicle_position::{OccupancyStatus, VehicleStopStatus},
+        trip_descriptor::ScheduleRelationship,
+        Alert, FeedMessage, TranslatedString,
+    },
+    proto::json,
+    render::activity::RouteActivity,
+    render::color::{ColorMode, Marker},
     render::stop::{StopInstance, StopState},
+    render::vehicle::VehicleInstance,
+    simulate,
+    stats::SessionStats,
+    status::{self, LineStatuses, RouteSignals},
+    util::{
+        self,
+        clock::{Clock, SystemClock},
+        latest,
+    },
+    timetable::{ServicePattern, Timetable},
+    validate::ScheduleValidator,
+    webhook::{Event, Webhooks},
 };
 
+// how far from a stop to look for its track, in meters
+const TRACK_SEARCH_RADIUS: f32 = 200.0;
+// the MTA refreshes feeds about every 30 seconds, low bandwidth mode fetches each no more often
+const LOW_BANDWIDTH_INTERVAL: Duration = Duration::from_secs(30);
+// how often a feed is assumed to publish until two of its messages have been seen,
+// the gap between consecutive header timestamps is used after that, within these bounds
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_PUBLISH_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+// a fetch that failed or brought no newer message is retried this soon
+const FETCH_RETRY: Duration = Duration::from_secs(5);
+// added to each feed's next fetch at random, so feeds published together aren't all fetched at once
+const FETCH_JITTER: Duration = Duration::from_secs(3);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// connections outlive the gaps between fetches, so they're reused rather than handshaking again
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+// updates buffered for each stream subscriber, slower ones skip ahead
+const UPDATE_BUFFER: usize = 1024;
+// weight of a new prediction that brings an arrival earlier
+const ETA_SMOOTHING: f64 = 0.5;
+// a prediction this much later than the displayed one is a genuine schedule change, in seconds
+const ETA_RESCHEDULE: i64 = 300;
+// scheduled trains are drawn darker and smaller than realtime ones
+const SCHEDULED_TINT: f32 = 0.45;
+const SCHEDULED_SCALE: f32 = 0.6;
+// trains not yet assigned a trip by NYCT are drawn smaller, they may never leave their terminal
+const UNASSIGNED_SCALE: f32 = 0.75;
+// stations and trains of feeds that have stopped updating are dimmed, short of hiding them
+const STALE_TINT: f32 = 0.35;
+// how bright stations and trains off the spotlit route are drawn, the renderer dims the map under them as much
+pub const SPOTLIGHT_DIM: f32 = 0.25;
+// positions kept for each train's trail, and the spacing of the dots it's drawn with, in meters
+const TRAIL_POINTS: usize = 64;
+const TRAIL_SPACING: f32 = 40.0;
+// the selected train's trail fades from its route color to this fraction of it at the oldest end
+const TRAIL_FADE: f32 = 0.15;
+// upper bound on the dots in a trail, sizes the renderer's instance buffer
+pub const MAX_TRAIL_DOTS: usize = 2048;
+// the latest message of each feed is kept in this cache subdirectory, for --offline
+const FEED_CACHE_DIR: &str = "feeds";
+
+// where FeedManager gets each feed's messages
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FeedSource {
+    #[default]
+    Network,
+    // the last cached message of each feed, replayed once
+    Cache,
+    // generated from the timetable, on a clock that may run faster than real time
+    Simulation,
+}
+
+// how feed messages are encoded, JSON as the proto3 JSON mapping of GTFS-realtime or as serde writes FeedMessage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    // JSON when the message starts with {, protobuf otherwise
+    #[default]
+    Auto,
+    Protobuf,
+    Json,
+}
+
+// named as the MTA names its feeds
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum Feed {
     ACE,
@@ -51,268 +164,2139 @@ impl Feed {
             Self::SIR => "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fgtfs-si",
         }
     }
+
+    // static route_ids carried by the feed
+    pub fn routes(&self) -> &[&str] {
+        match self {
+            Self::ACE => &["A", "C", "E", "H", "FS"],
+            Self::G => &["G"],
+            Self::NQRW => &["N", "Q", "R", "W"],
+            Self::S1234567 => &["1", "2", "3", "4", "5", "6", "6X", "7", "7X", "GS"],
+            Self::BDFM => &["B", "D", "F", "FX", "M"],
+            Self::JZ => &["J", "Z"],
+            Self::L => &["L"],
+            Self::SIR => &["SI"],
+        }
+    }
 }
 
-struct FeedEntity<'a> {
-    stop_id: &'a String,
-    route_id: String,
-    trip_id: String,
+struct FeedEntity {
+    stop: StopHandle,
+    route: RouteHandle,
+    trip: TripHandle,
     timestamp: u64,
     color: Option<[f32; 3]>,
+    // reported delay of the trip, in seconds
+    delay: Option<i32>,
+    occupancy: Option<OccupancyStatus>,
+    // from NYCT's trip descriptor, or the platform the train stopped at
+    direction: Option<Direction>,
+}
+
+// a train at, or in transit to, its reported stop
+struct Vehicle {
+    trip: TripHandle,
+    route: RouteHandle,
+    stop: StopHandle,
+    // the following stop on the trip, gives the train's heading
+    next_stop: Option<StopHandle>,
+    stopped: bool,
+    // placed from the timetable, the feed had no realtime position
+    scheduled: bool,
+    // the stretch run to `stop`, while in transit
+    segment: Option<Segment>,
+    direction: Option<Direction>,
+    // from NYCT's extensions, realtime trains only
+    nyct: Option<NyctTrip>,
+    // the track at `stop`
+    track: Option<Track>,
+}
+
+// a train's run between stations, times are POSIX timestamps
+struct Segment {
+    from: StopHandle,
+    departed: i64,
+    arrival: i64,
+}
+
+// a train's arrival at a station as its feed predicts it, published as an Arrival
+struct Prediction {
+    route: RouteHandle,
+    trip: TripHandle,
+    time: i64,
+    service_pattern: Option<ServicePattern>,
+}
+
+// a snapshot of the feeds, sent to the renderer
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub stops: Vec<StopInstance>,
+    pub vehicles: Vec<VehicleInstance>,
+    // the trip_id of each of `vehicles`
+    pub vehicle_trips: Vec<TripId>,
+    // the selected train's trail, oldest first
+    pub trail: Vec<StopInstance>,
+    // by route_id
+    pub activity: HashMap<RouteId, RouteActivity>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Arrival {
+    pub route_id: RouteId,
+    pub trip_id: TripId,
+    // predicted arrival (or departure, for origin stops) as a POSIX timestamp
+    pub time: i64,
+    // `time` smoothed across polls, for displaying countdowns
+    pub smoothed: i64,
+    // whether the train runs express from the stop, when it can be told
+    pub service_pattern: Option<ServicePattern>,
+}
+
+// minutes until each upcoming arrival of a route at a stop
+#[derive(Debug, Clone, Serialize)]
+pub struct Countdown {
+    pub route_id: RouteId,
+    // express and local trains of a route are counted down apart
+    pub service_pattern: Option<ServicePattern>,
+    pub minutes: Vec<i64>,
+    // POSIX timestamp of the route's next arrival
+    pub next_arrival: i64,
+}
+
+// upcoming arrivals by parent stop_id across all feeds, sorted by time
+#[derive(Clone, Default)]
+pub struct Arrivals(Arc<RwLock<HashMap<StopId, Vec<Arrival>>>>);
+
+impl Arrivals {
+    // smooth each arrival against its last published prediction
+    fn publish(&self, mut arrivals: HashMap<StopId, Vec<Arrival>>, now: i64) {
+        let mut published = self.0.write().unwrap();
+        for (stop_id, stop_arrivals) in arrivals.iter_mut() {
+            let Some(previous) = published.get(stop_id) else {
+                continue;
+            };
+            for arrival in stop_arrivals.iter_mut() {
+                if let Some(last) = previous
+                    .iter()
+                    .find(|last| last.trip_id == arrival.trip_id)
+                {
+                    arrival.smoothed = smooth_eta(last.smoothed, arrival.time, now);
+                }
+            }
+        }
+        *published = arrivals;
+    }
+
+    // raw predictions are in `time`, `smoothed` is what countdowns show
+    pub fn upcoming(&self, stop_id: &str, now: i64) -> Vec<Arrival> {
+        self.0
+            .read()
+            .unwrap()
+            .get(stop_id)
+            .map(|arrivals| {
+                arrivals
+                    .iter()
+                    .filter(|arrival| arrival.time >= now)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // countdowns per route and service pattern, ordered by each one's next arrival
+    pub fn countdowns(&self, stop_id: &str, now: i64) -> Vec<Countdown> {
+        self.merged_countdowns(&[stop_id], now)
+    }
+
+    // countdowns across several stations, e.g. a station complex
+    pub fn merged_countdowns(&self, stop_ids: &[&str], now: i64) -> Vec<Countdown> {
+        let mut countdowns: Vec<Countdown> = Vec::new();
+        let mut upcoming: Vec<_> = stop_ids
+            .iter()
+            .flat_map(|stop_id| self.upcoming(stop_id, now))
+            .collect();
+        upcoming.sort_by_key(|arrival| arrival.smoothed);
+        for arrival in upcoming {
+            let minutes = (arrival.smoothed - now).max(0) / 60;
+            match countdowns.iter_mut().find(|countdown| {
+                countdown.route_id == arrival.route_id && countdown.service_pattern == arrival.service_pattern
+            }) {
+                Some(countdown) => countdown.minutes.push(minutes),
+                None => countdowns.push(Countdown {
+                    route_id: arrival.route_id,
+                    service_pattern: arrival.service_pattern,
+                    minutes: vec![minutes],
+                    next_arrival: arrival.smoothed,
+                }),
+            }
+        }
+        countdowns
+    }
+}
+
+// the displayed arrival time given a new prediction, countdowns only count down
+// unless the prediction moves far enough out to be a schedule change, or the displayed time has passed
+fn smooth_eta(displayed: i64, predicted: i64, now: i64) -> i64 {
+    if predicted >= displayed + ETA_RESCHEDULE || (displayed < now && predicted >= now) {
+        predicted
+    } else if predicted > displayed {
+        displayed
+    } else {
+        displayed + ((predicted - displayed) as f64 * ETA_SMOOTHING).round() as i64
+    }
+}
+
+// a train as of the last publish, stop_ids are parent stations
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VehicleStatus {
+    pub trip_id: TripId,
+    pub route_id: RouteId,
+    // where the train is stopped, or the station it's running to
+    pub stop_id: StopId,
+    pub next_stop_id: Option<StopId>,
+    pub stopped: bool,
+    pub scheduled: bool,
+    // the station it left, and POSIX timestamps of the departure and arrival at `stop_id`, while in transit
+    pub from_stop_id: Option<StopId>,
+    pub departed: Option<i64>,
+    pub arrival: Option<i64>,
+    pub direction: Option<Direction>,
+    // NYCT's own identifier for the train, and whether it has been assigned the trip, realtime trains only
+    pub train_id: Option<String>,
+    pub assigned: Option<bool>,
+    // the scheduled and actual track at `stop_id`
+    pub track: Option<Track>,
+}
+
+// a station a train has yet to reach, as its trip update predicts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpcomingStop {
+    // the parent station
+    pub stop_id: StopId,
+    // POSIX timestamp of the predicted arrival, or departure where the feed only has that
+    pub time: i64,
+}
+
+// a realtime train's trip, as the selected train's panel shows it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TripDetails {
+    pub trip_id: TripId,
+    pub route_id: RouteId,
+    pub direction: Option<Direction>,
+    // seconds behind schedule as the feed reports it, negative when early
+    pub delay: Option<i32>,
+    // soonest first
+    pub next_stops: Vec<UpcomingStop>,
+}
+
+// an alert in effect, from any feed
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveAlert {
+    pub id: String,
+    pub header: String,
+    pub description: String,
+    // e.g. SIGNIFICANT_DELAYS, as named in the GTFS-realtime spec
+    pub effect: String,
+    pub routes: Vec<RouteId>,
+}
+
+// a change to the feed state, pushed to stream subscribers as it's applied
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedUpdate {
+    // a train arrived at a station, which is now drawn active
+    StopActive {
+        stop_id: StopId,
+        route_id: RouteId,
+        trip_id: TripId,
+        timestamp: u64,
+    },
+    // the train left, the station is inactive unless another train is there
+    StopInactive { stop_id: StopId, trip_id: TripId },
+    // every train of a route, sent when any of them changed
+    Vehicles {
+        route_id: RouteId,
+        vehicles: Vec<VehicleStatus>,
+    },
+    // a feed went stale or caught up
+    Feed(FeedAge),
+}
+
+// feed updates as they happen, for any number of subscribers
+#[derive(Clone)]
+pub struct FeedUpdates(broadcast::Sender<FeedUpdate>);
+
+impl Default for FeedUpdates {
+    fn default() -> Self {
+        Self(broadcast::channel(UPDATE_BUFFER).0)
+    }
+}
+
+impl FeedUpdates {
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedUpdate> {
+        self.0.subscribe()
+    }
+
+    fn send(&self, update: FeedUpdate) {
+        // there's usually nobody listening
+        let _ = self.0.send(update);
+    }
+}
+
+// what happened to a train, for consumers that react to it rather than diffing snapshots
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TrainEvent {
+    // stopped at a station, stop_ids are parent stations
+    TrainArrived {
+        trip_id: TripId,
+        route_id: RouteId,
+        stop_id: StopId,
+        // POSIX timestamp of the vehicle position that put the train there
+        timestamp: u64,
+    },
+    // left the station it last arrived at, as of the message it was first missing from
+    TrainDeparted {
+        trip_id: TripId,
+        route_id: RouteId,
+        stop_id: StopId,
+        timestamp: u64,
+    },
+    // the feed marked the trip canceled or deleted, it won't run the rest of its stops
+    TripCancelled { trip_id: TripId, route_id: RouteId },
 }
 
-enum FeedOp<'a> {
-    Add(FeedEntity<'a>),
-    Remove(String),
+// train events as FeedManager applies them, for any number of subscribers
+#[derive(Clone)]
+pub struct TrainEvents(broadcast::Sender<TrainEvent>);
+
+impl Default for TrainEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(UPDATE_BUFFER).0)
+    }
+}
+
+impl TrainEvents {
+    // events from now on. the map only reads them through plugins, which it registers none of itself
+    pub fn subscribe(&self) -> TrainEventReceiver {
+        TrainEventReceiver(self.0.subscribe())
+    }
+
+    fn send(&self, event: TrainEvent) {
+        // there's usually nobody listening
+        let _ = self.0.send(event);
+    }
+}
+
+// one subscriber's events, in the order they happened. a subscriber that falls more than UPDATE_BUFFER
+// events behind skips the oldest. iterating blocks until the next event, so it's for threads of their own and
+// panics inside a tokio runtime, async code awaits `recv` or reads `stream`
+pub struct TrainEventReceiver(broadcast::Receiver<TrainEvent>);
+
+impl TrainEventReceiver {
+    // the next event, None once every FeedManager emitting them is gone.
+    // the map only polls with try_recv, this is for embedders of the library
+    pub async fn recv(&mut self) -> Option<TrainEvent> {
+        loop {
+            match self.0.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => warn!("Train event subscriber fell behind, skipped {}", skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    // the events as an async Stream, ending once every FeedManager emitting them is gone
+    pub fn stream(self) -> impl Stream<Item = TrainEvent> {
+        BroadcastStream::new(self.0).filter_map(|event| match event {
+            Ok(event) => Some(event),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                warn!("Train event subscriber fell behind, skipped {}", skipped);
+                None
+            }
+        })
+    }
+
+    // the next event already emitted, without waiting for one
+    pub fn try_recv(&mut self) -> Option<TrainEvent> {
+        loop {
+            match self.0.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(skipped)) => warn!("Train event subscriber fell behind, skipped {}", skipped),
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Iterator for TrainEventReceiver {
+    type Item = TrainEvent;
+
+    fn next(&mut self) -> Option<TrainEvent> {
+        loop {
+            match self.0.blocking_recv() {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => warn!("Train event subscriber fell behind, skipped {}", skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+// a train stopped at a station, as it lights up on the map
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveStop {
+    // the parent station
+    pub stop_id: StopId,
+    pub route_id: RouteId,
+    pub trip_id: TripId,
+    // POSIX timestamp of the vehicle position that put the train there
+    pub timestamp: u64,
+}
+
+// trains by route_id, active stops and alerts across all feeds
+#[derive(Default)]
+struct Snapshot {
+    vehicles: HashMap<RouteId, Vec<VehicleStatus>>,
+    active_stops: Vec<ActiveStop>,
+    alerts: Vec<ActiveAlert>,
+    feeds: Vec<FeedAge>,
+    // the trip of the train selected on the map
+    selected_trip: Option<TripDetails>,
+    // seconds each trip is running late, as its latest report has it
+    trip_delays: Arc<HashMap<TripId, i32>>,
+    // every feed's dataset, less trips another feed reported more recently
+    entities: Vec<realtime::FeedEntity>,
+    // the newest of the feeds' header timestamps
+    timestamp: u64,
+}
+
+// how current a feed's data is, as of the last publish
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedAge {
+    pub feed: String,
+    pub routes: Vec<RouteId>,
+    // POSIX timestamp of the latest feed header, None until the feed is first fetched
+    pub updated: Option<u64>,
+    pub stale: bool,
+}
+
+impl FeedAge {
+    // seconds since the feed's header timestamp
+    pub fn age(&self, now: i64) -> Option<i64> {
+        self.updated.map(|updated| (now - updated as i64).max(0))
+    }
+}
+
+// the latest merged feed state, for consumers other than the renderer
+#[derive(Clone, Default)]
+pub struct FeedSnapshot(Arc<RwLock<Snapshot>>);
+
+impl FeedSnapshot {
+    pub fn vehicles(&self, route_id: &str) -> Vec<VehicleStatus> {
+        self.0
+            .read()
+            .unwrap()
+            .vehicles
+            .get(route_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // every route's trains, in route_id order
+    pub fn all_vehicles(&self) -> Vec<VehicleStatus> {
+        let snapshot = self.0.read().unwrap();
+        let mut route_ids: Vec<&RouteId> = snapshot.vehicles.keys().collect();
+        route_ids.sort();
+        route_ids
+            .into_iter()
+            .flat_map(|route_id| snapshot.vehicles[route_id].iter().cloned())
+            .collect()
+    }
+
+    pub fn active_stops(&self) -> Vec<ActiveStop> {
+        self.0.read().unwrap().active_stops.clone()
+    }
+
+    pub fn alerts(&self) -> Vec<ActiveAlert> {
+        self.0.read().unwrap().alerts.clone()
+    }
+
+    // the alerts' headers as the ticker scrolls them, ordered by the first route each affects.
+    // the same header is often posted for several routes, it's only listed once
+    pub fn headlines(&self) -> Vec<String> {
+        let mut alerts = self.alerts();
+        alerts.sort_by(|a, b| a.routes.first().cmp(&b.routes.first()));
+        let mut seen = HashSet::new();
+        alerts
+            .into_iter()
+            .map(|alert| alert.header)
+            .filter(|header| !header.trim().is_empty() && seen.insert(header.clone()))
+            .collect()
+    }
+
+    pub fn feeds(&self) -> Vec<FeedAge> {
+        self.0.read().unwrap().feeds.clone()
+    }
+
+    // None while no train is selected, or the selected one was placed from the timetable
+    pub fn selected_trip(&self) -> Option<TripDetails> {
+        self.0.read().unwrap().selected_trip.clone()
+    }
+
+    // seconds behind schedule by trip_id, for trips with a delay reported
+    pub fn trip_delays(&self) -> Arc<HashMap<TripId, i32>> {
+        self.0.read().unwrap().trip_delays.clone()
+    }
+
+    // the feeds merged into one full dataset, as GTFS-realtime without NYCT's extensions. entity ids are
+    // prefixed with their feed's name, they're only unique within it
+    pub fn feed_message(&self) -> FeedMessage {
+        let snapshot = self.0.read().unwrap();
+        FeedMessage {
+            header: realtime::FeedHeader {
+                gtfs_realtime_version: "2.0".to_owned(),
+                incrementality: Some(Incrementality::FullDataset.into()),
+                timestamp: Some(snapshot.timestamp),
+            },
+            entity: snapshot.entities.clone(),
+        }
+    }
+}
+
+// runtime adjustable feed settings, shared with the GUI
+pub struct FeedControls {
+    pub hidden_routes: HashSet<RouteId>,
+    // delay between FeedManager updates
+    pub polling_interval: Duration,
+    pub color_mode: ColorMode,
+    // time FeedManager spends applying queued feed changes per update
+    pub update_budget: Duration,
+    // stop fetching feeds, the last frame stays on screen
+    pub paused: bool,
+    // only fetch feeds with visible routes, each at most every LOW_BANDWIDTH_INTERVAL
+    pub low_bandwidth: bool,
+    // draw and query each station complex as a single station
+    pub merge_complexes: bool,
+    // a feed whose header timestamp hasn't advanced in this long is drawn as stale
+    pub stale_after: Duration,
+    // the train whose trail is drawn
+    pub selected_trip: Option<TripId>,
+    // the route whose stations and trains are drawn as usual, with everything else dimmed
+    pub spotlight: Option<RouteId>,
+}
+
+impl Default for FeedControls {
+    fn default() -> Self {
+        Self {
+            hidden_routes: HashSet::new(),
+            polling_interval: Duration::from_millis(200),
+            color_mode: ColorMode::default(),
+            update_budget: Duration::from_millis(10),
+            paused: false,
+            low_bandwidth: false,
+            merge_complexes: false,
+            stale_after: Duration::from_secs(180),
+            selected_trip: None,
+            spotlight: None,
+        }
+    }
+}
+
+pub type SharedControls = Arc<RwLock<FeedControls>>;
+
+// state shared between the feed thread and the renderer
+#[derive(Clone, Default)]
+pub struct SharedFeedState {
+    pub arrivals: Arrivals,
+    pub controls: SharedControls,
+    pub statuses: LineStatuses,
+    pub stats: SessionStats,
+    pub outages: Outages,
+    pub snapshot: FeedSnapshot,
+    pub updates: FeedUpdates,
+    pub events: TrainEvents,
+    pub headways: Headways,
+}
+
+enum FeedOp {
+    Add(FeedEntity),
+    Remove(TripHandle),
 }
 
 pub struct FeedManager<'a> {
-    client: Client,
+    transport: Arc<dyn Transport>,
+    clock: Arc<dyn Clock>,
     feeds: Vec<FeedProcessor<'a>>,
     feed_idx: usize,
-    tx: Sender<Vec<StopInstance>>,
-    stops: &'a EntityCollection<BTreeMap<String, Stop>>,
-    parent_stops: Vec<&'a String>,
+    // frames for the renderer, headless managers don't build them. only the latest is kept for it,
+    // a renderer that falls behind skips the frames it missed
+    tx: Option<latest::Sender<Frame>>,
+    stops: &'a EntityCollection<BTreeMap<StopId, Stop>>,
+    // the schedule's stop and route ids, and every trip_id the feeds and timetable have had.
+    // NYCT's trip_ids are the same from one day to the next, so the trips interned level off
+    ids: Arc<ScheduleIds>,
+    trips: Interner<TripHandle>,
+    // every station, in stop_id order
+    stations: Vec<StopHandle>,
+    shapes: &'a ShapeIndex,
+    shared: SharedFeedState,
+    // largest gap between each route's trains as of the last publish, by route
+    headway_gaps: HashMap<RouteHandle, i64>,
+    // places trains for feeds without realtime positions
+    timetable: Option<&'a Timetable>,
+    source: FeedSource,
+    motion: Motion,
+    // where merged complexes are drawn, by station
+    complex_centers: HashMap<StopHandle, Coord<f32>>,
+    // recent positions of each train in the feeds, oldest first
+    trails: HashMap<TripHandle, VecDeque<Coord<f32>>>,
+    // the selected train as of the last frame
+    trail_trip: Option<TripId>,
+    // the spotlit route as of the last frame, and the stations its trips stop at in the timetable
+    spotlight: Option<(RouteHandle, HashSet<StopHandle>)>,
 }
 
 struct FeedProcessor<'a> {
-    stops: &'a EntityCollection<BTreeMap<String, Stop>>,
-    routes: &'a EntityCollection<HashMap<String, Route>>,
+    ids: Arc<ScheduleIds>,
     fetched_at: u64,
-    queue: VecDeque<FeedOp<'a>>,
-    active_stops: HashMap<String, FeedEntity<'a>>,
-    active_stops_current: HashMap<String, bool>,
+    queue: VecDeque<FeedOp>,
+    active_stops: HashMap<TripHandle, FeedEntity>,
+    active_stops_current: HashMap<TripHandle, bool>,
+    // by station
+    arrivals: HashMap<StopHandle, Vec<Prediction>>,
+    vehicles: Vec<Vehicle>,
+    // effects of currently active alerts, by route_id
+    alert_effects: HashMap<RouteId, Vec<Effect>>,
+    alerts: Vec<ActiveAlert>,
+    // reported trip delays in seconds, by route
+    delays: HashMap<RouteHandle, Vec<i32>>,
+    // and by trip
+    trip_delays: HashMap<TripHandle, i32>,
     feed: &'a Feed,
+    // the feed's MTA endpoint unless a mirror is configured
+    endpoint: String,
+    format: FeedFormat,
+    webhooks: Webhooks,
+    entity_log: EntityLog,
+    updates: FeedUpdates,
+    events: TrainEvents,
+    stats: SessionStats,
+    // checks trip updates against the schedule in strict mode
+    validator: Option<&'a ScheduleValidator>,
+    // tells express trains from local ones
+    timetable: Option<&'a Timetable>,
+    seen_alerts: HashSet<String>,
+    // trips the last message had cancelled, each is announced once
+    cancelled: HashSet<TripHandle>,
+    // the start date (YYYYMMDD) and newest timestamp of each trip the last message reported
+    reported: HashMap<TripHandle, (Option<u32>, u64)>,
+    // trips another feed reported more recently for the same start date, shuttles and transfers can appear in
+    // several feeds. they're left out of everything published
+    superseded: HashSet<TripHandle>,
+    // the whole dataset as of the last message by entity id,
+    // which a differential message only adds to, replaces or deletes entities from
+    entities: BTreeMap<String, realtime::FeedEntity>,
+    extensions: Extensions,
+    outage: bool,
+    // the header timestamp is older than FeedControls::stale_after
+    stale: bool,
+    // the last station each trip was seen stopped at, and when
+    last_stops: HashMap<TripHandle, (StopHandle, i64)>,
+    headways: HeadwayTracker,
+    last_fetch: Option<Instant>,
+    // when the feed is next fetched, just after it's expected to publish again
+    next_fetch: Option<Instant>,
+    // the gap between the feed's last two header timestamps
+    publish_interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+// the client realtime feeds are fetched with, its clones share one pool of keep-alive connections.
+// HTTP/2 is negotiated with servers that offer it, and bodies are counted in the session stats decompressed
+pub fn client() -> Client {
+    Client::builder()
+        .gzip(true)
+        .deflate(true)
+        .timeout(FETCH_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .pool_max_idle_per_host(FEEDS.len())
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .build()
+        .unwrap_or_else(|err| {
+            warn!("Failed to configure the feed client, using the defaults: {}", err);
+            Client::new()
+        })
+}
+
+// how feed messages are downloaded, a Client unless they're served some other way, e.g. by a test
+pub trait Transport: Send + Sync {
+    fn get(&self, url: &str) -> Result<Bytes>;
+}
+
+impl Transport for Client {
+    fn get(&self, url: &str) -> Result<Bytes> {
+        let failed = || format!("Failed to fetch '{url}'");
+        let res = Client::get(self, url).send().and_then(|res| res.error_for_status()).with_context(failed)?;
+        res.bytes().with_context(failed)
+    }
 }
 
 impl<'a> FeedManager<'a> {
     pub fn new(
-        stops: &'a EntityCollection<BTreeMap<String, Stop>>,
-        routes: &'a EntityCollection<HashMap<String, Route>>,
-        tx: Sender<Vec<StopInstance>>,
+        stops: &'a EntityCollection<BTreeMap<StopId, Stop>>,
+        routes: &'a EntityCollection<HashMap<RouteId, Route>>,
+        shapes: &'a ShapeIndex,
+        shared: SharedFeedState,
+        webhooks: Webhooks,
     ) -> Self {
-        let client = Client::new();
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let ids = Arc::new(ScheduleIds::new(stops, routes));
         let feeds = FEEDS
             .iter()
             .map(|feed| FeedProcessor {
-                stops,
-                routes,
+                ids: ids.clone(),
                 fetched_at: 0,
                 queue: VecDeque::new(),
                 active_stops: HashMap::new(),
                 active_stops_current: HashMap::new(),
+                arrivals: HashMap::new(),
+                vehicles: Vec::new(),
+                alert_effects: HashMap::new(),
+                alerts: Vec::new(),
+                delays: HashMap::new(),
+                trip_delays: HashMap::new(),
                 feed,
+                endpoint: feed.endpoint().to_owned(),
+                format: FeedFormat::default(),
+                webhooks: webhooks.clone(),
+                entity_log: EntityLog::default(),
+                updates: shared.updates.clone(),
+                events: shared.events.clone(),
+                stats: shared.stats.clone(),
+                validator: None,
+                timetable: None,
+                seen_alerts: HashSet::new(),
+                cancelled: HashSet::new(),
+                reported: HashMap::new(),
+                superseded: HashSet::new(),
+                entities: BTreeMap::new(),
+                extensions: Extensions::default(),
+                outage: false,
+                stale: false,
+                last_stops: HashMap::new(),
+                headways: HeadwayTracker::default(),
+                last_fetch: None,
+                next_fetch: None,
+                publish_interval: PUBLISH_INTERVAL,
+                clock: clock.clone(),
             })
             .collect::<Vec<_>>();
+        let stations = stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .filter_map(|stop| ids.stops.get(stop.id.as_str()))
+            .collect();
 
         Self {
             feed_idx: 0,
-            client,
+            transport: Arc::new(client()),
+            clock,
             feeds,
             stops,
-            parent_stops: stops
-                .values()
-                .filter_map(|s| {
-                    if let None = &s.parent {
-                        Some(&s.id)
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-            tx,
+            ids,
+            trips: Interner::default(),
+            stations,
+            shapes,
+            tx: None,
+            shared,
+            headway_gaps: HashMap::new(),
+            timetable: None,
+            source: FeedSource::Network,
+            motion: Motion::default(),
+            complex_centers: HashMap::new(),
+            trails: HashMap::new(),
+            trail_trip: None,
+            spotlight: None,
+        }
+    }
+
+    // share another manager's connections, e.g. one that's replaced when the schedule is
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.transport = Arc::new(client);
+        self
+    }
+
+    // download feeds some other way than over HTTP, the map and `serve` always use a Client
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    // time fetches and compare feed timestamps by another clock than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        for feed in &mut self.feeds {
+            feed.clock = clock.clone();
         }
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_frames(mut self, tx: latest::Sender<Frame>) -> Self {
+        self.tx = Some(tx);
+        self
+    }
+
+    pub fn with_validator(mut self, validator: &'a ScheduleValidator) -> Self {
+        for feed in &mut self.feeds {
+            feed.validator = Some(validator);
+        }
+        self
+    }
+
+    pub fn with_entity_log(mut self, entity_log: EntityLog) -> Self {
+        for feed in &mut self.feeds {
+            feed.entity_log = entity_log.clone();
+        }
+        self
+    }
+
+    // fetch feeds from mirrors, and how their messages are encoded
+    pub fn with_feeds(mut self, config: &FeedsConfig) -> Self {
+        for feed in &mut self.feeds {
+            if let Some(endpoint) = config.endpoints.get(&format!("{:?}", feed.feed)) {
+                feed.endpoint = endpoint.to_owned();
+            }
+            feed.format = config.format;
+        }
+        for name in config.endpoints.keys() {
+            if !self.feeds.iter().any(|feed| format!("{:?}", feed.feed) == *name) {
+                warn!("No feed is named {}, its endpoint is ignored", name);
+            }
+        }
+        self
+    }
+
+    pub fn with_source(mut self, source: FeedSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_timetable(mut self, timetable: &'a Timetable) -> Self {
+        for feed in &mut self.feeds {
+            feed.timetable = Some(timetable);
+        }
+        self.timetable = Some(timetable);
+        self
+    }
+
+    pub fn with_motion(mut self, motion: Motion) -> Self {
+        self.motion = motion;
+        self
+    }
+
+    pub fn with_complexes(mut self, complexes: &Complexes) -> Self {
+        self.complex_centers = complexes
+            .centers(self.stops)
+            .into_iter()
+            .filter_map(|(stop_id, center)| Some((self.ids.stops.get(stop_id.as_str())?, center)))
+            .collect();
+        self
+    }
+
+    pub fn polling_interval(&self) -> Duration {
+        self.shared.controls.read().unwrap().polling_interval
     }
 
     pub fn update(&mut self) {
         if self.feed_idx >= self.feeds.len() {
             self.feed_idx = 0;
         }
+        let (budget, paused, due, stale_after, reselected) = {
+            let controls = self.shared.controls.read().unwrap();
+            let feed = &self.feeds[self.feed_idx];
+            let due = feed.fetch_due()
+                && (!controls.low_bandwidth || feed.low_bandwidth_due(&controls.hidden_routes));
+            let reselected = controls.selected_trip != self.trail_trip;
+            (controls.update_budget, controls.paused, due, controls.stale_after, reselected)
+        };
+        if paused {
+            return;
+        }
+        let started = self.clock.now();
         let feed = &mut self.feeds[self.feed_idx];
+        let _span = info_span!("feed_update", feed = ?feed.feed).entered();
+        // drain queued ops until the budget is spent, so latency doesn't grow with the queue
+        let queue = debug_span!("queue", queued = feed.queue.len(), processed = field::Empty).entered();
+        let mut processed = 0;
+        while feed.update(&self.trips).is_some() {
+            processed += 1;
+            if self.clock.now().duration_since(started) >= budget {
+                break;
+            }
+        }
+        queue.record("processed", processed);
+        drop(queue);
 
-        let batch = feed.queue.len() as f32 / 10.;
+        // an op applied along with a new message is drawn like those drained from the queue
+        let mut applied = false;
+        if processed > 0 {
+            self.send_frame();
+        } else if self.source == FeedSource::Cache {
+            // the cached message is all there is, it's replayed once
+            if feed.last_fetch.is_none() {
+                if let Err(err) = feed.replay(&mut self.trips) {
+                    feed.report_outage(err);
+                }
+                applied = self.apply_fetched();
+            }
+        } else if self.source == FeedSource::Simulation {
+            if let Some(timetable) = self.timetable {
+                feed.simulate(timetable, &mut self.trips);
+                applied = self.apply_fetched();
+            }
+        } else if due {
+            let previous = feed.fetched_at;
+            match feed.fetch(self.transport.as_ref(), &mut self.trips) {
+                Ok(bytes) => self.shared.stats.fetched(bytes),
+                Err(err) => {
+                    self.shared.stats.fetch_failed(&err);
+                    feed.report_outage(err);
+                }
+            }
+            feed.schedule_fetch(previous);
+            applied = self.apply_fetched();
+        }
+        // going stale or catching up changes how the feed is drawn, even with nothing else to process
+        let feed = &mut self.feeds[self.feed_idx];
+        let stale = feed.is_stale(self.clock.posix_now(), stale_after);
+        if stale != feed.stale {
+            feed.stale = stale;
+            if stale {
+                warn!(feed = ?feed.feed, fetched_at = feed.fetched_at, "Feed is stale");
+            }
+            self.shared.updates.send(FeedUpdate::Feed(feed.age(stale)));
+            self.send_frame();
+            // readers of the API see it now rather than when the feed is next fetched
+            if processed > 0 || !due {
+                self.publish_snapshot();
+            }
+        } else if reselected || applied {
+            // as does selecting a train, which shows its trail
+            self.send_frame();
+        }
+        self.feed_idx += 1;
+    }
 
-        for _ in 0..batch.ceil().max(1.) as u32 {
-            let feed = &mut self.feeds[self.feed_idx];
-            if let Some(_) = feed.update() {
-                let mut active_stops: Vec<_> = self
-                    .feeds
-                    .iter()
-                    .flat_map(|feed| feed.active_stops.values())
-                    .collect();
-                active_stops.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    // apply the first op of a message just fetched, replayed or simulated and publish it. the message's trips are
+    // claimed first, so trips another feed reported more recently aren't told of
+    fn apply_fetched(&mut self) -> bool {
+        self.claim_trips();
+        let applied = self.feeds[self.feed_idx].update(&self.trips).is_some();
+        self.publish();
+        applied
+    }
 
-                let sorted_stops = active_stops
-                    .into_iter()
-                    .fold(HashMap::new(), |mut acc, fe| {
-                        acc.entry(&fe.stop_id).or_insert(fe);
-                        acc
+    #[instrument(level = "debug", skip_all)]
+    fn send_frame(&mut self) {
+        if self.tx.is_none() {
+            return;
+        }
+        let scheduled = self.scheduled_vehicles();
+        let spotlight = self.shared.controls.read().unwrap().spotlight.clone();
+        let spotlight = spotlight.and_then(|route_id| self.ids.routes.get(route_id.as_str()));
+        if self.spotlight.as_ref().map(|(route, _)| *route) != spotlight {
+            self.spotlight = spotlight.map(|route| (route, self.scheduled_stations(route)));
+        }
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let controls = self.shared.controls.read().unwrap();
+        let color_source = controls.color_mode.source();
+        let hidden_routes: HashSet<RouteHandle> = controls
+            .hidden_routes
+            .iter()
+            .filter_map(|route_id| self.ids.routes.get(route_id.as_str()))
+            .collect();
+        let stale_routes: HashSet<RouteHandle> = self
+            .feeds
+            .iter()
+            .filter(|feed| feed.stale)
+            .flat_map(|feed| feed.feed.routes().iter())
+            .filter_map(|route_id| self.ids.routes.get(route_id))
+            .collect();
+        let mut active_stops: Vec<_> = self
+            .feeds
+            .iter()
+            .flat_map(|feed| feed.claimed_stops())
+            .filter(|fe| !hidden_routes.contains(&fe.route))
+            .collect();
+        active_stops.sort_by_key(|fe| Reverse(fe.timestamp));
+
+        let sorted_stops = active_stops
+            .into_iter()
+            .fold(HashMap::new(), |mut acc, fe| {
+                acc.entry(fe.stop).or_insert(fe);
+                acc
+            });
+
+        // merged complexes draw all their stations at its center, active ones sort on top
+        let position = |stop: StopHandle| {
+            let coord = self
+                .complex_centers
+                .get(&stop)
+                .filter(|_| controls.merge_complexes)
+                .copied()
+                .unwrap_or_else(|| self.ids.coord(stop));
+            [coord.x, coord.y, 0.0]
+        };
+        let mut stateful_instances: Vec<_> = self
+            .stations
+            .iter()
+            .map(|stop| match sorted_stops.get(stop) {
+                None => StopState::Inactive(StopInstance {
+                    position: position(*stop),
+                    ..Default::default()
+                }),
+                Some(feed_entity) => {
+                    let marker = Marker {
+                        route_color: feed_entity.color.unwrap(),
+                        delay: feed_entity.delay,
+                        headway: self.headway_gaps.get(&feed_entity.route).copied(),
+                        occupancy: feed_entity.occupancy,
+                    };
+                    let mut color = color_source.color(&marker);
+                    if stale_routes.contains(&feed_entity.route) {
+                        color = color.map(|channel| channel * STALE_TINT);
+                    }
+                    StopState::Active(StopInstance {
+                        position: position(*stop),
+                        color,
+                        scale: 0.5,
+                    })
+                }
+            })
+            .zip(&self.stations)
+            .map(|(mut state, stop)| {
+                if self.spotlight.as_ref().is_some_and(|(route, stations)| !self.serves(*route, stations, *stop)) {
+                    let (StopState::Active(instance) | StopState::Inactive(instance)) = &mut state;
+                    instance.color = instance.color.map(|channel| channel * SPOTLIGHT_DIM);
+                }
+                state
+            })
+            .collect();
+        // there's no depth buffer, instances are drawn in order. the sort is stable, so stations only change
+        // slots, and are uploaded again, when they're lit or go out
+        stateful_instances.sort();
+        let instances: Vec<_> = stateful_instances
+            .into_iter()
+            .map(StopInstance::from)
+            .collect();
+
+        let mut vehicles = self.vehicle_instances(&scheduled, &hidden_routes);
+        if let Some((route, _)) = &self.spotlight {
+            let bullet = self.ids.bullet(*route);
+            for (_, vehicle) in vehicles.iter_mut().filter(|(_, vehicle)| vehicle.route != bullet) {
+                vehicle.color = vehicle.color.map(|channel| channel * SPOTLIGHT_DIM);
+            }
+        }
+        // trains leave their trail behind when they leave the feeds
+        let trips: HashSet<TripHandle> = vehicles.iter().map(|(trip, _)| *trip).collect();
+        self.trails.retain(|trip, _| trips.contains(trip));
+        for (trip, vehicle) in &vehicles {
+            let coord = Coord { x: vehicle.position[0], y: vehicle.position[1] };
+            let trail = self.trails.entry(*trip).or_default();
+            if trail.back() != Some(&coord) {
+                trail.push_back(coord);
+                if trail.len() > TRAIL_POINTS {
+                    trail.pop_front();
+                }
+            }
+        }
+        let selected = controls.selected_trip.as_ref();
+        let trail = selected
+            .and_then(|trip_id| {
+                let trip = self.trips.get(trip_id.as_str())?;
+                let (_, vehicle) = vehicles.iter().find(|(id, _)| *id == trip)?;
+                Some(trail_instances(self.trails.get(&trip)?, vehicle.color))
+            })
+            .unwrap_or_default();
+        self.trail_trip = selected.cloned();
+        // the renderer picks trains by trip_id
+        let (vehicle_trips, vehicles) = vehicles
+            .into_iter()
+            .map(|(trip, vehicle)| (self.trips.resolve(trip).clone(), vehicle))
+            .unzip();
+        let activity = self.activity();
+        tx.send(Frame {
+            stops: instances,
+            vehicles,
+            vehicle_trips,
+            trail,
+            activity,
+        })
+        .unwrap();
+    }
+
+    // trains are drawn at their reported stop, pointing along the track towards the following stop
+    fn vehicle_instances(
+        &self,
+        scheduled: &[(usize, Vehicle)],
+        hidden_routes: &HashSet<RouteHandle>,
+    ) -> Vec<(TripHandle, VehicleInstance)> {
+        let now = self.clock.posix_now();
+        self.feeds
+            .iter()
+            .filter(|feed| !feed.needs_schedule())
+            .flat_map(|feed| feed.claimed_vehicles().map(move |vehicle| (feed, vehicle)))
+            .chain(scheduled.iter().map(|(feed_idx, vehicle)| (&self.feeds[*feed_idx], vehicle)))
+            .filter(|(_, vehicle)| !hidden_routes.contains(&vehicle.route))
+            .map(|(feed, vehicle)| {
+                let mut color = self.ids.color(vehicle.route);
+                let mut scale = if vehicle.stopped { 1.0 } else { 0.7 };
+                if vehicle.scheduled {
+                    color = color.map(|channel| channel * SCHEDULED_TINT);
+                    scale *= SCHEDULED_SCALE;
+                } else if feed.stale {
+                    color = color.map(|channel| channel * STALE_TINT);
+                }
+                if vehicle.nyct.as_ref().is_some_and(|nyct| !nyct.assigned) {
+                    scale *= UNASSIGNED_SCALE;
+                }
+                let stop = self.ids.coord(vehicle.stop);
+                // trains in transit are eased along the straight line between stations
+                let segment = vehicle.segment.as_ref().filter(|_| !vehicle.stopped);
+                let (coord, heading) = match segment {
+                    Some(segment) => {
+                        let from = self.ids.coord(segment.from);
+                        (
+                            self.motion.position(
+                                from,
+                                stop,
+                                now - segment.departed,
+                                segment.arrival - segment.departed,
+                            ),
+                            Some(stop - from),
+                        )
+                    }
+                    None => (stop, vehicle.next_stop.map(|next| self.ids.coord(next) - stop)),
+                };
+                // realtime trip_ids end with their shape_id, e.g. 053850_1..S03R
+                let trip_id = self.trips.resolve(vehicle.trip);
+                let shape_id = trip_id.as_str().split_once('_').map(|(_, shape_id)| shape_id);
+                let track = self
+                    .shapes
+                    .direction(coord, TRACK_SEARCH_RADIUS, shape_id, self.ids.routes.resolve(vehicle.route))
+                    .map(|track| match heading {
+                        Some(heading) if track.x * heading.x + track.y * heading.y < 0.0 => -track,
+                        _ => track,
                     });
+                let rotation = track
+                    .or(heading)
+                    .map(|direction| direction.y.atan2(direction.x))
+                    .unwrap_or_default();
+                let instance = VehicleInstance {
+                    position: [coord.x, coord.y, 0.0],
+                    color,
+                    rotation,
+                    scale,
+                    route: self.ids.bullet(vehicle.route),
+                };
+                (vehicle.trip, instance)
+            })
+            .collect()
+    }
+
+    // the stations a route's trips stop at in the timetable
+    fn scheduled_stations(&self, route: RouteHandle) -> HashSet<StopHandle> {
+        let Some(timetable) = self.timetable else {
+            return HashSet::new();
+        };
+        timetable
+            .route_stops(self.ids.routes.resolve(route).as_str())
+            .into_iter()
+            .filter_map(|stop_id| self.ids.station(stop_id.as_str()))
+            .collect()
+    }
 
-                let mut stateful_instances: Vec<_> = self
-                    .parent_stops
+    // the route stops at the station in the timetable, or a feed predicts one of its trains there
+    fn serves(&self, route: RouteHandle, scheduled: &HashSet<StopHandle>, station: StopHandle) -> bool {
+        scheduled.contains(&station)
+            || self.feeds.iter().any(|feed| {
+                feed.arrivals
+                    .get(&station)
+                    .is_some_and(|predictions| predictions.iter().any(|prediction| prediction.route == route))
+            })
+    }
+
+    // where a realtime trip is predicted to stop next, and how late it's running. None for trips no feed reports,
+    // including trains placed from the timetable
+    pub fn trip(&self, trip_id: &str) -> Option<TripDetails> {
+        let trip = self.trips.get(trip_id)?;
+        let now = self.clock.posix_now();
+        self.feeds
+            .iter()
+            .filter(|feed| !feed.needs_schedule() && !feed.superseded.contains(&trip))
+            .find_map(|feed| {
+                let vehicle = feed.vehicles.iter().find(|vehicle| vehicle.trip == trip);
+                let mut predictions: Vec<(StopHandle, &Prediction)> = feed
+                    .arrivals
                     .iter()
-                    .map(|stop_id| {
-                        if !sorted_stops.contains_key(stop_id) {
-                            let stop = self.stops.get(*stop_id).unwrap();
-                            StopState::Inactive(StopInstance {
-                                position: [stop.coord.x, stop.coord.y, 0.0],
-                                ..Default::default()
-                            })
-                        } else {
-                            let feed_entity = sorted_stops.get(stop_id).unwrap();
-                            let stop = self.stops.get(*stop_id).unwrap();
-                            StopState::Active(StopInstance {
-                                position: [stop.coord.x, stop.coord.y, 0.0],
-                                color: feed_entity.color.unwrap(),
-                                scale: 0.5,
-                            })
-                        }
+                    .flat_map(|(station, predictions)| {
+                        predictions
+                            .iter()
+                            .filter(|prediction| prediction.trip == trip)
+                            .map(|prediction| (*station, prediction))
                     })
                     .collect();
-                stateful_instances.sort();
-                let instances: Vec<_> = stateful_instances
-                    .into_iter()
-                    .map(StopInstance::from)
-                    .collect();
+                let route = vehicle
+                    .map(|vehicle| vehicle.route)
+                    .or_else(|| predictions.first().map(|(_, prediction)| prediction.route))?;
+                predictions.retain(|(_, prediction)| prediction.time >= now);
+                predictions.sort_by_key(|(_, prediction)| prediction.time);
+                Some(TripDetails {
+                    trip_id: TripId::from(trip_id),
+                    route_id: self.ids.routes.resolve(route).to_owned(),
+                    direction: vehicle.and_then(|vehicle| vehicle.direction),
+                    delay: feed.trip_delays.get(&trip).copied(),
+                    next_stops: predictions
+                        .into_iter()
+                        .map(|(station, prediction)| UpcomingStop {
+                            stop_id: self.ids.stops.resolve(station).to_owned(),
+                            time: prediction.time,
+                        })
+                        .collect(),
+                })
+            })
+    }
 
-                self.tx.send(instances).unwrap();
-                // for (idx, state) in old_state.into_iter().enumerate() {
-                //     if !sorted_stops.contains(&idx) && state == true {
-                //         self.stops[idx] = false;
-                //         self.tx.send(StopState::Inactive(idx)).unwrap();
-                //     }
-
-                //     if sorted_stops.contains(&idx) && state == false {
-                //         self.stops[idx] = true;
-                //         let fe = sorted_stops.get(&idx).unwrap();
-                //         self.tx
-                //             .send(StopState::Active((idx, fe.color.unwrap())))
-                //             .unwrap();
-                //     }
-                // }
-            } else {
-                feed.fetch(&self.client);
-                feed.update();
-                break;
+    // trains placed from the timetable, for feeds that are down or report no vehicle positions, by feed index
+    fn scheduled_vehicles(&mut self) -> Vec<(usize, Vehicle)> {
+        let Some(timetable) = self.timetable else {
+            return Vec::new();
+        };
+        let now = self.clock.posix_now();
+        let mut vehicles = Vec::new();
+        for (feed_idx, feed) in self.feeds.iter().enumerate() {
+            if !feed.needs_schedule() {
+                continue;
+            }
+            for route_id in feed.feed.routes() {
+                let Some(route) = self.ids.routes.get(route_id) else {
+                    continue;
+                };
+                for position in timetable.positions(route_id, now) {
+                    let Some(stop) = self.ids.station(position.stop_id.as_str()) else {
+                        continue;
+                    };
+                    let next = position
+                        .next_stop_id
+                        .and_then(|stop_id| self.ids.station(stop_id.as_str()))
+                        .zip(position.next_arrival);
+                    let trip = self.trips.intern(position.trip_id.as_str());
+                    let direction = Direction::from_platform(position.stop_id.as_str());
+                    let vehicle = match next {
+                        // between stations, running to the next one
+                        Some((next_stop, arrival)) if !position.stopped => Vehicle {
+                            trip,
+                            route,
+                            stop: next_stop,
+                            next_stop: None,
+                            stopped: false,
+                            scheduled: true,
+                            segment: Some(Segment {
+                                from: stop,
+                                departed: position.departed,
+                                arrival,
+                            }),
+                            direction,
+                            nyct: None,
+                            track: None,
+                        },
+                        _ => Vehicle {
+                            trip,
+                            route,
+                            stop,
+                            next_stop: next.map(|(next_stop, _)| next_stop),
+                            stopped: position.stopped,
+                            scheduled: true,
+                            segment: None,
+                            direction,
+                            nyct: None,
+                            track: None,
+                        },
+                    };
+                    vehicles.push((feed_idx, vehicle));
+                }
             }
         }
-        self.feed_idx += 1;
+        vehicles
+    }
+
+    fn activity(&self) -> HashMap<RouteId, RouteActivity> {
+        let mut activity: HashMap<RouteId, RouteActivity> = HashMap::new();
+        for feed in &self.feeds {
+            for vehicle in feed.claimed_vehicles() {
+                activity.entry(self.ids.routes.resolve(vehicle.route).clone()).or_default().trains += 1;
+            }
+            for (route, delays) in &feed.delays {
+                if !delays.is_empty() {
+                    activity.entry(self.ids.routes.resolve(*route).clone()).or_default().average_delay =
+                        delays.iter().sum::<i32>() as f32 / delays.len() as f32;
+                }
+            }
+        }
+        activity
+    }
+
+    // a trip reported by several feeds for the same start date is kept from the feed that reported it most
+    // recently, the first of them on a tie. feeds that are down don't claim their trips
+    fn claim_trips(&mut self) {
+        let mut owners: HashMap<(TripHandle, Option<u32>), (usize, u64)> = HashMap::new();
+        for (feed_idx, feed) in self.feeds.iter().enumerate() {
+            if feed.needs_schedule() {
+                continue;
+            }
+            for (trip, (start_date, timestamp)) in &feed.reported {
+                let owner = owners.entry((*trip, *start_date)).or_insert((feed_idx, *timestamp));
+                if *timestamp > owner.1 {
+                    *owner = (feed_idx, *timestamp);
+                }
+            }
+        }
+        for (feed_idx, feed) in self.feeds.iter_mut().enumerate() {
+            feed.superseded = feed
+                .reported
+                .iter()
+                .filter(|(trip, (start_date, _))| {
+                    owners
+                        .get(&(**trip, *start_date))
+                        .is_some_and(|(owner, _)| *owner != feed_idx)
+                })
+                .map(|(trip, _)| *trip)
+                .collect();
+        }
+    }
+
+    // merge the latest state of every feed and share it with the renderer
+    #[instrument(level = "debug", skip_all)]
+    fn publish(&mut self) {
+        self.claim_trips();
+        let mut arrivals: HashMap<StopId, Vec<Arrival>> = HashMap::new();
+        let mut signals: HashMap<RouteId, RouteSignals> = HashMap::new();
+        for feed in &self.feeds {
+            for (stop, predictions) in &feed.arrivals {
+                let predictions = predictions
+                    .iter()
+                    .filter(|prediction| !feed.superseded.contains(&prediction.trip));
+                arrivals
+                    .entry(self.ids.stops.resolve(*stop).clone())
+                    .or_default()
+                    .extend(predictions.map(|prediction| Arrival {
+                        route_id: self.ids.routes.resolve(prediction.route).clone(),
+                        trip_id: self.trips.resolve(prediction.trip).clone(),
+                        time: prediction.time,
+                        smoothed: prediction.time,
+                        service_pattern: prediction.service_pattern,
+                    }));
+            }
+            for (route_id, effects) in &feed.alert_effects {
+                let route_signals = signals.entry(route_id.to_owned()).or_default();
+                route_signals.alert_effects.extend(effects);
+            }
+            for (route, delays) in &feed.delays {
+                let route_signals = signals.entry(self.ids.routes.resolve(*route).clone()).or_default();
+                route_signals.delays.extend(delays);
+            }
+        }
+        for stop_arrivals in arrivals.values_mut() {
+            stop_arrivals.sort_by_key(|arrival| arrival.time);
+        }
+        let now = self.clock.posix_now();
+        let headway_gaps = status::headway_gaps(arrivals.values(), now);
+        for (route_id, gap) in &headway_gaps {
+            signals.entry(route_id.to_owned()).or_default().headway_gap = Some(*gap);
+        }
+        self.headway_gaps = headway_gaps
+            .into_iter()
+            .filter_map(|(route_id, gap)| Some((self.ids.routes.get(route_id.as_str())?, gap)))
+            .collect();
+
+        self.shared.arrivals.publish(arrivals, now);
+        self.shared.statuses.publish(signals);
+        let headways = self.feeds.iter_mut().flat_map(|feed| feed.headways.summaries(now)).collect();
+        self.shared.headways.publish(headways);
+        self.publish_snapshot();
+    }
+
+    fn publish_snapshot(&mut self) {
+        let scheduled = self.scheduled_vehicles();
+        let mut vehicles: HashMap<RouteId, Vec<VehicleStatus>> = HashMap::new();
+        for vehicle in self
+            .feeds
+            .iter()
+            .filter(|feed| !feed.needs_schedule())
+            .flat_map(|feed| feed.claimed_vehicles())
+            .chain(scheduled.iter().map(|(_, vehicle)| vehicle))
+        {
+            let segment = vehicle.segment.as_ref().filter(|_| !vehicle.stopped);
+            let route_id = self.ids.routes.resolve(vehicle.route);
+            vehicles
+                .entry(route_id.to_owned())
+                .or_default()
+                .push(VehicleStatus {
+                    trip_id: self.trips.resolve(vehicle.trip).to_owned(),
+                    route_id: route_id.to_owned(),
+                    stop_id: self.ids.stops.resolve(vehicle.stop).to_owned(),
+                    next_stop_id: vehicle.next_stop.map(|stop| self.ids.stops.resolve(stop).to_owned()),
+                    stopped: vehicle.stopped,
+                    scheduled: vehicle.scheduled,
+                    from_stop_id: segment.map(|segment| self.ids.stops.resolve(segment.from).to_owned()),
+                    departed: segment.map(|segment| segment.departed),
+                    arrival: segment.map(|segment| segment.arrival),
+                    direction: vehicle.direction,
+                    train_id: vehicle.nyct.as_ref().and_then(|nyct| nyct.train_id.clone()),
+                    assigned: vehicle.nyct.as_ref().map(|nyct| nyct.assigned),
+                    track: vehicle.track.clone(),
+                });
+        }
+        for route_vehicles in vehicles.values_mut() {
+            route_vehicles.sort_by(|a, b| a.trip_id.cmp(&b.trip_id));
+        }
+        // the same alert can appear in several feeds
+        let mut seen = HashSet::new();
+        let alerts = self
+            .feeds
+            .iter()
+            .flat_map(|feed| feed.alerts.iter())
+            .filter(|alert| seen.insert(&alert.id))
+            .cloned()
+            .collect();
+        let mut snapshot = self.shared.snapshot.0.write().unwrap();
+        // only routes whose trains changed are streamed, including routes that no longer have any
+        for route_id in snapshot.vehicles.keys().filter(|route_id| !vehicles.contains_key(*route_id)) {
+            self.shared.updates.send(FeedUpdate::Vehicles {
+                route_id: route_id.to_owned(),
+                vehicles: Vec::new(),
+            });
+        }
+        for (route_id, route_vehicles) in &vehicles {
+            if snapshot.vehicles.get(route_id) != Some(route_vehicles) {
+                self.shared.updates.send(FeedUpdate::Vehicles {
+                    route_id: route_id.to_owned(),
+                    vehicles: route_vehicles.clone(),
+                });
+            }
+        }
+        let (stale_after, controls_trip) = {
+            let controls = self.shared.controls.read().unwrap();
+            (controls.stale_after, controls.selected_trip.clone())
+        };
+        let now = self.clock.posix_now();
+        let feeds = self.feeds.iter().map(|feed| feed.age(feed.is_stale(now, stale_after))).collect();
+        let mut active_stops: Vec<ActiveStop> = self
+            .feeds
+            .iter()
+            .flat_map(|feed| feed.claimed_stops())
+            .map(|feed_entity| ActiveStop {
+                stop_id: self.ids.stops.resolve(feed_entity.stop).to_owned(),
+                route_id: self.ids.routes.resolve(feed_entity.route).to_owned(),
+                trip_id: self.trips.resolve(feed_entity.trip).to_owned(),
+                timestamp: feed_entity.timestamp,
+            })
+            .collect();
+        active_stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id).then(a.trip_id.cmp(&b.trip_id)));
+        let selected_trip = controls_trip.and_then(|trip_id| self.trip(trip_id.as_str()));
+        let trip_delays = self
+            .feeds
+            .iter()
+            .filter(|feed| !feed.needs_schedule())
+            .flat_map(|feed| {
+                feed.trip_delays
+                    .iter()
+                    .filter(|(trip, _)| !feed.superseded.contains(*trip))
+            })
+            .map(|(trip, delay)| (self.trips.resolve(*trip).to_owned(), *delay))
+            .collect::<HashMap<_, _>>()
+            .into();
+        // alerts posted to several feeds are re-published once
+        let mut seen = HashSet::new();
+        let mut entities = Vec::new();
+        for feed in self.feeds.iter().filter(|feed| !feed.needs_schedule()) {
+            for entity in feed.claimed_entities(&self.trips) {
+                if entity.alert.is_some() && !seen.insert(&entity.id) {
+                    continue;
+                }
+                let mut entity = entity.clone();
+                entity.id = format!("{:?}-{}", feed.feed, entity.id);
+                entities.push(entity);
+            }
+        }
+        let timestamp = self.feeds.iter().map(|feed| feed.fetched_at).max().unwrap_or_default();
+        *snapshot = Snapshot {
+            vehicles,
+            active_stops,
+            alerts,
+            feeds,
+            selected_trip,
+            trip_delays,
+            entities,
+            timestamp,
+        };
     }
 }
 
 impl FeedProcessor<'_> {
-    fn update(&mut self) -> Option<()> {
+    // `trips` resolves the trip_ids the feed was interned with, for everything that reports them
+    fn update(&mut self, trips: &Interner<TripHandle>) -> Option<()> {
         match self.queue.pop_front() {
             Some(FeedOp::Add(mut feed_entity)) => {
-                let route = self.routes.get(&feed_entity.route_id);
-
-                if route.is_none() {
-                    return Some(());
+                feed_entity.color = Some(self.ids.color(feed_entity.route));
+                // another feed reported the trip more recently, and tells of it instead
+                if !self.superseded.contains(&feed_entity.trip) {
+                    self.arrived(&feed_entity, trips);
                 }
-
-                let color = route.unwrap().color();
-
-                feed_entity.color = Some(color);
-                self.active_stops
-                    .insert(feed_entity.trip_id.to_owned(), feed_entity);
+                self.active_stops.insert(feed_entity.trip, feed_entity);
                 Some(())
             }
-            Some(FeedOp::Remove(trip_id)) => {
-                self.active_stops.remove(&trip_id);
+            Some(FeedOp::Remove(trip)) => {
+                let removed = self.active_stops.remove(&trip);
+                if let Some(feed_entity) = removed.filter(|_| !self.superseded.contains(&trip)) {
+                    self.updates.send(FeedUpdate::StopInactive {
+                        stop_id: self.ids.stops.resolve(feed_entity.stop).to_owned(),
+                        trip_id: trips.resolve(trip).to_owned(),
+                    });
+                    self.events.send(TrainEvent::TrainDeparted {
+                        trip_id: trips.resolve(trip).to_owned(),
+                        route_id: self.ids.routes.resolve(feed_entity.route).to_owned(),
+                        stop_id: self.ids.stops.resolve(feed_entity.stop).to_owned(),
+                        timestamp: self.fetched_at,
+                    });
+                }
                 Some(())
             }
             None => None,
         }
     }
 
-    pub fn fetch(&mut self, client: &Client) {
-        let response = client.get(self.feed.endpoint()).send().unwrap();
-        let msg = FeedMessage::decode(response.bytes().unwrap()).unwrap();
+    // everything that's told of a train stopping at a station
+    fn arrived(&mut self, feed_entity: &FeedEntity, trips: &Interner<TripHandle>) {
+        let stop_id = self.ids.stops.resolve(feed_entity.stop);
+        let route_id = self.ids.routes.resolve(feed_entity.route);
+        let trip_id = trips.resolve(feed_entity.trip);
+        self.stats.arrived(trip_id, stop_id);
+        if let Some(direction) = feed_entity.direction {
+            self.headways.arrived(route_id, direction, stop_id, trip_id, feed_entity.timestamp);
+        }
+        self.webhooks.emit(Event::StationArrival {
+            stop_id: stop_id.to_owned(),
+            route_id: route_id.to_owned(),
+            trip_id: trip_id.to_owned(),
+            timestamp: feed_entity.timestamp,
+        });
+        self.updates.send(FeedUpdate::StopActive {
+            stop_id: stop_id.to_owned(),
+            route_id: route_id.to_owned(),
+            trip_id: trip_id.to_owned(),
+            timestamp: feed_entity.timestamp,
+        });
+        self.events.send(TrainEvent::TrainArrived {
+            trip_id: trip_id.to_owned(),
+            route_id: route_id.to_owned(),
+            stop_id: stop_id.to_owned(),
+            timestamp: feed_entity.timestamp,
+        });
+    }
+
+    // in low bandwidth mode, feeds are only fetched while one of their routes is shown
+    fn low_bandwidth_due(&self, hidden_routes: &HashSet<RouteId>) -> bool {
+        let visible = self
+            .feed
+            .routes()
+            .iter()
+            .any(|route_id| !hidden_routes.contains(*route_id));
+        visible
+            && self
+                .last_fetch
+                .is_none_or(|at| self.clock.now().duration_since(at) >= LOW_BANDWIDTH_INTERVAL)
+    }
+
+    fn fetch_due(&self) -> bool {
+        self.next_fetch.is_none_or(|at| self.clock.now() >= at)
+    }
+
+    // time the next fetch for just after the feed should publish again, as of its header timestamp.
+    // `previous` is the header timestamp before the last fetch, if it hasn't advanced there's no point waiting a whole interval
+    fn schedule_fetch(&mut self, previous: u64) {
+        let wait = if self.fetched_at > previous {
+            let observed = Duration::from_secs(self.fetched_at - previous);
+            // a longer gap is a publish that was missed, or the feed lagging, rather than its cadence
+            if previous > 0 && observed <= MAX_PUBLISH_INTERVAL {
+                self.publish_interval = observed.max(MIN_PUBLISH_INTERVAL);
+            }
+            let next_publish = self.fetched_at as i64 + self.publish_interval.as_secs() as i64;
+            Duration::from_secs((next_publish - self.clock.posix_now()).max(0) as u64).max(FETCH_RETRY)
+        } else {
+            FETCH_RETRY
+        };
+        let jitter = Duration::from_millis(fastrand::u64(0..=FETCH_JITTER.as_millis() as u64));
+        self.next_fetch = Some(self.clock.now() + wait + jitter);
+    }
+
+    fn age(&self, stale: bool) -> FeedAge {
+        FeedAge {
+            feed: format!("{:?}", self.feed),
+            routes: self.feed.routes().iter().map(|route_id| RouteId::from(*route_id)).collect(),
+            updated: Some(self.fetched_at).filter(|at| *at > 0),
+            stale,
+        }
+    }
+
+    // feeds that have never been fetched aren't stale, they have nothing to show yet
+    fn is_stale(&self, now: i64, stale_after: Duration) -> bool {
+        self.fetched_at > 0 && now - self.fetched_at as i64 > stale_after.as_secs() as i64
+    }
+
+    // the feed's trains, less those another feed reported more recently
+    fn claimed_vehicles(&self) -> impl Iterator<Item = &Vehicle> {
+        self.vehicles
+            .iter()
+            .filter(|vehicle| !self.superseded.contains(&vehicle.trip))
+    }
+
+    fn claimed_stops(&self) -> impl Iterator<Item = &FeedEntity> {
+        self.active_stops
+            .values()
+            .filter(|feed_entity| !self.superseded.contains(&feed_entity.trip))
+    }
+
+    // down, or fetched without any vehicle positions
+    fn needs_schedule(&self) -> bool {
+        self.outage || (self.fetched_at > 0 && self.vehicles.is_empty())
+    }
+
+    // the feed's dataset, less the trips another feed reported more recently
+    fn claimed_entities<'s>(
+        &'s self,
+        trips: &'s Interner<TripHandle>,
+    ) -> impl Iterator<Item = &'s realtime::FeedEntity> {
+        self.entities.values().filter(|entity| {
+            let trip = entity
+                .trip_update
+                .as_ref()
+                .map(|trip_update| &trip_update.trip)
+                .or_else(|| entity.vehicle.as_ref().and_then(|vehicle| vehicle.trip.as_ref()));
+            trip.and_then(|trip| trips.get(trip.trip_id()))
+                .is_none_or(|trip| !self.superseded.contains(&trip))
+        })
+    }
+
+    // only the first failure of an outage is reported
+    fn report_outage(&mut self, err: anyhow::Error) {
+        warn!(
+            feed = ?self.feed,
+            decode = err.is::<prost::DecodeError>() || err.is::<serde_json::Error>(),
+            "Failed to fetch feed: {}",
+            err
+        );
+        if !self.outage {
+            self.outage = true;
+            self.webhooks.emit(Event::FeedOutage {
+                feed: format!("{:?}", self.feed),
+                error: err.to_string(),
+            });
+        }
+    }
+
+    // returns the size of the downloaded feed in bytes, endpoints that are local files are read instead
+    #[instrument(skip_all, fields(feed = ?self.feed, bytes, entities))]
+    pub fn fetch(&mut self, transport: &dyn Transport, trips: &mut Interner<TripHandle>) -> Result<usize> {
+        self.last_fetch = Some(self.clock.now());
+        let body = match local_path(&self.endpoint)? {
+            Some(path) => info_span!("read").in_scope(|| {
+                fs::read(&path)
+                    .map(Bytes::from)
+                    .with_context(|| format!("Failed to read the feed from {}", path.display()))
+            })?,
+            None => info_span!("download").in_scope(|| transport.get(&self.endpoint))?,
+        };
+        let fetched_at = self.fetched_at;
+        let size = self.process(body.clone(), trips)?;
+        if self.fetched_at > fetched_at {
+            if let Err(err) = self.cache_path().and_then(|path| {
+                fs::write(&path, &body).with_context(|| format!("Failed to write {}", path.display()))
+            }) {
+                warn!(feed = ?self.feed, "Failed to cache feed: {}", err);
+            }
+        }
+        Ok(size)
+    }
+
+    // processes the feed's cached message instead of downloading it
+    #[instrument(skip_all, fields(feed = ?self.feed, bytes, entities))]
+    pub fn replay(&mut self, trips: &mut Interner<TripHandle>) -> Result<usize> {
+        self.last_fetch = Some(self.clock.now());
+        let path = self.cache_path()?;
+        let body = fs::read(&path)
+            .with_context(|| format!("No cached copy of the feed at {}", path.display()))?;
+        self.process(Bytes::from(body), trips)
+    }
+
+    // processes a message generated from the timetable instead of downloading one
+    #[instrument(skip_all, fields(feed = ?self.feed, entities))]
+    pub fn simulate(&mut self, timetable: &Timetable, trips: &mut Interner<TripHandle>) {
+        self.last_fetch = Some(self.clock.now());
+        let msg = simulate::message(timetable, self.feed.routes(), self.clock.posix_now());
+        self.apply(msg, Extensions::default(), trips);
+    }
+
+    fn cache_path(&self) -> Result<PathBuf> {
+        let file = format!("{FEED_CACHE_DIR}/{:?}.pb", self.feed);
+        Ok(util::get_xdg()?.place_cache_file(file)?)
+    }
+
+    fn process(&mut self, body: Bytes, trips: &mut Interner<TripHandle>) -> Result<usize> {
+        let size = body.len();
+        tracing::Span::current().record("bytes", size);
+        let json = match self.format {
+            FeedFormat::Auto => json::is_json(&body),
+            FeedFormat::Protobuf => false,
+            FeedFormat::Json => true,
+        };
+        let (msg, extensions) = if json {
+            debug_span!("decode_json").in_scope(|| {
+                let value = json::parse(&body)?;
+                Ok::<_, serde_json::Error>((FeedMessage::deserialize(&value)?, Extensions::from_json(&value)?))
+            })?
+        } else {
+            debug_span!("decode").in_scope(|| {
+                Ok::<_, prost::DecodeError>((FeedMessage::decode(body.clone())?, Extensions::decode(body)?))
+            })?
+        };
+        self.apply(msg, extensions, trips);
+        Ok(size)
+    }
+
+    // every entity of the message goes to the entity log, less the trips another feed reported more recently
+    fn log_entities(&self, msg: &FeedMessage, extensions: &Extensions, trips: &Interner<TripHandle>) {
+        if !self.entity_log.enabled() {
+            return;
+        }
+        let processed_at = self.clock.posix_now();
+        for entity in &msg.entity {
+            let trip_update = entity.trip_update.as_ref();
+            let vehicle = entity.vehicle.as_ref();
+            let trip = trip_update
+                .map(|trip_update| &trip_update.trip)
+                .or_else(|| vehicle.and_then(|vehicle| vehicle.trip.as_ref()));
+            if trip
+                .and_then(|trip| trips.get(trip.trip_id()))
+                .is_some_and(|trip| self.superseded.contains(&trip))
+            {
+                continue;
+            }
+            let kind = if entity.is_deleted() {
+                "deleted"
+            } else if trip_update.is_some() {
+                "trip_update"
+            } else if vehicle.is_some() {
+                "vehicle"
+            } else if entity.alert.is_some() {
+                "alert"
+            } else {
+                continue;
+            };
+            let stop_id = vehicle
+                .and_then(|vehicle| vehicle.stop_id.as_deref())
+                .or_else(|| trip_update.and_then(|trip_update| trip_update.stop_time_update.first()?.stop_id.as_deref()));
+            let track = trip.zip(stop_id).and_then(|(trip, stop_id)| extensions.track(trip.trip_id(), stop_id));
+            let delay = trip_update.and_then(|trip_update| {
+                trip_update
+                    .delay
+                    .or_else(|| trip_update.stop_time_update.first()?.arrival.as_ref()?.delay)
+            });
+            let timestamp = vehicle
+                .and_then(|vehicle| vehicle.timestamp)
+                .or_else(|| trip_update.and_then(|trip_update| trip_update.timestamp))
+                .unwrap_or(self.fetched_at);
+            self.entity_log.log(EntityRow {
+                feed: format!("{:?}", self.feed),
+                entity_id: entity.id.clone(),
+                kind,
+                trip_id: trip.map(|trip| TripId::from(trip.trip_id())),
+                route_id: trip.and_then(|trip| trip.route_id.as_deref()).map(RouteId::from),
+                stop_id: stop_id.map(StopId::from),
+                status: vehicle
+                    .and_then(|vehicle| vehicle.current_status.map(|_| vehicle.current_status().as_str_name().to_owned())),
+                timestamp,
+                delay,
+                occupancy: vehicle.and_then(|vehicle| {
+                    vehicle.occupancy_status.map(|_| vehicle.occupancy_status().as_str_name().to_owned())
+                }),
+                track: track.and_then(|track| track.current().map(str::to_owned)),
+                track_changed: track.is_some_and(Track::changed),
+                processed_at,
+            });
+        }
+    }
+
+    // bring the dataset up to date with a message. a full dataset replaces it, skipping deleted entities,
+    // a differential message only replaces the entities it has and removes those it deletes
+    fn merge(&mut self, msg: FeedMessage, extensions: Extensions) {
+        match msg.header.incrementality() {
+            Incrementality::FullDataset => {
+                self.entities = msg
+                    .entity
+                    .into_iter()
+                    .filter(|entity| !entity.is_deleted())
+                    .map(|entity| (entity.id.clone(), entity))
+                    .collect();
+                self.extensions = extensions;
+            }
+            Incrementality::Differential => {
+                for entity in msg.entity {
+                    if entity.is_deleted() {
+                        self.entities.remove(&entity.id);
+                    } else {
+                        self.entities.insert(entity.id.clone(), entity);
+                    }
+                }
+                self.extensions.extend(extensions);
+                // the extensions of trips no entity reports anymore went with the entities deleted
+                let trip_ids: HashSet<&str> = self
+                    .entities
+                    .values()
+                    .flat_map(|entity| {
+                        let trip_update = entity.trip_update.as_ref().map(|trip_update| &trip_update.trip);
+                        let vehicle = entity.vehicle.as_ref().and_then(|vehicle| vehicle.trip.as_ref());
+                        trip_update.into_iter().chain(vehicle)
+                    })
+                    .map(|trip| trip.trip_id())
+                    .collect();
+                self.extensions.retain(|trip_id| trip_ids.contains(trip_id));
+            }
+        }
+    }
+
+    // trips are interned as they appear, stops and routes missing from the schedule are skipped
+    fn apply(&mut self, msg: FeedMessage, extensions: Extensions, trips: &mut Interner<TripHandle>) {
+        tracing::Span::current().record("entities", msg.entity.len());
+        self.outage = false;
         let timestamp = msg.header.timestamp();
 
         if self.fetched_at >= timestamp {
             return;
         }
+        // alerts already active on startup aren't announced
+        let announce_alerts = self.fetched_at > 0;
         self.fetched_at = timestamp;
+        self.log_entities(&msg, &extensions, trips);
+        self.merge(msg, extensions);
+        let extensions = &self.extensions;
 
-        let mut latest_trip_stop: HashMap<String, &String> = HashMap::new();
+        let mut latest_trip_stop: HashMap<TripHandle, StopHandle> = HashMap::new();
         let mut vehicle_updates = Vec::new();
-        for entity in msg.entity {
-            // get stopped vehicles
-            if let Some(vehicle_pos) = entity.vehicle {
-                if vehicle_pos.stop_id.is_some() && vehicle_pos.trip.is_some() {
-                    if let VehicleStopStatus::StoppedAt = vehicle_pos.current_status() {
-                        let trip = vehicle_pos.trip.as_ref().unwrap();
-                        let stop_id = vehicle_pos.stop_id().to_owned();
-                        // some stops are not public stations and are not part of the static schedule, e.g. R60S, R60N
-                        if let Some(stop) = self.stops.get(&stop_id) {
-                            let static_stop_id = if let Some(p_stop_id) = &stop.parent {
-                                p_stop_id
-                            } else {
-                                &stop.id
-                            };
+        let mut arrivals: HashMap<StopHandle, Vec<Prediction>> = HashMap::new();
+        let mut alert_effects: HashMap<RouteId, Vec<Effect>> = HashMap::new();
+        let mut alerts: Vec<ActiveAlert> = Vec::new();
+        let mut delays: HashMap<RouteHandle, Vec<i32>> = HashMap::new();
+        let mut trip_delays: HashMap<TripHandle, i32> = HashMap::new();
+        let mut vehicles: Vec<Vehicle> = Vec::new();
+        // upcoming stations of each trip
+        let mut trip_stops: HashMap<TripHandle, Vec<StopHandle>> = HashMap::new();
+        let mut stopped_at: HashMap<TripHandle, (StopHandle, i64)> = HashMap::new();
+        let mut cancelled: HashSet<TripHandle> = HashSet::new();
+        let mut reported: HashMap<TripHandle, (Option<u32>, u64)> = HashMap::new();
+        let mut report = |trip: TripHandle, descriptor: &realtime::TripDescriptor, at: Option<u64>| {
+            let at = at.filter(|at| *at > 0).unwrap_or(timestamp);
+            let entry = reported.entry(trip).or_insert((start_date(descriptor), at));
+            entry.1 = entry.1.max(at);
+        };
+        let now = self.clock.posix_now();
+        for entity in self.entities.values() {
+            if let Some(alert) = &entity.alert {
+                let routes: Vec<RouteId> = alert
+                    .informed_entity
+                    .iter()
+                    .filter_map(|selector| selector.route_id.as_deref().map(RouteId::from))
+                    .collect();
+                if is_active(alert, now) {
+                    for route_id in &routes {
+                        alert_effects
+                            .entry(route_id.to_owned())
+                            .or_default()
+                            .push(alert.effect());
+                    }
+                    alerts.push(ActiveAlert {
+                        id: entity.id.to_owned(),
+                        header: text(alert.header_text.as_ref()),
+                        description: text(alert.description_text.as_ref()),
+                        effect: alert.effect().as_str_name().to_owned(),
+                        routes: routes.clone(),
+                    });
+                }
+
+                if self.seen_alerts.insert(entity.id.to_owned()) && announce_alerts {
+                    self.webhooks.emit(Event::AlertPosted {
+                        id: entity.id.to_owned(),
+                        header: text(alert.header_text.as_ref()),
+                        routes,
+                    });
+                }
+            }
+            // get vehicles, stopped vehicles also mark their stop active
+            if let Some(vehicle_pos) = &entity.vehicle {
+                if let (Some(trip), Some(stop_id)) = (vehicle_pos.trip.as_ref(), vehicle_pos.stop_id.as_deref()) {
+                    // some stops are not public stations and are not part of the static schedule, e.g. R60S, R60N
+                    if let (Some(station), Some(route)) =
+                        (self.ids.station(stop_id), self.ids.routes.get(trip.route_id()))
+                    {
+                        let trip_handle = trips.intern(trip.trip_id());
+                        report(trip_handle, trip, vehicle_pos.timestamp);
+                        let stopped = vehicle_pos.current_status() == VehicleStopStatus::StoppedAt;
+                        let nyct = extensions.trip(trip.trip_id());
+                        let direction = nyct
+                            .and_then(|nyct| nyct.direction)
+                            .or_else(|| Direction::from_platform(stop_id));
+                        let track = extensions.track(trip.trip_id(), stop_id).cloned();
+                        vehicles.push(Vehicle {
+                            trip: trip_handle,
+                            route,
+                            stop: station,
+                            next_stop: None,
+                            stopped,
+                            scheduled: false,
+                            segment: None,
+                            direction,
+                            nyct: nyct.cloned(),
+                            track,
+                        });
+                        if stopped {
+                            stopped_at.insert(trip_handle, (station, vehicle_pos.timestamp() as i64));
                             vehicle_updates.push(FeedEntity {
-                                trip_id: trip.trip_id().to_owned(),
+                                trip: trip_handle,
                                 timestamp: vehicle_pos.timestamp(),
-                                route_id: trip.route_id().to_owned(),
-                                stop_id: static_stop_id,
+                                route,
+                                stop: station,
                                 color: None,
+                                delay: None,
+                                occupancy: vehicle_pos
+                                    .occupancy_status
+                                    .map(|_| vehicle_pos.occupancy_status()),
+                                direction,
                             });
                         }
                     }
                 }
             }
             // get the latest stop_time_update for each trip, which contains the next stop being approached or stopped at
-            if let Some(trip_update) = entity.trip_update {
+            if let Some(trip_update) = &entity.trip_update {
                 let trip_id = trip_update.trip.trip_id();
-                if let Some(stop_update) = trip_update.stop_time_update.first() {
-                    let stop_id = stop_update.stop_id();
-                    if let Some(stop) = self.stops.get(stop_id) {
-                        let static_stop_id = if let Some(p_stop_id) = &stop.parent {
-                            p_stop_id
-                        } else {
-                            &stop.id
-                        };
-
-                        latest_trip_stop.insert(trip_id.into(), static_stop_id);
+                if let Some(validator) = self.validator {
+                    for discrepancy in validator.check(trip_update) {
+                        self.stats.discrepancy(discrepancy);
+                    }
+                }
+                let trip = trips.intern(trip_id);
+                report(trip, &trip_update.trip, trip_update.timestamp);
+                let route = self.ids.routes.get(trip_update.trip.route_id());
+                if matches!(
+                    trip_update.trip.schedule_relationship(),
+                    ScheduleRelationship::Canceled | ScheduleRelationship::Deleted
+                ) {
+                    if !self.cancelled.contains(&trip) {
+                        self.events.send(TrainEvent::TripCancelled {
+                            trip_id: TripId::from(trip_id),
+                            route_id: RouteId::from(trip_update.trip.route_id()),
+                        });
+                    }
+                    cancelled.insert(trip);
+                    // none of its predictions will come true
+                    continue;
+                }
+                let delay = trip_update.delay.or_else(|| {
+                    trip_update
+                        .stop_time_update
+                        .first()
+                        .and_then(|stop_update| stop_update.arrival.as_ref())
+                        .and_then(|event| event.delay)
+                });
+                if let Some(delay) = delay {
+                    trip_delays.insert(trip, delay);
+                    if let Some(route) = route {
+                        delays.entry(route).or_default().push(delay);
+                    }
+                }
+                if let Some(station) = trip_update
+                    .stop_time_update
+                    .first()
+                    .and_then(|stop_update| self.ids.station(stop_update.stop_id()))
+                {
+                    latest_trip_stop.insert(trip, station);
+                }
+
+                let stations = trip_stops.entry(trip).or_default();
+                for (idx, stop_update) in trip_update.stop_time_update.iter().enumerate() {
+                    let Some(station) = self.ids.station(stop_update.stop_id()) else {
+                        continue;
+                    };
+                    stations.push(station);
+                    let time = stop_update
+                        .arrival
+                        .as_ref()
+                        .or(stop_update.departure.as_ref())
+                        .and_then(|event| event.time);
+                    if let (Some(route), Some(time)) = (route, time) {
+                        // told by the stops the train has left, or failing that the track it's due on
+                        let service_pattern = self
+                            .timetable
+                            .and_then(|timetable| {
+                                let rest = trip_update.stop_time_update[idx..].iter().map(|update| update.stop_id());
+                                timetable.service_pattern(rest)
+                            })
+                            .or_else(|| {
+                                extensions
+                                    .track(trip_id, stop_update.stop_id())
+                                    .and_then(|track| track.service_pattern())
+                            });
+                        arrivals.entry(station).or_default().push(Prediction {
+                            route,
+                            trip,
+                            time,
+                            service_pattern,
+                        });
                     }
                 }
             }
         }
+        for vehicle in &mut vehicles {
+            vehicle.next_stop = trip_stops.get(&vehicle.trip).and_then(|stations| {
+                let idx = stations.iter().position(|station| *station == vehicle.stop)?;
+                stations.get(idx + 1).copied()
+            });
+            if vehicle.stopped {
+                continue;
+            }
+            // in transit from the station the train was last seen stopped at
+            vehicle.segment = self
+                .last_stops
+                .get(&vehicle.trip)
+                .filter(|(from, _)| *from != vehicle.stop)
+                .and_then(|(from, departed)| {
+                    let arrival = arrivals
+                        .get(&vehicle.stop)?
+                        .iter()
+                        .find(|prediction| prediction.trip == vehicle.trip)?
+                        .time;
+                    Some(Segment {
+                        from: *from,
+                        departed: *departed,
+                        arrival,
+                    })
+                });
+        }
+        // trips that left the feed are forgotten
+        self.last_stops
+            .retain(|trip_id, _| trip_stops.contains_key(trip_id));
+        self.last_stops.extend(stopped_at);
+        self.cancelled = cancelled;
+        self.reported = reported;
+        self.vehicles = vehicles;
+        self.arrivals = arrivals;
+        self.alert_effects = alert_effects;
+        self.alerts = alerts;
+        self.delays = delays;
 
         // only get vehicles that are at the current stop for the trip
         // vehicle positions are only updated when they stop at a stop, so remove vehicles that are in transit to the current stop for the trip
-        let current_stopped: HashMap<String, FeedEntity> = vehicle_updates
+        let current_stopped: HashMap<TripHandle, FeedEntity> = vehicle_updates
             .into_iter()
             .filter(|fe| {
-                if let Some(latest_stop_id) = latest_trip_stop.get(&fe.trip_id) {
-                    *latest_stop_id == fe.stop_id
+                if let Some(latest_stop) = latest_trip_stop.get(&fe.trip) {
+                    *latest_stop == fe.stop
                 } else {
                     false
                 }
             })
-            .fold(HashMap::new(), |mut acc, fe| {
-                acc.insert(fe.trip_id.to_owned(), fe);
+            .fold(HashMap::new(), |mut acc, mut fe| {
+                fe.delay = trip_delays.get(&fe.trip).copied();
+                acc.insert(fe.trip, fe);
                 acc
             });
+        self.trip_delays = trip_delays;
 
         // queue remove old stops from state
-        let current_trips: Vec<_> = self.active_stops_current.keys().map(|k| k.to_owned()).collect();
+        let current_trips: Vec<_> = self.active_stops_current.keys().copied().collect();
         for prev in current_trips {
-            if current_stopped.contains_key(&prev) == false {
+            if !current_stopped.contains_key(&prev) {
                 self.active_stops_current.remove(&prev);
-                self.queue
-                    .push_back(FeedOp::Remove(prev.to_owned()));
+                self.queue.push_back(FeedOp::Remove(prev));
             }
         }
 
         // queue add new stops to state
         for entity in current_stopped.into_values() {
-            if self.active_stops_current.contains_key(&entity.trip_id) == false {
-                self.active_stops_current.insert(entity.trip_id.clone(), true);
+            if self.active_stops_current.insert(entity.trip, true).is_none() {
                 self.queue.push_back(FeedOp::Add(entity));
             }
         }
     }
 }
+
+// the file a feed endpoint points at, either a file:// URL or a path without any scheme
+pub fn local_path(endpoint: &str) -> Result<Option<PathBuf>> {
+    if endpoint.starts_with("file:") {
+        let url = reqwest::Url::parse(endpoint).with_context(|| format!("Invalid feed endpoint {endpoint}"))?;
+        let path = url
+            .to_file_path()
+            .map_err(|_| anyhow!("Feed endpoint {endpoint} isn't a local file"))?;
+        return Ok(Some(path));
+    }
+    Ok((!endpoint.contains("://")).then(|| PathBuf::from(endpoint)))
+}
+
+// dots evenly spaced along a train's trail, fading and shrinking towards its oldest end
+fn trail_instances(trail: &VecDeque<Coord<f32>>, color: [f32; 3]) -> Vec<StopInstance> {
+    let mut dots = Vec::new();
+    for (from, to) in trail.iter().zip(trail.iter().skip(1)) {
+        let delta = *to - *from;
+        let steps = ((delta.x.hypot(delta.y) / TRAIL_SPACING).ceil() as usize).max(1);
+        dots.extend((0..steps).map(|step| *from + delta * (step as f32 / steps as f32)));
+    }
+    // the newest end is the one worth keeping
+    let dots = &dots[dots.len().saturating_sub(MAX_TRAIL_DOTS)..];
+    let count = dots.len().max(1) as f32;
+    dots.iter()
+        .enumerate()
+        .map(|(idx, coord)| {
+            let t = (idx + 1) as f32 / count;
+            StopInstance {
+                position: [coord.x, coord.y, 0.0],
+                color: color.map(|channel| channel * (TRAIL_FADE + (1.0 - TRAIL_FADE) * t)),
+                scale: -0.85 + 0.35 * t,
+            }
+        })
+        .collect()
+}
+
+// a trip descriptor's start date as YYYYMMDD, None when it's missing or malformed
+fn start_date(trip: &realtime::TripDescriptor) -> Option<u32> {
+    trip.start_date.as_deref()?.parse().ok()
+}
+
+// the first translation of an alert's text
+fn text(text: Option<&TranslatedString>) -> String {
+    text.and_then(|text| text.translation.first())
+        .map(|translation| translation.text.to_owned())
+        .unwrap_or_default()
+}
+
+// alerts without active periods are always active, open ended periods are 0 or missing
+fn is_active(alert: &Alert, now: i64) -> bool {
+    let now = now as u64;
+    alert.active_period.is_empty()
+        || alert.active_period.iter().any(|period| {
+            period.start.unwrap_or(0) <= now && (period.end.unwrap_or(0) == 0 || now <= period.end())
+        })
+}
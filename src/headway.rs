@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use crate::ids::{RouteId, StopId, TripId};
+
+// only headways observed this recently are summarized, in seconds
+const HEADWAY_WINDOW: i64 = 30 * 60;
+// trains arriving this soon after the one before them, in seconds, are bunched
+pub const BUNCHING_THRESHOLD: i64 = 2 * 60;
+// trains arriving this long after the one before them, in seconds, leave a gap
+pub const LONG_GAP_THRESHOLD: i64 = 15 * 60;
+
+// which way a train runs, from the N or S suffix of NYCT platform stop_ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    North,
+    South,
+}
+
+impl Direction {
+    pub fn from_platform(stop_id: &str) -> Option<Self> {
+        match stop_id.chars().last()? {
+            'N' => Some(Self::North),
+            'S' => Some(Self::South),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::North => "Uptown",
+            Self::South => "Downtown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeadwayFlag {
+    Bunching,
+    LongGap,
+}
+
+impl HeadwayFlag {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Bunching => "bunching",
+            Self::LongGap => "long gap",
+        }
+    }
+}
+
+// time between trains of a route running one way, observed at its stations over the last HEADWAY_WINDOW
+#[derive(Debug, Clone, Serialize)]
+pub struct Headway {
+    pub route_id: RouteId,
+    pub direction: Direction,
+    // in seconds
+    pub median: i64,
+    pub shortest: i64,
+    pub longest: i64,
+    // how many headways were observed
+    pub samples: usize,
+    // a long gap is flagged over bunching, it's the one riders wait through
+    pub flag: Option<HeadwayFlag>,
+}
+
+// the time between each of `times` and the next, sorting them in place
+pub fn gaps(times: &mut [i64]) -> impl Iterator<Item = i64> + '_ {
+    times.sort();
+    times.windows(2).map(|pair| pair[1] - pair[0])
+}
+
+// the middle of `values`, sorting them in place
+pub fn median(values: &mut [i64]) -> Option<i64> {
+    values.sort();
+    values.get(values.len() / 2).copied()
+}
+
+// headways from consecutive arrivals of a route's trains at the same station, running the same way
+#[derive(Default)]
+pub struct HeadwayTracker {
+    // the trains to arrive within HEADWAY_WINDOW and the POSIX timestamp of each, oldest first
+    arrivals: HashMap<(RouteId, Direction, StopId), VecDeque<(TripId, i64)>>,
+}
+
+impl HeadwayTracker {
+    pub fn arrived(&mut self, route_id: &RouteId, direction: Direction, stop_id: &StopId, trip_id: &TripId, timestamp: u64) {
+        let timestamp = timestamp as i64;
+        let arrivals = self.arrivals.entry((route_id.to_owned(), direction, stop_id.to_owned())).or_default();
+        // a train reported stopped again, or positions arriving out of order
+        let repeated = |(previous_trip, previous_at): &(TripId, i64)| previous_trip == trip_id || timestamp <= *previous_at;
+        if arrivals.back().is_some_and(repeated) {
+            return;
+        }
+        arrivals.push_back((trip_id.to_owned(), timestamp));
+    }
+
+    // summaries by route and direction, forgetting arrivals that have left the window
+    pub fn summaries(&mut self, now: i64) -> Vec<Headway> {
+        let since = now - HEADWAY_WINDOW;
+        self.arrivals.retain(|_, arrivals| {
+            while arrivals.front().is_some_and(|(_, at)| *at < since) {
+                arrivals.pop_front();
+            }
+            !arrivals.is_empty()
+        });
+        let mut observed: HashMap<(&RouteId, Direction), Vec<i64>> = HashMap::new();
+        for ((route_id, direction, _), arrivals) in &self.arrivals {
+            let mut times: Vec<i64> = arrivals.iter().map(|(_, at)| *at).collect();
+            observed.entry((route_id, *direction)).or_default().extend(gaps(&mut times));
+        }
+        observed
+            .into_iter()
+            .filter_map(|((route_id, direction), mut seconds)| {
+                let median = median(&mut seconds)?;
+                let (shortest, longest) = (seconds[0], seconds[seconds.len() - 1]);
+                let flag = if longest > LONG_GAP_THRESHOLD {
+                    Some(HeadwayFlag::LongGap)
+                } else if shortest < BUNCHING_THRESHOLD {
+                    Some(HeadwayFlag::Bunching)
+                } else {
+                    None
+                };
+                Some(Headway {
+                    route_id: route_id.to_owned(),
+                    direction,
+                    median,
+                    shortest,
+                    longest,
+                    samples: seconds.len(),
+                    flag,
+                })
+            })
+            .collect()
+    }
+}
+
+// the latest headways across all feeds, in route_id and direction order
+#[derive(Clone, Default)]
+pub struct Headways(Arc<RwLock<Vec<Headway>>>);
+
+impl Headways {
+    pub fn publish(&self, mut headways: Vec<Headway>) {
+        headways.sort_by(|a, b| a.route_id.cmp(&b.route_id).then(a.direction.cmp(&b.direction)));
+        *self.0.write().unwrap() = headways;
+    }
+
+    pub fn all(&self) -> Vec<Headway> {
+        self.0.read().unwrap().clone()
+    }
+}
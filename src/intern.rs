@@ -0,0 +1,139 @@
+use geo::Coord;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::entities::{EntityCollection, ParentTable, Route, Stop};
+use crate::ids::{RouteId, StopId, TripId};
+
+// an id's index in an Interner, copied, hashed and compared as an integer instead of a string
+pub trait Handle: Copy + Eq + Hash {
+    type Id: Clone + Eq + Hash + Borrow<str> + for<'a> From<&'a str>;
+
+    fn from_index(index: usize) -> Self;
+    fn index(self) -> usize;
+}
+
+macro_rules! handle {
+    ($name:ident, $id:ty) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(u32);
+
+        impl Handle for $name {
+            type Id = $id;
+
+            fn from_index(index: usize) -> Self {
+                Self(index as u32)
+            }
+
+            fn index(self) -> usize {
+                self.0 as usize
+            }
+        }
+    };
+}
+
+handle!(StopHandle, StopId);
+handle!(RouteHandle, RouteId);
+handle!(TripHandle, TripId);
+
+// each distinct id is stored once, handles are given out in the order ids are first interned
+pub struct Interner<H: Handle> {
+    ids: Vec<H::Id>,
+    handles: HashMap<H::Id, H>,
+}
+
+impl<H: Handle> Default for Interner<H> {
+    fn default() -> Self {
+        Self {
+            ids: Vec::new(),
+            handles: HashMap::new(),
+        }
+    }
+}
+
+impl<H: Handle> Interner<H> {
+    pub fn intern(&mut self, id: &str) -> H {
+        if let Some(handle) = self.handles.get(id) {
+            return *handle;
+        }
+        let handle = H::from_index(self.ids.len());
+        let id = H::Id::from(id);
+        self.ids.push(id.clone());
+        self.handles.insert(id, handle);
+        handle
+    }
+
+    // without interning ids that haven't been
+    pub fn get(&self, id: &str) -> Option<H> {
+        self.handles.get(id).copied()
+    }
+
+    pub fn resolve(&self, handle: H) -> &H::Id {
+        &self.ids[handle.index()]
+    }
+}
+
+// the static schedule's stop and route ids, interned when it's loaded, with what the feeds look up by them.
+// stops are in stop_id order and routes in route_id order, so handles sort as their ids do
+pub struct ScheduleIds {
+    pub stops: Interner<StopHandle>,
+    pub routes: Interner<RouteHandle>,
+    // each stop_id's parent station, stations are their own parent
+    parents: ParentTable,
+    coords: Vec<Coord<f32>>,
+    route_colors: Vec<[f32; 3]>,
+}
+
+impl ScheduleIds {
+    pub fn new(
+        stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+        routes: &EntityCollection<HashMap<RouteId, Route>>,
+    ) -> Self {
+        let mut stop_ids = Interner::default();
+        let coords = stops
+            .values()
+            .map(|stop| {
+                stop_ids.intern(stop.id.as_str());
+                stop.coord
+            })
+            .collect();
+        // stops are interned in the order the table counts them in
+        let parents = ParentTable::new(stops);
+        let mut sorted: Vec<&Route> = routes.values().collect();
+        sorted.sort_by(|a, b| a.id().cmp(b.id()));
+        let mut route_ids = Interner::default();
+        let route_colors = sorted
+            .into_iter()
+            .map(|route| {
+                route_ids.intern(route.id().as_str());
+                route.color()
+            })
+            .collect();
+        Self {
+            stops: stop_ids,
+            routes: route_ids,
+            parents,
+            coords,
+            route_colors,
+        }
+    }
+
+    // the parent station of a platform stop_id from a feed, stops missing from the schedule have none
+    pub fn station(&self, stop_id: &str) -> Option<StopHandle> {
+        self.parents.get(stop_id).map(StopHandle::from_index)
+    }
+
+    pub fn coord(&self, stop: StopHandle) -> Coord<f32> {
+        self.coords[stop.index()]
+    }
+
+    pub fn color(&self, route: RouteHandle) -> [f32; 3] {
+        self.route_colors[route.index()]
+    }
+
+    // the 1-based index the renderer draws the route's bullet with, its routes are in route_id order too
+    pub fn bullet(&self, route: RouteHandle) -> u32 {
+        route.index() as u32 + 1
+    }
+}
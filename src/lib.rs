@@ -1,5 +1,36 @@
 pub mod proto;
+pub mod accessibility;
+pub mod annotations;
+pub mod board;
+pub mod cache;
+pub mod complexes;
+pub mod config;
+pub mod doctor;
 pub mod entities;
+pub mod entity_log;
+pub mod ids;
+pub mod intern;
+pub mod motion;
+pub mod nearby;
+pub mod nyct;
+pub mod planner;
 pub mod util;
+pub mod validate;
 pub mod render;
+pub mod ridership;
 pub mod feed;
+pub mod headway;
+pub mod palette;
+pub mod playback;
+pub mod recorder;
+pub mod serve;
+pub mod simulate;
+pub mod snapshot;
+pub mod status;
+pub mod stats;
+pub mod style;
+pub mod tiles;
+pub mod timetable;
+#[cfg(feature = "tray")]
+pub mod tray;
+pub mod webhook;
@@ -1,83 +1,388 @@
-use feed::FeedManager;
+use feed::{FeedManager, FeedSource, SharedFeedState};
+use ids::{RouteId, StopId};
 use lyon::geom::point;
 use lyon::path::Path;
 use lyon::tessellation::{
-    BuffersBuilder, FillOptions, FillTessellator, FillVertex, StrokeOptions, StrokeTessellator,
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, LineJoin, StrokeOptions, StrokeTessellator,
     StrokeVertex, VertexBuffers,
 };
 use render::stop::StopInstance;
+use rayon::prelude::*;
 use std::sync::mpsc::{channel, TryRecvError};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::time::Duration;
-use tokio;
-use std::collections::HashMap;
-
-use lyon;
 
 use winit::{
     dpi::PhysicalSize,
     event::*,
     event_loop::EventLoop,
     keyboard::{KeyCode, PhysicalKey},
-    window::WindowBuilder,
+    window::Window,
 };
 
-use anyhow::Result;
-use env_logger;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan, fmt::writer::BoxMakeWriter, EnvFilter};
 use geo::{
-    BoundingRect, Coord, CoordsIter, MultiPolygon, Point, Rect, Translate, TriangulateEarcut,
+    BoundingRect, Coord, CoordsIter, Geometry, LineString, MultiPolygon, Point, Rect, Simplify, Translate,
+    TriangulateEarcut,
 };
 
-use entities::{GTFSData, CollectibleEntity, Stop};
+use cache::{ATLAS_CACHE, SCHEDULE_CACHE};
+use config::CaptureSink;
+use entity_log::{EntityLog, LogFormat};
+use palette::PaletteConfig;
+use entities::{
+    CollectibleEntity, EntityCollection, Features, Properties, Route, Schedule, ShapeIndex,
+    ShapeRoutes, ShapeSeq, Stop, Transfers,
+};
+use render::picker::StopPicker;
+use render::atlas::{push_triangles, Detail, GeometryAtlas};
+use render::layer::{
+    BOROS_LAYER, PARKS_LAYER, SHAPES_LAYER, STOPS_LAYER, TRANSFERS_LAYER, VEHICLES_LAYER,
+    WATER_LAYER,
+};
 use render::{CameraUniform, Vertex};
+use style::{StyleRule, StyleTarget};
+use webhook::Webhooks;
+use validate::ScheduleValidator;
+use util::latest;
 use util::static_data::{
-    self, BOROUGH_BOUNDARIES_STATIC, COASTLINE_STATIC, GTFS_STATIC, PARKS_STATIC,
+    self, BOROUGH_BOUNDARIES_STATIC, COASTLINE_STATIC, GTFS_STATIC, PARKS_STATIC, RIDERSHIP_STATIC,
+    STATIONS_STATIC,
+};
+
+use nyc_subway::{
+    accessibility, annotations, board, cache, complexes, config, doctor, entities, entity_log, feed,
+    ids, palette, planner, recorder, render, ridership, serve, style, tiles, util, validate, webhook,
 };
+#[cfg(feature = "tray")]
+use nyc_subway::tray;
+
+// how far apart routes sharing track are drawn, in meters
+const LINE_WIDTH: f32 = 70.;
+// route lines are the same width on screen however far the map is zoomed, in pixels
+const LINE_PIXELS: f32 = 5.;
+// express runs are drawn with a stripe of this width down the middle of their line, paler than the route's color
+const EXPRESS_STRIPE_PIXELS: f32 = 2.;
+const EXPRESS_STRIPE_TINT: f32 = 0.6;
+// how far from a shape a station at the end of an express run can be, in meters
+const EXPRESS_SEARCH_RADIUS: f32 = 300.;
+const BACKGROUND_COLOR: [f32; 3] = [0.05, 0.05, 0.05];
+const BORO_COLOR: [f32; 3] = [0.3, 0.3, 0.3];
+const PARK_COLOR: [f32; 3] = [0.2, 0.3, 0.2];
+const WATER_COLOR: [f32; 3] = [0.08, 0.12, 0.18];
+const SHORE_COLOR: [f32; 3] = [0.14, 0.2, 0.28];
+const SHORE_WIDTH: f32 = 40.;
+// how far open water extends past the shoreline
+const WATER_MARGIN: f32 = 20_000.;
+// distance of annotation markers from their station, in meters
+const MARKER_OFFSET: f32 = 150.;
+const TRANSFER_COLOR: [f32; 3] = [0.75, 0.75, 0.75];
+const TRANSFER_WIDTH: f32 = 30.;
+// in $XDG_STATE_HOME, written instead of the terminal while the board is shown
+const LOG_FILE: &str = "board.log";
+// how far transfer arcs bow out, as a fraction of their length
+const TRANSFER_BOW: f32 = 0.25;
+// half the side of the quad trains' route bullets are drawn on, in meters
+const BULLET_EXTENT: f32 = 200.;
+// bump when the atlas is built differently from the same inputs, so cached ones are rebuilt
+const ATLAS_REVISION: u32 = 3;
 
-mod entities;
-mod feed;
-mod proto;
-mod render;
-mod util;
+// the stations at either end of each stretch the trips along a shape run express, by shape_id
+type ExpressRuns = HashMap<String, Vec<(Coord<f32>, Coord<f32>)>>;
+
+#[derive(Parser)]
+#[command(version, about = "Live map of the NYC subway")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Download static data again even if the cached copies are fresh
+    #[arg(long)]
+    force_refresh: bool,
+    /// Check realtime trips against the schedule, logging and counting discrepancies
+    #[arg(long)]
+    strict: bool,
+    /// Only poll feeds for visible routes, and less often, for metered connections
+    #[arg(long)]
+    low_bandwidth: bool,
+    /// Skip the network, showing the last cached feeds over the static data already downloaded
+    #[arg(long, conflicts_with = "force_refresh")]
+    offline: bool,
+    /// Run trains from the schedule instead of the realtime feeds, with time passing SPEED times faster
+    #[arg(
+        long,
+        value_name = "SPEED",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        conflicts_with = "offline",
+        value_parser = positive_speed
+    )]
+    simulate: Option<f64>,
+    /// Stream feed updates over a WebSocket at ws://<ADDR>/stream alongside the window
+    #[arg(long, value_name = "ADDR")]
+    stream: Option<SocketAddr>,
+    /// Capture rendered frames from launch, as numbered PNGs or a video encoded by ffmpeg
+    #[arg(long, value_name = "SINK")]
+    capture: Option<CaptureSink>,
+    /// Capture every Nth rendered frame
+    #[arg(long, value_name = "N")]
+    capture_every: Option<u32>,
+    /// Time the map, bloom and GUI render passes on the GPU, shown in the status bar and logged every few seconds
+    #[arg(long)]
+    gpu_timing: bool,
+    /// Append each train arrival the feeds report to hourly files under DIR
+    #[arg(long, value_name = "DIR")]
+    log_entities: Option<PathBuf>,
+    /// Format of the --log-entities files, parquet needs a build with the parquet feature
+    #[arg(long, value_enum, default_value_t = LogFormat::Csv, requires = "log_entities")]
+    log_format: LogFormat,
+    /// Print line-oriented text without box drawing, colors or progress bars, for screen readers and scripts
+    #[arg(long, global = true)]
+    plain: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check config, static data, network and GPU, and explain what to fix
+    Doctor,
+    /// Poll the feeds without a window and serve their state as JSON over HTTP
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+        /// Also write the trains and active stations to PATH as GeoJSON, every --geojson-every seconds
+        #[arg(long, value_name = "PATH")]
+        geojson: Option<PathBuf>,
+        #[arg(long, value_name = "SECS", default_value_t = 30, requires = "geojson")]
+        geojson_every: u64,
+        /// Also serve a web page that shows the trains on a map, at /
+        #[arg(long)]
+        viewer: bool,
+    },
+    /// Poll the feeds without a window and show a departure board for each station in the terminal
+    Board {
+        /// Stations by stop_id, or by part of their name
+        #[arg(required = true)]
+        stations: Vec<String>,
+    },
+    /// Write the route lines, stations, boroughs and parks as Mapbox Vector Tiles, to DIR/{z}/{x}/{y}.mvt
+    Tiles {
+        #[arg(value_name = "DIR")]
+        out: PathBuf,
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=22))]
+        min_zoom: u8,
+        #[arg(long, default_value_t = 14, value_parser = clap::value_parser!(u8).range(0..=22))]
+        max_zoom: u8,
+    },
+}
+
+// time can't stand still or run backwards
+fn positive_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        Ok(_) => Err("must be greater than 0".to_owned()),
+        Err(err) => Err(err.to_string()),
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    let cli = Cli::parse();
+    if cli.plain {
+        util::set_plain();
+    }
+    // the board's TUI takes over the terminal, anything logged goes to a file rather than through it.
+    // otherwise logs go to stderr, leaving stdout to what commands print
+    let tui = matches!(cli.command, Some(Command::Board { .. })) && !cli.plain;
+    let log_writer = if tui {
+        let path = util::get_xdg()?.place_state_file(LOG_FILE)?;
+        let file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create the log file '{}'", path.display()))?;
+        BoxMakeWriter::new(Mutex::new(file))
+    } else {
+        BoxMakeWriter::new(std::io::stderr)
+    };
+    // RUST_LOG picks what's shown, as with env_logger, span timings are reported when each span closes
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::ERROR.into())
+                .from_env_lossy(),
+        )
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(!cli.plain && !tui)
+        .with_writer(log_writer)
+        .init();
+    if let Some(Command::Doctor) = &cli.command {
+        if !doctor::run().await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = config::Config::load()?;
+    let annotations = Arc::new(annotations::Annotations::load()?);
     let xdg = util::get_xdg()?;
-    if static_data::shoud_fetch(GTFS_STATIC) {
-        let gtfs_zip = static_data::fetch(GTFS_STATIC, None).await?;
-        static_data::unzip(gtfs_zip).await?;
+    let force = cli.force_refresh;
+    let offline = cli.offline;
+    let source = if offline {
+        FeedSource::Cache
+    } else if cli.simulate.is_some() {
+        FeedSource::Simulation
+    } else {
+        FeedSource::Network
+    };
+    if let Some(speed) = cli.simulate {
+        util::accelerate(speed);
+    }
+    let should_fetch = |endpoint| !offline && (force || static_data::shoud_fetch(endpoint));
+    if should_fetch(GTFS_STATIC) {
+        // only unpack a new schedule
+        if let Some(gtfs_zip) = static_data::fetch(GTFS_STATIC, None, force).await? {
+            static_data::unzip(gtfs_zip).await?;
+        }
+    }
+    // parsing the schedule is slow, it's cached against the zip it came from
+    let gtfs_zip = xdg
+        .find_cache_file(GTFS_STATIC.1)
+        .map(|path| fs::read(&path).with_context(|| format!("Failed to read '{}'", path.display())))
+        .transpose()?;
+    let schedule_key = gtfs_zip.as_deref().map(cache::schedule_key);
+    let validator = if cli.strict {
+        Some(ScheduleValidator::load()?)
+    } else {
+        None
+    };
+    let entity_log = match &cli.log_entities {
+        Some(dir) => EntityLog::spawn(dir.clone(), cli.log_format)?,
+        None => EntityLog::default(),
+    };
+
+    // serving only needs the schedule
+    if let Some(Command::Serve {
+        addr,
+        geojson,
+        geojson_every,
+        viewer,
+    }) = &cli.command
+    {
+        let schedule = cache::load_or_build(SCHEDULE_CACHE, schedule_key.as_deref(), Schedule::load)?;
+        let geojson = geojson.clone().map(|path| (path, Duration::from_secs(*geojson_every)));
+        return serve::run(*addr, schedule, config, validator, source, geojson, entity_log, *viewer, annotations).await;
+    }
+    if let Some(Command::Board { stations }) = &cli.command {
+        let schedule = cache::load_or_build(SCHEDULE_CACHE, schedule_key.as_deref(), Schedule::load)?;
+        return board::run(stations, schedule, config, validator, source, cli.plain);
     }
 
-    if static_data::shoud_fetch(COASTLINE_STATIC) {
-        static_data::fetch(COASTLINE_STATIC, Some(xdg.get_data_home())).await?;
+    if should_fetch(COASTLINE_STATIC) {
+        static_data::fetch(COASTLINE_STATIC, Some(xdg.get_data_home()), force).await?;
     }
 
-    if static_data::shoud_fetch(BOROUGH_BOUNDARIES_STATIC) {
-        println!("fetching static");
-        static_data::fetch(BOROUGH_BOUNDARIES_STATIC, Some(xdg.get_data_home())).await?;
+    if should_fetch(BOROUGH_BOUNDARIES_STATIC) {
+        tracing::info!("Fetching borough boundaries");
+        static_data::fetch(BOROUGH_BOUNDARIES_STATIC, Some(xdg.get_data_home()), force).await?;
     }
 
-    if static_data::shoud_fetch(PARKS_STATIC) {
-        static_data::fetch(PARKS_STATIC, Some(xdg.get_data_home())).await?;
+    if should_fetch(PARKS_STATIC) {
+        static_data::fetch(PARKS_STATIC, Some(xdg.get_data_home()), force).await?;
     }
 
-    let mut boros = entities::Boro::load_collection()?;
-    let mut shapes = entities::ShapeSeq::load_collection()?;
-    let mut stops = entities::Stop::load_collection()?;
-    let mut parks = entities::Park::load_collection()?;
-    let routes = entities::Route::load_collection()?;
+    // complexes are optional, the map works without them
+    if should_fetch(STATIONS_STATIC) {
+        if let Err(err) = static_data::fetch(STATIONS_STATIC, Some(xdg.get_data_home()), force).await {
+            tracing::warn!("Failed to fetch station complexes: {}", err);
+        }
+    }
+    let complexes = Arc::new(complexes::Complexes::load().unwrap_or_else(|err| {
+        tracing::warn!("Station complexes unavailable: {}", err);
+        complexes::Complexes::default()
+    }));
+    // as is ridership, which needs the complexes to place it
+    if should_fetch(RIDERSHIP_STATIC) {
+        if let Err(err) = static_data::fetch(RIDERSHIP_STATIC, Some(xdg.get_data_home()), force).await {
+            tracing::warn!("Failed to fetch station ridership: {}", err);
+        }
+    }
+    let ridership = ridership::Ridership::load().unwrap_or_else(|err| {
+        tracing::warn!("Station ridership unavailable: {}", err);
+        ridership::Ridership::default()
+    });
+
+    // the schedule and each GeoJSON dataset load concurrently
+    let (schedule, ((boros, parks), coastline)) = rayon::join(
+        || cache::load_or_build(SCHEDULE_CACHE, schedule_key.as_deref(), Schedule::load),
+        || {
+            rayon::join(
+                || {
+                    rayon::join(
+                        entities::Boro::load_collection,
+                        entities::Park::load_collection,
+                    )
+                },
+                entities::Coastline::load_collection,
+            )
+        },
+    );
+    let Schedule {
+        mut stops,
+        mut shapes,
+        mut routes,
+        shape_routes,
+        timetable,
+        transfers,
+    } = schedule?;
+    let (mut boros, mut parks, mut coastline) = (boros?, parks?, coastline?);
+    for route in routes.values_mut() {
+        route.set_palette(config.palette.routes);
+    }
+    // tiles are exported before anything is projected to the map, in longitude and latitude
+    if let Some(Command::Tiles {
+        out,
+        min_zoom,
+        max_zoom,
+    }) = &cli.command
+    {
+        let sources = tiles::TileSources {
+            stops: &stops,
+            shapes: &shapes,
+            shape_routes: &shape_routes,
+            routes: &routes,
+            boros: &boros,
+            parks: &parks,
+        };
+        let written = tiles::export(out, *min_zoom..=*max_zoom, &sources)?;
+        println!("Wrote {} tiles to '{}'", written, out.display());
+        return Ok(());
+    }
+    // patterns that don't run today, e.g. weekend only reroutes, aren't drawn
+    let now = util::posix_now();
+    shapes.retain(|shape_id, _| timetable.shape_active(shape_id, now));
 
     let o_rect = boros.bounding_rect().unwrap();
     let origin: Point<f32> = o_rect.center().into();
 
-    boros.translate_origin_from(&origin);
-    parks.translate_origin_from(&origin);
-    shapes.translate_origin_from(&origin);
-    stops.translate_origin_from(&origin);
+    boros.translate_origin_from(&origin, config.map.projection);
+    parks.translate_origin_from(&origin, config.map.projection);
+    coastline.translate_origin_from(&origin, config.map.projection);
+    shapes.translate_origin_from(&origin, config.map.projection);
+    stops.translate_origin_from(&origin, config.map.projection);
     let rc_stops = Arc::new(stops);
+    let shape_index = ShapeIndex::new(&shapes, &shape_routes);
     let boros_rect = boros.bounding_rect().unwrap();
+    // the overlay flies the camera to each borough
+    let boroughs: Vec<_> = boros
+        .iter_features()
+        .filter_map(|(geo, properties)| {
+            let name = properties.get("boro_name")?.as_str()?.to_owned();
+            Some((name, geo.bounding_rect()?))
+        })
+        .collect();
     let v_scale = 0.8;
     let mut viewport = Rect::new(
         Coord::zero(),
@@ -87,75 +392,92 @@ async fn main() -> Result<()> {
         },
     );
     viewport.translate_mut(
-        viewport.center().x * -1. * 0.8,
-        viewport.center().y * -1. * 0.82,
+        -viewport.center().x * 0.8,
+        -viewport.center().y * 0.82,
     );
 
     let camera_uniform = CameraUniform::new(viewport);
-    let boro_vertices: Vec<_> = boros
-        .iter()
-        .flat_map(|geo| {
-            let geo = geo.clone();
-            let poly: MultiPolygon<f32> = geo.try_into().unwrap();
-            poly.into_iter().flat_map(|p| {
-                p.earcut_triangles()
-                    .into_iter()
-                    .flat_map(|tri| tri.coords_iter().map(|coord| Vertex::from(coord)))
-            })
-        })
+
+    let mut route_colors: Vec<_> = routes
+        .values()
+        .map(|route| (route.id().to_owned(), route.color()))
         .collect();
+    route_colors.sort_by(|a, b| a.0.cmp(&b.0));
 
-    // let park_vertices = parks.iter().flat_map(|geo| {
-    //     let geo = geo.clone();
-    //     let poly: MultiPolygon<f32> = geo.try_into().unwrap();
-    //     poly.into_iter().flat_map(|p| {
-    //         p.earcut_triangles().into_iter().flat_map(|tri| {
-    //             tri.coords_iter().map(|coord| Vertex {
-    //                 position: [coord.x, coord.y, 0.0],
-    //                 color: [0.20, 0.3, 0.20],
-    //                 ..Vertex::default()
-    //             })
-    //         })
-    //     })
-    // });
-
-    // boro_vertices.extend(park_vertices);
-
-    let mut geo: VertexBuffers<Vertex, u32> = VertexBuffers::new();
-    let mut stroke = Path::builder();
-
-    for shape in shapes.values() {
-        let first = shape[0].coord();
-        stroke.begin(point(first.x, first.y));
-        for seq in &shape[1..] {
-            let coord = seq.coord();
-            stroke.line_to(point(coord.x, coord.y));
+    // tessellating is slow too, it's cached against the data and styles it came from
+    let atlas_key = match &gtfs_zip {
+        Some(zip) => {
+            let mut inputs = vec![
+                ATLAS_REVISION.to_le_bytes().to_vec(),
+                zip.clone(),
+                format!("{:?}", config.styles).into_bytes(),
+                format!("{:?}", config.palette).into_bytes(),
+                format!("{:?}", config.map.projection).into_bytes(),
+                // which shapes run changes from day to day
+                shapes.keys().cloned().collect::<Vec<_>>().join(",").into_bytes(),
+                transfers.pairs().map(|(from, to)| format!("{from}-{to},")).collect::<String>().into_bytes(),
+            ];
+            for (_, file, _) in [COASTLINE_STATIC, BOROUGH_BOUNDARIES_STATIC, PARKS_STATIC] {
+                inputs.extend(
+                    xdg.find_data_file(file)
+                        .map(|path| fs::read(&path).with_context(|| format!("Failed to read '{}'", path.display())))
+                        .transpose()?,
+                );
+            }
+            Some(cache::key(inputs.iter().map(Vec::as_slice)))
         }
-        stroke.end(false);
+        None => None,
+    };
+    let express_runs: ExpressRuns = shapes
+        .keys()
+        .map(|shape_id| {
+            let runs: Vec<_> = timetable
+                .express_runs(shape_id)
+                .iter()
+                .filter_map(|(from, to)| {
+                    Some((rc_stops.get(from.as_str())?.coord, rc_stops.get(to.as_str())?.coord))
+                })
+                .collect();
+            (shape_id.to_owned(), runs)
+        })
+        .filter(|(_, runs)| !runs.is_empty())
+        .collect();
+    // the window opens on a loading screen while the atlas is loaded or tessellated
+    let routes = Arc::new(routes);
+    // shared by the feed thread and the trip planner
+    let timetable = Arc::new(timetable);
+    let planner = planner::Planner::new(&rc_stops, &transfers);
+    let transfers = Arc::new(transfers);
+    let (atlas_tx, atlas_rx) = channel();
+    {
+        let (routes, stops, transfers) = (routes.clone(), rc_stops.clone(), transfers.clone());
+        let (styles, palette, route_colors) = (config.styles.clone(), config.palette.clone(), route_colors.clone());
+        thread::spawn(move || {
+            let atlas = cache::load_or_build(ATLAS_CACHE, atlas_key.as_deref(), || {
+                Ok(build_atlas(
+                    &boros,
+                    &parks,
+                    &coastline,
+                    &styles,
+                    &palette,
+                    &routes,
+                    &shapes,
+                    &shape_routes,
+                    &express_runs,
+                    &route_colors,
+                    &stops,
+                    &transfers,
+                ))
+            });
+            // errors are shown on the loading screen
+            let _ = atlas_tx.send(atlas);
+        });
     }
 
-    let stroke_path = stroke.build();
-
-    let mut stroke_tessellator = StrokeTessellator::new();
-    let mut fill_tessellator = FillTessellator::new();
-
-    stroke_tessellator
-        .tessellate_path(
-            &stroke_path,
-            &StrokeOptions::default().with_line_width(70.),
-            &mut BuffersBuilder::new(&mut geo, |vertex: StrokeVertex| Vertex {
-                position: vertex.position().to_3d().to_array(),
-                normal: [0.0, 0.0, 0.0],
-                color: [1.0, 1.0, 1.0],
-                miter: 0.0,
-            }),
-        )
-        .unwrap();
-
     let stop_instances: Vec<_> = rc_stops
         .values()
         .filter_map(|stop| {
-            if let None = stop.parent {
+            if stop.parent.is_none() {
                 Some(StopInstance {
                     position: [stop.coord.x, stop.coord.y, 0.0],
                     ..StopInstance::default()
@@ -165,101 +487,491 @@ async fn main() -> Result<()> {
             }
         })
         .collect();
-    let geo_range = 0..geo.indices.len() as u32;
-
-    fill_tessellator
-        .tessellate_circle(
-            point(0.0, 0.0),
-            120.,
-            &FillOptions::default(),
-            &mut BuffersBuilder::new(&mut geo, |vertex: FillVertex| Vertex {
-                position: vertex.position().to_3d().to_array(),
-                normal: [0.0, 0.0, 0.0],
-                color: [1.0, 1.0, 1.0],
-                miter: 0.0,
-            }),
-        )
-        .unwrap();
-    let stop_range = geo_range.end..geo.indices.len() as u32;
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    // small markers beside annotated stations
+    let marker_instances: Vec<_> = annotations
+        .stations
+        .keys()
+        .filter_map(|stop_id| rc_stops.get(stop_id.as_str()))
+        .map(|stop| StopInstance {
+            position: [
+                stop.coord.x + MARKER_OFFSET,
+                stop.coord.y + MARKER_OFFSET,
+                0.0,
+            ],
+            color: [1.0, 0.65, 0.0],
+            scale: -0.6,
+        })
+        .collect();
+    let ridership_instances = ridership.instances(&rc_stops, &complexes);
+    let accessibility = Arc::new(accessibility::Accessibility::load(&rc_stops));
+
+    let event_loop = EventLoop::new().context("Failed to create the event loop")?;
+    // winit 0.30 deprecates creating windows outside of an ApplicationHandler, State borrows the window for its lifetime
+    #[allow(deprecated)]
+    let window = event_loop
+        .create_window(Window::default_attributes())
+        .context("Failed to create the window")?;
     window.set_min_inner_size(Some(PhysicalSize::new(1600, 1600)));
     window.set_max_inner_size(Some(PhysicalSize::new(1600, 1600)));
 
+    let shared = SharedFeedState::default();
+    shared.controls.write().unwrap().low_bandwidth = cli.low_bandwidth;
+    if source == FeedSource::Network {
+        shared.outages.spawn(rc_stops.clone());
+    }
+    if let Some(addr) = cli.stream {
+        serve::spawn_stream(addr, shared.updates.clone());
+    }
+    if config.history.enabled {
+        recorder::spawn(&config.history, &shared.updates)?;
+    }
+    #[cfg(feature = "tray")]
+    let mut tray = tray::Tray::new(shared.clone())?;
+    let mut capture = config.capture;
+    capture.sink = cli.capture.unwrap_or(capture.sink);
+    capture.every = cli.capture_every.unwrap_or(capture.every);
     let mut state = render::State::new(
         &window,
         camera_uniform,
-        &boro_vertices[..],
-        geo,
+        atlas_rx,
         &stop_instances[..],
-        geo_range,
-        stop_range,
+        &marker_instances[..],
+        &ridership_instances[..],
+        StopPicker::new(rc_stops.clone(), shared.arrivals.clone())
+            .with_transfers(transfers)
+            .with_complexes(complexes.clone(), shared.controls.clone())
+            .with_accessibility(accessibility.clone()),
+        route_colors,
+        shared.clone(),
+        config.format,
+        config.palette.background.unwrap_or(BACKGROUND_COLOR),
+        config.map.bloom,
+        annotations,
+        accessibility,
+        &config.export,
+        capture,
     )
-    .await;
+    .await
+    .with_boroughs(boroughs)
+    .with_planner(planner, timetable.clone(), shared.snapshot.clone());
+    if cli.capture.is_some() {
+        state.toggle_capture();
+    }
+    if cli.gpu_timing {
+        state.enable_gpu_timing();
+    }
 
-    let (tx, rx) = channel();
+    let stats = shared.stats.clone();
+    let save_summary = config.session.save_summary;
+    let (tx, rx) = latest::channel();
     let stops_collection = rc_stops.clone();
+    let motion = config.motion;
+    let feeds = config.feeds;
+    let webhooks = Webhooks::spawn(config.webhooks);
+    let closed_log = entity_log.clone();
     thread::spawn(move || {
-        let mut feed_manager = FeedManager::new(&stops_collection, &routes, tx);
+        let mut feed_manager =
+            FeedManager::new(&stops_collection, &routes, &shape_index, shared, webhooks)
+                .with_frames(tx);
+        if let Some(validator) = &validator {
+            feed_manager = feed_manager.with_validator(validator);
+        }
+        feed_manager = feed_manager
+            .with_source(source)
+            .with_feeds(&feeds)
+            .with_timetable(&timetable)
+            .with_motion(motion)
+            .with_complexes(&complexes)
+            .with_entity_log(entity_log);
 
         loop {
             feed_manager.update();
-            thread::sleep(Duration::from_millis(200));
+            thread::sleep(feed_manager.polling_interval());
         }
     });
 
+    #[allow(deprecated)]
     let _ = event_loop.run(move |event, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == state.window().id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => control_flow.exit(),
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::RedrawRequested => {
-                        state.window().request_redraw();
-                        match rx.try_recv() {
-                            Ok(data) => {
-                                state.update_stops(data);
-                            }
-                            Err(TryRecvError::Disconnected) => {
-                                panic!("Unable to fetch data");
-                            }
-                            _ => {}
+        } if window_id == state.window().id() && !state.input(event) => {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => {
+                    // with a tray icon the window only hides, quitting is done from the tray menu
+                    #[cfg(feature = "tray")]
+                    state.window().set_visible(false);
+                    #[cfg(not(feature = "tray"))]
+                    control_flow.exit();
+                }
+                WindowEvent::Resized(physical_size) => {
+                    state.resize(*physical_size);
+                }
+                WindowEvent::RedrawRequested => {
+                    state.window().request_redraw();
+                    match rx.try_recv() {
+                        Ok(data) => {
+                            state.update_frame(data);
+                        }
+                        // the feed thread only drops its sender if it panicked
+                        Err(TryRecvError::Disconnected) => {
+                            tracing::error!("Feed thread stopped, unable to fetch data");
+                            control_flow.exit();
+                            return;
                         }
+                        _ => {}
+                    }
+                    state.update();
 
-                        match state.render() {
-                            Ok(_) => {}
-                            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                                state.resize(state.size)
-                            }
-                            Err(wgpu::SurfaceError::OutOfMemory) => {
-                                log::error!("OutOfMemory");
-                                control_flow.exit();
-                            }
+                    match state.render() {
+                        Ok(_) => {}
+                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                            state.resize(state.size)
+                        }
+                        Err(wgpu::SurfaceError::OutOfMemory) => {
+                            tracing::error!("OutOfMemory");
+                            control_flow.exit();
+                        }
 
-                            Err(wgpu::SurfaceError::Timeout) => {
-                                log::warn!("Surface timeout")
-                            }
+                        Err(wgpu::SurfaceError::Timeout) => {
+                            tracing::warn!("Surface timeout")
                         }
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
+        #[cfg(feature = "tray")]
+        Event::AboutToWait => {
+            for action in tray.update() {
+                match action {
+                    tray::TrayAction::Open => {
+                        state.window().set_visible(true);
+                        state.window().focus_window();
+                    }
+                    tray::TrayAction::Quit => control_flow.exit(),
+                }
+            }
+            // keep handling the tray while the window is hidden
+            control_flow.set_control_flow(winit::event_loop::ControlFlow::WaitUntil(
+                std::time::Instant::now() + tray::REFRESH_INTERVAL,
+            ));
+        }
+        // a video is only playable once ffmpeg has finished it
+        Event::LoopExiting => state.stop_capture(),
         _ => {}
     });
+
+    closed_log.close();
+    let summary = stats.summary(&rc_stops);
+    println!("{summary}");
+    if save_summary {
+        summary.save()?;
+    }
     Ok(())
 }
+
+// triangulate polygon features, colored per feature
+fn fill_features(
+    buffers: &mut VertexBuffers<Vertex, u32>,
+    features: &Features,
+    detail: Detail,
+    color: impl Fn(&Properties) -> [f32; 3],
+) {
+    for (geo, properties) in features.iter_features() {
+        let color = color(properties);
+        let Ok(poly) = MultiPolygon::<f32>::try_from(geo.clone()) else {
+            continue;
+        };
+        for p in poly.simplify(&detail.tolerance()) {
+            let triangles = p.earcut_triangles().into_iter();
+            push_triangles(
+                buffers,
+                triangles.flat_map(|tri| tri.coords_iter().map(|coord| Vertex::new(coord, color))),
+            );
+        }
+    }
+}
+
+// tessellate the map's static geometry, in translated coordinates
+// each layer is tessellated into its own atlas on the rayon pool, then they're merged into one
+#[allow(clippy::too_many_arguments)]
+fn build_atlas(
+    boros: &Features,
+    parks: &Features,
+    coastline: &Features,
+    styles: &[StyleRule],
+    palette: &PaletteConfig,
+    routes: &HashMap<RouteId, Route>,
+    shapes: &BTreeMap<String, Vec<ShapeSeq>>,
+    shape_routes: &ShapeRoutes,
+    express_runs: &ExpressRuns,
+    route_colors: &[(RouteId, [f32; 3])],
+    stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+    transfers: &Transfers,
+) -> GeometryAtlas {
+    // open water under everything, with the shoreline traced on top
+    let boros_rect = boros.bounding_rect().unwrap();
+    let water = coastline.bounding_rect().unwrap_or(boros_rect);
+    let water = Rect::new(
+        water.min() - Coord { x: WATER_MARGIN, y: WATER_MARGIN },
+        water.max() + Coord { x: WATER_MARGIN, y: WATER_MARGIN },
+    );
+    let water_color = palette.water.unwrap_or(WATER_COLOR);
+    let water_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        let mut stroke_tessellator = StrokeTessellator::new();
+        atlas.add(WATER_LAYER, |detail, buffers| {
+            let triangles = water.to_polygon().earcut_triangles().into_iter();
+            push_triangles(
+                buffers,
+                triangles.flat_map(|tri| tri.coords_iter().map(|coord| Vertex::new(coord, water_color))),
+            );
+
+            let mut shore = Path::builder();
+            for geo in coastline.iter() {
+                let lines = match geo {
+                    Geometry::LineString(line) => vec![line.clone()],
+                    Geometry::MultiLineString(lines) => lines.0.clone(),
+                    _ => continue,
+                };
+                for line in lines {
+                    let line = line.simplify(&detail.tolerance()).0;
+                    let Some((first, rest)) = line.split_first() else {
+                        continue;
+                    };
+                    shore.begin(point(first.x, first.y));
+                    for coord in rest {
+                        shore.line_to(point(coord.x, coord.y));
+                    }
+                    shore.end(false);
+                }
+            }
+            stroke_tessellator
+                .tessellate_path(
+                    &shore.build(),
+                    &StrokeOptions::default().with_line_width(SHORE_WIDTH),
+                    &mut BuffersBuilder::new(buffers, |vertex: StrokeVertex| Vertex {
+                        position: vertex.position().to_3d().to_array(),
+                        normal: [0.0, 0.0, 0.0],
+                        color: SHORE_COLOR,
+                        miter: 0.0,
+                        route: 0,
+                    }),
+                )
+                .unwrap();
+        });
+        atlas
+    };
+    let boros_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        atlas.add(BOROS_LAYER, |detail, buffers| {
+            fill_features(buffers, boros, detail, |properties| {
+                style::resolve(styles, StyleTarget::Boros, properties)
+                    .unwrap_or(palette.boros.unwrap_or(BORO_COLOR))
+            });
+        });
+        atlas
+    };
+    let parks_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        atlas.add(PARKS_LAYER, |detail, buffers| {
+            fill_features(buffers, parks, detail, |properties| {
+                style::resolve(styles, StyleTarget::Parks, properties)
+                    .unwrap_or(palette.parks.unwrap_or(PARK_COLOR))
+            });
+        });
+        atlas
+    };
+
+    let lines: Vec<_> = shapes
+        .iter()
+        .map(|(shape_id, shape)| {
+            let route_id = shape_routes.get(shape_id).map_or("", RouteId::as_str);
+            (route_id, shape.iter().map(|seq| seq.coord()).collect())
+        })
+        .collect();
+    // routes sharing track are drawn side by side rather than on top of each other
+    let offset_lines = util::geo::offset_shared_track(&lines, LINE_WIDTH);
+
+    let shapes_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        let mut stroke_tessellator = StrokeTessellator::new();
+        atlas.add(SHAPES_LAYER, |detail, buffers| {
+            // route color and 1-based route index as custom attributes on each path endpoint
+            let mut stroke = Path::builder_with_attributes(4);
+            let mut stripe = Path::builder_with_attributes(4);
+            for (((route_id, original), line), shape_id) in lines.iter().zip(&offset_lines).zip(shapes.keys()) {
+                let [r, g, b] = routes
+                    .get(*route_id)
+                    .map(|route| route.color())
+                    .unwrap_or([1.0, 1.0, 1.0]);
+                let route = route_colors
+                    .iter()
+                    .position(|(id, _)| id == route_id)
+                    .map_or(0, |idx| idx + 1);
+                let color = [r, g, b, route as f32];
+                let simplified = LineString::new(line.clone()).simplify(&detail.tolerance()).0;
+                stroke.begin(point(simplified[0].x, simplified[0].y), &color);
+                for coord in &simplified[1..] {
+                    stroke.line_to(point(coord.x, coord.y), &color);
+                }
+                stroke.end(false);
+
+                // the shape's vertex nearest each station, the offset line has the same vertices
+                let nearest = |station: Coord<f32>| {
+                    original
+                        .iter()
+                        .map(|coord| {
+                            let offset = *coord - station;
+                            offset.x.hypot(offset.y)
+                        })
+                        .enumerate()
+                        .filter(|(_, distance)| *distance <= EXPRESS_SEARCH_RADIUS)
+                        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .map(|(idx, _)| idx)
+                };
+                let pale = [r, g, b].map(|channel| channel + (1.0 - channel) * EXPRESS_STRIPE_TINT);
+                let pale = [pale[0], pale[1], pale[2], route as f32];
+                for (from, to) in express_runs.get(shape_id).into_iter().flatten() {
+                    let (Some(from), Some(to)) = (nearest(*from), nearest(*to)) else {
+                        continue;
+                    };
+                    let run = &line[from.min(to)..=from.max(to)];
+                    if run.len() < 2 {
+                        continue;
+                    }
+                    let run = LineString::new(run.to_vec()).simplify(&detail.tolerance()).0;
+                    stripe.begin(point(run[0].x, run[0].y), &pale);
+                    for coord in &run[1..] {
+                        stripe.line_to(point(coord.x, coord.y), &pale);
+                    }
+                    stripe.end(false);
+                }
+            }
+
+            // stripes are tessellated after the lines so they're drawn on top.
+            // vertices are left on the line and pushed out to its width on screen by the shader, the width given
+            // here only decides how finely round joins are divided
+            for (path, width) in [(stroke.build(), LINE_PIXELS), (stripe.build(), EXPRESS_STRIPE_PIXELS)] {
+                stroke_tessellator
+                    .tessellate_path(
+                        &path,
+                        &StrokeOptions::default()
+                            .with_line_width(width)
+                            .with_line_join(LineJoin::Round),
+                        &mut BuffersBuilder::new(&mut *buffers, |mut vertex: StrokeVertex| {
+                            let position = vertex.position_on_path().to_3d().to_array();
+                            // longer than 1 at miter joins
+                            let normal = vertex.normal();
+                            let miter = normal.length();
+                            let color = vertex.interpolated_attributes();
+                            Vertex {
+                                position,
+                                normal: (normal / miter).to_3d().to_array(),
+                                color: [color[0], color[1], color[2]],
+                                miter: miter * width / 2.0,
+                                route: color[3].round() as u32,
+                            }
+                        }),
+                    )
+                    .unwrap();
+            }
+        });
+        atlas
+    };
+
+    // short arcs between connected stations, bowed so they stand out from the track they often run along
+    let arcs: Vec<_> = transfers
+        .pairs()
+        .filter_map(|(from, to)| Some((stops.station(from.as_str())?, stops.station(to.as_str())?)))
+        .filter(|(from, to)| from.id != to.id)
+        .map(|(from, to)| (from.coord, to.coord))
+        .collect();
+    let transfers_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        let mut stroke_tessellator = StrokeTessellator::new();
+        atlas.add(TRANSFERS_LAYER, |_, buffers| {
+            let mut path = Path::builder();
+            for (from, to) in &arcs {
+                let mid = (*from + *to) / 2.0;
+                let bow = Coord { x: from.y - to.y, y: to.x - from.x } * TRANSFER_BOW;
+                path.begin(point(from.x, from.y));
+                path.quadratic_bezier_to(point(mid.x + bow.x, mid.y + bow.y), point(to.x, to.y));
+                path.end(false);
+            }
+            stroke_tessellator
+                .tessellate_path(
+                    &path.build(),
+                    &StrokeOptions::default().with_line_width(TRANSFER_WIDTH),
+                    &mut BuffersBuilder::new(buffers, |vertex: StrokeVertex| Vertex {
+                        position: vertex.position().to_3d().to_array(),
+                        normal: [0.0, 0.0, 0.0],
+                        color: TRANSFER_COLOR,
+                        miter: 0.0,
+                        route: 0,
+                    }),
+                )
+                .unwrap();
+        });
+        atlas
+    };
+
+    let markers_layer = || {
+        let mut atlas = GeometryAtlas::default();
+        atlas.add_fixed(STOPS_LAYER, |buffers| {
+            FillTessellator::new()
+                .tessellate_circle(
+                    point(0.0, 0.0),
+                    120.,
+                    &FillOptions::default(),
+                    &mut BuffersBuilder::new(buffers, |vertex: FillVertex| Vertex {
+                        position: vertex.position().to_3d().to_array(),
+                        normal: [0.0, 0.0, 0.0],
+                        color: [1.0, 1.0, 1.0],
+                        miter: 0.0,
+                        route: 0,
+                    }),
+                )
+                .unwrap();
+        });
+
+        // a quad for each train's route bullet, the normal holds its corner for texturing
+        atlas.add_fixed(VEHICLES_LAYER, |buffers| {
+            let corner = |x: f32, y: f32| Vertex {
+                position: [x * BULLET_EXTENT, y * BULLET_EXTENT, 0.0],
+                normal: [x, y, 0.0],
+                color: [1.0, 1.0, 1.0],
+                miter: 0.0,
+                route: 0,
+            };
+            let quad = [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)];
+            push_triangles(buffers, [0, 1, 2, 0, 2, 3].into_iter().map(|idx| quad[idx]));
+        });
+        atlas
+    };
+
+    let layers: [&(dyn Fn() -> GeometryAtlas + Sync); 6] = [
+        &water_layer,
+        &boros_layer,
+        &parks_layer,
+        &shapes_layer,
+        &transfers_layer,
+        &markers_layer,
+    ];
+    let parts: Vec<GeometryAtlas> = layers.par_iter().map(|layer| layer()).collect();
+    let mut atlas = GeometryAtlas::default();
+    for part in parts {
+        atlas.merge(part);
+    }
+    atlas
+}
@@ -0,0 +1,58 @@
+use geo::Coord;
+use serde::Deserialize;
+
+// how trains move between stops
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    // accelerate out of the last stop and brake into the next
+    EaseInOut,
+    // hold at the last stop for the dwell time, then ease in and out
+    Dwell,
+}
+
+// train motion settings, from the `[motion]` config section
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Motion {
+    pub easing: Easing,
+    // seconds a train holds at a station with the dwell easing
+    pub dwell: u32,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            easing: Easing::default(),
+            dwell: 30,
+        }
+    }
+}
+
+impl Motion {
+    // fraction of a stop-to-stop segment covered `elapsed` seconds into a `duration` second run
+    pub fn progress(&self, elapsed: i64, duration: i64) -> f32 {
+        let (elapsed, duration) = match self.easing {
+            Easing::Dwell => {
+                let dwell = (self.dwell as i64).min(duration);
+                (elapsed - dwell, duration - dwell)
+            }
+            _ => (elapsed, duration),
+        };
+        if duration <= 0 {
+            return if elapsed > 0 { 1.0 } else { 0.0 };
+        }
+        let t = (elapsed as f32 / duration as f32).clamp(0.0, 1.0);
+        match self.easing {
+            Easing::Linear => t,
+            Easing::EaseInOut | Easing::Dwell => t * t * (3.0 - 2.0 * t),
+        }
+    }
+
+    // position between two stops
+    pub fn position(&self, from: Coord<f32>, to: Coord<f32>, elapsed: i64, duration: i64) -> Coord<f32> {
+        from + (to - from) * self.progress(elapsed, duration)
+    }
+}
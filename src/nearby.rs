@@ -0,0 +1,123 @@
+use geo::{HaversineDistance, Point};
+use rstar::{primitives::GeomWithData, RTree};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::entities::{EntityCollection, Stop};
+use crate::feed::{Arrivals, Countdown};
+use crate::ids::StopId;
+use crate::util;
+
+// meters per radian of latitude, on a sphere of the mean earth radius
+const METERS_PER_RADIAN: f64 = 6_371_008.8;
+// within-radius searches of the index look this much further, its flattened distances are a little off
+const RADIUS_SLACK: f64 = 1.01;
+
+type IndexedStation = GeomWithData<[f64; 2], usize>;
+
+struct Station {
+    stop_id: StopId,
+    name: String,
+    point: Point<f64>,
+}
+
+// a station near a point, with when its trains arrive
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyStop {
+    // the parent station
+    pub stop_id: StopId,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    // from the point searched around, in meters
+    pub distance: f64,
+    pub countdowns: Vec<Countdown>,
+}
+
+// stations by distance from a longitude and latitude, for "what's near me" lookups from outside the map.
+// stations are indexed flattened around their mean latitude, which is close enough across the city,
+// and measured along the earth's surface
+pub struct Nearby {
+    tree: RTree<IndexedStation>,
+    stations: Vec<Station>,
+    // shrinks longitudes to meters east at the stations' latitude
+    cos_lat: f64,
+    arrivals: Arrivals,
+}
+
+impl Nearby {
+    // `stops` in longitude and latitude, before they're projected onto the map
+    pub fn new(stops: &EntityCollection<BTreeMap<StopId, Stop>>, arrivals: Arrivals) -> Self {
+        let stations: Vec<Station> = stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .map(|stop| Station {
+                stop_id: stop.id.clone(),
+                name: stop.name.clone(),
+                point: Point::new(stop.coord.x as f64, stop.coord.y as f64),
+            })
+            .collect();
+        let mean_lat = stations.iter().map(|station| station.point.y()).sum::<f64>() / stations.len().max(1) as f64;
+        let cos_lat = mean_lat.to_radians().cos();
+        let points = stations
+            .iter()
+            .enumerate()
+            .map(|(idx, station)| GeomWithData::new(flatten(station.point, cos_lat), idx))
+            .collect();
+        Self {
+            tree: RTree::bulk_load(points),
+            stations,
+            cos_lat,
+            arrivals,
+        }
+    }
+
+    // the `n` stations closest to the point, nearest first
+    pub fn nearest_stops(&self, lat: f64, lon: f64, n: usize) -> Vec<NearbyStop> {
+        let point = Point::new(lon, lat);
+        let found = self
+            .tree
+            .nearest_neighbor_iter(&flatten(point, self.cos_lat))
+            .take(n)
+            .map(|indexed| indexed.data);
+        self.nearby(point, found, f64::INFINITY)
+    }
+
+    // every station within `meters` of the point, nearest first
+    pub fn stops_within_radius(&self, lat: f64, lon: f64, meters: f64) -> Vec<NearbyStop> {
+        let point = Point::new(lon, lat);
+        let slack = meters * RADIUS_SLACK;
+        let found = self
+            .tree
+            .locate_within_distance(flatten(point, self.cos_lat), slack * slack)
+            .map(|indexed| indexed.data);
+        self.nearby(point, found, meters)
+    }
+
+    fn nearby(&self, point: Point<f64>, found: impl Iterator<Item = usize>, max_distance: f64) -> Vec<NearbyStop> {
+        let now = util::posix_now();
+        let mut nearby: Vec<NearbyStop> = found
+            .map(|idx| &self.stations[idx])
+            .map(|station| (station, point.haversine_distance(&station.point)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .map(|(station, distance)| NearbyStop {
+                stop_id: station.stop_id.clone(),
+                name: station.name.clone(),
+                lat: station.point.y(),
+                lon: station.point.x(),
+                distance,
+                countdowns: self.arrivals.countdowns(station.stop_id.as_str(), now),
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        nearby
+    }
+}
+
+// meters east and north of null island, at the stations' latitude
+fn flatten(point: Point<f64>, cos_lat: f64) -> [f64; 2] {
+    [
+        point.x().to_radians() * cos_lat * METERS_PER_RADIAN,
+        point.y().to_radians() * METERS_PER_RADIAN,
+    ]
+}
@@ -0,0 +1,198 @@
+use prost::bytes::Bytes;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::headway::Direction;
+use crate::ids::{StopId, TripId};
+use crate::proto::gtfs::realtime::{nyct_trip_descriptor, NyctStopTimeUpdate, NyctTripDescriptor};
+use crate::timetable::ServicePattern;
+
+// prost doesn't support proto2 extensions and skips them when decoding a FeedMessage,
+// these mirror just enough of it to decode NYCT's extensions from the same message a second time.
+// JSON messages are read into them too, once proto::json has named the extensions' keys as fields
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedFeedMessage {
+    #[prost(message, repeated, tag = "2")]
+    entity: Vec<ExtendedFeedEntity>,
+}
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedFeedEntity {
+    #[prost(message, optional, tag = "3")]
+    trip_update: Option<ExtendedTripUpdate>,
+    #[prost(message, optional, tag = "4")]
+    vehicle: Option<ExtendedVehiclePosition>,
+}
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedTripUpdate {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<ExtendedTripDescriptor>,
+    #[prost(message, repeated, tag = "2")]
+    stop_time_update: Vec<ExtendedStopTimeUpdate>,
+}
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedVehiclePosition {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<ExtendedTripDescriptor>,
+}
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedTripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    trip_id: Option<String>,
+    #[prost(message, optional, tag = "1001")]
+    nyct_trip_descriptor: Option<NyctTripDescriptor>,
+}
+
+#[derive(Clone, PartialEq, Message, Deserialize)]
+#[serde(default)]
+struct ExtendedStopTimeUpdate {
+    #[prost(string, optional, tag = "4")]
+    stop_id: Option<String>,
+    #[prost(message, optional, tag = "1001")]
+    nyct_stop_time_update: Option<NyctStopTimeUpdate>,
+}
+
+// a trip as NYCT's train supervision knows it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NyctTrip {
+    // for internal use by NYCT, e.g. "06 0123+ PEL/BBR"
+    pub train_id: Option<String>,
+    // a physical train has been given the trip, unassigned trips are still waiting at their terminal
+    pub assigned: bool,
+    pub direction: Option<Direction>,
+}
+
+impl From<&NyctTripDescriptor> for NyctTrip {
+    fn from(descriptor: &NyctTripDescriptor) -> Self {
+        // the generated getter defaults to NORTH when the field is missing
+        let direction = descriptor.direction.and_then(|_| match descriptor.direction() {
+            nyct_trip_descriptor::Direction::North => Some(Direction::North),
+            nyct_trip_descriptor::Direction::South => Some(Direction::South),
+            // not used by NYCT
+            nyct_trip_descriptor::Direction::East | nyct_trip_descriptor::Direction::West => None,
+        });
+        Self {
+            train_id: descriptor.train_id.clone(),
+            assigned: descriptor.is_assigned(),
+            direction,
+        }
+    }
+}
+
+// the track a train arrives at a station on, e.g. in Manhattan 1 and 4 are local and 2 and 3 express
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Track {
+    pub scheduled: Option<String>,
+    // only known shortly before the train reaches the station
+    pub actual: Option<String>,
+}
+
+impl Track {
+    // the train was rerouted off its planned track, its predictions may no longer hold
+    pub fn changed(&self) -> bool {
+        matches!((&self.scheduled, &self.actual), (Some(scheduled), Some(actual)) if scheduled != actual)
+    }
+
+    // the track the train is running on, as far as it's known
+    pub fn current(&self) -> Option<&str> {
+        self.actual.as_deref().or(self.scheduled.as_deref())
+    }
+
+    // by Manhattan's numbering of its four track trunk lines, other stations number their tracks their own way
+    pub fn service_pattern(&self) -> Option<ServicePattern> {
+        match self.current()? {
+            "1" | "4" => Some(ServicePattern::Local),
+            "2" | "3" => Some(ServicePattern::Express),
+            _ => None,
+        }
+    }
+}
+
+// NYCT extensions from a feed message, by realtime trip_id
+#[derive(Debug, Default)]
+pub struct Extensions {
+    trips: HashMap<TripId, NyctTrip>,
+    // by platform stop_id, e.g. 127N
+    tracks: HashMap<TripId, HashMap<StopId, Track>>,
+}
+
+impl Extensions {
+    pub fn decode(body: Bytes) -> Result<Self, prost::DecodeError> {
+        Ok(Self::from_message(ExtendedFeedMessage::decode(body)?))
+    }
+
+    // from a message parsed by proto::json::parse
+    pub fn from_json(value: &serde_json::Value) -> serde_json::Result<Self> {
+        Ok(Self::from_message(ExtendedFeedMessage::deserialize(value)?))
+    }
+
+    // add a later message's extensions, replacing those of the trips it has
+    pub fn extend(&mut self, other: Extensions) {
+        self.trips.extend(other.trips);
+        self.tracks.extend(other.tracks);
+    }
+
+    // keep only the extensions of trips `keep` returns true for
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.trips.retain(|trip_id, _| keep(trip_id.as_str()));
+        self.tracks.retain(|trip_id, _| keep(trip_id.as_str()));
+    }
+
+    fn from_message(msg: ExtendedFeedMessage) -> Self {
+        let mut extensions = Self::default();
+        for entity in msg.entity {
+            let descriptors = entity
+                .trip_update
+                .as_ref()
+                .and_then(|trip_update| trip_update.trip.as_ref())
+                .into_iter()
+                .chain(entity.vehicle.as_ref().and_then(|vehicle| vehicle.trip.as_ref()));
+            for descriptor in descriptors {
+                if let (Some(trip_id), Some(nyct)) = (&descriptor.trip_id, &descriptor.nyct_trip_descriptor) {
+                    extensions.trips.insert(trip_id.as_str().into(), nyct.into());
+                }
+            }
+
+            let Some(trip_update) = entity.trip_update else {
+                continue;
+            };
+            let Some(trip_id) = trip_update.trip.and_then(|trip| trip.trip_id) else {
+                continue;
+            };
+            let tracks: HashMap<StopId, Track> = trip_update
+                .stop_time_update
+                .into_iter()
+                .filter_map(|stop_update| {
+                    let nyct = stop_update.nyct_stop_time_update?;
+                    let track = Track {
+                        scheduled: nyct.scheduled_track,
+                        actual: nyct.actual_track,
+                    };
+                    Some((stop_update.stop_id?.into(), track))
+                })
+                .collect();
+            if !tracks.is_empty() {
+                extensions.tracks.insert(trip_id.into(), tracks);
+            }
+        }
+        extensions
+    }
+
+    pub fn trip(&self, trip_id: &str) -> Option<&NyctTrip> {
+        self.trips.get(trip_id)
+    }
+
+    pub fn track(&self, trip_id: &str, stop_id: &str) -> Option<&Track> {
+        self.tracks.get(trip_id)?.get(stop_id)
+    }
+}
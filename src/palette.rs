@@ -0,0 +1,95 @@
+use serde::Deserialize;
+
+use crate::entities;
+
+// each trunk line's routes, with its sRGB colors in the deuteranopia and high contrast palettes
+const TRUNKS: [(&[&str], [u8; 3], [u8; 3]); 11] = [
+    (&["A", "C", "E"], [0x00, 0x72, 0xb2], [0x4d, 0x94, 0xff]),
+    (&["B", "D", "F", "FX", "M"], [0xe6, 0x9f, 0x00], [0xff, 0x7f, 0x00]),
+    (&["G"], [0x56, 0xb4, 0xe9], [0x40, 0xff, 0x40]),
+    (&["J", "Z"], [0xff, 0xff, 0xff], [0xd2, 0x91, 0x5a]),
+    (&["L"], [0xa0, 0xa0, 0xa0], [0xff, 0xff, 0xff]),
+    (&["N", "Q", "R", "W"], [0xf0, 0xe4, 0x42], [0xff, 0xff, 0x00]),
+    (&["1", "2", "3"], [0xd5, 0x5e, 0x00], [0xff, 0x30, 0x30]),
+    (&["4", "5", "6", "6X"], [0x00, 0x9e, 0x73], [0x00, 0xd0, 0x60]),
+    (&["7", "7X"], [0xcc, 0x79, 0xa7], [0xff, 0x40, 0xff]),
+    (&["GS", "FS", "H"], [0x70, 0x70, 0x70], [0xc8, 0xc8, 0xc8]),
+    (&["SI"], [0x00, 0x72, 0xb2], [0x40, 0xe0, 0xff]),
+];
+const MONOCHROME: [u8; 3] = [0xd0, 0xd0, 0xd0];
+
+// route colors, as in routes.txt or one of the alternatives
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Palette {
+    #[default]
+    Mta,
+    // trunk lines told apart with red-green color blindness, after Okabe and Ito
+    Deuteranopia,
+    // saturated trunk colors, brightened against the dark map
+    HighContrast,
+    // every route the same grey, told apart by the labels on their bullets
+    Monochrome,
+}
+
+impl Palette {
+    // a route's color in the palette, `color` is its route_color from routes.txt
+    // routes outside the palette keep theirs
+    pub fn route_color(self, route_id: &str, color: [f32; 3]) -> [f32; 3] {
+        let trunk = TRUNKS.iter().find(|(routes, _, _)| routes.contains(&route_id));
+        let srgb = match (self, trunk) {
+            (Self::Mta, _) => return color,
+            (Self::Monochrome, _) => MONOCHROME,
+            (Self::Deuteranopia, Some((_, deuteranopia, _))) => *deuteranopia,
+            (Self::HighContrast, Some((_, _, high_contrast))) => *high_contrast,
+            (_, None) => return color,
+        };
+        srgb::gamma::linear_from_u8(srgb)
+    }
+}
+
+// the map's colors, from the `[palette]` config section
+// unset colors are the defaults, and style rules still apply over the boros and parks colors
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "PaletteRows")]
+pub struct PaletteConfig {
+    pub routes: Palette,
+    pub background: Option<[f32; 3]>,
+    pub water: Option<[f32; 3]>,
+    pub boros: Option<[f32; 3]>,
+    pub parks: Option<[f32; 3]>,
+}
+
+// the section as written, colors are hex sRGB, e.g. "#101010"
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct PaletteRows {
+    routes: Palette,
+    background: Option<String>,
+    water: Option<String>,
+    boros: Option<String>,
+    parks: Option<String>,
+}
+
+impl TryFrom<PaletteRows> for PaletteConfig {
+    type Error = String;
+
+    // a color that isn't hex is an error naming its key, rather than a color nobody asked for
+    fn try_from(rows: PaletteRows) -> Result<Self, Self::Error> {
+        let color = |key: &str, value: Option<String>| {
+            value
+                .map(|value| {
+                    entities::parse_hex(&value)
+                        .ok_or_else(|| format!("palette.{key} = \"{value}\" isn't a hex color, e.g. \"#101010\""))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            routes: rows.routes,
+            background: color("background", rows.background)?,
+            water: color("water", rows.water)?,
+            boros: color("boros", rows.boros)?,
+            parks: color("parks", rows.parks)?,
+        })
+    }
+}
@@ -0,0 +1,390 @@
+use chrono::{DateTime, NaiveDate, Timelike};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::entities::{EntityCollection, Stop, Transfers};
+use crate::ids::{RouteId, StopId, TripId};
+use crate::timetable::{Timetable, AGENCY_TIMEZONE};
+
+// seconds to get off one train and onto another at the same station
+const CHANGE_TIME: i64 = 120;
+// seconds to walk a transfer transfers.txt gives no time for
+const WALK_TIME: u32 = 180;
+// trains scheduled to leave this long before a journey sets off are still scanned, running late they may not have
+const MAX_DELAY: i64 = 30 * 60;
+const DAY: i64 = 24 * 60 * 60;
+
+// part of a journey, stations are parent stop_ids and times POSIX timestamps
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Leg {
+    // on one train, from the station it's boarded at to the one it's left at, with those it stops at between
+    Ride {
+        route_id: RouteId,
+        trip_id: TripId,
+        stations: Vec<StopId>,
+        departure: i64,
+        arrival: i64,
+    },
+    // a transfer between stations on foot
+    Walk { from: StopId, to: StopId, seconds: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Journey {
+    // when the first train leaves, or the journey sets off when it starts with a walk
+    pub departure: i64,
+    pub arrival: i64,
+    pub legs: Vec<Leg>,
+}
+
+impl Journey {
+    // every station the journey passes through, in order
+    pub fn stations(&self) -> Vec<&StopId> {
+        let mut stations: Vec<&StopId> = Vec::new();
+        for leg in &self.legs {
+            let passed: Vec<&StopId> = match leg {
+                Leg::Ride { stations, .. } => stations.iter().collect(),
+                Leg::Walk { from, to, .. } => vec![from, to],
+            };
+            for station in passed {
+                if stations.last() != Some(&station) {
+                    stations.push(station);
+                }
+            }
+        }
+        stations
+    }
+}
+
+// a train's hop between stations, by their position in the planner's `stations`. times are seconds after
+// midnight of the service day it runs in
+#[derive(Clone, Copy)]
+struct Hop {
+    // by the position of its route and trip in the day's `runs`
+    run: usize,
+    from: usize,
+    to: usize,
+    departure: i64,
+    arrival: i64,
+}
+
+// a service day's hops in order of departure, worked out once for every journey planned over it
+struct ServiceDay {
+    hops: Vec<Hop>,
+    runs: Vec<(RouteId, TripId)>,
+}
+
+// a hop as the scan takes it, shifted by its trip's delay, times POSIX timestamps
+#[derive(Clone, Copy)]
+struct Connection {
+    // by the service day's position in the scan's days
+    day: usize,
+    hop: Hop,
+    departure: i64,
+    arrival: i64,
+}
+
+// hops of several service days from a POSIX timestamp on, in order of departure as trains run
+struct Departures<'d> {
+    // with their midnights as POSIX timestamps
+    days: &'d [(Arc<ServiceDay>, i64)],
+    // each day's first hop not yet read
+    next: Vec<usize>,
+    delays: &'d HashMap<TripId, i32>,
+    // the most seconds any train runs early, no hop scheduled after the next one can depart before it less these
+    early: i64,
+    // hops read with their delays, earliest departure first
+    read: BinaryHeap<Reverse<(i64, usize, usize)>>,
+}
+
+impl<'d> Departures<'d> {
+    fn new(days: &'d [(Arc<ServiceDay>, i64)], after: i64, delays: &'d HashMap<TripId, i32>) -> Self {
+        let next = days
+            .iter()
+            .map(|(day, midnight)| day.hops.partition_point(|hop| midnight + hop.departure < after))
+            .collect();
+        Self {
+            days,
+            next,
+            delays,
+            early: delays.values().min().map_or(0, |delay| (*delay as i64).min(0)),
+            read: BinaryHeap::new(),
+        }
+    }
+
+    fn delay(&self, day: usize, hop: &Hop) -> i64 {
+        let (_, trip_id) = &self.days[day].0.runs[hop.run];
+        self.delays.get(trip_id).copied().unwrap_or_default() as i64
+    }
+}
+
+impl Iterator for Departures<'_> {
+    type Item = Connection;
+
+    fn next(&mut self) -> Option<Connection> {
+        loop {
+            // the day with the next scheduled departure
+            let scheduled = self
+                .days
+                .iter()
+                .enumerate()
+                .filter_map(|(day, (service_day, midnight))| {
+                    let hop = service_day.hops.get(self.next[day])?;
+                    Some((midnight + hop.departure, day))
+                })
+                .min();
+            let Some((departure, day)) = scheduled else {
+                break;
+            };
+            if self.read.peek().is_some_and(|Reverse((earliest, _, _))| *earliest <= departure + self.early) {
+                break;
+            }
+            let idx = self.next[day];
+            self.next[day] += 1;
+            let delay = self.delay(day, &self.days[day].0.hops[idx]);
+            self.read.push(Reverse((departure + delay, day, idx)));
+        }
+        let Reverse((_, day, idx)) = self.read.pop()?;
+        let (service_day, midnight) = &self.days[day];
+        let hop = service_day.hops[idx];
+        let delay = self.delay(day, &hop);
+        Some(Connection {
+            day,
+            hop,
+            departure: midnight + hop.departure + delay,
+            arrival: midnight + hop.arrival + delay,
+        })
+    }
+}
+
+// how the scan first reached a station, by connection index
+#[derive(Clone, Copy)]
+enum Reached {
+    Origin,
+    Ride { boarded: usize, alighted: usize },
+    Walk { from: usize, seconds: u32 },
+}
+
+// the earliest each station has been reached so far, and when a train can be boarded there
+struct Scan {
+    arrival: Vec<i64>,
+    ready: Vec<i64>,
+    reached: Vec<Option<Reached>>,
+}
+
+impl Scan {
+    // whether `at` is earlier than the station was reached before
+    fn reach(&mut self, station: usize, at: i64, ready: i64, how: Reached) -> bool {
+        if at >= self.arrival[station] {
+            return false;
+        }
+        self.arrival[station] = at;
+        self.ready[station] = ready;
+        self.reached[station] = Some(how);
+        true
+    }
+}
+
+// the earliest arriving journeys between stations over the static schedule, found by scanning its connections in
+// order of departure (the Connection Scan Algorithm). trains running late can be shifted by their reported delays
+pub struct Planner {
+    // parent stop_ids
+    stations: Vec<StopId>,
+    // each stop_id's station, by its position in `stations`
+    station_idx: HashMap<StopId, usize>,
+    // transfers from each station, to another with the seconds they take
+    walks: Vec<Vec<(usize, u32)>>,
+    // the service days around those journeys were last planned in
+    days: Mutex<HashMap<NaiveDate, Arc<ServiceDay>>>,
+}
+
+impl Planner {
+    pub fn new(stops: &EntityCollection<BTreeMap<StopId, Stop>>, transfers: &Transfers) -> Self {
+        let stations: Vec<StopId> = stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .map(|stop| stop.id.clone())
+            .collect();
+        let positions: HashMap<&StopId, usize> = stations.iter().enumerate().map(|(idx, id)| (id, idx)).collect();
+        let station_idx: HashMap<StopId, usize> = stops
+            .keys()
+            .filter_map(|stop_id| {
+                let station = stops.station(stop_id.as_str())?;
+                Some((stop_id.clone(), *positions.get(&station.id)?))
+            })
+            .collect();
+        let mut walks = vec![Vec::new(); stations.len()];
+        for (stop_id, from) in &station_idx {
+            for transfer in transfers.from(stop_id.as_str()) {
+                match station_idx.get(&transfer.to) {
+                    Some(to) if to != from => walks[*from].push((*to, transfer.min_time.unwrap_or(WALK_TIME))),
+                    _ => {}
+                }
+            }
+        }
+        Self {
+            stations,
+            station_idx,
+            walks,
+            days: Mutex::default(),
+        }
+    }
+
+    // the earliest arriving journey between two stations, or platforms of them, setting off at the POSIX timestamp
+    // `depart`. trips are shifted by `delays` in seconds, by trip_id, as the feeds report them
+    pub fn plan(
+        &self,
+        timetable: &Timetable,
+        from: &str,
+        to: &str,
+        depart: i64,
+        delays: &HashMap<TripId, i32>,
+    ) -> Option<Journey> {
+        let origin = *self.station_idx.get(from)?;
+        let destination = *self.station_idx.get(to)?;
+        if origin == destination {
+            return None;
+        }
+        let local = DateTime::from_timestamp(depart, 0)?.with_timezone(&AGENCY_TIMEZONE);
+        let today = local.date_naive();
+        let midnight = depart - local.num_seconds_from_midnight() as i64;
+        // trips running past midnight belong to the previous day's service, and a journey late in the day may
+        // only arrive with the next day's
+        let days: Vec<(Arc<ServiceDay>, i64)> = [(today.pred_opt(), -DAY), (Some(today), 0), (today.succ_opt(), DAY)]
+            .into_iter()
+            .filter_map(|(date, offset)| Some((self.service_day(timetable, date?, today), midnight + offset)))
+            .collect();
+        let departures = Departures::new(&days, depart - MAX_DELAY, delays)
+            .filter(|connection| connection.departure >= depart);
+
+        let mut scan = Scan {
+            arrival: vec![i64::MAX; self.stations.len()],
+            ready: vec![i64::MAX; self.stations.len()],
+            reached: vec![None; self.stations.len()],
+        };
+        scan.reach(origin, depart, depart, Reached::Origin);
+        self.walk(&mut scan, origin, depart);
+        // where each run of a trip was boarded, by its day and run
+        let mut boardings: HashMap<(usize, usize), usize> = HashMap::new();
+        // those scanned so far, for the journey to be read back from
+        let mut connections: Vec<Connection> = Vec::new();
+        for connection in departures {
+            if connection.departure >= scan.arrival[destination] {
+                break;
+            }
+            let idx = connections.len();
+            connections.push(connection);
+            let Hop { run, from, to, .. } = connection.hop;
+            let boarded = match boardings.get(&(connection.day, run)).copied() {
+                Some(boarded) => boarded,
+                None if scan.ready[from] <= connection.departure => {
+                    boardings.insert((connection.day, run), idx);
+                    idx
+                }
+                None => continue,
+            };
+            let ride = Reached::Ride { boarded, alighted: idx };
+            if scan.reach(to, connection.arrival, connection.arrival + CHANGE_TIME, ride) {
+                self.walk(&mut scan, to, connection.arrival);
+            }
+        }
+
+        // back from the destination to the origin
+        let mut legs = Vec::new();
+        let mut station = destination;
+        while station != origin {
+            // each step back reaches a station no later than the last, a cycle would never end
+            if legs.len() > self.stations.len() {
+                return None;
+            }
+            match scan.reached[station]? {
+                Reached::Origin => break,
+                Reached::Ride { boarded, alighted } => {
+                    let (first, last) = (&connections[boarded], &connections[alighted]);
+                    let mut stations = vec![self.stations[first.hop.from].clone()];
+                    stations.extend(
+                        connections[boarded..=alighted]
+                            .iter()
+                            .filter(|connection| (connection.day, connection.hop.run) == (first.day, first.hop.run))
+                            .map(|connection| self.stations[connection.hop.to].clone()),
+                    );
+                    let (route_id, trip_id) = &days[first.day].0.runs[first.hop.run];
+                    legs.push(Leg::Ride {
+                        route_id: route_id.clone(),
+                        trip_id: trip_id.clone(),
+                        stations,
+                        departure: first.departure,
+                        arrival: last.arrival,
+                    });
+                    station = first.hop.from;
+                }
+                Reached::Walk { from, seconds } => {
+                    legs.push(Leg::Walk {
+                        from: self.stations[from].clone(),
+                        to: self.stations[station].clone(),
+                        seconds,
+                    });
+                    station = from;
+                }
+            }
+        }
+        legs.reverse();
+        let departure = match legs.first() {
+            Some(Leg::Ride { departure, .. }) => *departure,
+            _ => depart,
+        };
+        Some(Journey {
+            departure,
+            arrival: scan.arrival[destination],
+            legs,
+        })
+    }
+
+    // the hops of a service day between stations, worked out the first time it's planned over. days further than
+    // one from `today` aren't planned over again and are let go
+    fn service_day(&self, timetable: &Timetable, date: NaiveDate, today: NaiveDate) -> Arc<ServiceDay> {
+        let mut days = self.days.lock().unwrap();
+        days.retain(|cached, _| (*cached - today).num_days().abs() <= 1);
+        days.entry(date)
+            .or_insert_with(|| {
+                let mut runs = Vec::new();
+                // runs of the timetable's day by their position in `runs`
+                let mut run_idx = HashMap::new();
+                let hops = timetable
+                    .day_connections(date)
+                    .into_iter()
+                    .filter_map(|connection| {
+                        let from = *self.station_idx.get(connection.from)?;
+                        let to = *self.station_idx.get(connection.to)?;
+                        if from == to {
+                            return None;
+                        }
+                        let run = *run_idx.entry(connection.run).or_insert_with(|| {
+                            runs.push((connection.route_id.clone(), connection.trip_id.clone()));
+                            runs.len() - 1
+                        });
+                        Some(Hop {
+                            run,
+                            from,
+                            to,
+                            departure: connection.departure,
+                            arrival: connection.arrival,
+                        })
+                    })
+                    .collect();
+                Arc::new(ServiceDay { hops, runs })
+            })
+            .clone()
+    }
+
+    // walk the transfers from a station reached at `at`, a train can be boarded as soon as they're walked
+    fn walk(&self, scan: &mut Scan, from: usize, at: i64) {
+        for (to, seconds) in &self.walks[from] {
+            let walked = at + *seconds as i64;
+            scan.reach(*to, walked, walked, Reached::Walk { from, seconds: *seconds });
+        }
+    }
+}
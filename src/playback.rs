@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+// how far back frames are kept for scrubbing
+pub const HISTORY_WINDOW: Duration = Duration::from_secs(60 * 60);
+// frames received closer together than this replace the previous frame, which keeps an hour of history to ~3600 frames
+const FRAME_RESOLUTION: Duration = Duration::from_secs(1);
+const MAX_SPEED: f32 = 64.0;
+
+pub struct History<T> {
+    frames: VecDeque<(SystemTime, T)>,
+    window: Duration,
+}
+
+impl<T> History<T> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            frames: VecDeque::new(),
+            window,
+        }
+    }
+
+    pub fn record(&mut self, at: SystemTime, frame: T) {
+        if let Some((last_at, last)) = self.frames.back_mut() {
+            if at.duration_since(*last_at).unwrap_or_default() < FRAME_RESOLUTION {
+                *last = frame;
+                return;
+            }
+        }
+        self.frames.push_back((at, frame));
+
+        while let Some((first_at, _)) = self.frames.front() {
+            if at.duration_since(*first_at).unwrap_or_default() > self.window {
+                self.frames.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn start(&self) -> Option<SystemTime> {
+        self.frames.front().map(|(at, _)| *at)
+    }
+
+    pub fn end(&self) -> Option<SystemTime> {
+        self.frames.back().map(|(at, _)| *at)
+    }
+
+    pub fn latest(&self) -> Option<&(SystemTime, T)> {
+        self.frames.back()
+    }
+
+    // frames recorded within `span` before `at`, inclusive
+    pub fn window(&self, at: SystemTime, span: Duration) -> impl Iterator<Item = &(SystemTime, T)> {
+        let start = at.checked_sub(span).unwrap_or(SystemTime::UNIX_EPOCH);
+        self.frames
+            .iter()
+            .skip_while(move |(frame_at, _)| *frame_at < start)
+            .take_while(move |(frame_at, _)| *frame_at <= at)
+    }
+
+    // the most recent frame recorded at or before `at`, falling back to the oldest frame
+    pub fn frame_at(&self, at: SystemTime) -> Option<&(SystemTime, T)> {
+        let idx = self.frames.partition_point(|(frame_at, _)| *frame_at <= at);
+        self.frames.get(idx.saturating_sub(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Playback {
+    Live,
+    // speed is a multiple of real time, negative speeds play backwards and 0 is paused
+    Replay { position: SystemTime, speed: f32 },
+}
+
+pub struct PlaybackClock {
+    playback: Playback,
+    last_tick: Instant,
+}
+
+impl PlaybackClock {
+    pub fn new() -> Self {
+        Self {
+            playback: Playback::Live,
+            last_tick: Instant::now(),
+        }
+    }
+
+    pub fn playback(&self) -> Playback {
+        self.playback
+    }
+
+    // advance the playback position by the time elapsed since the last tick, clamped to the recorded range
+    // returns to live once a replay catches up to the newest frame
+    pub fn tick(&mut self, start: SystemTime, end: SystemTime) -> Playback {
+        let elapsed = self.last_tick.elapsed();
+        self.last_tick = Instant::now();
+
+        if let Playback::Replay { position, speed } = self.playback {
+            let offset = Duration::from_secs_f32(elapsed.as_secs_f32() * speed.abs());
+            let position = if speed >= 0.0 {
+                position + offset
+            } else {
+                position.checked_sub(offset).unwrap_or(start)
+            };
+
+            self.playback = if speed > 0.0 && position >= end {
+                Playback::Live
+            } else {
+                Playback::Replay {
+                    position: position.clamp(start, end),
+                    speed,
+                }
+            };
+        }
+        self.playback
+    }
+
+    pub fn toggle_pause(&mut self, end: SystemTime) {
+        self.playback = match self.playback {
+            Playback::Live => Playback::Replay {
+                position: end,
+                speed: 0.0,
+            },
+            Playback::Replay {
+                position,
+                speed: 0.0,
+            } => Playback::Replay {
+                position,
+                speed: 1.0,
+            },
+            Playback::Replay { position, .. } => Playback::Replay {
+                position,
+                speed: 0.0,
+            },
+        };
+    }
+
+    // each press doubles the rewind speed
+    pub fn rewind(&mut self, end: SystemTime) {
+        self.playback = match self.playback {
+            Playback::Live => Playback::Replay {
+                position: end,
+                speed: -2.0,
+            },
+            Playback::Replay { position, speed } => Playback::Replay {
+                position,
+                speed: if speed < 0.0 {
+                    (speed * 2.0).max(-MAX_SPEED)
+                } else {
+                    -2.0
+                },
+            },
+        };
+    }
+
+    // each press doubles the fast-forward speed, live playback can't be fast-forwarded
+    pub fn fast_forward(&mut self) {
+        if let Playback::Replay { position, speed } = self.playback {
+            self.playback = Playback::Replay {
+                position,
+                speed: if speed > 1.0 {
+                    (speed * 2.0).min(MAX_SPEED)
+                } else {
+                    2.0
+                },
+            };
+        }
+    }
+
+    pub fn go_live(&mut self) {
+        self.playback = Playback::Live;
+    }
+}
+
+impl Default for PlaybackClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,11 @@
+pub mod json;
+
 pub mod gtfs {
     pub mod realtime {
         include!(concat!(env!("OUT_DIR"), "/transit_realtime.rs"));
     }
 }
+
+pub mod mvt {
+    include!(concat!(env!("OUT_DIR"), "/vector_tile.rs"));
+}
@@ -0,0 +1,116 @@
+use serde::de::{Deserialize, Deserializer, Error};
+use serde_json::Value;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::gtfs::realtime::{
+    alert, feed_header, nyct_trip_descriptor, trip_descriptor, trip_update, vehicle_descriptor, vehicle_position,
+};
+
+// GTFS-realtime as JSON, read into the same types protobuf messages are decoded to.
+// both the proto3 JSON mapping, with lowerCamelCase names, 64-bit integers as strings and enums by name,
+// and the field names and numbers serde writes these types with are accepted
+
+// whether a feed body is JSON rather than protobuf, which can't start with {.
+// leading whitespace isn't skipped: a FeedMessage starts with its header's tag, 0x0A, a newline
+pub fn is_json(body: &[u8]) -> bool {
+    body.first() == Some(&b'{')
+}
+
+// a JSON message with its keys as proto field names, to be deserialized as a FeedMessage
+pub fn parse(body: &[u8]) -> serde_json::Result<Value> {
+    let mut value = serde_json::from_slice(body)?;
+    normalize(&mut value);
+    Ok(value)
+}
+
+// lowerCamelCase keys to snake_case, and extensions, e.g. "[transit_realtime.nyct_trip_descriptor]", by their name
+pub fn normalize(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    normalize(&mut value);
+                    (field_name(&key), value)
+                })
+                .collect();
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize),
+        _ => {}
+    }
+}
+
+pub fn field_name(key: &str) -> String {
+    let key = key
+        .strip_prefix('[')
+        .and_then(|key| key.strip_suffix(']'))
+        .map_or(key, |extension| extension.rsplit('.').next().unwrap_or(extension));
+    let mut name = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+            name.push(c.to_ascii_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+fn number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match Option::<NumberOrString<T>>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(value)) => Ok(Some(value)),
+        Some(NumberOrString::String(value)) => value.parse().map(Some).map_err(D::Error::custom),
+    }
+}
+
+pub fn uint64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    number(deserializer)
+}
+
+pub fn int64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    number(deserializer)
+}
+
+// an enum field, by its value's name as in the proto file or by its number
+macro_rules! enumeration {
+    ($($name:ident: $enumeration:ty),* $(,)?) => {$(
+        pub fn $name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i32>, D::Error> {
+            match Option::<NumberOrString<i32>>::deserialize(deserializer)? {
+                None => Ok(None),
+                Some(NumberOrString::Number(value)) => Ok(Some(value)),
+                Some(NumberOrString::String(name)) => <$enumeration>::from_str_name(&name)
+                    .map(|value| Some(value as i32))
+                    .ok_or_else(|| D::Error::custom(format!("unknown {} {}", stringify!($name), name))),
+            }
+        }
+    )*};
+}
+
+enumeration! {
+    incrementality: feed_header::Incrementality,
+    stop_schedule_relationship: trip_update::stop_time_update::ScheduleRelationship,
+    trip_schedule_relationship: trip_descriptor::ScheduleRelationship,
+    vehicle_stop_status: vehicle_position::VehicleStopStatus,
+    congestion_level: vehicle_position::CongestionLevel,
+    occupancy_status: vehicle_position::OccupancyStatus,
+    cause: alert::Cause,
+    effect: alert::Effect,
+    severity_level: alert::SeverityLevel,
+    wheelchair_accessible: vehicle_descriptor::WheelchairAccessible,
+    nyct_direction: nyct_trip_descriptor::Direction,
+}
@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::config::HistoryConfig;
+use crate::feed::{FeedUpdate, FeedUpdates, VehicleStatus};
+use crate::ids::{RouteId, StopId, TripId};
+use crate::util;
+
+pub const HISTORY_FILE: &str = "history.sqlite";
+// observations older than the retention period are deleted this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS vehicle_observations (
+    trip_id TEXT NOT NULL,
+    route_id TEXT NOT NULL,
+    stop_id TEXT NOT NULL,
+    -- POSIX timestamp the train was first seen with this stop and status
+    timestamp INTEGER NOT NULL,
+    -- STOPPED_AT or IN_TRANSIT_TO, as in GTFS-realtime
+    status TEXT NOT NULL,
+    -- placed from the timetable, the feed had no realtime position
+    scheduled INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS vehicle_observations_stop ON vehicle_observations (stop_id, route_id, timestamp);
+CREATE INDEX IF NOT EXISTS vehicle_observations_timestamp ON vehicle_observations (timestamp);
+";
+
+// records where each train was seen in a SQLite database, from the feed update stream,
+// only the moments a train's stop or status changes are kept
+pub fn spawn(config: &HistoryConfig, updates: &FeedUpdates) -> Result<()> {
+    let path = match &config.path {
+        Some(path) => path.clone(),
+        None => util::get_xdg()?.place_data_file(HISTORY_FILE)?,
+    };
+    let connection =
+        Connection::open(&path).with_context(|| format!("Failed to open the history database '{}'", path.display()))?;
+    connection
+        .execute_batch(SCHEMA)
+        .with_context(|| format!("Failed to create the history tables in '{}'", path.display()))?;
+    info!("Recording vehicle history to '{}'", path.display());
+
+    let retention = config.retention_days;
+    let mut updates = updates.subscribe();
+    thread::spawn(move || {
+        let mut recorder = Recorder {
+            connection,
+            path,
+            last_seen: HashMap::new(),
+        };
+        recorder.prune(retention);
+        let mut pruned = Instant::now();
+        loop {
+            if pruned.elapsed() >= PRUNE_INTERVAL {
+                recorder.prune(retention);
+                pruned = Instant::now();
+            }
+            match updates.blocking_recv() {
+                Ok(FeedUpdate::Vehicles { route_id, vehicles }) => recorder.record(&route_id, &vehicles),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Vehicle history fell behind, skipped {} updates", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Recorder {
+    connection: Connection,
+    path: PathBuf,
+    // each train's route, and its last recorded stop and whether it was stopped there
+    last_seen: HashMap<TripId, (RouteId, StopId, bool)>,
+}
+
+impl Recorder {
+    // every train of a route, trains that are gone are forgotten
+    fn record(&mut self, route_id: &RouteId, vehicles: &[VehicleStatus]) {
+        let now = util::posix_now();
+        self.last_seen.retain(|trip_id, (route, ..)| {
+            route != route_id || vehicles.iter().any(|vehicle| vehicle.trip_id == *trip_id)
+        });
+        let changed: Vec<&VehicleStatus> = vehicles
+            .iter()
+            .filter(|vehicle| {
+                let seen = (vehicle.route_id.clone(), vehicle.stop_id.clone(), vehicle.stopped);
+                self.last_seen.insert(vehicle.trip_id.clone(), seen.clone()) != Some(seen)
+            })
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        if let Err(err) = self.insert(&changed, now) {
+            warn!("Failed to record vehicle history to '{}': {}", self.path.display(), err);
+        }
+    }
+
+    fn insert(&mut self, vehicles: &[&VehicleStatus], now: i64) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO vehicle_observations (trip_id, route_id, stop_id, timestamp, status, scheduled)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for vehicle in vehicles {
+                let status = if vehicle.stopped { "STOPPED_AT" } else { "IN_TRANSIT_TO" };
+                insert.execute(params![
+                    vehicle.trip_id.as_str(),
+                    vehicle.route_id.as_str(),
+                    vehicle.stop_id.as_str(),
+                    now,
+                    status,
+                    vehicle.scheduled,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    fn prune(&self, retention_days: u32) {
+        if retention_days == 0 {
+            return;
+        }
+        let cutoff = util::posix_now() - retention_days as i64 * SECONDS_PER_DAY;
+        match self
+            .connection
+            .execute("DELETE FROM vehicle_observations WHERE timestamp < ?1", [cutoff])
+        {
+            Ok(0) => {}
+            Ok(deleted) => info!("Pruned {} vehicle observations older than {} days", deleted, retention_days),
+            Err(err) => warn!("Failed to prune vehicle history: {}", err),
+        }
+    }
+}
@@ -1,6 +1,26 @@
 pub use state::*;
 
+pub mod activity;
+pub mod atlas;
+pub mod bloom;
+pub mod bullet;
+pub mod camera;
+pub mod capture;
+pub mod color;
+pub mod export;
+pub mod fade;
+pub mod gesture;
+pub mod gui;
+pub mod journey;
+pub mod layer;
+pub mod picker;
+pub mod plugin;
+#[cfg(debug_assertions)]
+pub mod reload;
+pub mod spotlight;
 pub mod state;
 pub mod stop;
+pub mod timing;
+pub mod vehicle;
 
 // https://sotrh.github.io/learn-wgpu/beginner/tutorial1-window/
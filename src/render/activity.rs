@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::playback::History;
+use crate::ids::RouteId;
+
+// routes beyond this share no activity with the shader
+pub const MAX_ROUTES: usize = 64;
+// activity is averaged over frames recorded in this window
+pub const ACTIVITY_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+// a route's service at a point in time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteActivity {
+    pub trains: usize,
+    // average reported delay, in seconds
+    pub average_delay: f32,
+}
+
+// per-route aggregates for shader effects, indexed like the routes passed to State
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+pub struct ActivityUniform {
+    // x is the average number of trains, y the average delay in seconds
+    routes: [[f32; 4]; MAX_ROUTES],
+    // x is the most trains averaged by any route
+    peak: [f32; 4],
+}
+
+impl Default for ActivityUniform {
+    fn default() -> Self {
+        Self {
+            routes: [[0.0; 4]; MAX_ROUTES],
+            peak: [0.0; 4],
+        }
+    }
+}
+
+impl ActivityUniform {
+    // average the activity of frames recorded in the window ending at `at`
+    pub fn aggregate<T>(
+        history: &History<T>,
+        at: SystemTime,
+        routes: &[(RouteId, [f32; 3])],
+        activity: impl Fn(&T) -> &HashMap<RouteId, RouteActivity>,
+    ) -> Self {
+        let mut uniform = Self::default();
+        let frames: Vec<_> = history
+            .window(at, ACTIVITY_WINDOW)
+            .map(|(_, frame)| activity(frame))
+            .collect();
+        if frames.is_empty() {
+            return uniform;
+        }
+
+        for (idx, (route_id, _)) in routes.iter().take(MAX_ROUTES).enumerate() {
+            let samples = frames.iter().filter_map(|frame| frame.get(route_id));
+            let (trains, delay) = samples.fold((0.0, 0.0), |(trains, delay), sample| {
+                (trains + sample.trains as f32, delay + sample.average_delay)
+            });
+            let n = frames.len() as f32;
+            uniform.routes[idx] = [trains / n, delay / n, 0.0, 0.0];
+            uniform.peak[0] = uniform.peak[0].max(trains / n);
+        }
+        uniform
+    }
+}
@@ -0,0 +1,135 @@
+use lyon::tessellation::VertexBuffers;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+use super::Vertex;
+
+// levels of detail static geometry is tessellated at, finest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detail {
+    Full,
+    Simplified,
+    UltraSimplified,
+}
+
+impl Detail {
+    pub const ALL: [Detail; 3] = [Self::Full, Self::Simplified, Self::UltraSimplified];
+
+    // Ramer-Douglas-Peucker tolerance, in meters
+    pub fn tolerance(&self) -> f32 {
+        match self {
+            Self::Full => 0.0,
+            Self::Simplified => 10.0,
+            Self::UltraSimplified => 50.0,
+        }
+    }
+
+    // the coarsest level whose simplification stays under half a pixel
+    pub fn for_scale(meters_per_pixel: f32) -> Self {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .find(|detail| detail.tolerance() <= meters_per_pixel / 2.0)
+            .unwrap_or(Self::Full)
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+// index ranges of one atlas entry, by detail level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetailRanges([Range<u32>; 3]);
+
+impl DetailRanges {
+    pub fn get(&self, detail: Detail) -> Range<u32> {
+        self.0[detail.index()].clone()
+    }
+}
+
+// static geometry tessellated at every detail level into a single vertex and index buffer
+#[derive(Default, Serialize, Deserialize)]
+pub struct GeometryAtlas {
+    buffers: VertexBuffers<Vertex, u32>,
+    entries: HashMap<String, DetailRanges>,
+}
+
+impl GeometryAtlas {
+    // tessellate an entry once per detail level
+    pub fn add(
+        &mut self,
+        name: &'static str,
+        mut tessellate: impl FnMut(Detail, &mut VertexBuffers<Vertex, u32>),
+    ) {
+        let ranges = Detail::ALL.map(|detail| {
+            let start = self.buffers.indices.len() as u32;
+            tessellate(detail, &mut self.buffers);
+            start..self.buffers.indices.len() as u32
+        });
+        self.entries.insert(name.to_owned(), DetailRanges(ranges));
+    }
+
+    // tessellate an entry that looks the same at every detail level, e.g. markers
+    pub fn add_fixed(
+        &mut self,
+        name: &'static str,
+        tessellate: impl FnOnce(&mut VertexBuffers<Vertex, u32>),
+    ) {
+        let start = self.buffers.indices.len() as u32;
+        tessellate(&mut self.buffers);
+        let range = start..self.buffers.indices.len() as u32;
+        self.entries.insert(
+            name.to_owned(),
+            DetailRanges([range.clone(), range.clone(), range]),
+        );
+    }
+
+    // append another atlas, tessellated separately e.g. on another thread
+    pub fn merge(&mut self, other: GeometryAtlas) {
+        let vertex_offset = self.buffers.vertices.len() as u32;
+        let index_offset = self.buffers.indices.len() as u32;
+        self.buffers.vertices.extend(other.buffers.vertices);
+        self.buffers
+            .indices
+            .extend(other.buffers.indices.into_iter().map(|idx| idx + vertex_offset));
+        for (name, DetailRanges(ranges)) in other.entries {
+            let ranges = ranges.map(|range| range.start + index_offset..range.end + index_offset);
+            self.entries.insert(name, DetailRanges(ranges));
+        }
+    }
+
+    pub fn ranges(&self, name: &str) -> Option<DetailRanges> {
+        self.entries.get(name).cloned()
+    }
+
+    pub fn into_buffers(self, device: &wgpu::Device) -> AtlasBuffers {
+        AtlasBuffers {
+            vertices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Atlas Vertex Buffer"),
+                contents: bytemuck::cast_slice(&self.buffers.vertices[..]),
+                usage: wgpu::BufferUsages::VERTEX,
+            }),
+            indices: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Atlas Index Buffer"),
+                contents: bytemuck::cast_slice(&self.buffers.indices[..]),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+        }
+    }
+}
+
+// append a triangle list without shared vertices
+pub fn push_triangles(buffers: &mut VertexBuffers<Vertex, u32>, vertices: impl Iterator<Item = Vertex>) {
+    for vertex in vertices {
+        buffers.indices.push(buffers.vertices.len() as u32);
+        buffers.vertices.push(vertex);
+    }
+}
+
+pub struct AtlasBuffers {
+    pub vertices: wgpu::Buffer,
+    pub indices: wgpu::Buffer,
+}
@@ -0,0 +1,302 @@
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+use super::timing::{GpuTimer, BLOOM_PASS};
+
+// the map is drawn into a float target so the glow added over it isn't clipped before it's composited.
+// its alpha is how much each pixel glows, rather than coverage
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// times the glow is blurred horizontally then vertically, each widens it
+const BLUR_PASSES: usize = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    intensity: [f32; 4],
+}
+
+// the offscreen targets, sized to the window
+struct Targets {
+    scene: wgpu::TextureView,
+    // at half the window's size, blurred back and forth between them
+    bright: wgpu::TextureView,
+    blurred: wgpu::TextureView,
+    extract: wgpu::BindGroup,
+    horizontal: wgpu::BindGroup,
+    vertical: wgpu::BindGroup,
+    composite: wgpu::BindGroup,
+}
+
+// lit stations and trains glow softly against the dark map: the glowing parts of the map are drawn at half size,
+// blurred, and added over the map as it's copied to the window
+pub struct Bloom {
+    // 0 skips the glow and only copies the map to the window
+    intensity: f32,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform: wgpu::Buffer,
+    extract: wgpu::RenderPipeline,
+    horizontal: wgpu::RenderPipeline,
+    vertical: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+    targets: Targets,
+}
+
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+        intensity: f32,
+    ) -> Self {
+        let intensity = intensity.max(0.0);
+        let shader = device.create_shader_module(wgpu::include_wgsl!("bloom.wgsl"));
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform {
+                intensity: [intensity, 0.0, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create = |label: &str, fragment_entry_point: &str, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_fullscreen",
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment_entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let extract = create("Bloom Extract Pipeline", "fs_extract", HDR_FORMAT);
+        let horizontal = create("Bloom Horizontal Blur Pipeline", "fs_blur_horizontal", HDR_FORMAT);
+        let vertical = create("Bloom Vertical Blur Pipeline", "fs_blur_vertical", HDR_FORMAT);
+        let composite = create("Bloom Composite Pipeline", "fs_composite", surface_format);
+
+        let targets = Self::targets(device, &layout, &sampler, &uniform, size);
+        Self {
+            intensity,
+            layout,
+            sampler,
+            uniform,
+            extract,
+            horizontal,
+            vertical,
+            composite,
+            targets,
+        }
+    }
+
+    fn targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform: &wgpu::Buffer,
+        size: PhysicalSize<u32>,
+    ) -> Targets {
+        let target = |label, width: u32, height: u32| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let scene = target("Scene Texture", size.width, size.height);
+        let bright = target("Bloom Bright Texture", size.width / 2, size.height / 2);
+        let blurred = target("Bloom Blurred Texture", size.width / 2, size.height / 2);
+        let bind_group = |label, source: &wgpu::TextureView, glow: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(glow),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: uniform.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        // the glow texture is only read by the composite, the others are given the source again
+        Targets {
+            extract: bind_group("bloom_extract_bind_group", &scene, &scene),
+            horizontal: bind_group("bloom_horizontal_bind_group", &bright, &bright),
+            vertical: bind_group("bloom_vertical_bind_group", &blurred, &blurred),
+            composite: bind_group("bloom_composite_bind_group", &scene, &bright),
+            scene,
+            bright,
+            blurred,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        self.targets = Self::targets(device, &self.layout, &self.sampler, &self.uniform, size);
+    }
+
+    // what the map is drawn into, cleared to no glow
+    pub fn scene(&self) -> &wgpu::TextureView {
+        &self.targets.scene
+    }
+
+    // blur the scene's glow and draw the scene with it into the window's `view`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, timer: Option<&GpuTimer>) {
+        let glowing = self.intensity > 0.0;
+        if glowing {
+            self.pass(
+                encoder,
+                "Bloom Extract Pass",
+                &self.extract,
+                &self.targets.extract,
+                &self.targets.bright,
+                timer.map(|timer| timer.start_writes(BLOOM_PASS)),
+            );
+            for _ in 0..BLUR_PASSES {
+                self.pass(
+                    encoder,
+                    "Bloom Horizontal Blur Pass",
+                    &self.horizontal,
+                    &self.targets.horizontal,
+                    &self.targets.blurred,
+                    None,
+                );
+                self.pass(
+                    encoder,
+                    "Bloom Vertical Blur Pass",
+                    &self.vertical,
+                    &self.targets.vertical,
+                    &self.targets.bright,
+                    None,
+                );
+            }
+        }
+        let writes = timer.map(|timer| {
+            if glowing {
+                timer.end_writes(BLOOM_PASS)
+            } else {
+                timer.pass_writes(BLOOM_PASS)
+            }
+        });
+        self.pass(
+            encoder,
+            "Bloom Composite Pass",
+            &self.composite,
+            &self.targets.composite,
+            view,
+            writes,
+        );
+    }
+
+    fn pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
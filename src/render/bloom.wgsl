@@ -0,0 +1,66 @@
+// the map as drawn, rgb its color and alpha how much each pixel glows, or the glow being blurred
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+// the blurred glow, composited over the map
+@group(0) @binding(1)
+var glow: texture_2d<f32>;
+@group(0) @binding(2)
+var bloom_sampler: sampler;
+
+struct Bloom {
+  // x how strongly the glow is added to the map
+  intensity: vec4<f32>,
+};
+@group(0) @binding(3)
+var<uniform> bloom: Bloom;
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // 0 to 1 across the target, y down the screen as textures are
+    @location(0) uv: vec2<f32>,
+};
+
+// one triangle covering the target, without a vertex buffer
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.uv = uv;
+    out.clip_position = vec4<f32>(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+// the glowing parts of the map, at the size of the target they're drawn into
+@fragment
+fn fs_extract(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(source, bloom_sampler, in.uv);
+    return vec4<f32>(texel.rgb * texel.a, 1.0);
+}
+
+// a 9 tap gaussian in 5 samples, between texels so the sampler weighs each pair
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let offset = direction / vec2<f32>(textureDimensions(source));
+    var color = textureSample(source, bloom_sampler, uv).rgb * 0.2270270270;
+    color += textureSample(source, bloom_sampler, uv + offset * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(source, bloom_sampler, uv - offset * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(source, bloom_sampler, uv + offset * 3.2307692308).rgb * 0.0702702703;
+    color += textureSample(source, bloom_sampler, uv - offset * 3.2307692308).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2(0.0, 1.0));
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let map = textureSample(source, bloom_sampler, in.uv).rgb;
+    let glowing = textureSample(glow, bloom_sampler, in.uv).rgb;
+    return vec4<f32>(map + glowing * bloom.intensity.x, 1.0);
+}
@@ -0,0 +1,257 @@
+use wgpu::util::DeviceExt;
+
+use crate::ids::RouteId;
+
+// side of each route's cell in the bullet texture, in pixels
+pub const BULLET_CELL: u32 = 64;
+const COLUMNS: u32 = 8;
+const RADIUS: f32 = 30.0;
+// labels are scaled down to fit this width, so longer ones stay inside the circle
+const LABEL_WIDTH: u32 = 52;
+const MAX_GLYPH_SCALE: u32 = 6;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+// routes whose color is brighter than this get a black label, as the yellow N Q R W do on signage
+const LIGHT_LUMINANCE: f32 = 0.5;
+
+// 5x7 glyphs for route labels, a row per byte from the top with the leftmost pixel in bit 4
+const DIGITS: [[u8; 7]; 10] = [
+    [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+    [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+];
+const LETTERS: [[u8; 7]; 26] = [
+    [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+    [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+    [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+    [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+    [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+    [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+    [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+    [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+    [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+    [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+    [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+    [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+    [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+    [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+    [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+    [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+];
+
+// route bullets as on station signage, a colored circle with the route's letter or number
+// each cell keeps the circle's coverage in red and the label's in green, the route color is applied when drawn.
+// route n is in cell n, as with Vertex::route, and cell 0 is an unlabeled bullet for unknown routes
+pub struct BulletAtlas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    colors: Vec<[f32; 3]>,
+}
+
+impl BulletAtlas {
+    pub fn new(routes: &[(RouteId, [f32; 3])]) -> Self {
+        let cells = routes.len() as u32 + 1;
+        let width = COLUMNS * BULLET_CELL;
+        let height = cells.div_ceil(COLUMNS) * BULLET_CELL;
+        let mut atlas = Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            colors: vec![[0.5, 0.5, 0.5]],
+        };
+        atlas.draw(0, "");
+        for (idx, (route_id, color)) in routes.iter().enumerate() {
+            atlas.draw(idx + 1, label(route_id.as_str()));
+            atlas.colors.push(*color);
+        }
+        atlas
+    }
+
+    fn origin(index: usize) -> (u32, u32) {
+        let index = index as u32;
+        ((index % COLUMNS) * BULLET_CELL, (index / COLUMNS) * BULLET_CELL)
+    }
+
+    fn draw(&mut self, index: usize, label: &str) {
+        let glyphs: Vec<_> = label.chars().filter_map(glyph).take(3).collect();
+        let count = glyphs.len() as u32;
+        // glyphs are a pixel apart before scaling
+        let columns = (count * (GLYPH_WIDTH + 1)).saturating_sub(1);
+        let scale = (LABEL_WIDTH / columns.max(1)).min(MAX_GLYPH_SCALE);
+        let left = (BULLET_CELL - columns * scale) / 2;
+        let top = (BULLET_CELL - GLYPH_HEIGHT * scale) / 2;
+
+        let (ox, oy) = Self::origin(index);
+        let center = BULLET_CELL as f32 / 2.0;
+        for y in 0..BULLET_CELL {
+            for x in 0..BULLET_CELL {
+                let distance = (x as f32 + 0.5 - center).hypot(y as f32 + 0.5 - center);
+                let circle = (RADIUS + 0.5 - distance).clamp(0.0, 1.0);
+                let (column, row) = ((x.wrapping_sub(left)) / scale, (y.wrapping_sub(top)) / scale);
+                let text = column < columns
+                    && row < GLYPH_HEIGHT
+                    && column % (GLYPH_WIDTH + 1) < GLYPH_WIDTH
+                    && glyphs[(column / (GLYPH_WIDTH + 1)) as usize][row as usize]
+                        & (1 << (GLYPH_WIDTH - 1 - column % (GLYPH_WIDTH + 1)))
+                        != 0;
+                let offset = (((oy + y) * self.width + ox + x) * 4) as usize;
+                self.pixels[offset] = (circle * 255.0).round() as u8;
+                self.pixels[offset + 1] = if text { 255 } else { 0 };
+                self.pixels[offset + 3] = 255;
+            }
+        }
+    }
+
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bullet_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        // coverage masks, not colors, so no sRGB conversion
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Bullet Texture"),
+                size: wgpu::Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &self.pixels,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bullet Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bullet_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        })
+    }
+
+    // the bullets colored in, for the GUI
+    pub fn image(&self) -> egui::ColorImage {
+        let mut rgba = vec![0; self.pixels.len()];
+        for (index, color) in self.colors.iter().enumerate() {
+            let fill = srgb::gamma::u8_from_linear(*color);
+            let text = srgb::gamma::u8_from_linear(label_color(*color));
+            let (ox, oy) = Self::origin(index);
+            for y in oy..oy + BULLET_CELL {
+                for x in ox..ox + BULLET_CELL {
+                    let offset = ((y * self.width + x) * 4) as usize;
+                    let mix = self.pixels[offset + 1] as u32;
+                    for channel in 0..3 {
+                        rgba[offset + channel] =
+                            ((fill[channel] as u32 * (255 - mix) + text[channel] as u32 * mix) / 255) as u8;
+                    }
+                    rgba[offset + 3] = self.pixels[offset];
+                }
+            }
+        }
+        egui::ColorImage::from_rgba_unmultiplied([self.width as usize, self.height as usize], &rgba)
+    }
+
+    // where route n's bullet is in image(), in normalized texture coordinates
+    pub fn uv(&self, index: usize) -> egui::Rect {
+        let (x, y) = Self::origin(index);
+        let (width, height) = (self.width as f32, self.height as f32);
+        egui::Rect::from_min_max(
+            egui::pos2(x as f32 / width, y as f32 / height),
+            egui::pos2((x + BULLET_CELL) as f32 / width, (y + BULLET_CELL) as f32 / height),
+        )
+    }
+}
+
+// the letter or number riders know a route by, express and shuttle variants share their line's bullet
+pub fn label(route_id: &str) -> &str {
+    match route_id {
+        "FS" | "GS" | "H" => "S",
+        "SI" => "SIR",
+        _ => route_id
+            .strip_suffix('X')
+            .filter(|base| !base.is_empty())
+            .unwrap_or(route_id),
+    }
+}
+
+// white on dark routes, black on light ones, matched by fs_bullet
+pub fn label_color(color: [f32; 3]) -> [f32; 3] {
+    let luminance = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+    if luminance > LIGHT_LUMINANCE {
+        [0.0, 0.0, 0.0]
+    } else {
+        [1.0, 1.0, 1.0]
+    }
+}
+
+fn glyph(c: char) -> Option<[u8; 7]> {
+    match c {
+        '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+        'A'..='Z' => Some(LETTERS[c as usize - 'A' as usize]),
+        _ => None,
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+use super::CameraUniform;
+
+const FLIGHT_DURATION: Duration = Duration::from_millis(500);
+
+// an eased transition between two views, instead of jumping straight to the second
+pub struct CameraFlight {
+    from: CameraUniform,
+    to: CameraUniform,
+    started: Instant,
+}
+
+impl CameraFlight {
+    pub fn new(from: CameraUniform, to: CameraUniform) -> Self {
+        Self {
+            from,
+            to,
+            started: Instant::now(),
+        }
+    }
+
+    // the view now, and whether the flight has landed on its destination
+    pub fn position(&self) -> (CameraUniform, bool) {
+        let t = self.started.elapsed().as_secs_f32() / FLIGHT_DURATION.as_secs_f32();
+        if t >= 1.0 {
+            return (self.to, true);
+        }
+        (self.from.lerp(&self.to, ease_in_out(t)), false)
+    }
+}
+
+// cubic, slow to leave and slow to arrive
+fn ease_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::Local;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+use winit::dpi::PhysicalSize;
+
+use super::export::{write_png, EXPORT_DIR};
+use crate::config::{CaptureConfig, CaptureSink};
+use crate::util;
+
+// frames waiting to be written, rendering waits on the writer past this
+const CAPTURE_BUFFER: usize = 8;
+
+// records rendered frames, without the GUI, as numbered PNGs or a video encoded by ffmpeg.
+// frames are written on their own thread so encoding doesn't hold up rendering until the buffer fills
+pub struct Capture {
+    every: u32,
+    rendered: u64,
+    captured: u64,
+    // frames are all this size, ffmpeg can't take a change midway
+    pub size: PhysicalSize<u32>,
+    output: PathBuf,
+    tx: SyncSender<Vec<u8>>,
+    writer: JoinHandle<Result<()>>,
+}
+
+impl Capture {
+    pub fn start(config: &CaptureConfig, size: PhysicalSize<u32>) -> Result<Self> {
+        let xdg = util::get_xdg()?;
+        let name = format!("{EXPORT_DIR}/capture-{}", Local::now().format("%Y%m%d-%H%M%S"));
+        let (tx, rx) = sync_channel::<Vec<u8>>(CAPTURE_BUFFER);
+        let (output, writer) = match config.sink {
+            CaptureSink::Png => {
+                let dir = xdg
+                    .create_data_directory(&name)
+                    .map_err(|err| anyhow!("Failed to create the capture directory: {err}"))?;
+                let frames = dir.clone();
+                let writer = thread::spawn(move || {
+                    for (idx, pixels) in rx.into_iter().enumerate() {
+                        write_png(&frames.join(format!("frame-{idx:06}.png")), size, &pixels)?;
+                    }
+                    Ok(())
+                });
+                (dir, writer)
+            }
+            CaptureSink::Ffmpeg => {
+                let path = xdg
+                    .place_data_file(format!("{name}.{}", config.extension))
+                    .map_err(|err| anyhow!("Failed to create the export directory: {err}"))?;
+                let mut ffmpeg = Command::new("ffmpeg")
+                    .args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pix_fmt", "rgba"])
+                    .args(["-s", &format!("{}x{}", size.width, size.height)])
+                    .args(["-framerate", &config.framerate.to_string()])
+                    .args(["-i", "-"])
+                    .args(&config.ffmpeg_args)
+                    .arg(&path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .context("Failed to start ffmpeg, is it installed?")?;
+                let mut stdin = ffmpeg.stdin.take().unwrap();
+                let writer = thread::spawn(move || {
+                    for pixels in rx {
+                        stdin.write_all(&pixels).context("ffmpeg stopped taking frames")?;
+                    }
+                    // closing stdin ends the video
+                    drop(stdin);
+                    let status = ffmpeg.wait()?;
+                    if !status.success() {
+                        return Err(anyhow!("ffmpeg exited with {status}"));
+                    }
+                    Ok(())
+                });
+                (path, writer)
+            }
+        };
+        Ok(Self {
+            every: config.every.max(1),
+            rendered: 0,
+            captured: 0,
+            size,
+            output,
+            tx,
+            writer,
+        })
+    }
+
+    // count a rendered frame, true when it's one to capture
+    pub fn tick(&mut self) -> bool {
+        self.rendered += 1;
+        (self.rendered - 1).is_multiple_of(self.every as u64)
+    }
+
+    pub fn captured(&self) -> u64 {
+        self.captured
+    }
+
+    // queue a frame's RGBA pixels, waiting if the writer is behind
+    pub fn push(&mut self, pixels: Vec<u8>) -> Result<()> {
+        self.tx
+            .send(pixels)
+            .map_err(|_| anyhow!("The capture writer stopped"))?;
+        self.captured += 1;
+        Ok(())
+    }
+
+    // write out what's queued, returning where the frames went and how many there were
+    pub fn finish(self) -> Result<(PathBuf, u64)> {
+        drop(self.tx);
+        self.writer
+            .join()
+            .map_err(|_| anyhow!("The capture writer panicked"))??;
+        Ok((self.output, self.captured))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::proto::gtfs::realtime::vehicle_position::OccupancyStatus;
+use crate::status::{DELAY_THRESHOLD, HEADWAY_GAP_THRESHOLD};
+
+const GOOD: [f32; 3] = [0.0, 0.55, 0.1];
+const FAIR: [f32; 3] = [1.0, 0.6, 0.0];
+const POOR: [f32; 3] = [0.85, 0.03, 0.03];
+// shown when a source has no data for a stop
+const NO_DATA: [f32; 3] = [0.3, 0.3, 0.3];
+// headways at or under this, in seconds, are considered good
+const HEADWAY_GOOD: i64 = 5 * 60;
+
+// live data about the train at an active stop, available to color sources
+pub struct Marker {
+    pub route_color: [f32; 3],
+    // reported delay of the train's trip, in seconds
+    pub delay: Option<i32>,
+    // current largest gap between the route's trains, in seconds
+    pub headway: Option<i64>,
+    pub occupancy: Option<OccupancyStatus>,
+}
+
+// picks the color of active stop markers
+pub trait ColorSource {
+    fn color(&self, marker: &Marker) -> [f32; 3];
+}
+
+pub struct RouteColor;
+
+impl ColorSource for RouteColor {
+    fn color(&self, marker: &Marker) -> [f32; 3] {
+        marker.route_color
+    }
+}
+
+// on time is good, DELAY_THRESHOLD or later is poor
+pub struct DelayGradient;
+
+impl ColorSource for DelayGradient {
+    fn color(&self, marker: &Marker) -> [f32; 3] {
+        match marker.delay {
+            Some(delay) => gradient(delay as f32 / DELAY_THRESHOLD as f32),
+            None => NO_DATA,
+        }
+    }
+}
+
+// HEADWAY_GOOD or less is good, HEADWAY_GAP_THRESHOLD or more is poor
+pub struct HeadwayGradient;
+
+impl ColorSource for HeadwayGradient {
+    fn color(&self, marker: &Marker) -> [f32; 3] {
+        match marker.headway {
+            Some(headway) => gradient(
+                (headway - HEADWAY_GOOD) as f32 / (HEADWAY_GAP_THRESHOLD - HEADWAY_GOOD) as f32,
+            ),
+            None => NO_DATA,
+        }
+    }
+}
+
+pub struct Occupancy;
+
+impl ColorSource for Occupancy {
+    fn color(&self, marker: &Marker) -> [f32; 3] {
+        let load = match marker.occupancy {
+            Some(OccupancyStatus::Empty) => 0.0,
+            Some(OccupancyStatus::ManySeatsAvailable) => 0.2,
+            Some(OccupancyStatus::FewSeatsAvailable) => 0.4,
+            Some(OccupancyStatus::StandingRoomOnly) => 0.6,
+            Some(OccupancyStatus::CrushedStandingRoomOnly) => 0.8,
+            Some(OccupancyStatus::Full | OccupancyStatus::NotAcceptingPassengers) => 1.0,
+            _ => return NO_DATA,
+        };
+        gradient(load)
+    }
+}
+
+// selectable at runtime from the overlay panel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    #[default]
+    Route,
+    Delay,
+    Headway,
+    Occupancy,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 4] = [Self::Route, Self::Delay, Self::Headway, Self::Occupancy];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Route => "Route color",
+            Self::Delay => "Delay",
+            Self::Headway => "Headway",
+            Self::Occupancy => "Occupancy",
+        }
+    }
+
+    pub fn source(&self) -> Box<dyn ColorSource> {
+        match self {
+            Self::Route => Box::new(RouteColor),
+            Self::Delay => Box::new(DelayGradient),
+            Self::Headway => Box::new(HeadwayGradient),
+            Self::Occupancy => Box::new(Occupancy),
+        }
+    }
+}
+
+// good to poor over 0..=1
+fn gradient(t: f32) -> [f32; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let (from, to, t) = if t < 0.5 {
+        (GOOD, FAIR, t * 2.0)
+    } else {
+        (FAIR, POOR, (t - 0.5) * 2.0)
+    };
+    [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t)
+}
@@ -0,0 +1,239 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Local;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use winit::dpi::PhysicalSize;
+
+use super::atlas::Detail;
+use super::layer::LayerStack;
+use super::state::{CameraUniform, Pipelines};
+use crate::config::ExportConfig;
+use crate::util;
+
+pub const EXPORT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+pub const EXPORT_SAMPLE_COUNT: u32 = 4;
+// supersampling beyond this adds little once MSAA is resolved
+const MAX_SCALE: u32 = 4;
+pub const EXPORT_DIR: &str = "exports";
+
+// renders the map offscreen at a multiple of the window size for print quality images. its pipelines are built
+// with the window's, the textures it renders into are only created for each export
+pub struct Exporter {
+    pipelines: Pipelines,
+    scale: u32,
+}
+
+impl Exporter {
+    pub fn new(pipelines: Pipelines, config: &ExportConfig) -> Self {
+        Self {
+            pipelines,
+            scale: config.scale.clamp(1, MAX_SCALE),
+        }
+    }
+
+    // swap in pipelines from a reloaded shader
+    #[cfg(debug_assertions)]
+    pub fn set_pipelines(&mut self, pipelines: Pipelines) {
+        self.pipelines = pipelines;
+    }
+
+    // render the visible layers supersampled and with MSAA, downsample to `size` and save as PNG
+    #[allow(clippy::too_many_arguments)]
+    pub fn export(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &LayerStack,
+        bind_groups: [&wgpu::BindGroup; 3],
+        camera: &CameraUniform,
+        clear_color: wgpu::Color,
+        size: PhysicalSize<u32>,
+    ) -> Result<PathBuf> {
+        // the supersampled target has to fit the device's texture limits
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        let scale = self
+            .scale
+            .min(max_dimension / size.width.max(size.height).max(1))
+            .max(1);
+        let pixels = self.render(device, queue, layers, bind_groups, camera, clear_color, size, scale)?;
+
+        let xdg = util::get_xdg()?;
+        let file_name = format!(
+            "{EXPORT_DIR}/map-{}.png",
+            Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let path = xdg
+            .place_data_file(file_name)
+            .map_err(|err| anyhow!("Failed to create the export directory: {err}"))?;
+        write_png(&path, size, &pixels)?;
+        Ok(path)
+    }
+
+    // render the visible layers offscreen at `scale` times `size` with MSAA, returning RGBA pixels at `size`
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layers: &LayerStack,
+        bind_groups: [&wgpu::BindGroup; 3],
+        camera: &CameraUniform,
+        clear_color: wgpu::Color,
+        size: PhysicalSize<u32>,
+        scale: u32,
+    ) -> Result<Vec<u8>> {
+        if size.width == 0 || size.height == 0 {
+            bail!("Nothing to render while the window is minimized");
+        }
+        let extent = wgpu::Extent3d {
+            width: size.width * scale,
+            height: size.height * scale,
+            depth_or_array_layers: 1,
+        };
+        let target = |label, sample_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: extent,
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: EXPORT_FORMAT,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let multisampled = target(
+            "Export Multisampled Texture",
+            EXPORT_SAMPLE_COUNT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        let resolved = target(
+            "Export Resolved Texture",
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
+        let multisampled_view = multisampled.create_view(&wgpu::TextureViewDescriptor::default());
+        let resolved_view = resolved.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // rows of a texture copy are padded to COPY_BYTES_PER_ROW_ALIGNMENT
+        let row_bytes = extent.width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Export Readback Buffer"),
+            size: (padded_row_bytes * extent.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Export Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Export Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &multisampled_view,
+                    resolve_target: Some(&resolved_view),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear_color),
+                        store: wgpu::StoreOp::Discard,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            for (index, bind_group) in bind_groups.into_iter().enumerate() {
+                render_pass.set_bind_group(index as u32, bind_group, &[]);
+            }
+            // detail is chosen for the supersampled resolution
+            let detail = Detail::for_scale(camera.view_width() / extent.width as f32);
+            for layer in layers.visible() {
+                render_pass.set_pipeline(self.pipelines.get(layer.pipeline));
+                layer.draw(&mut render_pass, detail);
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            resolved.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            extent,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+        let pixels = downsample(
+            &slice.get_mapped_range(),
+            padded_row_bytes as usize,
+            size,
+            scale,
+        );
+        readback.unmap();
+        Ok(pixels)
+    }
+}
+
+pub fn write_png(path: &Path, size: PhysicalSize<u32>, pixels: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path).with_context(|| format!("Failed to create '{}'", path.display()))?),
+        size.width,
+        size.height,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
+
+// box filter each `scale` x `scale` block of sRGB texels into one pixel, averaging in linear space
+fn downsample(texels: &[u8], row_bytes: usize, size: PhysicalSize<u32>, scale: u32) -> Vec<u8> {
+    let (width, height, scale) = (size.width as usize, size.height as usize, scale as usize);
+    // nothing to average, only the row padding to drop
+    if scale == 1 {
+        return texels
+            .chunks(row_bytes)
+            .take(height)
+            .flat_map(|row| &row[..width * 4])
+            .copied()
+            .collect();
+    }
+    let samples = (scale * scale) as f32;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 4];
+            for sy in y * scale..(y + 1) * scale {
+                let row = &texels[sy * row_bytes..];
+                for sx in x * scale..(x + 1) * scale {
+                    let texel = &row[sx * 4..sx * 4 + 4];
+                    for channel in 0..3 {
+                        sum[channel] += srgb::gamma::expand_u8(texel[channel]);
+                    }
+                    sum[3] += texel[3] as f32;
+                }
+            }
+            for channel in sum.iter().take(3) {
+                pixels.push(srgb::gamma::compress_u8(channel / samples));
+            }
+            pixels.push((sum[3] / samples).round() as u8);
+        }
+    }
+    pixels
+}
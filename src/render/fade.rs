@@ -0,0 +1,87 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::stop::StopInstance;
+
+// how long a station takes to light up when a train arrives, or to go dark when it leaves
+const STOP_FADE: Duration = Duration::from_millis(300);
+
+// a station easing from how it was drawn when its frame changed to how the frame has it
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: StopInstance,
+    to: StopInstance,
+    started: Instant,
+}
+
+impl Fade {
+    // stations don't move, merging complexes fades them in at their center
+    fn at(&self, now: Instant) -> StopInstance {
+        let t = (now.saturating_duration_since(self.started).as_secs_f32() / STOP_FADE.as_secs_f32()).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        StopInstance {
+            position: self.to.position,
+            color: std::array::from_fn(|channel| lerp(self.from.color[channel], self.to.color[channel])),
+            scale: lerp(self.from.scale, self.to.scale),
+        }
+    }
+
+    fn done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= STOP_FADE
+    }
+}
+
+// eases each station's scale and color between frames instead of switching them outright.
+// easing the color from the dark station to the lit one looks the same as fading the lit one in over it
+#[derive(Default)]
+pub struct StopFades {
+    // in the order of the last frame's stations
+    fades: Vec<Fade>,
+    drawn: Vec<StopInstance>,
+    // a fade was still underway, or a new frame arrived, as of the last step
+    moving: bool,
+}
+
+impl StopFades {
+    // start fading the stations whose scale or color differs in a new frame, from wherever they're drawn now.
+    // lit stations sort after the rest, so stations are matched to their fades by where they're drawn, not their slot
+    pub fn retarget(&mut self, stops: &[StopInstance], now: Instant) {
+        let mut previous: HashMap<[u32; 3], VecDeque<Fade>> = HashMap::new();
+        for fade in self.fades.drain(..) {
+            previous.entry(fade.to.position.map(f32::to_bits)).or_default().push_back(fade);
+        }
+        for to in stops {
+            let fade = match previous.get_mut(&to.position.map(f32::to_bits)).and_then(VecDeque::pop_front) {
+                Some(fade) if fade.to.scale == to.scale && fade.to.color == to.color => Fade { to: *to, ..fade },
+                Some(fade) => Fade {
+                    from: fade.at(now),
+                    to: *to,
+                    started: now,
+                },
+                // a station's first frame, or its first since complexes were merged or split, fades in from dark
+                None => Fade {
+                    from: StopInstance {
+                        position: to.position,
+                        ..Default::default()
+                    },
+                    to: *to,
+                    started: now,
+                },
+            };
+            self.fades.push(fade);
+        }
+        self.moving = true;
+    }
+
+    // the stations as they're drawn at `now`, nothing once every fade has finished and been drawn
+    pub fn step(&mut self, now: Instant) -> Option<&[StopInstance]> {
+        if !self.moving {
+            return None;
+        }
+        self.drawn.clear();
+        self.drawn.extend(self.fades.iter().map(|fade| fade.at(now)));
+        self.moving = self.fades.iter().any(|fade| !fade.done(now));
+        Some(&self.drawn)
+    }
+}
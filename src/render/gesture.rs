@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use winit::dpi::PhysicalPosition;
+use winit::event::{Touch, TouchPhase};
+
+// a touch that ends within this many pixels of where it started is a tap, rather than a drag
+const TAP_SLOP: f64 = 10.0;
+
+// what fingers on a touchscreen do to the map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchGesture {
+    // the map follows the fingers: moved from where their midpoint was to where it is,
+    // and zoomed around it by how much further apart they are
+    Drag {
+        from: PhysicalPosition<f64>,
+        to: PhysicalPosition<f64>,
+        zoom: f64,
+    },
+    // a finger lifted without dragging, which selects what's under it like a click
+    Tap(PhysicalPosition<f64>),
+}
+
+// the fingers on a touchscreen, by winit's touch id
+#[derive(Default)]
+pub struct Touches {
+    down: HashMap<u64, PhysicalPosition<f64>>,
+    // how far the fingers have moved since the first went down
+    travelled: f64,
+}
+
+impl Touches {
+    pub fn touch(&mut self, touch: &Touch) -> Option<TouchGesture> {
+        match touch.phase {
+            TouchPhase::Started => {
+                if self.down.is_empty() {
+                    self.travelled = 0.0;
+                }
+                self.down.insert(touch.id, touch.location);
+                None
+            }
+            TouchPhase::Moved => {
+                let before = self.span()?;
+                let previous = self.down.insert(touch.id, touch.location)?;
+                self.travelled += distance(previous, touch.location);
+                let after = self.span()?;
+                let zoom = match (before.1, after.1) {
+                    (Some(from), Some(to)) if from > 0.0 => to / from,
+                    _ => 1.0,
+                };
+                Some(TouchGesture::Drag {
+                    from: before.0,
+                    to: after.0,
+                    zoom,
+                })
+            }
+            TouchPhase::Ended => {
+                let tapped = self.down.len() == 1 && self.travelled < TAP_SLOP;
+                self.down.remove(&touch.id)?;
+                tapped.then_some(TouchGesture::Tap(touch.location))
+            }
+            TouchPhase::Cancelled => {
+                self.down.remove(&touch.id);
+                None
+            }
+        }
+    }
+
+    // the midpoint of the first two fingers down, and how far apart they are when there are two
+    fn span(&self) -> Option<(PhysicalPosition<f64>, Option<f64>)> {
+        let mut ids: Vec<u64> = self.down.keys().copied().collect();
+        ids.sort_unstable();
+        let first = self.down[ids.first()?];
+        let Some(second) = ids.get(1).map(|id| self.down[id]) else {
+            return Some((first, None));
+        };
+        let midpoint = PhysicalPosition::new((first.x + second.x) / 2.0, (first.y + second.y) / 2.0);
+        Some((midpoint, Some(distance(first, second))))
+    }
+}
+
+fn distance(a: PhysicalPosition<f64>, b: PhysicalPosition<f64>) -> f64 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
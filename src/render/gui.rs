@@ -0,0 +1,710 @@
+use egui::{Color32, RichText};
+use egui_wgpu::ScreenDescriptor;
+use geo::Rect;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use super::bullet::BulletAtlas;
+use super::color::ColorMode;
+use super::journey::{JourneyAction, TripPlan};
+use super::layer::LayerStack;
+use super::picker::StopPicker;
+use super::plugin::Plugins;
+use crate::annotations::Annotations;
+use crate::feed::{Countdown, SharedFeedState};
+use crate::headway::HeadwayFlag;
+use crate::ids::{RouteId, StopId};
+use crate::planner::Leg;
+use crate::status::LineStatus;
+use crate::timetable::AGENCY_TIMEZONE;
+use crate::util::{self, format::{self, Formatter}};
+
+// per-frame data shown in the overlay
+pub struct PanelData<'a> {
+    pub picker: &'a StopPicker,
+    // None without a timetable to plan with
+    pub trip_plan: Option<&'a TripPlan>,
+    pub layers: &'a mut LayerStack,
+    pub plugins: &'a mut Plugins,
+    // width of the visible map, in meters
+    pub view_width: f32,
+    // frames captured so far, while capturing
+    pub captured: Option<u64>,
+    // names and bounds to fly the camera to
+    pub boroughs: &'a [(String, Rect<f32>)],
+    // the map is still being tessellated
+    pub loading: bool,
+    // why the map couldn't be tessellated, it stays loading
+    pub load_error: Option<&'a str>,
+    // smoothed milliseconds the GPU spent in each render pass, with --gpu-timing
+    pub gpu_times: Option<Vec<(&'static str, f64)>>,
+}
+
+// size route bullets are shown at in the panel, in points
+const BULLET_SIZE: f32 = 18.0;
+const SEARCH_RESULTS: usize = 8;
+// how fast alert headlines scroll, in points per second
+const TICKER_SPEED: f64 = 60.0;
+const TICKER_SEPARATOR: &str = "   \u{2022}   ";
+
+// egui overlay panel for adjusting runtime controls
+pub struct Gui {
+    ctx: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    routes: Vec<(RouteId, [f32; 3])>,
+    shared: SharedFeedState,
+    formatter: Formatter,
+    annotations: Arc<Annotations>,
+    bullets: egui::TextureHandle,
+    // texture coordinates of each route's bullet, indexed as BulletAtlas
+    bullet_uvs: Vec<egui::Rect>,
+    // where the camera was asked to fly, until State takes it
+    fly_to: Option<Rect<f32>>,
+    // station search, and the result picked from it until State takes it
+    query: String,
+    searched: Option<StopId>,
+    // where a trip is to be planned from, or that it's cleared, until State takes it
+    journey_action: Option<JourneyAction>,
+}
+
+impl Gui {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        window: &Window,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        routes: Vec<(RouteId, [f32; 3])>,
+        shared: SharedFeedState,
+        formatter: Formatter,
+        annotations: Arc<Annotations>,
+        bullets: &BulletAtlas,
+    ) -> Self {
+        let ctx = egui::Context::default();
+        let bullet_uvs = (0..=routes.len()).map(|index| bullets.uv(index)).collect();
+        let bullets = ctx.load_texture("route_bullets", bullets.image(), egui::TextureOptions::LINEAR);
+        let state = egui_winit::State::new(
+            ctx.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+        let renderer = egui_wgpu::Renderer::new(device, format, None, 1, false);
+
+        Self {
+            ctx,
+            state,
+            renderer,
+            routes,
+            shared,
+            formatter,
+            annotations,
+            bullets,
+            bullet_uvs,
+            fly_to: None,
+            query: String::new(),
+            searched: None,
+            journey_action: None,
+        }
+    }
+
+    pub fn take_fly_to(&mut self) -> Option<Rect<f32>> {
+        self.fly_to.take()
+    }
+
+    pub fn take_searched(&mut self) -> Option<StopId> {
+        self.searched.take()
+    }
+
+    pub fn take_journey_action(&mut self) -> Option<JourneyAction> {
+        self.journey_action.take()
+    }
+
+    // returns whether egui consumed the event
+    pub fn input(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.state.on_window_event(window, event).consumed
+    }
+
+    // lay out the panel and draw it on top of `view`
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        window: &Window,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+        mut data: PanelData,
+    ) {
+        let input = self.state.take_egui_input(window);
+        let ctx = self.ctx.clone();
+        let output = ctx.run(input, |ctx| {
+            self.status_bar(ctx, data.captured, data.gpu_times.as_deref());
+            self.ticker(ctx);
+            self.panel(ctx, &mut data);
+            if data.loading {
+                egui::Area::new(egui::Id::new("loading"))
+                    .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                    .show(ctx, |ui| match data.load_error {
+                        Some(err) => {
+                            ui.label(RichText::new(format!("Unable to draw the map: {err}")).strong());
+                        }
+                        None => {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(RichText::new("Drawing the map...").strong());
+                            });
+                        }
+                    });
+            }
+        });
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        let paint_jobs = ctx.tessellate(output.shapes, output.pixels_per_point);
+        let size = window.inner_size();
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.renderer
+            .update_buffers(device, queue, encoder, &paint_jobs, &screen_descriptor);
+        {
+            let mut render_pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("GUI Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes,
+                })
+                .forget_lifetime();
+            self.renderer
+                .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+        }
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+
+    // routes grouped by line status, as colored route pills
+    fn status_bar(&self, ctx: &egui::Context, captured: Option<u64>, gpu_times: Option<&[(&str, f64)]>) {
+        let statuses = self.shared.statuses.all();
+        egui::TopBottomPanel::top("line_status").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for status in [
+                    LineStatus::GoodService,
+                    LineStatus::Delays,
+                    LineStatus::ServiceChange,
+                ] {
+                    let routes: Vec<_> = self
+                        .routes
+                        .iter()
+                        .filter(|(route_id, _)| statuses.get(route_id) == Some(&status))
+                        .collect();
+                    if routes.is_empty() {
+                        continue;
+                    }
+                    ui.label(RichText::new(status.label()).color(status_color(status)));
+                    for (route_id, color) in routes {
+                        route_pill(ui, route_id.as_str(), *color);
+                    }
+                    ui.separator();
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let now = util::posix_now();
+                    let feeds = self.shared.snapshot.feeds();
+                    ui.label(RichText::new(format!("{} ET", self.formatter.clock(now, AGENCY_TIMEZONE))).strong());
+                    // the newest header across feeds, lagging data shows as a gap to the clock
+                    if let Some(updated) = feeds.iter().filter_map(|feed| feed.updated).max() {
+                        ui.label(format!("data as of {}", self.formatter.clock(updated as i64, AGENCY_TIMEZONE)));
+                    }
+                    ui.separator();
+                    ui.label(format!("{}/s", format::bytes(self.shared.stats.bandwidth())));
+                    if self.shared.controls.read().unwrap().low_bandwidth {
+                        ui.label("low bandwidth");
+                    }
+                    let stale = feeds.iter().filter(|feed| feed.stale).count();
+                    if stale > 0 {
+                        let label = if stale == 1 {
+                            "1 feed stale".to_string()
+                        } else {
+                            format!("{stale} feeds stale")
+                        };
+                        ui.label(RichText::new(label).color(STALE_COLOR));
+                    }
+                    if let Some(frames) = captured {
+                        ui.label(RichText::new(format!("capturing, {frames} frames")).color(Color32::RED));
+                    }
+                    if let Some(times) = gpu_times {
+                        let times: Vec<String> = times.iter().map(|(pass, ms)| format!("{pass} {ms:.2} ms")).collect();
+                        ui.label(RichText::new(format!("GPU {}", times.join(", "))).monospace());
+                    }
+                });
+            });
+        });
+    }
+
+    // alert headlines scrolling right to left along the bottom of the window, nothing without alerts
+    fn ticker(&self, ctx: &egui::Context) {
+        let headlines = self.shared.snapshot.headlines();
+        if headlines.is_empty() {
+            return;
+        }
+        egui::TopBottomPanel::bottom("alert_ticker").show(ctx, |ui| {
+            let color = ui.visuals().strong_text_color();
+            let galley = ui.painter().layout_no_wrap(
+                headlines.join(TICKER_SEPARATOR),
+                egui::TextStyle::Body.resolve(ui.style()),
+                color,
+            );
+            let (rect, _) = ui.allocate_exact_size(
+                egui::vec2(ui.available_width(), galley.size().y),
+                egui::Sense::hover(),
+            );
+            // enters at the right edge and starts over once it's left past the left
+            let travel = rect.width() + galley.size().x;
+            let scrolled = (ui.input(|input| input.time) * TICKER_SPEED % travel as f64) as f32;
+            ui.painter()
+                .with_clip_rect(rect)
+                .galley(egui::pos2(rect.right() - scrolled, rect.top()), galley, color);
+        });
+    }
+
+    fn panel(&mut self, ctx: &egui::Context, data: &mut PanelData) {
+        egui::Window::new("NYC Subway")
+            .default_pos([10.0, 10.0])
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.query).hint_text("Search stations"));
+                for stop in data.picker.search(&self.query, SEARCH_RESULTS) {
+                    let result = ui.selectable_label(false, &stop.name).on_hover_text(stop.id.as_str());
+                    if result.clicked() {
+                        self.searched = Some(stop.id.clone());
+                        self.query.clear();
+                    }
+                }
+
+                ui.collapsing("Layers", |ui| {
+                    for layer in data.layers.iter_mut() {
+                        ui.checkbox(&mut layer.visible, layer.name);
+                    }
+                    for (name, visible) in data.plugins.toggles() {
+                        ui.checkbox(visible, name);
+                    }
+                    let mut controls = self.shared.controls.write().unwrap();
+                    ui.checkbox(&mut controls.merge_complexes, "merge station complexes")
+                        .on_hover_text("draw connected stations as one, with their arrivals combined");
+                });
+                ui.label(format!(
+                    "Showing {} across",
+                    self.formatter.distance(data.view_width)
+                ));
+                if !data.boroughs.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        for (name, rect) in data.boroughs {
+                            if ui.small_button(name).clicked() {
+                                self.fly_to = Some(*rect);
+                            }
+                        }
+                    });
+                }
+
+                ui.collapsing("Routes", |ui| {
+                    let mut controls = self.shared.controls.write().unwrap();
+                    ui.horizontal_wrapped(|ui| {
+                        for (route_id, color) in &self.routes {
+                            let mut visible = !controls.hidden_routes.contains(route_id);
+                            let label = RichText::new(route_id.as_str()).color(route_color(*color));
+                            let mut checkbox = ui.checkbox(&mut visible, label);
+                            if let Some(annotation) = self.annotations.route(route_id.as_str()) {
+                                checkbox = checkbox.on_hover_text(annotation.summary());
+                            }
+                            if checkbox.changed() {
+                                toggle(&mut controls.hidden_routes, route_id, visible);
+                            }
+                        }
+                    });
+                    egui::ComboBox::from_label("spotlight")
+                        .selected_text(controls.spotlight.as_ref().map_or("none", RouteId::as_str))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut controls.spotlight, None, "none");
+                            for (route_id, color) in &self.routes {
+                                let label = RichText::new(route_id.as_str()).color(route_color(*color));
+                                ui.selectable_value(&mut controls.spotlight, Some(route_id.clone()), label);
+                            }
+                        })
+                        .response
+                        .on_hover_text("dim everything but one route's line, stations and trains");
+                });
+
+                ui.collapsing("Station colors", |ui| {
+                    let mut controls = self.shared.controls.write().unwrap();
+                    for mode in ColorMode::ALL {
+                        ui.radio_value(&mut controls.color_mode, mode, mode.label());
+                    }
+                });
+
+                ui.collapsing("Polling", |ui| {
+                    let mut controls = self.shared.controls.write().unwrap();
+                    ui.checkbox(&mut controls.paused, "paused");
+                    ui.checkbox(&mut controls.low_bandwidth, "low bandwidth")
+                        .on_hover_text("only fetch feeds for visible routes, at most every 30 seconds");
+                    let mut millis = controls.polling_interval.as_millis() as u64;
+                    if ui
+                        .add(egui::Slider::new(&mut millis, 50..=5000).text("interval (ms)"))
+                        .changed()
+                    {
+                        controls.polling_interval = Duration::from_millis(millis);
+                    }
+                    let mut millis = controls.update_budget.as_millis() as u64;
+                    if ui
+                        .add(egui::Slider::new(&mut millis, 1..=100).text("update budget (ms)"))
+                        .changed()
+                    {
+                        controls.update_budget = Duration::from_millis(millis);
+                    }
+                    let mut minutes = controls.stale_after.as_secs() / 60;
+                    if ui
+                        .add(egui::Slider::new(&mut minutes, 1..=30).text("stale after (min)"))
+                        .on_hover_text("dim a feed's stations and trains when it hasn't updated in this long")
+                        .changed()
+                    {
+                        controls.stale_after = Duration::from_secs(minutes * 60);
+                    }
+                });
+
+                ui.collapsing("Feeds", |ui| {
+                    let now = util::posix_now();
+                    egui::Grid::new("feed_ages").show(ui, |ui| {
+                        for feed in self.shared.snapshot.feeds() {
+                            let routes: Vec<_> = feed.routes.iter().map(RouteId::as_str).collect();
+                            ui.label(routes.join(" "));
+                            let age = match feed.age(now) {
+                                Some(age) => format!("updated {} ago", format::age(age)),
+                                None => "not fetched yet".to_string(),
+                            };
+                            let color = if feed.stale { STALE_COLOR } else { ui.visuals().text_color() };
+                            ui.label(RichText::new(age).color(color));
+                            if let Some(updated) = feed.updated {
+                                ui.label(self.formatter.clock(updated as i64, AGENCY_TIMEZONE));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                ui.collapsing("Headways", |ui| {
+                    let headways = self.shared.headways.all();
+                    if headways.is_empty() {
+                        ui.label("waiting for trains to arrive");
+                    }
+                    egui::Grid::new("headways").show(ui, |ui| {
+                        for headway in headways {
+                            let color = self
+                                .routes
+                                .iter()
+                                .find(|(route_id, _)| *route_id == headway.route_id)
+                                .map_or([1.0; 3], |(_, color)| *color);
+                            ui.label(RichText::new(headway.route_id.as_str()).strong().color(route_color(color)));
+                            ui.label(headway.direction.label());
+                            ui.label(format!("every {}", format::age(headway.median)))
+                                .on_hover_text(format!(
+                                    "{} to {} over {} trains",
+                                    format::age(headway.shortest),
+                                    format::age(headway.longest),
+                                    headway.samples + 1
+                                ));
+                            match headway.flag {
+                                Some(flag) => ui.label(RichText::new(flag.label()).color(headway_color(flag))),
+                                None => ui.label(""),
+                            };
+                            ui.end_row();
+                        }
+                    });
+                });
+
+                self.selected_train(ui, data.picker);
+                self.trip_plan(ui, data.picker, data.trip_plan);
+
+                if let Some(stop) = data.picker.selected_stop() {
+                    ui.separator();
+                    ui.heading(data.picker.selected_name().unwrap_or_default());
+                    if data.trip_plan.is_some() && ui.small_button("Plan a trip from here").clicked() {
+                        self.journey_action = Some(JourneyAction::From(stop.id.clone()));
+                    }
+                    if let Some(annotation) = self.annotations.station(stop.id.as_str()) {
+                        ui.label(RichText::new(annotation.summary()).italics());
+                    }
+                    if let Some(station) = data.picker.accessibility() {
+                        let label = if station.notes.is_empty() {
+                            station.access.label().to_string()
+                        } else {
+                            format!("{}: {}", station.access.label(), station.notes)
+                        };
+                        if self.shared.outages.elevator_out(stop.id.as_str()) {
+                            ui.label(RichText::new(format!("{label}, but not right now")).color(OUTAGE_COLOR))
+                                .on_hover_text("an elevator is out of service");
+                        } else {
+                            ui.label(label);
+                        }
+                    }
+                    for outage in self.shared.outages.station(stop.id.as_str()) {
+                        ui.label(RichText::new(outage.summary()).color(OUTAGE_COLOR));
+                    }
+                    let countdowns = data.picker.countdowns();
+                    if countdowns.is_empty() {
+                        ui.label("No upcoming arrivals");
+                    }
+                    egui::Grid::new("countdowns").show(ui, |ui| {
+                        for countdown in &countdowns {
+                            self.countdown_row(ui, countdown);
+                        }
+                    });
+
+                    // services at stations connected by a transfer
+                    let nearby: Vec<_> = data
+                        .picker
+                        .nearby()
+                        .into_iter()
+                        .filter(|(_, _, countdowns)| !countdowns.is_empty())
+                        .collect();
+                    if !nearby.is_empty() {
+                        ui.separator();
+                        ui.label(RichText::new("Also nearby").strong());
+                        egui::Grid::new("nearby_countdowns").show(ui, |ui| {
+                            for (stop, min_time, countdowns) in &nearby {
+                                ui.label(RichText::new(&stop.name).italics());
+                                if let Some(seconds) = min_time {
+                                    ui.label(format!("{} min walk", seconds.div_ceil(60)));
+                                }
+                                ui.end_row();
+                                for countdown in countdowns {
+                                    self.countdown_row(ui, countdown);
+                                }
+                            }
+                        });
+                    }
+                }
+            });
+    }
+
+    // the selected train's direction, assignment and track, from NYCT's extensions to the feed,
+    // then how late it's running and where it stops next
+    fn selected_train(&self, ui: &mut egui::Ui, picker: &StopPicker) {
+        let Some(trip_id) = self.shared.controls.read().unwrap().selected_trip.clone() else {
+            return;
+        };
+        let Some(vehicle) = self
+            .shared
+            .snapshot
+            .all_vehicles()
+            .into_iter()
+            .find(|vehicle| vehicle.trip_id == trip_id)
+        else {
+            return;
+        };
+        ui.separator();
+        let heading = ui.heading(format!("{} train", vehicle.route_id));
+        if let Some(train_id) = &vehicle.train_id {
+            heading.on_hover_text(train_id);
+        }
+        let mut details = Vec::new();
+        if let Some(direction) = vehicle.direction {
+            details.push(direction.label().to_string());
+        }
+        match vehicle.assigned {
+            Some(true) => details.push("assigned".to_string()),
+            Some(false) => details.push("not yet assigned".to_string()),
+            None if vehicle.scheduled => details.push("scheduled".to_string()),
+            None => {}
+        }
+        if !details.is_empty() {
+            ui.label(details.join(", "));
+        }
+        if let Some(track) = &vehicle.track {
+            match (&track.scheduled, &track.actual) {
+                (Some(scheduled), Some(actual)) if track.changed() => {
+                    let label = format!("on track {actual}, scheduled for {scheduled}");
+                    ui.label(RichText::new(label).color(STALE_COLOR))
+                        .on_hover_text("moved off its scheduled track, predictions may not hold");
+                }
+                _ => {
+                    if let Some(current) = track.current() {
+                        ui.label(format!("track {current}"));
+                    }
+                }
+            }
+        }
+        let Some(trip) = self.shared.snapshot.selected_trip().filter(|trip| trip.trip_id == trip_id) else {
+            return;
+        };
+        match trip.delay {
+            Some(delay) if delay >= 60 => {
+                ui.label(RichText::new(format!("{} late", format::age(delay as i64))).color(STALE_COLOR));
+            }
+            Some(delay) if delay <= -60 => {
+                ui.label(format!("{} early", format::age(-delay as i64)));
+            }
+            Some(_) => {
+                ui.label("on time");
+            }
+            None => {}
+        }
+        if trip.next_stops.is_empty() {
+            return;
+        }
+        let now = util::posix_now();
+        egui::Grid::new("next_stops").show(ui, |ui| {
+            for stop in &trip.next_stops {
+                ui.label(picker.name(stop.stop_id.as_str()).unwrap_or(stop.stop_id.as_str()));
+                ui.label(format!("{} min", (stop.time - now).max(0) / 60));
+                ui.label(self.formatter.time(stop.time, AGENCY_TIMEZONE));
+                ui.end_row();
+            }
+        });
+    }
+
+    // where the trip being planned is from, then each leg of the journey found once a destination's picked
+    fn trip_plan(&mut self, ui: &mut egui::Ui, picker: &StopPicker, trip_plan: Option<&TripPlan>) {
+        let Some((trip_plan, origin)) = trip_plan.and_then(|trip_plan| Some((trip_plan, trip_plan.origin()?))) else {
+            return;
+        };
+        let name = |stop_id: &StopId| picker.name(stop_id.as_str()).unwrap_or(stop_id.as_str()).to_owned();
+        ui.separator();
+        ui.heading(format!("From {}", name(origin)));
+        match (trip_plan.destination(), trip_plan.journey()) {
+            (None, _) => {
+                ui.label("Pick a destination on the map");
+            }
+            (Some(destination), _) if trip_plan.planning() => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Planning a trip to {}", name(destination)));
+                });
+            }
+            (Some(destination), None) => {
+                ui.label(format!("No trains found to {}", name(destination)));
+            }
+            (Some(destination), Some(journey)) => {
+                ui.label(format!(
+                    "To {}, arriving {} after {}",
+                    name(destination),
+                    self.formatter.time(journey.arrival, AGENCY_TIMEZONE),
+                    format::age((journey.arrival - util::posix_now()).max(0))
+                ));
+                egui::Grid::new("journey_legs").show(ui, |ui| {
+                    for leg in &journey.legs {
+                        match leg {
+                            Leg::Ride {
+                                route_id,
+                                stations,
+                                departure,
+                                arrival,
+                                ..
+                            } => {
+                                let color = self
+                                    .routes
+                                    .iter()
+                                    .find(|(id, _)| id == route_id)
+                                    .map_or([1.0; 3], |(_, color)| *color);
+                                route_pill(ui, route_id.as_str(), color);
+                                if let (Some(first), Some(last)) = (stations.first(), stations.last()) {
+                                    ui.label(format!("{} to {}", name(first), name(last)))
+                                        .on_hover_text(format!("{} stops", stations.len() - 1));
+                                }
+                                ui.label(format!(
+                                    "{} - {}",
+                                    self.formatter.time(*departure, AGENCY_TIMEZONE),
+                                    self.formatter.time(*arrival, AGENCY_TIMEZONE)
+                                ));
+                            }
+                            Leg::Walk { from, to, seconds } => {
+                                ui.label("walk");
+                                ui.label(format!("{} to {}", name(from), name(to)));
+                                ui.label(format!("{} min", seconds.div_ceil(60)));
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            }
+        }
+        if ui.small_button("Clear trip").clicked() {
+            self.journey_action = Some(JourneyAction::Clear);
+        }
+    }
+
+    fn countdown_row(&self, ui: &mut egui::Ui, countdown: &Countdown) {
+        let index = self
+            .routes
+            .iter()
+            .position(|(route_id, _)| *route_id == countdown.route_id)
+            .map_or(0, |idx| idx + 1);
+        let bullet = egui::Image::new((self.bullets.id(), egui::vec2(BULLET_SIZE, BULLET_SIZE)))
+            .uv(self.bullet_uvs[index]);
+        ui.add(bullet).on_hover_text(countdown.route_id.as_str());
+        let pattern = countdown.service_pattern.map_or("", |pattern| pattern.label());
+        ui.label(RichText::new(pattern).weak());
+        let minutes: Vec<_> = countdown.minutes.iter().map(i64::to_string).collect();
+        ui.label(format!("{} min", minutes.join(", ")));
+        ui.label(self.formatter.time(countdown.next_arrival, AGENCY_TIMEZONE));
+        ui.end_row();
+    }
+}
+
+fn toggle(hidden: &mut HashSet<RouteId>, route_id: &RouteId, visible: bool) {
+    if visible {
+        hidden.remove(route_id);
+    } else {
+        hidden.insert(route_id.to_owned());
+    }
+}
+
+fn route_pill(ui: &mut egui::Ui, route_id: &str, color: [f32; 3]) {
+    egui::Frame::none()
+        .fill(route_color(color))
+        .rounding(8.0)
+        .inner_margin(egui::Margin::symmetric(6.0, 1.0))
+        .show(ui, |ui| {
+            ui.label(RichText::new(route_id).strong().color(Color32::WHITE));
+        });
+}
+
+const OUTAGE_COLOR: Color32 = Color32::from_rgb(0x4d, 0x8f, 0xff);
+const STALE_COLOR: Color32 = Color32::from_rgb(0xff, 0x98, 0x00);
+
+fn headway_color(flag: HeadwayFlag) -> Color32 {
+    match flag {
+        HeadwayFlag::Bunching => STALE_COLOR,
+        HeadwayFlag::LongGap => status_color(LineStatus::Delays),
+    }
+}
+
+fn status_color(status: LineStatus) -> Color32 {
+    let [r, g, b] = status.color();
+    Color32::from_rgb(r, g, b)
+}
+
+// route colors are linear, egui expects sRGB
+fn route_color(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = srgb::gamma::u8_from_linear(color);
+    Color32::from_rgb(r, g, b)
+}
@@ -0,0 +1,149 @@
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use super::picker::StopPicker;
+use super::stop::StopInstance;
+use crate::feed::FeedSnapshot;
+use crate::ids::{RouteId, StopId};
+use crate::planner::{Journey, Leg, Planner};
+use crate::timetable::Timetable;
+use crate::util;
+
+// upper bound on stations marked along a trip plan, sizes their instance buffer
+pub const MAX_JOURNEY_STOPS: usize = 256;
+// walks between stations are marked in white
+const WALK_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+// what the overlay asks of the trip plan, until State takes it
+#[derive(Debug, Clone, PartialEq)]
+pub enum JourneyAction {
+    // plan from a station, to the next one picked on the map
+    From(StopId),
+    Clear,
+}
+
+// a trip planned on the map: an origin picked from the overlay, then a destination clicked on the map
+pub struct TripPlan {
+    planner: Arc<Planner>,
+    timetable: Arc<Timetable>,
+    // trains running late are planned with their delays
+    snapshot: FeedSnapshot,
+    origin: Option<StopId>,
+    destination: Option<StopId>,
+    journey: Option<Journey>,
+    // the journey being planned on a thread of its own, so the map isn't held up
+    planning: Option<Receiver<Option<Journey>>>,
+}
+
+impl TripPlan {
+    pub fn new(planner: Planner, timetable: Arc<Timetable>, snapshot: FeedSnapshot) -> Self {
+        Self {
+            planner: Arc::new(planner),
+            timetable,
+            snapshot,
+            origin: None,
+            destination: None,
+            journey: None,
+            planning: None,
+        }
+    }
+
+    // plan from a station, replacing any plan already shown
+    pub fn start(&mut self, origin: StopId) {
+        self.origin = Some(origin);
+        self.destination = None;
+        self.journey = None;
+        self.planning = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.origin = None;
+        self.destination = None;
+        self.journey = None;
+        self.planning = None;
+    }
+
+    // the origin, while no destination has been picked
+    pub fn waiting(&self) -> Option<&StopId> {
+        self.origin.as_ref().filter(|_| self.destination.is_none())
+    }
+
+    // start planning from the origin to a station, leaving now. `planned` has the journey once it's found
+    pub fn plan_to(&mut self, destination: StopId) {
+        let Some(origin) = self.waiting().cloned() else {
+            return;
+        };
+        let (tx, rx) = channel();
+        let (planner, timetable) = (self.planner.clone(), self.timetable.clone());
+        let delays = self.snapshot.trip_delays();
+        let to = destination.clone();
+        thread::spawn(move || {
+            let journey = planner.plan(&timetable, origin.as_str(), to.as_str(), util::posix_now(), &delays);
+            // nobody's waiting for it when another plan was started since
+            let _ = tx.send(journey);
+        });
+        self.destination = Some(destination);
+        self.planning = Some(rx);
+    }
+
+    // whether a journey was being planned and has been since the last call, None when none was found
+    pub fn planned(&mut self) -> Option<Option<&Journey>> {
+        let journey = match self.planning.as_ref()?.try_recv() {
+            Ok(journey) => journey,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => None,
+        };
+        self.planning = None;
+        self.journey = journey;
+        Some(self.journey.as_ref())
+    }
+
+    // a destination has been picked and the journey to it is still being planned
+    pub fn planning(&self) -> bool {
+        self.planning.is_some()
+    }
+
+    pub fn origin(&self) -> Option<&StopId> {
+        self.origin.as_ref()
+    }
+
+    pub fn destination(&self) -> Option<&StopId> {
+        self.destination.as_ref()
+    }
+
+    pub fn journey(&self) -> Option<&Journey> {
+        self.journey.as_ref()
+    }
+
+    // a marker on each station of the journey, in the color of the route it's ridden on
+    pub fn instances(&self, picker: &StopPicker, routes: &[(RouteId, [f32; 3])]) -> Vec<StopInstance> {
+        let Some(journey) = &self.journey else {
+            return Vec::new();
+        };
+        let mut instances = Vec::new();
+        for leg in &journey.legs {
+            let (stations, color): (Vec<&StopId>, [f32; 3]) = match leg {
+                Leg::Ride { route_id, stations, .. } => (
+                    stations.iter().collect(),
+                    routes
+                        .iter()
+                        .find(|(id, _)| id == route_id)
+                        .map_or(WALK_COLOR, |(_, color)| *color),
+                ),
+                Leg::Walk { from, to, .. } => (vec![from, to], WALK_COLOR),
+            };
+            instances.extend(
+                stations
+                    .into_iter()
+                    .filter_map(|stop_id| picker.coord(stop_id.as_str()))
+                    .map(|coord| StopInstance {
+                        position: [coord.x, coord.y, 0.0],
+                        color,
+                        scale: 0.25,
+                    }),
+            );
+        }
+        instances
+    }
+}
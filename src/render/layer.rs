@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use super::atlas::{AtlasBuffers, Detail, DetailRanges};
+
+pub const WATER_LAYER: &str = "Water";
+pub const BOROS_LAYER: &str = "Boroughs";
+pub const PARKS_LAYER: &str = "Parks";
+pub const SHAPES_LAYER: &str = "Route lines";
+pub const TRANSFERS_LAYER: &str = "Transfers";
+pub const STOPS_LAYER: &str = "Stations";
+pub const VEHICLES_LAYER: &str = "Trains";
+pub const ANNOTATIONS_LAYER: &str = "Annotations";
+pub const OUTAGES_LAYER: &str = "Elevator outages";
+pub const ACCESSIBLE_LAYER: &str = "Accessible stations";
+pub const RIDERSHIP_LAYER: &str = "Ridership";
+pub const TRAIL_LAYER: &str = "Trip trail";
+pub const JOURNEY_LAYER: &str = "Trip plan";
+
+// render pipelines a layer can be drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pipeline {
+    Geo,
+    Instanced,
+    // instanced stations, glowing once they're lit
+    Stations,
+    Vehicle,
+}
+
+// unchanged instances between dirty ones that are rewritten anyway, rather than splitting the upload
+const DIRTY_GAP: usize = 8;
+
+pub struct Instances {
+    buffer: wgpu::Buffer,
+    count: u32,
+    // a copy of what's in the buffer, to find the instances that changed
+    contents: Vec<u8>,
+}
+
+impl Instances {
+    pub fn new(buffer: wgpu::Buffer) -> Self {
+        Self {
+            buffer,
+            count: 0,
+            contents: Vec::new(),
+        }
+    }
+
+    // a buffer already holding `instances`
+    pub fn with_contents<T: bytemuck::Pod>(buffer: wgpu::Buffer, instances: &[T]) -> Self {
+        Self {
+            buffer,
+            count: instances.len() as u32,
+            contents: bytemuck::cast_slice(instances).to_vec(),
+        }
+    }
+}
+
+pub struct Layer {
+    pub name: &'static str,
+    // layers are drawn from lowest to highest
+    pub z_order: i32,
+    pub visible: bool,
+    pub pipeline: Pipeline,
+    geometry: Arc<AtlasBuffers>,
+    ranges: DetailRanges,
+    instances: Option<Instances>,
+}
+
+impl Layer {
+    pub fn new(
+        name: &'static str,
+        z_order: i32,
+        pipeline: Pipeline,
+        geometry: Arc<AtlasBuffers>,
+        ranges: DetailRanges,
+    ) -> Self {
+        Self {
+            name,
+            z_order,
+            visible: true,
+            pipeline,
+            geometry,
+            ranges,
+            instances: None,
+        }
+    }
+
+    pub fn with_instances(mut self, instances: Instances) -> Self {
+        self.instances = Some(instances);
+        self
+    }
+
+    // replace the layer's instances, anything past the buffer's capacity is dropped.
+    // only runs of instances that differ from what's in the buffer are uploaded, returns the bytes written
+    pub fn write_instances<T: bytemuck::Pod>(&mut self, queue: &wgpu::Queue, instances: &[T]) -> usize {
+        let Some(current) = &mut self.instances else {
+            return 0;
+        };
+        let stride = std::mem::size_of::<T>();
+        let capacity = current.buffer.size() as usize / stride;
+        let instances = &instances[..instances.len().min(capacity)];
+        let bytes: &[u8] = bytemuck::cast_slice(instances);
+        current.count = instances.len() as u32;
+
+        let mut written = 0;
+        let mut dirty = bytes
+            .chunks(stride)
+            .zip(0..)
+            .filter(|(instance, idx)| current.contents.get(idx * stride..(idx + 1) * stride) != Some(*instance))
+            .map(|(_, idx)| idx)
+            .peekable();
+        while let Some(start) = dirty.next() {
+            let mut end = start + 1;
+            while let Some(next) = dirty.next_if(|next| next - end <= DIRTY_GAP) {
+                end = next + 1;
+            }
+            let run = &bytes[start * stride..end * stride];
+            queue.write_buffer(&current.buffer, (start * stride) as wgpu::BufferAddress, run);
+            written += run.len();
+        }
+        current.contents.clear();
+        current.contents.extend_from_slice(bytes);
+        written
+    }
+
+    // expects the layer's pipeline to already be set
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, detail: Detail) {
+        let instances = match &self.instances {
+            Some(Instances { count: 0, .. }) => return,
+            Some(instances) => {
+                render_pass.set_vertex_buffer(1, instances.buffer.slice(..));
+                0..instances.count
+            }
+            None => 0..1,
+        };
+        render_pass.set_vertex_buffer(0, self.geometry.vertices.slice(..));
+        render_pass.set_index_buffer(self.geometry.indices.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(self.ranges.get(detail), 0, instances);
+    }
+}
+
+// registered layers, kept in z-order
+#[derive(Default)]
+pub struct LayerStack(Vec<Layer>);
+
+impl LayerStack {
+    pub fn register(&mut self, layer: Layer) {
+        let index = self.0.partition_point(|other| other.z_order <= layer.z_order);
+        self.0.insert(index, layer);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.0.iter_mut().find(|layer| layer.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Layer> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Layer> {
+        self.0.iter_mut()
+    }
+
+    pub fn visible(&self) -> impl Iterator<Item = &Layer> {
+        self.iter().filter(|layer| layer.visible)
+    }
+}
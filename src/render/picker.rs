@@ -0,0 +1,189 @@
+use geo::Coord;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::accessibility::{Accessibility, AccessibleStation};
+use crate::complexes::Complexes;
+use crate::entities::{EntityCollection, Stop, StopIndex, Transfers};
+use crate::feed::{Arrivals, Countdown, SharedControls};
+use crate::ids::StopId;
+use crate::util;
+
+// resolves clicked map coordinates to stops and looks up their arrivals
+pub struct StopPicker {
+    index: StopIndex,
+    // where stations are drawn with complexes merged, and the index over them
+    complex_centers: HashMap<StopId, Coord<f32>>,
+    merged_index: StopIndex,
+    stops: Arc<EntityCollection<BTreeMap<StopId, Stop>>>,
+    arrivals: Arrivals,
+    transfers: Arc<Transfers>,
+    complexes: Arc<Complexes>,
+    accessibility: Arc<Accessibility>,
+    controls: SharedControls,
+    selected: Option<StopId>,
+}
+
+impl StopPicker {
+    pub fn new(stops: Arc<EntityCollection<BTreeMap<StopId, Stop>>>, arrivals: Arrivals) -> Self {
+        Self {
+            index: StopIndex::new(&stops),
+            complex_centers: HashMap::new(),
+            merged_index: StopIndex::new(&stops),
+            stops,
+            arrivals,
+            transfers: Arc::default(),
+            complexes: Arc::default(),
+            accessibility: Arc::default(),
+            controls: SharedControls::default(),
+            selected: None,
+        }
+    }
+
+    pub fn with_transfers(mut self, transfers: Arc<Transfers>) -> Self {
+        self.transfers = transfers;
+        self
+    }
+
+    pub fn with_complexes(mut self, complexes: Arc<Complexes>, controls: SharedControls) -> Self {
+        self.complex_centers = complexes.centers(&self.stops);
+        self.merged_index = StopIndex::with_positions(&self.stops, &self.complex_centers);
+        self.complexes = complexes;
+        self.controls = controls;
+        self
+    }
+
+    pub fn with_accessibility(mut self, accessibility: Arc<Accessibility>) -> Self {
+        self.accessibility = accessibility;
+        self
+    }
+
+    // the selected station and, with complexes merged, the rest of its complex
+    fn selected_members(&self) -> &[StopId] {
+        match &self.selected {
+            Some(stop_id) if self.merged() => self.complexes.members(stop_id),
+            Some(stop_id) => std::slice::from_ref(stop_id),
+            None => &[],
+        }
+    }
+
+    // the selected station's name, or every name in its complex when merged
+    pub fn selected_name(&self) -> Option<String> {
+        self.selected_stop()?;
+        let mut names: Vec<&str> = Vec::new();
+        for stop in self.selected_members().iter().filter_map(|id| self.stops.get(id)) {
+            if !names.contains(&stop.name.as_str()) {
+                names.push(&stop.name);
+            }
+        }
+        Some(names.join(" / "))
+    }
+
+    fn merged(&self) -> bool {
+        self.controls.read().unwrap().merge_complexes
+    }
+
+    // select the stop drawn nearest to `coord`, clearing the selection when nothing is within `max_distance`
+    pub fn pick(&mut self, coord: Coord<f32>, max_distance: f32) -> Option<&Stop> {
+        let index = if self.merged() { &self.merged_index } else { &self.index };
+        self.selected = index.nearest(coord, max_distance).cloned();
+        self.selected_stop()
+    }
+
+    pub fn select(&mut self, stop_id: &StopId) -> Option<&Stop> {
+        self.selected = Some(stop_id.clone());
+        self.selected_stop()
+    }
+
+    // stations whose name fuzzily matches `query`, best first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&Stop> {
+        if query.trim().is_empty() {
+            return Vec::new();
+        }
+        let mut matches: Vec<_> = self
+            .stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .filter_map(|stop| Some((fuzzy_score(query, &stop.name)?, stop)))
+            .collect();
+        // equally good matches go to the shorter name, which the query covers more of
+        matches.sort_by(|(a, a_stop), (b, b_stop)| {
+            b.cmp(a).then(a_stop.name.len().cmp(&b_stop.name.len()))
+        });
+        matches.into_iter().take(limit).map(|(_, stop)| stop).collect()
+    }
+
+    pub fn name(&self, stop_id: &str) -> Option<&str> {
+        self.stops.get(stop_id).map(|stop| stop.name.as_str())
+    }
+
+    // where a stop is drawn on the map
+    pub fn coord(&self, stop_id: &str) -> Option<Coord<f32>> {
+        let stop = self.stops.get(stop_id)?;
+        let center = self.complex_centers.get(&stop.id).filter(|_| self.merged());
+        Some(center.copied().unwrap_or(stop.coord))
+    }
+
+    pub fn selected_stop(&self) -> Option<&Stop> {
+        self.selected.as_ref().and_then(|id| self.stops.get(id))
+    }
+
+    // None when the selected station isn't accessible
+    pub fn accessibility(&self) -> Option<&AccessibleStation> {
+        self.selected.as_ref().and_then(|stop_id| self.accessibility.station(stop_id.as_str()))
+    }
+
+    pub fn countdowns(&self) -> Vec<Countdown> {
+        let stop_ids: Vec<&str> = self.selected_members().iter().map(StopId::as_str).collect();
+        self.arrivals.merged_countdowns(&stop_ids, util::posix_now())
+    }
+
+    // countdowns at the stations the selected one has transfers to, with the minimum transfer time in seconds
+    pub fn nearby(&self) -> Vec<(&Stop, Option<u32>, Vec<Countdown>)> {
+        let Some(stop_id) = &self.selected else {
+            return Vec::new();
+        };
+        let now = util::posix_now();
+        let members = self.selected_members();
+        let mut nearby: Vec<(&Stop, Option<u32>)> = Vec::new();
+        for transfer in self.transfers.from(stop_id.as_str()) {
+            // platforms are shown as their station
+            let Some(station) = self.stops.station(transfer.to.as_str()) else {
+                continue;
+            };
+            // merged complex stations are already part of the selection
+            if station.id != *stop_id && !members.contains(&station.id) && !nearby.iter().any(|(other, _)| other.id == station.id) {
+                nearby.push((station, transfer.min_time));
+            }
+        }
+        nearby
+            .into_iter()
+            .map(|(stop, min_time)| {
+                (stop, min_time, self.arrivals.countdowns(stop.id.as_str(), now))
+            })
+            .collect()
+    }
+}
+
+// how well `query` matches `name` as a case-insensitive subsequence, ignoring spaces, None when it doesn't
+// runs of consecutive characters and matches at the start of words score higher, skipping over characters lower
+fn fuzzy_score(query: &str, name: &str) -> Option<i32> {
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let idx = (next..name.len()).find(|idx| name[*idx] == c)?;
+        score += 1;
+        if last.is_some_and(|last| last + 1 == idx) {
+            score += 5;
+        }
+        if idx == 0 || !name[idx - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (idx - next).min(3) as i32;
+        last = Some(idx);
+        next = idx + 1;
+    }
+    Some(score)
+}
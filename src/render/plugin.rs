@@ -0,0 +1,87 @@
+use crate::feed::{TrainEvent, TrainEventReceiver, TrainEvents};
+
+// what a plugin creates its pipelines and buffers with
+pub struct PluginContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    // the map is drawn into this format, without multisampling. its alpha is how much each pixel glows,
+    // leave it alone for a layer that shouldn't
+    pub format: wgpu::TextureFormat,
+    // group 0 while plugins draw: the camera's view-projection at binding 0 and the viewport's size in pixels
+    // at binding 1, for pipelines that follow the map as it's panned and zoomed
+    pub camera_layout: &'a wgpu::BindGroupLayout,
+}
+
+// a visual layer from outside the crate, e.g. weather radar or taxi pickups, drawn over the map's own layers
+// without changes to State. exports and captures are drawn without plugins
+pub trait Plugin {
+    // shown in the overlay's Layers section, where the plugin is toggled like the map's own layers
+    fn name(&self) -> &str;
+
+    // create GPU resources, once as the plugin is registered
+    fn init(&mut self, context: &PluginContext);
+
+    // once a frame before drawing, with the train events applied since the last frame
+    fn update(&mut self, _queue: &wgpu::Queue, _events: &[TrainEvent]) {}
+
+    // draw into the map's render pass, with the camera already bound as group 0
+    fn render(&self, pass: &mut wgpu::RenderPass<'_>);
+}
+
+struct Registered {
+    plugin: Box<dyn Plugin>,
+    visible: bool,
+}
+
+// registered plugins, drawn in the order they were registered
+pub struct Plugins {
+    registered: Vec<Registered>,
+    // only subscribed to once a plugin is registered, which the map itself never does
+    events: TrainEvents,
+    // subscribed with the first plugin, so events don't back up with nobody reading them
+    receiver: Option<TrainEventReceiver>,
+    received: Vec<TrainEvent>,
+}
+
+impl Plugins {
+    pub fn new(events: TrainEvents) -> Self {
+        Self {
+            registered: Vec::new(),
+            events,
+            receiver: None,
+            received: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, mut plugin: Box<dyn Plugin>, context: &PluginContext) {
+        plugin.init(context);
+        tracing::info!("Registered plugin {}", plugin.name());
+        self.receiver.get_or_insert_with(|| self.events.subscribe());
+        self.registered.push(Registered { plugin, visible: true });
+    }
+
+    // hidden plugins are still updated, so they're current when they're shown again
+    pub fn update(&mut self, queue: &wgpu::Queue) {
+        let Some(receiver) = &mut self.receiver else {
+            return;
+        };
+        self.received.clear();
+        self.received.extend(std::iter::from_fn(|| receiver.try_recv()));
+        for registered in &mut self.registered {
+            registered.plugin.update(queue, &self.received);
+        }
+    }
+
+    pub fn render(&self, pass: &mut wgpu::RenderPass<'_>) {
+        for registered in self.registered.iter().filter(|registered| registered.visible) {
+            registered.plugin.render(pass);
+        }
+    }
+
+    // each plugin's name and whether it's drawn, for the overlay to toggle
+    pub fn toggles(&mut self) -> impl Iterator<Item = (&str, &mut bool)> {
+        self.registered
+            .iter_mut()
+            .map(|registered| (registered.plugin.name(), &mut registered.visible))
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::bloom::HDR_FORMAT;
+use super::export::{EXPORT_FORMAT, EXPORT_SAMPLE_COUNT};
+use super::state::Pipelines;
+
+// how often the shader's modification time is checked
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// watches the map's shader in the source tree, so debug builds can rebuild their pipelines when it's saved
+// instead of restarting and tessellating the map again. A binary run away from its checkout has nothing to watch
+pub struct ShaderReload {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    layout: wgpu::PipelineLayout,
+}
+
+impl ShaderReload {
+    pub fn new(layout: wgpu::PipelineLayout) -> Self {
+        let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/render/shader.wgsl"));
+        Self {
+            modified: modified(&path),
+            path,
+            checked: Instant::now(),
+            layout,
+        }
+    }
+
+    // pipelines for the window and for exports from the shader as it was saved, once it has been.
+    // a shader that doesn't compile is logged and the pipelines already in use are kept
+    pub fn reload(&mut self, device: &wgpu::Device) -> Option<(Pipelines, Pipelines)> {
+        let source = self.changed()?;
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipelines = Pipelines::new(device, &self.layout, &shader, HDR_FORMAT, 1, true);
+        let export = Pipelines::new(
+            device,
+            &self.layout,
+            &shader,
+            EXPORT_FORMAT,
+            EXPORT_SAMPLE_COUNT,
+            false,
+        );
+        match ready(device.pop_error_scope()) {
+            Some(None) => {
+                tracing::info!("Reloaded {}", self.path.display());
+                Some((pipelines, export))
+            }
+            Some(Some(err)) => {
+                tracing::error!("{} failed to compile, keeping the last pipelines: {err}", self.path.display());
+                None
+            }
+            None => {
+                tracing::warn!("{} was rebuilt but not checked, keeping the last pipelines", self.path.display());
+                None
+            }
+        }
+    }
+
+    // the shader's source, when it's been saved since it was last read
+    fn changed(&mut self) -> Option<String> {
+        if self.checked.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match fs::read_to_string(&self.path) {
+            Ok(source) => Some(source),
+            Err(err) => {
+                tracing::warn!("Failed to read {}: {err}", self.path.display());
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+// the output of a future that's already finished, as wgpu's error scopes are on native backends
+fn ready<F: Future>(future: F) -> Option<F::Output> {
+    let waker = Waker::from(Arc::new(NoopWake));
+    match pin!(future).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
@@ -6,11 +6,52 @@ struct CameraUniform {
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+struct Viewport {
+  // xy the window's size in pixels
+  size: vec4<f32>,
+};
+@group(0) @binding(1)
+var<uniform> viewport: Viewport;
+
+// per-route service, indexed by route - 1
+struct Activity {
+  // x trains, y average delay in seconds
+  routes: array<vec4<f32>, 64>,
+  // x most trains of any route
+  peak: vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> activity: Activity;
+
+// the route picked out in the overlay, see SpotlightUniform
+struct Spotlight {
+  // 1-based, 0 when no route is spotlit
+  route: u32,
+  // how bright geometry off the route is drawn
+  dim: f32,
+  padding: vec2<f32>,
+};
+@group(1) @binding(1)
+var<uniform> spotlight: Spotlight;
+
+// route bullets, circle coverage in red and label coverage in green, see BulletAtlas
+@group(2) @binding(0)
+var bullet_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var bullet_sampler: sampler;
+const BULLET_CELL: u32 = 64u;
+// how much of the quad the bullet fills, the rest is room for its pointer
+const BULLET_BODY: f32 = 0.75;
+const LIGHT_LUMINANCE: f32 = 0.5;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) color: vec3<f32>,
     @location(2) normal: vec3<f32>,
+    // pixels lines are pushed out along their normal, 0 for geometry drawn in map coordinates
     @location(3) miter: f32,
+    // 1-based route index, 0 for geometry without a route
+    @location(8) route: u32,
 };
 
 struct InstanceInput {
@@ -19,22 +60,69 @@ struct InstanceInput {
     @location(6) scale: f32,
 }
 
+struct VehicleInput {
+    @location(4) position: vec3<f32>,
+    @location(5) color: vec3<f32>,
+    @location(6) rotation: f32,
+    @location(7) scale: f32,
+    @location(9) route: u32,
+}
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    // how much it glows with bloom, 0 to 1
+    @location(1) glow: f32,
 };
 
+struct BulletOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    // -1 to 1 across the quad, y up the screen
+    @location(1) local: vec2<f32>,
+    // direction of travel on screen
+    @location(2) heading: vec2<f32>,
+    @location(3) @interpolate(flat) route: u32,
+};
+
+
+// routes are dimmed in proportion to how many trains they run compared to the busiest route
+fn service_level(route: u32) -> f32 {
+    if (route == 0u || activity.peak.x <= 0.0) {
+        return 1.0;
+    }
+    let trains = activity.routes[route - 1u].x;
+    return mix(0.35, 1.0, clamp(trains / activity.peak.x, 0.0, 1.0));
+}
+
+// everything but the spotlit route is dimmed, the map under it included
+fn spotlit(route: u32) -> f32 {
+    if (spotlight.route == 0u || route == spotlight.route) {
+        return 1.0;
+    }
+    return spotlight.dim;
+}
 
 @vertex
 fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.color = model.color;
-    let xy = model.position + vec3(model.normal * 20.0/2.0);
+    out.color = model.color * service_level(model.route) * spotlit(model.route);
+    out.glow = 0.0;
+    let xy = model.position;
     let x = 2.0 * (xy[1] - camera.min[0]) / camera.height - 1.0;
     let y = 2.0 * (xy[0] - camera.min[1]) / camera.width - 1.0;
     out.clip_position = vec4<f32>(x, y, model.position[2], 1.0);
+    // lines keep their width on screen at any zoom, the normal is turned onto the screen as the map is
+    let along = vec2(
+        model.normal[1] * viewport.size.x / camera.height,
+        model.normal[0] * viewport.size.y / camera.width,
+    );
+    if (model.miter > 0.0 && length(along) > 0.0) {
+        let pixels = normalize(along) * model.miter;
+        out.clip_position += vec4<f32>(2.0 * pixels / viewport.size.xy, 0.0, 0.0);
+    }
     return out;
 }
 
@@ -45,6 +133,8 @@ fn vs_main_instanced(
 ) -> VertexOutput {
     var out: VertexOutput;
     out.color = instance.color;
+    // lit stations are drawn at half again their size, dark ones at their size and trail dots smaller
+    out.glow = clamp(instance.scale * 2.0, 0.0, 1.0);
     let pos = model.position + instance.position;
     let xy = pos + vec3(model.normal * 20.0/2.0);
     let dx = xy[1] + ((xy[1] - instance.position[1]) * instance.scale);
@@ -55,7 +145,50 @@ fn vs_main_instanced(
     return out;
 }
 
+// the quad stays upright on screen so the label reads, the map's x axis runs up the screen
+@vertex
+fn vs_vehicle(
+    model: VertexInput,
+    instance: VehicleInput,
+) -> BulletOutput {
+    var out: BulletOutput;
+    out.color = instance.color;
+    out.local = model.normal.xy;
+    out.route = instance.route;
+    let heading = vec2(sin(instance.rotation) / camera.height, cos(instance.rotation) / camera.width);
+    out.heading = normalize(heading);
+    let corner = model.position.xy * instance.scale;
+    let xy = instance.position.xy + vec2(corner.y, corner.x);
+    let x = 2.0 * (xy[1] - camera.min[0]) / camera.height - 1.0;
+    let y = 2.0 * (xy[0] - camera.min[1]) / camera.width - 1.0;
+    out.clip_position = vec4<f32>(x, y, model.position[2], 1.0);
+    return out;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return vec4<f32>(in.color, 1.0);
 }
+
+// stations write how much they glow to the alpha the map is drawn with, read by the bloom passes
+@fragment
+fn fs_glow(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, in.glow);
+}
+
+@fragment
+fn fs_bullet(in: BulletOutput) -> @location(0) vec4<f32> {
+    let cells = textureDimensions(bullet_texture) / BULLET_CELL;
+    let cell = vec2<f32>(vec2(in.route % cells.x, in.route / cells.x));
+    let within = clamp((vec2(in.local.x, -in.local.y) / BULLET_BODY + 1.0) / 2.0, vec2(0.0), vec2(1.0));
+    let mask = textureSample(bullet_texture, bullet_sampler, (cell + within) / vec2<f32>(cells));
+
+    // a wedge outside the circle points along the direction of travel
+    let along = dot(in.local, in.heading);
+    let across = abs(in.local.x * in.heading.y - in.local.y * in.heading.x);
+    let pointer = select(0.0, 1.0, along > 0.5 && along < 1.0 && across < (1.0 - along) * 1.2);
+
+    let luminance = dot(in.color, vec3(0.2126, 0.7152, 0.0722));
+    let label = select(vec3(1.0), vec3(0.0), luminance > LIGHT_LUMINANCE);
+    return vec4<f32>(mix(in.color, label, mask.g * mask.r), max(mask.r, pointer));
+}
@@ -0,0 +1,22 @@
+use crate::feed::SPOTLIGHT_DIM;
+
+// the route picked out in the overlay, drawn as usual while other routes and the map under them are dimmed.
+// stations and trains are dimmed in the frames sent to the renderer, the geometry by the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpotlightUniform {
+    // 1-based like the routes passed to State, 0 when no route is spotlit
+    route: u32,
+    dim: f32,
+    _padding: [f32; 2],
+}
+
+impl SpotlightUniform {
+    pub fn new(route: Option<usize>) -> Self {
+        Self {
+            route: route.map_or(0, |idx| idx as u32 + 1),
+            dim: SPOTLIGHT_DIM,
+            _padding: [0.0; 2],
+        }
+    }
+}
@@ -1,14 +1,63 @@
 use geo::{Coord, Rect};
-use prost::bytes::BufMut;
-use std::io::Write;
-use std::num::NonZero;
-use std::ops::Range;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use wgpu::util::DeviceExt;
 use wgpu::Buffer;
-use winit::event::WindowEvent;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
 use winit::window::Window;
 
+use super::activity::ActivityUniform;
+use super::atlas::{Detail, GeometryAtlas};
+use super::bloom::{Bloom, HDR_FORMAT};
+use super::bullet::BulletAtlas;
+use super::camera::CameraFlight;
+use super::capture::Capture;
+use super::export::{Exporter, EXPORT_FORMAT, EXPORT_SAMPLE_COUNT};
+use super::fade::StopFades;
+use super::gesture::{TouchGesture, Touches};
+use super::gui::{Gui, PanelData};
+use super::journey::{JourneyAction, TripPlan, MAX_JOURNEY_STOPS};
+use super::layer::{
+    Instances, Layer, LayerStack, Pipeline, ACCESSIBLE_LAYER, ANNOTATIONS_LAYER, BOROS_LAYER, JOURNEY_LAYER, OUTAGES_LAYER, PARKS_LAYER, RIDERSHIP_LAYER, SHAPES_LAYER, TRANSFERS_LAYER,
+    WATER_LAYER, STOPS_LAYER, TRAIL_LAYER, VEHICLES_LAYER,
+};
+use super::picker::StopPicker;
+use super::plugin::{Plugin, PluginContext, Plugins};
+use super::spotlight::SpotlightUniform;
+#[cfg(debug_assertions)]
+use super::reload::ShaderReload;
 use super::stop::StopInstance;
+use super::timing::{GpuTimer, GUI_PASS, MAP_PASS};
+use super::vehicle::{VehicleInstance, MAX_VEHICLES};
+use crate::accessibility::{Accessibility, Outages};
+use crate::annotations::Annotations;
+use crate::config::{CaptureConfig, ExportConfig};
+use crate::feed::{FeedSnapshot, Frame, SharedControls, SharedFeedState, MAX_TRAIL_DOTS};
+use crate::ids::{RouteId, StopId, TripId};
+use crate::planner::Planner;
+use crate::playback::{History, Playback, PlaybackClock, HISTORY_WINDOW};
+use crate::timetable::Timetable;
+use crate::util::format::Formatter;
+
+// how close a click has to land to a station, in pixels
+const PICK_RADIUS: f32 = 15.0;
+// space left around what the camera flies to, as a multiple of its size
+const FRAMING_MARGIN: f32 = 1.2;
+// how much of the map is shown around a station found by search, in meters
+const STATION_VIEW: f32 = 3000.0;
+// upper bound on stations marked with elevator outages, sizes their instance buffer
+const MAX_OUTAGE_MARKERS: usize = 512;
+// the narrowest view the camera zooms in to, in meters
+const MIN_VIEW: f32 = 500.0;
+// how far the camera zooms out, as a multiple of the whole city
+const MAX_ZOOM_OUT: f32 = 2.0;
+// zoom per notch of a mouse wheel, and per pixel scrolled with ctrl held, which is how touchpads pinch outside macOS
+const LINE_ZOOM: f64 = 1.2;
+const PIXEL_ZOOM: f64 = 1.01;
 
 // https://sotrh.github.io/learn-wgpu/beginner/tutorial2-surface/#state-new
 pub struct State<'a> {
@@ -19,30 +68,82 @@ pub struct State<'a> {
     pub size: winit::dpi::PhysicalSize<u32>,
     window: &'a Window,
     clear_color: wgpu::Color,
-    render_pipeline: wgpu::RenderPipeline,
-    stops_render_pipeline: wgpu::RenderPipeline,
-    stops_instance_buffer: wgpu::Buffer,
-    vertex_buffer: wgpu::Buffer,
-    num_vertices: usize,
-    num_stop_instances: usize,
+    pipelines: Pipelines,
+    // the map is drawn offscreen, then copied to the window with its glow
+    bloom: Bloom,
+    exporter: Exporter,
+    #[cfg(debug_assertions)]
+    shader_reload: ShaderReload,
+    layers: LayerStack,
+    // drawn over the layers
+    plugins: Plugins,
+    // the map's instances, until the atlas arrives from the thread tessellating it
+    pending: Option<PendingLayers>,
+    camera: CameraUniform,
     camera_buffer: wgpu::Buffer,
+    viewport_buffer: wgpu::Buffer,
+    // only read as plugins are added, which the map itself never does
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
-    geo_vertex_buffer: wgpu::Buffer,
-    geo_index_buffer: wgpu::Buffer,
-    geo_range: Range<u32>,
-    stops_range: Range<u32>,
+    // the view on launch, and the eased transition to a new view while one's underway
+    home: CameraUniform,
+    flight: Option<CameraFlight>,
+    // borough names and bounds, in map coordinates, to fly to
+    boroughs: Vec<(String, Rect<f32>)>,
+    activity_buffer: wgpu::Buffer,
+    spotlight_buffer: wgpu::Buffer,
+    // the spotlit route as last written to its buffer
+    spotlight: Option<RouteId>,
+    activity_bind_group: wgpu::BindGroup,
+    bullet_bind_group: wgpu::BindGroup,
+    routes: Vec<(RouteId, [f32; 3])>,
+    history: History<Frame>,
+    playback_clock: PlaybackClock,
+    // recorded time of the frame currently in the instance buffers
+    shown_frame: Option<SystemTime>,
+    // stations easing towards how the shown frame has them
+    stop_fades: StopFades,
+    // where each train in the shown frame is, to pick them
+    shown_vehicles: Vec<(TripId, Coord<f32>)>,
+    cursor_position: PhysicalPosition<f64>,
+    modifiers: ModifiersState,
+    touches: Touches,
+    picker: StopPicker,
+    // a trip planned from one station to another picked after it, without a timetable there's nothing to plan with
+    trip_plan: Option<TripPlan>,
+    controls: SharedControls,
+    gui: Gui,
+    outages: Outages,
+    // generation of the outage markers in the instance buffer
+    outage_generation: u64,
+    // elevator outages grey out accessible stations' markers too
+    accessibility: Arc<Accessibility>,
+    capture_config: CaptureConfig,
+    capture: Option<Capture>,
+    // times the render passes, with --gpu-timing
+    gpu_timer: Option<GpuTimer>,
 }
 
 impl<'a> State<'a> {
     // https://sotrh.github.io/learn-wgpu/beginner/tutorial2-surface/#state-new
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         window: &'a Window,
         camera: CameraUniform,
-        static_verts: &[Vertex],
-        geo: lyon::tessellation::VertexBuffers<Vertex, u32>,
+        atlas: Receiver<anyhow::Result<GeometryAtlas>>,
         stop_instances: &[StopInstance],
-        geo_range: Range<u32>,
-        stops_range: Range<u32>,
+        marker_instances: &[StopInstance],
+        ridership_instances: &[StopInstance],
+        picker: StopPicker,
+        routes: Vec<(RouteId, [f32; 3])>,
+        shared: SharedFeedState,
+        formatter: Formatter,
+        background: [f32; 3],
+        bloom: f32,
+        annotations: Arc<Annotations>,
+        accessibility: Arc<Accessibility>,
+        export: &ExportConfig,
+        capture_config: CaptureConfig,
     ) -> State<'a> {
         let size = window.inner_size();
 
@@ -68,7 +169,8 @@ impl<'a> State<'a> {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
+                    // only used with --gpu-timing, but features can't be added once the device exists
+                    required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                     required_limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
@@ -103,140 +205,225 @@ impl<'a> State<'a> {
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
 
         let camera_buffer = camera.into_buffer(&device);
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[ViewportUniform::new(size)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: viewport_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("camera_bind_group"),
         });
 
+        let activity_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Activity Buffer"),
+            contents: bytemuck::cast_slice(&[ActivityUniform::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let spotlight_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spotlight Buffer"),
+            contents: bytemuck::cast_slice(&[SpotlightUniform::new(None)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let activity_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("activity_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let activity_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &activity_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: activity_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: spotlight_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("activity_bind_group"),
+        });
+
+        let bullets = BulletAtlas::new(&routes);
+        let bullet_bind_group_layout = BulletAtlas::bind_group_layout(&device);
+        let bullet_bind_group = bullets.bind_group(&device, &queue, &bullet_bind_group_layout);
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &activity_bind_group_layout,
+                    &bullet_bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
+        let pipelines = Pipelines::new(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            HDR_FORMAT,
+            1,
+            true,
+        );
+        let bloom = Bloom::new(&device, config.format, size, bloom);
+        let exporter = Exporter::new(
+            Pipelines::new(
+                &device,
+                &render_pipeline_layout,
+                &shader,
+                EXPORT_FORMAT,
+                EXPORT_SAMPLE_COUNT,
+                false,
+            ),
+            export,
+        );
+        #[cfg(debug_assertions)]
+        let shader_reload = ShaderReload::new(render_pipeline_layout);
+
+        let stops_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Active Stops Instance Buffer"),
+            contents: bytemuck::cast_slice(stop_instances),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
-        let stops_render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Stops Render Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main_instanced",
-                    buffers: &[Vertex::desc(), StopInstance::desc()],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        // 4.
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-                cache: None,
-            });
+        let vehicle_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Vehicle Instance Buffer"),
+            size: (MAX_VEHICLES * std::mem::size_of::<VehicleInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&static_verts[..]),
-            usage: wgpu::BufferUsages::VERTEX,
+        let outage_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outage Marker Instance Buffer"),
+            size: (MAX_OUTAGE_MARKERS * std::mem::size_of::<StopInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
-        let geo_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Geo Vertex Buffer"),
-            contents: bytemuck::cast_slice(&geo.vertices[..]),
-            usage: wgpu::BufferUsages::VERTEX,
+        let trail_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trip Trail Instance Buffer"),
+            size: (MAX_TRAIL_DOTS * std::mem::size_of::<StopInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let journey_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trip Plan Instance Buffer"),
+            size: (MAX_JOURNEY_STOPS * std::mem::size_of::<StopInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
-        let geo_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Geo Index Buffer"),
-            contents: bytemuck::cast_slice(&geo.indices[..]),
-            usage: wgpu::BufferUsages::INDEX,
+        let marker_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Annotation Marker Instance Buffer"),
+            contents: bytemuck::cast_slice(marker_instances),
+            usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let stops_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Active Stops Instance Buffer"),
-            contents: bytemuck::cast_slice(&stop_instances[..]),
+        let accessible_instances = accessibility.markers(&shared.outages);
+        let accessible_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Accessible Station Instance Buffer"),
+            contents: bytemuck::cast_slice(&accessible_instances),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
+        let ridership_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ridership Instance Buffer"),
+            contents: bytemuck::cast_slice(ridership_instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let pending = PendingLayers {
+            atlas,
+            failed: None,
+            stops: Instances::with_contents(stops_instance_buffer, stop_instances),
+            ridership: Instances::with_contents(ridership_instance_buffer, ridership_instances),
+            trail: Instances::new(trail_instance_buffer),
+            journey: Instances::new(journey_instance_buffer),
+            vehicles: Instances::new(vehicle_instance_buffer),
+            outages: Instances::new(outage_instance_buffer),
+            accessible: Instances::with_contents(accessible_instance_buffer, &accessible_instances),
+            markers: Instances::with_contents(marker_instance_buffer, marker_instances),
+        };
+
+        let outages = shared.outages.clone();
+        let controls = shared.controls.clone();
+        let plugins = Plugins::new(shared.events.clone());
+        let gui = Gui::new(
+            window,
+            &device,
+            config.format,
+            routes.clone(),
+            shared,
+            formatter,
+            annotations,
+            &bullets,
+        );
+
         Self {
             window,
             surface,
@@ -245,27 +432,58 @@ impl<'a> State<'a> {
             config,
             size,
             clear_color: wgpu::Color {
-                r: 0.05,
-                g: 0.05,
-                b: 0.05,
+                r: background[0] as f64,
+                g: background[1] as f64,
+                b: background[2] as f64,
                 a: 1.0,
             },
-            render_pipeline,
-            vertex_buffer,
-            stops_instance_buffer,
-            stops_render_pipeline,
-            num_vertices: static_verts.len(),
-            num_stop_instances: stop_instances.len(),
+            pipelines,
+            bloom,
+            exporter,
+            #[cfg(debug_assertions)]
+            shader_reload,
+            layers: LayerStack::default(),
+            plugins,
+            pending: Some(pending),
+            camera,
             camera_buffer,
+            viewport_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
-            geo_vertex_buffer,
-            geo_index_buffer,
-            geo_range,
-            stops_range,
+            home: camera,
+            flight: None,
+            boroughs: Vec::new(),
+            activity_buffer,
+            spotlight_buffer,
+            spotlight: None,
+            activity_bind_group,
+            bullet_bind_group,
+            routes,
+            history: History::new(HISTORY_WINDOW),
+            playback_clock: PlaybackClock::new(),
+            shown_frame: None,
+            stop_fades: StopFades::default(),
+            shown_vehicles: Vec::new(),
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            modifiers: ModifiersState::default(),
+            touches: Touches::default(),
+            picker,
+            trip_plan: None,
+            controls,
+            gui,
+            outages,
+            outage_generation: 0,
+            accessibility,
+            capture_config,
+            capture: None,
+            gpu_timer: None,
         }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.read(&self.device);
+        }
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -279,44 +497,194 @@ impl<'a> State<'a> {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: self.bloom.scene(),
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        // nothing glows until it's drawn
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            a: 0.0,
+                            ..self.clear_color
+                        }),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: None,
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: self.gpu_timer.as_ref().map(|timer| timer.pass_writes(MAP_PASS)),
             });
 
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..self.num_vertices as u32, 0..1);
-
-            render_pass.set_vertex_buffer(0, self.geo_vertex_buffer.slice(..));
-            render_pass
-                .set_index_buffer(self.geo_index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(self.geo_range.clone(), 0, 0..1);
-
-            render_pass.set_pipeline(&self.stops_render_pipeline);
-            render_pass.set_vertex_buffer(1, self.stops_instance_buffer.slice(..));
-            render_pass.draw_indexed(
-                self.stops_range.clone(),
-                0,
-                0..self.num_stop_instances as u32,
-            );
+            render_pass.set_bind_group(1, &self.activity_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.bullet_bind_group, &[]);
+            let detail = Detail::for_scale(self.camera.view_width() / self.size.width as f32);
+            for layer in self.layers.visible() {
+                render_pass.set_pipeline(self.pipelines.get(layer.pipeline));
+                layer.draw(&mut render_pass, detail);
+            }
+            self.plugins.render(&mut render_pass);
+        }
+        self.bloom.render(&mut encoder, &view, self.gpu_timer.as_ref());
+        self.gui.render(
+            self.window,
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &view,
+            self.gpu_timer.as_ref().map(|timer| timer.pass_writes(GUI_PASS)),
+            PanelData {
+                picker: &self.picker,
+                trip_plan: self.trip_plan.as_ref(),
+                layers: &mut self.layers,
+                plugins: &mut self.plugins,
+                view_width: self.camera.view_width(),
+                captured: self.capture.as_ref().map(Capture::captured),
+                boroughs: &self.boroughs,
+                loading: self.pending.is_some(),
+                load_error: self.pending.as_ref().and_then(|pending| pending.failed.as_deref()),
+                gpu_times: self.gpu_timer.as_ref().and_then(GpuTimer::times),
+            },
+        );
+        if let Some(rect) = self.gui.take_fly_to() {
+            self.fly_to(CameraUniform::framing(rect, FRAMING_MARGIN));
+        }
+        if let Some(stop_id) = self.gui.take_searched() {
+            if let Some(coord) = self.picker.select(&stop_id).map(|stop| stop.coord) {
+                let half = Coord { x: STATION_VIEW / 2.0, y: STATION_VIEW / 2.0 };
+                self.fly_to(CameraUniform::framing(Rect::new(coord - half, coord + half), 1.0));
+                self.show_selection();
+                self.plan_to_selection();
+            }
+        }
+        match self.gui.take_journey_action() {
+            Some(JourneyAction::From(stop_id)) => self.plan_from(stop_id),
+            Some(JourneyAction::Clear) => self.clear_trip_plan(),
+            None => {}
+        }
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
         }
         self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.submitted();
+        }
         output.present();
+        self.capture_frame();
 
         Ok(())
     }
 
+    // time the map and GUI render passes on the GPU, shown in the status bar and logged
+    pub fn enable_gpu_timing(&mut self) {
+        self.gpu_timer = GpuTimer::new(&self.device, &self.queue);
+        if self.gpu_timer.is_none() {
+            tracing::warn!("The GPU doesn't support timestamp queries, render passes can't be timed");
+        }
+    }
+
+    // start capturing frames, or stop and finish writing them
+    pub fn toggle_capture(&mut self) {
+        if self.capture.is_some() {
+            self.stop_capture();
+            return;
+        }
+        match Capture::start(&self.capture_config, self.size) {
+            Ok(capture) => {
+                tracing::info!("Capturing every {} frames", self.capture_config.every.max(1));
+                self.capture = Some(capture);
+            }
+            Err(err) => tracing::error!("Capture failed to start: {err:#}"),
+        }
+    }
+
+    // finish writing what's been captured
+    pub fn stop_capture(&mut self) {
+        let Some(capture) = self.capture.take() else {
+            return;
+        };
+        match capture.finish() {
+            Ok((path, frames)) => tracing::info!("Captured {frames} frames to '{}'", path.display()),
+            Err(err) => tracing::error!("Capture failed: {err:#}"),
+        }
+    }
+
+    // render the frame offscreen again, without the GUI, if it's one to capture
+    fn capture_frame(&mut self) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        if !capture.tick() {
+            return;
+        }
+        let result = if capture.size != self.size {
+            Err(anyhow::anyhow!("the window was resized"))
+        } else {
+            self.exporter
+                .render(
+                    &self.device,
+                    &self.queue,
+                    &self.layers,
+                    [&self.camera_bind_group, &self.activity_bind_group, &self.bullet_bind_group],
+                    &self.camera,
+                    self.clear_color,
+                    capture.size,
+                    1,
+                )
+                .and_then(|pixels| capture.push(pixels))
+        };
+        if let Err(err) = result {
+            tracing::error!("Stopping capture: {err:#}");
+            self.stop_capture();
+        }
+    }
+
+    // write the current view to a supersampled PNG, without the GUI
+    fn export(&self) {
+        let result = self.exporter.export(
+            &self.device,
+            &self.queue,
+            &self.layers,
+            [&self.camera_bind_group, &self.activity_bind_group, &self.bullet_bind_group],
+            &self.camera,
+            self.clear_color,
+            self.size,
+        );
+        match result {
+            Ok(path) => tracing::info!("Exported '{}'", path.display()),
+            Err(err) => tracing::error!("Export failed: {err:#}"),
+        }
+    }
+
+    pub fn with_boroughs(mut self, boroughs: Vec<(String, Rect<f32>)>) -> Self {
+        self.boroughs = boroughs;
+        self
+    }
+
+    // add a layer of the plugin's own over the map, it's initialized with the device now.
+    // the map draws no plugins of its own, this is for embedders of the library
+    pub fn with_plugin(mut self, plugin: Box<dyn Plugin>) -> Self {
+        let context = PluginContext {
+            device: &self.device,
+            queue: &self.queue,
+            format: HDR_FORMAT,
+            camera_layout: &self.camera_bind_group_layout,
+        };
+        self.plugins.register(plugin, &context);
+        self
+    }
+
+    // plan trips between stations picked on the map, over the static schedule and the feeds' delays
+    pub fn with_planner(mut self, planner: Planner, timetable: Arc<Timetable>, snapshot: FeedSnapshot) -> Self {
+        self.trip_plan = Some(TripPlan::new(planner, timetable, snapshot));
+        self
+    }
+
+    // ease the camera over to a new view, from wherever it is now
+    pub fn fly_to(&mut self, camera: CameraUniform) {
+        self.flight = Some(CameraFlight::new(self.camera, camera));
+    }
+
     pub fn window(&self) -> &Window {
-        &self.window
+        self.window
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -325,39 +693,644 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.bloom.resize(&self.device, new_size);
+            self.queue.write_buffer(
+                &self.viewport_buffer,
+                0,
+                bytemuck::cast_slice(&[ViewportUniform::new(new_size)]),
+            );
         }
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        // match event {
-        //     WindowEvent::CursorMoved {
-        //         device_id: _,
-        //         position,
-        //     } => {
-        //         self.window().request_redraw();
-        //         println!("{} {}", position.x, position.y);
-        //         self.clear_color = wgpu::Color {
-        //             r: position.x.abs() / self.size.width as f64,
-        //             g: position.y.abs() / self.size.height as f64,
-        //             ..self.clear_color
-        //         };
-        //         true
-        //     }
-        //     _ => false,
-        // }
-        false
-    }
-
-    pub fn update_stops(&mut self, instances: Vec<StopInstance>) {
-        let slice: &[u8] = bytemuck::cast_slice(&instances[..]);
-
-        let mut buf = self.queue.write_buffer_with(
-            &self.stops_instance_buffer,
+        if self.gui.input(self.window, event) {
+            return true;
+        }
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } => {
+                if *code == KeyCode::KeyP {
+                    self.export();
+                    return true;
+                }
+                if *code == KeyCode::KeyC {
+                    self.toggle_capture();
+                    return true;
+                }
+                if *code == KeyCode::KeyH {
+                    self.fly_to(self.home);
+                    return true;
+                }
+                let Some(end) = self.history.end() else {
+                    return false;
+                };
+                match code {
+                    KeyCode::Space => self.playback_clock.toggle_pause(end),
+                    KeyCode::ArrowLeft => self.playback_clock.rewind(end),
+                    KeyCode::ArrowRight => self.playback_clock.fast_forward(),
+                    KeyCode::End => self.playback_clock.go_live(),
+                    _ => return false,
+                }
+                tracing::info!("Playback: {:?}", self.playback_clock.playback());
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                false
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                false
+            }
+            WindowEvent::PinchGesture { delta, .. } => {
+                self.zoom(1.0 + delta, self.cursor_position);
+                true
+            }
+            WindowEvent::PanGesture { delta, .. } => {
+                self.pan(delta.x as f64, delta.y as f64);
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                match delta {
+                    MouseScrollDelta::PixelDelta(pixels) if self.modifiers.control_key() => {
+                        self.zoom(PIXEL_ZOOM.powf(pixels.y), self.cursor_position)
+                    }
+                    // touchpads scroll by pixels, two fingers drag the map
+                    MouseScrollDelta::PixelDelta(pixels) => self.pan(pixels.x, pixels.y),
+                    MouseScrollDelta::LineDelta(_, lines) => {
+                        self.zoom(LINE_ZOOM.powf(*lines as f64), self.cursor_position)
+                    }
+                }
+                true
+            }
+            WindowEvent::Touch(touch) => {
+                match self.touches.touch(touch) {
+                    Some(TouchGesture::Drag { from, to, zoom }) => {
+                        self.pan(to.x - from.x, to.y - from.y);
+                        self.zoom(zoom, to);
+                    }
+                    Some(TouchGesture::Tap(location)) => {
+                        self.cursor_position = location;
+                        self.select_stop();
+                    }
+                    None => {}
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.select_stop();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // zoom `factor` times around a window position, between MIN_VIEW and MAX_ZOOM_OUT times the whole city
+    fn zoom(&mut self, factor: f64, around: PhysicalPosition<f64>) {
+        let width = self.camera.view_width();
+        let furthest = width / (self.home.view_width() * MAX_ZOOM_OUT);
+        let factor = (factor as f32).clamp(furthest, (width / MIN_VIEW).max(furthest));
+        self.move_camera(self.camera.zoomed(factor, around, self.size));
+    }
+
+    // drag the map `dx` pixels right and `dy` down
+    fn pan(&mut self, dx: f64, dy: f64) {
+        self.move_camera(self.camera.panned(dx, dy, self.size));
+    }
+
+    // gestures move the camera straight away, stopping any flight underway
+    fn move_camera(&mut self, camera: CameraUniform) {
+        self.flight = None;
+        self.camera = camera;
+        self.queue
+            .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+    }
+
+    fn select_stop(&mut self) {
+        let coord = self.camera.unproject(self.cursor_position, self.size);
+        let max_distance = PICK_RADIUS * self.camera.height / self.size.width as f32;
+
+        // trains are drawn over the stations, so they're picked first
+        let train = self
+            .shown_vehicles
+            .iter()
+            .map(|(trip_id, position)| {
+                let offset = *position - coord;
+                (trip_id, offset.x.hypot(offset.y))
+            })
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(trip_id, _)| trip_id.clone());
+        self.controls.write().unwrap().selected_trip = train.clone();
+        if let Some(trip_id) = train {
+            self.window.set_title(&format!("NYC Subway - train {trip_id}"));
+            tracing::info!("Selected train {trip_id}");
+            return;
+        }
+
+        if self.picker.pick(coord, max_distance).is_none() {
+            self.window.set_title("NYC Subway");
+            return;
+        }
+        self.show_selection();
+        self.plan_to_selection();
+    }
+
+    // wait for a destination to be picked, the plan already shown is cleared
+    fn plan_from(&mut self, origin: StopId) {
+        let Some(trip_plan) = &mut self.trip_plan else {
+            return;
+        };
+        tracing::info!("Planning a trip from {origin}");
+        trip_plan.start(origin);
+        self.show_trip_plan();
+    }
+
+    fn clear_trip_plan(&mut self) {
+        if let Some(trip_plan) = &mut self.trip_plan {
+            trip_plan.clear();
+        }
+        self.show_trip_plan();
+    }
+
+    // with a trip plan waiting for its destination, plan it to the selected station
+    fn plan_to_selection(&mut self) {
+        let (Some(trip_plan), Some(stop)) = (&mut self.trip_plan, self.picker.selected_stop()) else {
+            return;
+        };
+        match trip_plan.waiting() {
+            Some(origin) if *origin != stop.id => {}
+            _ => return,
+        }
+        trip_plan.plan_to(stop.id.clone());
+        self.show_trip_plan();
+    }
+
+    // once the journey being planned is found, mark it on the map
+    fn show_planned(&mut self) {
+        let Some(trip_plan) = &mut self.trip_plan else {
+            return;
+        };
+        let destination = trip_plan.destination().cloned().unwrap_or_default();
+        match trip_plan.planned() {
+            Some(Some(journey)) => tracing::info!(
+                "Planned a trip to {} in {} legs, through {} stations",
+                destination,
+                journey.legs.len(),
+                journey.stations().len()
+            ),
+            Some(None) => tracing::info!("No trip found to {}", destination),
+            None => return,
+        }
+        self.show_trip_plan();
+    }
+
+    // mark the planned journey's stations on the map
+    fn show_trip_plan(&mut self) {
+        let instances = self
+            .trip_plan
+            .as_ref()
+            .map(|trip_plan| trip_plan.instances(&self.picker, &self.routes))
+            .unwrap_or_default();
+        if let Some(layer) = self.layers.get_mut(JOURNEY_LAYER) {
+            layer.write_instances(&self.queue, &instances);
+        }
+    }
+
+    // title the window after the selected station, and log its arrivals
+    fn show_selection(&self) {
+        let Some(stop) = self.picker.selected_stop() else {
+            return;
+        };
+        self.window.set_title(&format!("NYC Subway - {}", stop.name));
+        tracing::info!("Selected {} ({})", stop.name, stop.id);
+        let outages = self.outages.station(stop.id.as_str());
+
+        for countdown in self.picker.countdowns() {
+            let pattern = countdown.service_pattern.map_or("", |pattern| pattern.label());
+            tracing::info!("  {} {} train in {:?} min", countdown.route_id, pattern, countdown.minutes);
+        }
+        for outage in outages {
+            tracing::info!("  {}", outage.summary());
+        }
+        for (stop, _, countdowns) in self.picker.nearby() {
+            for countdown in countdowns {
+                tracing::info!(
+                    "  {} train at {} in {:?} min",
+                    countdown.route_id,
+                    stop.name,
+                    countdown.minutes
+                );
+            }
+        }
+    }
+
+    // once the atlas has been tessellated, upload it and register the map's layers
+    fn register_layers(&mut self) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        if pending.failed.is_some() {
+            return;
+        }
+        let atlas = match pending.atlas.try_recv() {
+            Ok(Ok(atlas)) => atlas,
+            Err(TryRecvError::Empty) => return,
+            Ok(Err(err)) => {
+                tracing::error!("Failed to build the map: {:#}", err);
+                pending.failed = Some(format!("{:#}", err));
+                return;
+            }
+            Err(TryRecvError::Disconnected) => {
+                tracing::error!("Failed to build the map: the worker stopped");
+                pending.failed = Some("the worker stopped".to_owned());
+                return;
+            }
+        };
+        let pending = self.pending.take().unwrap();
+
+        let water_ranges = atlas.ranges(WATER_LAYER).unwrap();
+        let boros_ranges = atlas.ranges(BOROS_LAYER).unwrap();
+        let parks_ranges = atlas.ranges(PARKS_LAYER).unwrap();
+        let shapes_ranges = atlas.ranges(SHAPES_LAYER).unwrap();
+        let transfers_ranges = atlas.ranges(TRANSFERS_LAYER).unwrap();
+        let stops_ranges = atlas.ranges(STOPS_LAYER).unwrap();
+        let vehicles_ranges = atlas.ranges(VEHICLES_LAYER).unwrap();
+        let geometry = Arc::new(atlas.into_buffers(&self.device));
+
+        self.layers.register(Layer::new(
+            WATER_LAYER,
+            -10,
+            Pipeline::Geo,
+            geometry.clone(),
+            water_ranges,
+        ));
+        self.layers.register(Layer::new(
+            BOROS_LAYER,
             0,
-            NonZero::new(slice.len() as u64).unwrap(),
-        ).unwrap();
-        let mut writer = buf.writer();
-        writer.write_all(slice).unwrap();
+            Pipeline::Geo,
+            geometry.clone(),
+            boros_ranges,
+        ));
+        self.layers.register(Layer::new(
+            PARKS_LAYER,
+            5,
+            Pipeline::Geo,
+            geometry.clone(),
+            parks_ranges,
+        ));
+        self.layers.register(Layer::new(
+            SHAPES_LAYER,
+            10,
+            Pipeline::Geo,
+            geometry.clone(),
+            shapes_ranges,
+        ));
+        self.layers.register(Layer::new(
+            TRANSFERS_LAYER,
+            15,
+            Pipeline::Geo,
+            geometry.clone(),
+            transfers_ranges,
+        ));
+        // under the stations, and off until it's asked for
+        let mut ridership = Layer::new(
+            RIDERSHIP_LAYER,
+            18,
+            Pipeline::Instanced,
+            geometry.clone(),
+            stops_ranges.clone(),
+        )
+        .with_instances(pending.ridership);
+        ridership.visible = false;
+        self.layers.register(ridership);
+        self.layers.register(
+            Layer::new(
+                STOPS_LAYER,
+                20,
+                Pipeline::Stations,
+                geometry.clone(),
+                stops_ranges.clone(),
+            )
+            .with_instances(pending.stops),
+        );
+        // over the stations, under the train it follows
+        self.layers.register(
+            Layer::new(
+                TRAIL_LAYER,
+                22,
+                Pipeline::Instanced,
+                geometry.clone(),
+                stops_ranges.clone(),
+            )
+            .with_instances(pending.trail),
+        );
+        // over the trail, so the stations of a planned trip stand out
+        self.layers.register(
+            Layer::new(
+                JOURNEY_LAYER,
+                23,
+                Pipeline::Instanced,
+                geometry.clone(),
+                stops_ranges.clone(),
+            )
+            .with_instances(pending.journey),
+        );
+        self.layers.register(
+            Layer::new(
+                VEHICLES_LAYER,
+                25,
+                Pipeline::Vehicle,
+                geometry.clone(),
+                vehicles_ranges,
+            )
+            .with_instances(pending.vehicles),
+        );
+        self.layers.register(
+            Layer::new(
+                OUTAGES_LAYER,
+                28,
+                Pipeline::Instanced,
+                geometry.clone(),
+                stops_ranges.clone(),
+            )
+            .with_instances(pending.outages),
+        );
+        // off until it's asked for, like ridership
+        let mut accessible = Layer::new(
+            ACCESSIBLE_LAYER,
+            29,
+            Pipeline::Instanced,
+            geometry.clone(),
+            stops_ranges.clone(),
+        )
+        .with_instances(pending.accessible);
+        accessible.visible = false;
+        self.layers.register(accessible);
+        self.layers.register(
+            Layer::new(
+                ANNOTATIONS_LAYER,
+                30,
+                Pipeline::Instanced,
+                geometry,
+                stops_ranges,
+            )
+            .with_instances(pending.markers),
+        );
+        // frames, outages and trip plans shown while loading had no layers to go to
+        self.shown_frame = None;
+        self.outage_generation = 0;
+        self.show_trip_plan();
+    }
+
+    // advance the playback clock, swapping in the recorded frame for the current playback position
+    pub fn update(&mut self) {
+        let _span = tracing::trace_span!("render_update").entered();
+        #[cfg(debug_assertions)]
+        if let Some((pipelines, export)) = self.shader_reload.reload(&self.device) {
+            self.pipelines = pipelines;
+            self.exporter.set_pipelines(export);
+        }
+        self.register_layers();
+        self.show_planned();
+        self.plugins.update(&self.queue);
+        if let Some((generation, markers)) = self.outages.markers_since(self.outage_generation) {
+            if let Some(layer) = self.layers.get_mut(OUTAGES_LAYER) {
+                layer.write_instances(&self.queue, &markers);
+            }
+            let accessible = self.accessibility.markers(&self.outages);
+            if let Some(layer) = self.layers.get_mut(ACCESSIBLE_LAYER) {
+                layer.write_instances(&self.queue, &accessible);
+            }
+            self.outage_generation = generation;
+        }
+        let spotlight = self.controls.read().unwrap().spotlight.clone();
+        if spotlight != self.spotlight {
+            let route = spotlight
+                .as_ref()
+                .and_then(|route_id| self.routes.iter().position(|(id, _)| id == route_id));
+            self.queue
+                .write_buffer(&self.spotlight_buffer, 0, bytemuck::cast_slice(&[SpotlightUniform::new(route)]));
+            self.spotlight = spotlight;
+        }
+        if let Some(flight) = &self.flight {
+            let (camera, landed) = flight.position();
+            self.camera = camera;
+            self.queue
+                .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera]));
+            if landed {
+                self.flight = None;
+            }
+        }
+
+        let (Some(start), Some(end)) = (self.history.start(), self.history.end()) else {
+            return;
+        };
+        let frame = match self.playback_clock.tick(start, end) {
+            Playback::Live => self.history.latest(),
+            Playback::Replay { position, .. } => self.history.frame_at(position),
+        };
+
+        let now = Instant::now();
+        if let Some((at, frame)) = frame {
+            if self.shown_frame != Some(*at) {
+                let mut uploaded = 0;
+                if let Some(layer) = self.layers.get_mut(STOPS_LAYER) {
+                    self.stop_fades.retarget(&frame.stops, now);
+                    if let Some(stops) = self.stop_fades.step(now) {
+                        uploaded += layer.write_instances(&self.queue, stops);
+                    }
+                }
+                if let Some(layer) = self.layers.get_mut(VEHICLES_LAYER) {
+                    uploaded += layer.write_instances(&self.queue, &frame.vehicles);
+                }
+                if let Some(layer) = self.layers.get_mut(TRAIL_LAYER) {
+                    uploaded += layer.write_instances(&self.queue, &frame.trail);
+                }
+                self.shown_vehicles = frame
+                    .vehicle_trips
+                    .iter()
+                    .cloned()
+                    .zip(frame.vehicles.iter().map(|vehicle| Coord {
+                        x: vehicle.position[0],
+                        y: vehicle.position[1],
+                    }))
+                    .collect();
+                tracing::debug!(
+                    stops = frame.stops.len(),
+                    vehicles = frame.vehicles.len(),
+                    uploaded,
+                    "Showing new frame"
+                );
+                let activity =
+                    ActivityUniform::aggregate(&self.history, *at, &self.routes, |frame| {
+                        &frame.activity
+                    });
+                self.queue
+                    .write_buffer(&self.activity_buffer, 0, bytemuck::cast_slice(&[activity]));
+                self.shown_frame = Some(*at);
+                return;
+            }
+        }
+        // stations still fading from the last frame shown
+        if let Some(layer) = self.layers.get_mut(STOPS_LAYER) {
+            if let Some(stops) = self.stop_fades.step(now) {
+                layer.write_instances(&self.queue, stops);
+            }
+        }
+    }
+
+    // record a new frame, it is shown on the next update() while playback is live
+    pub fn update_frame(&mut self, frame: Frame) {
+        self.history.record(SystemTime::now(), frame);
+    }
+}
+
+// instance buffers of the map's layers, filled before the geometry they're drawn with is ready
+struct PendingLayers {
+    atlas: Receiver<anyhow::Result<GeometryAtlas>>,
+    // why the atlas couldn't be built, the map stays unloaded
+    failed: Option<String>,
+    stops: Instances,
+    ridership: Instances,
+    trail: Instances,
+    journey: Instances,
+    vehicles: Instances,
+    outages: Instances,
+    accessible: Instances,
+    markers: Instances,
+}
+
+// a render pipeline for each kind of layer, targeting one format and sample count.
+// emissive pipelines draw the map for bloom, with how much each pixel glows in its alpha
+pub struct Pipelines {
+    geo: wgpu::RenderPipeline,
+    instanced: wgpu::RenderPipeline,
+    stations: wgpu::RenderPipeline,
+    vehicle: wgpu::RenderPipeline,
+}
+
+impl Pipelines {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        emissive: bool,
+    ) -> Self {
+        let create = |label: &str,
+                      entry_point: &str,
+                      fragment_entry_point: &str,
+                      blend: wgpu::BlendState,
+                      write_mask: wgpu::ColorWrites,
+                      buffers: &[wgpu::VertexBufferLayout],
+                      cull_mode: Option<wgpu::Face>| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point,
+                    buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: fragment_entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        // alpha is left as it was cleared, transparent so nothing glows, except where stations write their glow for the
+        // bloom passes. blending trains adds their own alpha to it, so they glow where they're drawn
+        let glow_mask = if emissive {
+            wgpu::ColorWrites::ALL
+        } else {
+            wgpu::ColorWrites::COLOR
+        };
+        Self {
+            geo: create(
+                "Render Pipeline",
+                "vs_main",
+                "fs_main",
+                wgpu::BlendState::REPLACE,
+                wgpu::ColorWrites::COLOR,
+                &[Vertex::desc()],
+                Some(wgpu::Face::Back),
+            ),
+            instanced: create(
+                "Instanced Render Pipeline",
+                "vs_main_instanced",
+                "fs_main",
+                wgpu::BlendState::REPLACE,
+                wgpu::ColorWrites::COLOR,
+                &[Vertex::desc(), StopInstance::desc()],
+                Some(wgpu::Face::Back),
+            ),
+            stations: create(
+                "Stops Render Pipeline",
+                "vs_main_instanced",
+                "fs_glow",
+                wgpu::BlendState::REPLACE,
+                glow_mask,
+                &[Vertex::desc(), StopInstance::desc()],
+                Some(wgpu::Face::Back),
+            ),
+            // the quad's axes are swapped onto the screen, so its winding is flipped
+            vehicle: create(
+                "Vehicle Render Pipeline",
+                "vs_vehicle",
+                "fs_bullet",
+                wgpu::BlendState::ALPHA_BLENDING,
+                wgpu::ColorWrites::ALL,
+                &[Vertex::desc(), VehicleInstance::desc()],
+                None,
+            ),
+        }
+    }
+
+    pub fn get(&self, pipeline: Pipeline) -> &wgpu::RenderPipeline {
+        match pipeline {
+            Pipeline::Geo => &self.geo,
+            Pipeline::Instanced => &self.instanced,
+            Pipeline::Stations => &self.stations,
+            Pipeline::Vehicle => &self.vehicle,
+        }
     }
 }
 
@@ -378,6 +1351,69 @@ impl CameraUniform {
         }
     }
 
+    // a square view centered on a rect of map coordinates, `margin` times its larger side across
+    // the camera's min is (y, x), as the shader maps the map's x axis up the screen
+    pub fn framing(rect: Rect<f32>, margin: f32) -> Self {
+        let side = rect.width().max(rect.height()) * margin;
+        let center = rect.center();
+        Self {
+            width: side,
+            height: side,
+            min: [center.y - side / 2.0, center.x - side / 2.0],
+        }
+    }
+
+    // the view `t` of the way from this one to `other`
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        Self {
+            width: lerp(self.width, other.width),
+            height: lerp(self.height, other.height),
+            min: [lerp(self.min[0], other.min[0]), lerp(self.min[1], other.min[1])],
+        }
+    }
+
+    // the shader maps the map's y axis across the screen
+    pub fn view_width(&self) -> f32 {
+        self.height
+    }
+
+    // inverse of the vertex shader transform, from window pixels to map coordinates
+    // note the shader maps the map's x axis to clip space y and vice versa
+    pub fn unproject(&self, position: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Coord<f32> {
+        let screen_x = position.x as f32 / size.width as f32;
+        let screen_y = position.y as f32 / size.height as f32;
+        Coord {
+            x: (1.0 - screen_y) * self.width + self.min[1],
+            y: screen_x * self.height + self.min[0],
+        }
+    }
+
+    // the view `factor` times closer, with the map under a window position staying under it
+    pub fn zoomed(&self, factor: f32, around: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Self {
+        let anchor = self.unproject(around, size);
+        let screen_x = around.x as f32 / size.width as f32;
+        let screen_y = around.y as f32 / size.height as f32;
+        let width = self.width / factor;
+        let height = self.height / factor;
+        Self {
+            width,
+            height,
+            min: [anchor.y - screen_x * height, anchor.x - (1.0 - screen_y) * width],
+        }
+    }
+
+    // the view with the map dragged `dx` pixels right and `dy` down the window
+    pub fn panned(&self, dx: f64, dy: f64, size: PhysicalSize<u32>) -> Self {
+        Self {
+            min: [
+                self.min[0] - dx as f32 / size.width as f32 * self.height,
+                self.min[1] + dy as f32 / size.height as f32 * self.width,
+            ],
+            ..*self
+        }
+    }
+
     pub fn into_buffer(self, device: &wgpu::Device) -> Buffer {
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -387,18 +1423,45 @@ impl CameraUniform {
     }
 }
 
+// the window's size, for lines drawn at a constant width on screen
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewportUniform {
+    size: [f32; 4],
+}
+
+impl ViewportUniform {
+    fn new(size: PhysicalSize<u32>) -> Self {
+        Self {
+            size: [size.width as f32, size.height as f32, 0.0, 0.0],
+        }
+    }
+}
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(
+    Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize,
+)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub color: [f32; 3],
+    // lines are drawn from their center, pushed out along the normal by `miter` pixels so they keep their width
+    // on screen. Train bullets' quads hold their corner here instead
     pub normal: [f32; 3],
+    // 0 for geometry drawn at its size in map coordinates
     pub miter: f32,
+    // 1-based index into State's routes, 0 for geometry without a route
+    pub route: u32,
 }
 
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 4] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x3, 3 => Float32];
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x3,
+        3 => Float32,
+        8 => Uint32
+    ];
 
     pub fn new(coord: Coord<f32>, color: [f32; 3]) -> Self {
         Self {
@@ -423,6 +1486,7 @@ impl From<Coord<f32>> for Vertex {
             color: [0.3, 0.3, 0.3],
             normal: [0.0, 0.0, 0.0],
             miter: 0.,
+            route: 0,
         }
     }
 }
@@ -434,6 +1498,7 @@ impl From<&Coord> for Vertex {
             color: [1.0, 1.0, 1.0],
             normal: [0.0, 0.0, 0.0],
             miter: 0.,
+            route: 0,
         }
     }
 }
@@ -0,0 +1,178 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+// the render passes that are timed, in the order they're recorded
+pub const TIMED_PASSES: [&str; 3] = ["map", "bloom", "gui"];
+pub const MAP_PASS: usize = 0;
+pub const BLOOM_PASS: usize = 1;
+pub const GUI_PASS: usize = 2;
+// a timestamp at the start and end of each pass
+const QUERIES: u32 = TIMED_PASSES.len() as u32 * 2;
+const TIMESTAMP_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+// weight of each new reading in the times shown in the status bar
+const SMOOTHING: f64 = 0.1;
+// the average time of each pass is logged this often
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+// GPU time spent in each render pass, from timestamp queries written at their start and end.
+// timestamps are read back a frame or more after they're written and only one readback is in flight at once,
+// so frames rendered in the meantime go unmeasured
+pub struct GpuTimer {
+    queries: wgpu::QuerySet,
+    resolved: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    // nanoseconds per timestamp tick
+    period: f64,
+    // the frame's timestamps were copied to the readback buffer, it's mapped once the frame is submitted
+    copied: bool,
+    // whether mapping the readback buffer succeeded, once it's done
+    mapped: Option<Arc<OnceLock<bool>>>,
+    // smoothed milliseconds spent in each pass, in TIMED_PASSES order
+    times: Option<[f64; TIMED_PASSES.len()]>,
+    // summed since the last log
+    totals: [f64; TIMED_PASSES.len()],
+    samples: u32,
+    logged: Instant,
+}
+
+impl GpuTimer {
+    // devices without timestamp queries can't time anything
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let queries = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERIES,
+        });
+        let size = QUERIES as wgpu::BufferAddress * TIMESTAMP_SIZE;
+        let resolved = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pass Timestamp Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pass Timestamp Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            queries,
+            resolved,
+            readback,
+            period: queue.get_timestamp_period() as f64,
+            copied: false,
+            mapped: None,
+            times: None,
+            totals: [0.0; TIMED_PASSES.len()],
+            samples: 0,
+            logged: Instant::now(),
+        })
+    }
+
+    // for the descriptor of the pass at `pass` in TIMED_PASSES
+    pub fn pass_writes(&self, pass: usize) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.queries,
+            beginning_of_pass_write_index: Some(pass as u32 * 2),
+            end_of_pass_write_index: Some(pass as u32 * 2 + 1),
+        }
+    }
+
+    // a run of passes timed as one, the first writes the start and the last writes the end
+    pub fn start_writes(&self, pass: usize) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            end_of_pass_write_index: None,
+            ..self.pass_writes(pass)
+        }
+    }
+
+    pub fn end_writes(&self, pass: usize) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            beginning_of_pass_write_index: None,
+            ..self.pass_writes(pass)
+        }
+    }
+
+    // copy the frame's timestamps for reading back, unless an earlier frame's are still being read
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.mapped.is_some() {
+            return;
+        }
+        encoder.resolve_query_set(&self.queries, 0..QUERIES, &self.resolved, 0);
+        encoder.copy_buffer_to_buffer(&self.resolved, 0, &self.readback, 0, self.readback.size());
+        self.copied = true;
+    }
+
+    // after the frame is submitted, start reading back the timestamps it copied
+    pub fn submitted(&mut self) {
+        if !std::mem::take(&mut self.copied) {
+            return;
+        }
+        let mapped = Arc::new(OnceLock::new());
+        let done = mapped.clone();
+        self.readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = done.set(result.is_ok());
+            });
+        self.mapped = Some(mapped);
+    }
+
+    // take in the timestamps read back since the last frame, if there are any
+    pub fn read(&mut self, device: &wgpu::Device) {
+        let Some(mapped) = &self.mapped else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let Some(&ok) = mapped.get() else {
+            return;
+        };
+        self.mapped = None;
+        if !ok {
+            return;
+        }
+        let view = self.readback.slice(..).get_mapped_range();
+        let ticks: Vec<u64> = view
+            .chunks_exact(TIMESTAMP_SIZE as usize)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        drop(view);
+        self.readback.unmap();
+
+        let mut measured = [0.0; TIMED_PASSES.len()];
+        for (pass, time) in measured.iter_mut().enumerate() {
+            let (start, end) = (ticks[pass * 2], ticks[pass * 2 + 1]);
+            // a pass that wasn't recorded, or a timer that wrapped, is left at zero
+            *time = end.saturating_sub(start) as f64 * self.period / 1e6;
+        }
+        self.times = Some(match self.times {
+            Some(times) => std::array::from_fn(|pass| times[pass] + (measured[pass] - times[pass]) * SMOOTHING),
+            None => measured,
+        });
+        for (total, time) in self.totals.iter_mut().zip(measured) {
+            *total += time;
+        }
+        self.samples += 1;
+        if self.logged.elapsed() >= LOG_INTERVAL {
+            let averages: Vec<String> = TIMED_PASSES
+                .iter()
+                .zip(self.totals)
+                .map(|(pass, total)| format!("{pass} {:.3} ms", total / self.samples as f64))
+                .collect();
+            tracing::info!(samples = self.samples, "GPU time per frame: {}", averages.join(", "));
+            self.totals = [0.0; TIMED_PASSES.len()];
+            self.samples = 0;
+            self.logged = Instant::now();
+        }
+    }
+
+    // smoothed milliseconds spent in each pass, once a frame has been measured
+    pub fn times(&self) -> Option<Vec<(&'static str, f64)>> {
+        let times = self.times?;
+        Some(TIMED_PASSES.iter().copied().zip(times).collect())
+    }
+}
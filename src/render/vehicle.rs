@@ -0,0 +1,32 @@
+// upper bound on trains drawn at once, sizes the vehicle instance buffer
+pub const MAX_VEHICLES: usize = 2048;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug, Default)]
+pub struct VehicleInstance {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    // radians from the map's x axis towards its y axis, the bullet's pointer shows it
+    pub rotation: f32,
+    pub scale: f32,
+    // 1-based index into State's routes for the route bullet, 0 for an unlabeled one
+    pub route: u32,
+}
+
+impl VehicleInstance {
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        4 => Float32x3,
+        5 => Float32x3,
+        6 => Float32,
+        7 => Float32,
+        9 => Uint32
+    ];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<VehicleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::complexes::Complexes;
+use crate::entities::{EntityCollection, Stop};
+use crate::ids::StopId;
+use crate::render::stop::StopInstance;
+use crate::util::{self, static_data::{RIDERSHIP_HOURS, RIDERSHIP_STATIC}};
+
+// heatmap markers grow from this to MAX_SCALE with ridership, as StopInstance scales
+const MIN_SCALE: f32 = -0.5;
+const MAX_SCALE: f32 = 2.0;
+// quiet stations are drawn cool, busy ones hot
+const COOL_COLOR: [f32; 3] = [0.05, 0.25, 0.6];
+const HOT_COLOR: [f32; 3] = [1.0, 0.25, 0.0];
+
+#[derive(Deserialize)]
+struct RidershipRow {
+    // not every complex_id is a number, e.g. the Roosevelt Island Tram's
+    station_complex_id: String,
+    // entries in one hour of the day, summed over the year
+    ridership: f32,
+}
+
+// average hourly entries at each station complex, from the MTA's hourly ridership dataset
+#[derive(Default)]
+pub struct Ridership {
+    by_complex: HashMap<u32, f32>,
+}
+
+impl Ridership {
+    pub fn load() -> Result<Self> {
+        let xdg = util::get_xdg()?;
+        let path = xdg
+            .find_data_file(RIDERSHIP_STATIC.1)
+            .ok_or_else(|| anyhow!("Could not find {} data", RIDERSHIP_STATIC.1))?;
+        let mut ridership = Self::default();
+        let unreadable = || format!("Failed to read '{}'", path.display());
+        for rec in csv::Reader::from_path(&path).with_context(unreadable)?.deserialize() {
+            let row: RidershipRow = rec.with_context(|| format!("Malformed row in '{}'", path.display()))?;
+            if let Ok(complex_id) = row.station_complex_id.parse() {
+                *ridership.by_complex.entry(complex_id).or_default() += row.ridership / RIDERSHIP_HOURS;
+            }
+        }
+        Ok(ridership)
+    }
+
+    // a marker under each station, sized and colored by its complex's ridership relative to the busiest
+    pub fn instances(
+        &self,
+        stops: &EntityCollection<BTreeMap<StopId, Stop>>,
+        complexes: &Complexes,
+    ) -> Vec<StopInstance> {
+        let busiest = self.by_complex.values().copied().fold(0.0, f32::max);
+        if busiest <= 0.0 {
+            return Vec::new();
+        }
+        stops
+            .values()
+            .filter(|stop| stop.parent.is_none())
+            .filter_map(|stop| {
+                let ridership = self.by_complex.get(&complexes.complex(&stop.id)?)?;
+                // by area, so a station twice as busy covers twice the map
+                let t = (ridership / busiest).sqrt();
+                Some(StopInstance {
+                    position: [stop.coord.x, stop.coord.y, 0.0],
+                    color: [0, 1, 2].map(|i| COOL_COLOR[i] + (HOT_COLOR[i] - COOL_COLOR[i]) * t),
+                    scale: MIN_SCALE + (MAX_SCALE - MIN_SCALE) * t,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,659 @@
+use anyhow::{Context, Result};
+use axum::body::{self, Body, Bytes};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use include_dir::{include_dir, Dir};
+use prost::Message as _;
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::annotations::{Annotation, Annotations};
+use crate::cache::{self, SCHEDULE_CACHE};
+use crate::config::{Config, ServeConfig};
+use crate::entity_log::EntityLog;
+use crate::entities::{EntityCollection, Route, Schedule, ShapeIndex, ShapeRoutes, ShapeSeq, Stop};
+use crate::feed::{
+    self, ActiveAlert, Arrivals, FeedAge, FeedManager, FeedSource, FeedUpdate, FeedUpdates, SharedFeedState,
+    VehicleStatus,
+};
+use crate::headway::Headway;
+use crate::ids::{RouteId, StopId, TripId};
+use crate::motion::Motion;
+use crate::nearby::{Nearby, NearbyStop};
+use crate::recorder;
+use crate::snapshot;
+use crate::status::LineStatus;
+use crate::timetable::{ServicePattern, Timetable, AGENCY_TIMEZONE};
+use crate::util::format::Formatter;
+use crate::util::{self, static_data::{self, GTFS_STATIC}};
+use crate::validate::ScheduleValidator;
+use crate::webhook::Webhooks;
+
+type Stops = EntityCollection<BTreeMap<StopId, Stop>>;
+type Routes = EntityCollection<HashMap<RouteId, Route>>;
+
+// how often a new GTFS schedule is looked for, it's only downloaded once the current one is due for revalidation
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// stations listed by /stops/nearby without a radius or count
+const NEARBY_STOPS: usize = 5;
+// the page `--viewer` serves, with its script and stylesheet
+static VIEWER: Dir = include_dir!("$CARGO_MANIFEST_DIR/src/viewer");
+// clients and responses kept before those gone quiet or stale are swept out
+const MAX_CLIENTS: usize = 1024;
+const MAX_CACHED: usize = 1024;
+
+// the schedule the feeds run against
+struct LoadedSchedule {
+    // bumped each time a new schedule replaces it
+    generation: u64,
+    // untranslated, coords are longitude and latitude
+    stops: Arc<Stops>,
+    routes: Arc<Routes>,
+    shapes: EntityCollection<BTreeMap<String, Vec<ShapeSeq>>>,
+    shape_routes: ShapeRoutes,
+    timetable: Timetable,
+    validator: Option<ScheduleValidator>,
+    nearby: Nearby,
+}
+
+impl LoadedSchedule {
+    fn new(generation: u64, schedule: Schedule, validator: Option<ScheduleValidator>, arrivals: Arrivals) -> Self {
+        let Schedule {
+            stops,
+            shapes,
+            routes,
+            shape_routes,
+            timetable,
+            ..
+        } = schedule;
+        Self {
+            generation,
+            nearby: Nearby::new(&stops, arrivals),
+            stops: Arc::new(stops),
+            routes: Arc::new(routes),
+            shapes,
+            shape_routes,
+            timetable,
+            validator,
+        }
+    }
+}
+
+// the latest schedule, swapped out without a restart when a new one is published
+#[derive(Clone)]
+struct CurrentSchedule(Arc<RwLock<Arc<LoadedSchedule>>>);
+
+impl CurrentSchedule {
+    fn get(&self) -> Arc<LoadedSchedule> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, schedule: Schedule, validator: Option<ScheduleValidator>, arrivals: Arrivals) {
+        let generation = self.get().generation + 1;
+        *self.0.write().unwrap() = Arc::new(LoadedSchedule::new(generation, schedule, validator, arrivals));
+    }
+}
+
+#[derive(Clone)]
+struct ApiState {
+    shared: SharedFeedState,
+    schedule: CurrentSchedule,
+    motion: Motion,
+    annotations: Arc<Annotations>,
+    // times and distances as text, as the window shows them
+    formatter: Formatter,
+}
+
+impl ApiState {
+    fn stops(&self) -> Arc<Stops> {
+        self.schedule.get().stops.clone()
+    }
+
+    fn routes(&self) -> Arc<Routes> {
+        self.schedule.get().routes.clone()
+    }
+}
+
+// each client's requests are held to a rate, and responses are reused while they're fresh, so a dashboard
+// refreshing every frame can't keep the feed state's locks busy
+#[derive(Clone)]
+struct Throttle {
+    // requests a second, and how many can be made at once
+    rate: f64,
+    burst: f64,
+    ttl: Duration,
+    // each client's requests left, and when they were last counted
+    clients: Arc<Mutex<HashMap<IpAddr, (f64, Instant)>>>,
+    // by path and query
+    responses: Arc<Mutex<HashMap<String, CachedResponse>>>,
+}
+
+struct CachedResponse {
+    at: Instant,
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+}
+
+impl Throttle {
+    fn new(config: ServeConfig) -> Self {
+        Self {
+            rate: config.rate_limit as f64,
+            burst: config.burst.max(1) as f64,
+            ttl: Duration::from_millis(config.cache_ms),
+            clients: Arc::default(),
+            responses: Arc::default(),
+        }
+    }
+
+    // whether the client has a request left, each is a token of a bucket refilled at the rate
+    fn allow(&self, client: IpAddr, now: Instant) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_CLIENTS {
+            // a client whose bucket would be full again is no different to one never seen
+            let refill = Duration::from_secs_f64(self.burst / self.rate);
+            clients.retain(|_, (_, at)| now.duration_since(*at) < refill);
+        }
+        let (tokens, at) = clients.entry(client).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.rate).min(self.burst);
+        *at = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    fn cached(&self, key: &str, now: Instant) -> Option<Response> {
+        let responses = self.responses.lock().unwrap();
+        let cached = responses.get(key).filter(|cached| now.duration_since(cached.at) < self.ttl)?;
+        let mut response = Response::new(Body::from(cached.body.clone()));
+        if let Some(content_type) = &cached.content_type {
+            response.headers_mut().insert(header::CONTENT_TYPE, content_type.clone());
+        }
+        let age = now.duration_since(cached.at).as_secs();
+        response.headers_mut().insert(header::AGE, HeaderValue::from(age));
+        Some(response)
+    }
+
+    // successful responses are kept, errors are left for the next request to retry
+    async fn cache(&self, key: String, response: Response, now: Instant) -> Response {
+        if self.ttl.is_zero() || response.status() != StatusCode::OK {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let body = match body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Failed to read a response to cache: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let content_type = parts.headers.get(header::CONTENT_TYPE).cloned();
+        let mut responses = self.responses.lock().unwrap();
+        if responses.len() >= MAX_CACHED {
+            responses.retain(|_, cached| now.duration_since(cached.at) < self.ttl);
+        }
+        let cached = CachedResponse {
+            at: now,
+            content_type,
+            body: body.clone(),
+        };
+        responses.insert(key, cached);
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+async fn throttle(
+    State(throttle): State<Throttle>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let now = Instant::now();
+    if !throttle.allow(client.ip(), now) {
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "1")]).into_response();
+    }
+    let key = request.uri().to_string();
+    if let Some(response) = throttle.cached(&key, now) {
+        return response;
+    }
+    let response = next.run(request).await;
+    throttle.cache(key, response, now).await
+}
+
+#[derive(Serialize)]
+struct StopArrivals {
+    stop_id: StopId,
+    name: String,
+    // the user's note and tags from annotations.toml
+    annotation: Option<Annotation>,
+    arrivals: Vec<ArrivalResponse>,
+}
+
+#[derive(Serialize)]
+struct ArrivalResponse {
+    route_id: RouteId,
+    trip_id: TripId,
+    // predicted arrival as a POSIX timestamp, and as the `[format]` config section has times shown
+    time: i64,
+    time_text: String,
+    // smoothed across polls, as the map shows it
+    minutes: i64,
+    // "express" or "local", when it can be told
+    service_pattern: Option<ServicePattern>,
+}
+
+// stations around a point, the `n` nearest or every one within `radius` meters
+#[derive(Deserialize)]
+struct NearbyQuery {
+    lat: f64,
+    lon: f64,
+    n: Option<usize>,
+    radius: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct NearbyResponse {
+    #[serde(flatten)]
+    stop: NearbyStop,
+    // as the `[format]` config section has distances shown
+    distance_text: String,
+}
+
+#[derive(Serialize)]
+struct StationResponse {
+    stop_id: StopId,
+    name: String,
+    lat: f32,
+    lon: f32,
+    annotation: Option<Annotation>,
+}
+
+#[derive(Serialize)]
+struct RouteResponse {
+    route_id: RouteId,
+    // "#rrggbb", as the map draws the route
+    route_color: String,
+    annotation: Option<Annotation>,
+}
+
+#[derive(Serialize)]
+struct VehicleResponse {
+    #[serde(flatten)]
+    vehicle: VehicleStatus,
+    // eased between stations while in transit, as the map draws it
+    lat: f32,
+    lon: f32,
+}
+
+// run the feeds without a window and serve their state as JSON
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    addr: SocketAddr,
+    schedule: Schedule,
+    config: Config,
+    validator: Option<ScheduleValidator>,
+    source: FeedSource,
+    geojson: Option<(PathBuf, Duration)>,
+    entity_log: EntityLog,
+    viewer: bool,
+    annotations: Arc<Annotations>,
+) -> Result<()> {
+    let strict = validator.is_some();
+    let shared = SharedFeedState::default();
+    let loaded = LoadedSchedule::new(0, schedule, validator, shared.arrivals.clone());
+    let state = ApiState {
+        shared,
+        schedule: CurrentSchedule(Arc::new(RwLock::new(Arc::new(loaded)))),
+        motion: config.motion,
+        annotations,
+        formatter: config.format,
+    };
+    if source == FeedSource::Network {
+        state.shared.outages.spawn(state.stops());
+    }
+    if config.history.enabled {
+        recorder::spawn(&config.history, &state.shared.updates)?;
+    }
+
+    let shared = state.shared.clone();
+    let current = state.schedule.clone();
+    let motion = config.motion;
+    let closed_log = entity_log.clone();
+    let feeds_config = config.feeds;
+    let webhooks = Webhooks::spawn(config.webhooks);
+    // the feeds start over on each new schedule, its trips and stops are what they're matched against
+    thread::spawn(move || {
+        // a blocking client can't be built on the async runtime
+        let client = feed::client();
+        loop {
+            let schedule = current.get();
+            let shape_index = ShapeIndex::new(&schedule.shapes, &schedule.shape_routes);
+            let mut feed_manager =
+                FeedManager::new(&schedule.stops, &schedule.routes, &shape_index, shared.clone(), webhooks.clone());
+            if let Some(validator) = &schedule.validator {
+                feed_manager = feed_manager.with_validator(validator);
+            }
+            feed_manager = feed_manager
+                .with_client(client.clone())
+                .with_source(source)
+                .with_feeds(&feeds_config)
+                .with_timetable(&schedule.timetable)
+                .with_motion(motion)
+                .with_entity_log(entity_log.clone());
+
+            while current.get().generation == schedule.generation {
+                feed_manager.update();
+                thread::sleep(feed_manager.polling_interval());
+            }
+            info!("Restarting the feeds on the new schedule");
+        }
+    });
+
+    if source == FeedSource::Network {
+        let current = state.schedule.clone();
+        let arrivals = state.shared.arrivals.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+            // the first tick is immediate, and the schedule was only just loaded
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match refresh_schedule(strict).await {
+                    Ok(Some((schedule, validator))) => {
+                        current.replace(schedule, validator, arrivals.clone());
+                        info!("Loaded a new schedule");
+                    }
+                    Ok(None) => {}
+                    Err(err) => warn!("Failed to load a new schedule: {}", err),
+                }
+            }
+        });
+    }
+
+    // written on a timer, as well as served on request
+    if let Some((path, every)) = geojson {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(err) = snapshot::write(&path, &state.shared, &state.stops(), &state.routes(), &state.motion) {
+                    warn!("Failed to write GeoJSON to '{}': {}", path.display(), err);
+                }
+            }
+        });
+    }
+
+    let updates = state.shared.updates.clone();
+    let throttled = Throttle::new(config.serve);
+    let app = Router::new()
+        .route("/stations", get(stations))
+        .route("/routes", get(routes))
+        .route("/stops/nearby", get(nearby_stops))
+        .route("/stops/:stop_id/arrivals", get(stop_arrivals))
+        .route("/routes/:route_id/vehicles", get(route_vehicles))
+        .route("/alerts", get(alerts))
+        .route("/alerts/headlines", get(headlines))
+        .route("/status", get(status))
+        .route("/feeds", get(feeds))
+        .route("/headways", get(headways))
+        .route("/geojson", get(geojson_snapshot))
+        .route("/gtfs-rt", get(gtfs_rt))
+        .with_state(state)
+        // the stream pushes updates as they happen, there's nothing to limit or cache
+        .layer(middleware::from_fn_with_state(throttled, throttle))
+        .route("/stream", get(stream).with_state(updates));
+    let app = if viewer {
+        app.route("/", get(|| viewer_file(Path(String::from("index.html")))))
+            .route("/:file", get(viewer_file))
+    } else {
+        app
+    };
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving feed state on http://{}", listener.local_addr()?);
+    // stopped with ctrl-c, so the entity log can write out the hour in progress
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    info!("Shutting down");
+    tokio::task::spawn_blocking(move || closed_log.close()).await?;
+    Ok(())
+}
+
+// download and unpack the GTFS schedule once it's due for revalidation, and load it if it's changed
+async fn refresh_schedule(strict: bool) -> Result<Option<(Schedule, Option<ScheduleValidator>)>> {
+    if !static_data::shoud_fetch(GTFS_STATIC) {
+        return Ok(None);
+    }
+    let Some(zip) = static_data::fetch(GTFS_STATIC, None, false).await? else {
+        return Ok(None);
+    };
+    let key = cache::schedule_key(&fs::read(&zip).with_context(|| format!("Failed to read '{}'", zip.display()))?);
+    static_data::unzip(zip).await?;
+    tokio::task::spawn_blocking(move || -> Result<_> {
+        let schedule = cache::load_or_build(SCHEDULE_CACHE, Some(&key), Schedule::load)?;
+        let validator = if strict {
+            Some(ScheduleValidator::load()?)
+        } else {
+            None
+        };
+        Ok(Some((schedule, validator)))
+    })
+    .await?
+}
+
+// serve only the update stream, alongside the window
+pub fn spawn_stream(addr: SocketAddr, updates: FeedUpdates) {
+    tokio::spawn(async move {
+        let app = Router::new().route("/stream", get(stream).with_state(updates));
+        let served = async {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!("Streaming feed updates on ws://{}/stream", listener.local_addr()?);
+            axum::serve(listener, app).await
+        };
+        if let Err(err) = served.await {
+            warn!("Failed to serve feed updates: {}", err);
+        }
+    });
+}
+
+async fn stream(State(updates): State<FeedUpdates>, ws: WebSocketUpgrade) -> Response {
+    let updates = updates.subscribe();
+    ws.on_upgrade(move |socket| push_updates(socket, updates))
+}
+
+// each update is sent as a JSON text message until the client goes away
+async fn push_updates(mut socket: WebSocket, mut updates: Receiver<FeedUpdate>) {
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Stream client fell behind, skipped {} updates", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(json) = serde_json::to_string(&update) else {
+            continue;
+        };
+        if socket.send(Message::Text(json)).await.is_err() {
+            break;
+        }
+    }
+}
+
+// the viewer's files, as they were when the binary was built
+async fn viewer_file(Path(file): Path<String>) -> Result<Response, StatusCode> {
+    let file = VIEWER.get_file(&file).ok_or(StatusCode::NOT_FOUND)?;
+    let content_type = match file.path().extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], file.contents()).into_response())
+}
+
+// every station, for placing the stop_ids of the other endpoints and the stream on a map
+async fn stations(State(state): State<ApiState>) -> Json<Vec<StationResponse>> {
+    let stations = state
+        .stops()
+        .values()
+        .filter(|stop| stop.parent.is_none())
+        .map(|stop| StationResponse {
+            stop_id: stop.id.clone(),
+            name: stop.name.clone(),
+            lat: stop.coord.y,
+            lon: stop.coord.x,
+            annotation: state.annotations.station(stop.id.as_str()).cloned(),
+        })
+        .collect();
+    Json(stations)
+}
+
+async fn routes(State(state): State<ApiState>) -> Json<Vec<RouteResponse>> {
+    let mut routes: Vec<RouteResponse> = state
+        .routes()
+        .values()
+        .map(|route| RouteResponse {
+            route_id: route.id().clone(),
+            route_color: format!("#{}", hex::encode(srgb::gamma::u8_from_linear(route.color()))),
+            annotation: state.annotations.route(route.id().as_str()).cloned(),
+        })
+        .collect();
+    routes.sort_by(|a, b| a.route_id.cmp(&b.route_id));
+    Json(routes)
+}
+
+// platform stop_ids are resolved to their station
+async fn stop_arrivals(
+    State(state): State<ApiState>,
+    Path(stop_id): Path<String>,
+) -> Result<Json<StopArrivals>, StatusCode> {
+    let stops = state.stops();
+    let station = stops.station(stop_id.as_str()).ok_or(StatusCode::NOT_FOUND)?;
+    let now = util::posix_now();
+    let arrivals = state
+        .shared
+        .arrivals
+        .upcoming(station.id.as_str(), now)
+        .into_iter()
+        .map(|arrival| ArrivalResponse {
+            route_id: arrival.route_id,
+            trip_id: arrival.trip_id,
+            time: arrival.time,
+            time_text: state.formatter.time(arrival.time, AGENCY_TIMEZONE),
+            minutes: (arrival.smoothed - now).max(0) / 60,
+            service_pattern: arrival.service_pattern,
+        })
+        .collect();
+    Ok(Json(StopArrivals {
+        stop_id: station.id.clone(),
+        name: station.name.clone(),
+        annotation: state.annotations.station(station.id.as_str()).cloned(),
+        arrivals,
+    }))
+}
+
+// nearest first, with each station's countdowns
+async fn nearby_stops(State(state): State<ApiState>, Query(query): Query<NearbyQuery>) -> Json<Vec<NearbyResponse>> {
+    let nearby = &state.schedule.get().nearby;
+    let stops = match query.radius {
+        Some(radius) => nearby.stops_within_radius(query.lat, query.lon, radius),
+        None => nearby.nearest_stops(query.lat, query.lon, query.n.unwrap_or(NEARBY_STOPS)),
+    };
+    Json(
+        stops
+            .into_iter()
+            .map(|stop| NearbyResponse {
+                distance_text: state.formatter.distance(stop.distance as f32),
+                stop,
+            })
+            .collect(),
+    )
+}
+
+async fn route_vehicles(
+    State(state): State<ApiState>,
+    Path(route_id): Path<String>,
+) -> Result<Json<Vec<VehicleResponse>>, StatusCode> {
+    if state.routes().get(route_id.as_str()).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let stops = state.stops();
+    let now = util::posix_now();
+    let vehicles = state
+        .shared
+        .snapshot
+        .vehicles(&route_id)
+        .into_iter()
+        .filter_map(|vehicle| {
+            let coord = snapshot::vehicle_coord(&stops, &state.motion, &vehicle, now)?;
+            Some(VehicleResponse {
+                vehicle,
+                lat: coord.y,
+                lon: coord.x,
+            })
+        })
+        .collect();
+    Ok(Json(vehicles))
+}
+
+async fn alerts(State(state): State<ApiState>) -> Json<Vec<ActiveAlert>> {
+    Json(state.shared.snapshot.alerts())
+}
+
+// as the window's ticker scrolls them
+async fn headlines(State(state): State<ApiState>) -> Json<Vec<String>> {
+    Json(state.shared.snapshot.headlines())
+}
+
+// line status by route_id
+async fn status(State(state): State<ApiState>) -> Json<HashMap<RouteId, LineStatus>> {
+    Json(state.shared.statuses.all())
+}
+
+// when each feed last updated, and whether it's stale
+async fn feeds(State(state): State<ApiState>) -> Json<Vec<FeedAge>> {
+    Json(state.shared.snapshot.feeds())
+}
+
+// observed time between trains by route and direction, with bunching and long gaps flagged
+async fn headways(State(state): State<ApiState>) -> Json<Vec<Headway>> {
+    Json(state.shared.headways.all())
+}
+
+// trains and active stops as a GeoJSON FeatureCollection
+async fn geojson_snapshot(State(state): State<ApiState>) -> Json<geojson::FeatureCollection> {
+    Json(snapshot::feature_collection(
+        &state.shared,
+        &state.stops(),
+        &state.routes(),
+        &state.motion,
+    ))
+}
+
+// the feeds merged and deduplicated into one GTFS-realtime message, as protobuf
+async fn gtfs_rt(State(state): State<ApiState>) -> Response {
+    let body = state.shared.snapshot.feed_message().encode_to_vec();
+    ([(header::CONTENT_TYPE, "application/x-protobuf")], body).into_response()
+}
@@ -0,0 +1,97 @@
+use crate::proto::gtfs::realtime::{
+    trip_update::{StopTimeEvent, StopTimeUpdate},
+    vehicle_position::VehicleStopStatus,
+    FeedEntity, FeedHeader, FeedMessage, TripDescriptor, TripUpdate, VehiclePosition,
+};
+use crate::timetable::{ScheduledPosition, Timetable};
+
+const GTFS_REALTIME_VERSION: &str = "1.0";
+
+// a feed message for `routes` at the POSIX timestamp `now`, as the MTA would send it
+// each train the timetable has running gets a trip update and a vehicle position
+pub fn message(timetable: &Timetable, routes: &[&str], now: i64) -> FeedMessage {
+    let entity = routes
+        .iter()
+        .flat_map(|route_id| {
+            timetable
+                .positions(route_id, now)
+                .into_iter()
+                .flat_map(move |position| entities(route_id, &position, now))
+        })
+        .collect();
+    FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: GTFS_REALTIME_VERSION.to_owned(),
+            timestamp: Some(now as u64),
+            ..Default::default()
+        },
+        entity,
+    }
+}
+
+// a scheduled train's trip update and vehicle position, ids are its trip_id suffixed by the kind of entity
+fn entities(route_id: &str, position: &ScheduledPosition, now: i64) -> Vec<FeedEntity> {
+    let trip = TripDescriptor {
+        trip_id: Some(position.trip_id.to_string()),
+        route_id: Some(route_id.to_owned()),
+        ..Default::default()
+    };
+    // a stopped train's first update is the station it's at, otherwise the one it's running to
+    let current = position
+        .stopped
+        .then_some((position.departed, position.stop_id));
+    let Some((_, stop_id)) = current.or_else(|| position.upcoming().next()) else {
+        return Vec::new();
+    };
+    let status = if position.stopped {
+        VehicleStopStatus::StoppedAt
+    } else {
+        VehicleStopStatus::InTransitTo
+    };
+    let event = |time| {
+        Some(StopTimeEvent {
+            time: Some(time),
+            ..Default::default()
+        })
+    };
+    // due to leave the station it's stopped at, and to arrive at the rest
+    let stop_time_update = current
+        .into_iter()
+        .map(|(time, stop_id)| StopTimeUpdate {
+            stop_id: Some(stop_id.to_string()),
+            departure: event(time),
+            ..Default::default()
+        })
+        .chain(position.upcoming().map(|(time, stop_id)| StopTimeUpdate {
+            stop_id: Some(stop_id.to_string()),
+            arrival: event(time),
+            ..Default::default()
+        }))
+        .collect();
+
+    // an entity each, ids are unique within a message
+    let id = position.trip_id.to_string();
+    vec![
+        FeedEntity {
+            id: format!("{id}-tu"),
+            trip_update: Some(TripUpdate {
+                trip: trip.clone(),
+                stop_time_update,
+                timestamp: Some(now as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        FeedEntity {
+            id: format!("{id}-vp"),
+            vehicle: Some(VehiclePosition {
+                trip: Some(trip),
+                stop_id: Some(stop_id.to_string()),
+                current_status: Some(status as i32),
+                timestamp: Some(now as u64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    ]
+}
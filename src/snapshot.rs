@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use geo::Coord;
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, Value};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::entities::{EntityCollection, Route, Stop};
+use crate::feed::{SharedFeedState, VehicleStatus};
+use crate::ids::{RouteId, StopId};
+use crate::motion::Motion;
+use crate::util;
+
+type Stops = EntityCollection<BTreeMap<StopId, Stop>>;
+type Routes = EntityCollection<HashMap<RouteId, Route>>;
+
+// where a train is drawn: eased between stations while in transit, otherwise at its station
+pub fn vehicle_coord(stops: &Stops, motion: &Motion, vehicle: &VehicleStatus, now: i64) -> Option<Coord<f32>> {
+    let stop = stops.get(&vehicle.stop_id)?;
+    let from = vehicle
+        .from_stop_id
+        .as_ref()
+        .and_then(|stop_id| stops.get(stop_id))
+        .zip(vehicle.departed.zip(vehicle.arrival));
+    Some(match from {
+        Some((from, (departed, arrival))) => {
+            motion.position(from.coord, stop.coord, now - departed, arrival - departed)
+        }
+        None => stop.coord,
+    })
+}
+
+// the trains and active stops of the latest snapshot as points, stops must be in longitude and latitude.
+// each feature's `kind` is "vehicle" or "active_stop", the rest of its properties are as the HTTP API serves them
+pub fn feature_collection(
+    shared: &SharedFeedState,
+    stops: &Stops,
+    routes: &Routes,
+    motion: &Motion,
+) -> FeatureCollection {
+    let now = util::posix_now();
+    let mut features = Vec::new();
+    for vehicle in shared.snapshot.all_vehicles() {
+        let Some(coord) = vehicle_coord(stops, motion, &vehicle, now) else {
+            continue;
+        };
+        features.push(point(coord, "vehicle", &vehicle, routes.get(&vehicle.route_id)));
+    }
+    for active in shared.snapshot.active_stops() {
+        let Some(stop) = stops.get(&active.stop_id) else {
+            continue;
+        };
+        let mut feature = point(stop.coord, "active_stop", &active, routes.get(&active.route_id));
+        if let Some(properties) = &mut feature.properties {
+            properties.insert("name".to_string(), stop.name.clone().into());
+        }
+        features.push(feature);
+    }
+    FeatureCollection {
+        bbox: None,
+        features,
+        foreign_members: None,
+    }
+}
+
+// replace the file at `path` with the latest snapshot, readers never see a partly written one
+pub fn write(
+    path: &Path,
+    shared: &SharedFeedState,
+    stops: &Stops,
+    routes: &Routes,
+    motion: &Motion,
+) -> Result<()> {
+    let collection = feature_collection(shared, stops, routes, motion);
+    let partial = path.with_extension("partial");
+    fs::write(&partial, collection.to_string()).with_context(|| format!("Failed to write '{}'", partial.display()))?;
+    fs::rename(&partial, path).with_context(|| format!("Failed to move the GeoJSON to '{}'", path.display()))?;
+    Ok(())
+}
+
+fn point<T: Serialize>(coord: Coord<f32>, kind: &str, properties: &T, route: Option<&Route>) -> Feature {
+    let mut properties = match serde_json::to_value(properties) {
+        Ok(serde_json::Value::Object(properties)) => properties,
+        _ => JsonObject::new(),
+    };
+    properties.insert("kind".to_string(), kind.into());
+    // styled by route in QGIS or kepler.gl without a join against routes.txt
+    if let Some(route) = route {
+        let color = srgb::gamma::u8_from_linear(route.color());
+        properties.insert("route_color".to_string(), format!("#{}", hex::encode(color)).into());
+    }
+    Feature {
+        bbox: None,
+        geometry: Some(Geometry::new(Value::Point(vec![coord.x as f64, coord.y as f64]))),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::entities::Stop;
+use crate::ids::{StopId, TripId};
+use crate::util::{self, format};
+use crate::validate::Discrepancy;
+
+pub const SESSIONS_FILE: &str = "sessions.log";
+// recent downloads are averaged over this window for the bandwidth estimate
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Counters {
+    started: Instant,
+    feeds_fetched: usize,
+    bytes_fetched: u64,
+    // size of each download within BANDWIDTH_WINDOW
+    recent_fetches: VecDeque<(Instant, usize)>,
+    fetch_failures: usize,
+    decode_failures: usize,
+    trips: HashSet<TripId>,
+    // by parent stop_id
+    station_arrivals: HashMap<StopId, usize>,
+    discrepancies: BTreeMap<Discrepancy, usize>,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            feeds_fetched: 0,
+            bytes_fetched: 0,
+            recent_fetches: VecDeque::new(),
+            fetch_failures: 0,
+            decode_failures: 0,
+            trips: HashSet::new(),
+            station_arrivals: HashMap::new(),
+            discrepancies: BTreeMap::new(),
+        }
+    }
+}
+
+// running totals for the session, shared between the feed thread and main
+#[derive(Clone, Default)]
+pub struct SessionStats(Arc<RwLock<Counters>>);
+
+impl SessionStats {
+    pub fn fetched(&self, bytes: usize) {
+        let mut counters = self.0.write().unwrap();
+        counters.feeds_fetched += 1;
+        counters.bytes_fetched += bytes as u64;
+        counters.recent_fetches.push_back((Instant::now(), bytes));
+        while counters
+            .recent_fetches
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > BANDWIDTH_WINDOW)
+        {
+            counters.recent_fetches.pop_front();
+        }
+    }
+
+    // estimated feed download rate, in bytes per second
+    pub fn bandwidth(&self) -> f64 {
+        let counters = self.0.read().unwrap();
+        let window = counters.started.elapsed().min(BANDWIDTH_WINDOW);
+        let bytes: usize = counters
+            .recent_fetches
+            .iter()
+            .filter(|(at, _)| at.elapsed() <= BANDWIDTH_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        bytes as f64 / window.as_secs_f64().max(1.0)
+    }
+
+    pub fn fetch_failed(&self, err: &anyhow::Error) {
+        let mut counters = self.0.write().unwrap();
+        counters.fetch_failures += 1;
+        if err.is::<prost::DecodeError>() {
+            counters.decode_failures += 1;
+        }
+    }
+
+    pub fn arrived(&self, trip_id: &TripId, stop_id: &StopId) {
+        let mut counters = self.0.write().unwrap();
+        if !counters.trips.contains(trip_id) {
+            counters.trips.insert(trip_id.clone());
+        }
+        *counters
+            .station_arrivals
+            .entry(stop_id.clone())
+            .or_default() += 1;
+    }
+
+    pub fn discrepancy(&self, discrepancy: Discrepancy) {
+        *self
+            .0
+            .write()
+            .unwrap()
+            .discrepancies
+            .entry(discrepancy)
+            .or_default() += 1;
+    }
+
+    pub fn summary(&self, stops: &BTreeMap<StopId, Stop>) -> Summary {
+        let counters = self.0.read().unwrap();
+        let busiest_station = counters
+            .station_arrivals
+            .iter()
+            .max_by_key(|(_, arrivals)| **arrivals)
+            .map(|(stop_id, arrivals)| {
+                let name = stops.get(stop_id).map_or(stop_id.as_str(), |stop| &stop.name);
+                (name.to_owned(), *arrivals)
+            });
+        Summary {
+            ended_at: Local::now(),
+            uptime: counters.started.elapsed(),
+            feeds_fetched: counters.feeds_fetched,
+            bytes_fetched: counters.bytes_fetched,
+            fetch_failures: counters.fetch_failures,
+            decode_failures: counters.decode_failures,
+            trains_observed: counters.trips.len(),
+            busiest_station,
+            discrepancies: counters.discrepancies.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    ended_at: DateTime<Local>,
+    uptime: Duration,
+    feeds_fetched: usize,
+    bytes_fetched: u64,
+    fetch_failures: usize,
+    decode_failures: usize,
+    trains_observed: usize,
+    // station name and arrivals seen there
+    busiest_station: Option<(String, usize)>,
+    // realtime disagreements with the schedule, only checked in strict mode
+    discrepancies: BTreeMap<Discrepancy, usize>,
+}
+
+impl Summary {
+    // append to the sessions log in the state dir
+    pub fn save(&self) -> Result<()> {
+        let xdg = util::get_xdg()?;
+        let path = xdg.place_state_file(SESSIONS_FILE)?;
+        let failed = || format!("Failed to save the session summary to '{}'", path.display());
+        let mut file = OpenOptions::new().create(true).append(true).open(&path).with_context(failed)?;
+        writeln!(file, "{self}").with_context(failed)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.uptime.as_secs();
+        writeln!(f, "Session ended {}", self.ended_at.format("%Y-%m-%d %H:%M:%S"))?;
+        writeln!(
+            f,
+            "  uptime: {}h {:02}m {:02}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )?;
+        writeln!(
+            f,
+            "  feeds fetched: {} ({})",
+            self.feeds_fetched,
+            format::bytes(self.bytes_fetched as f64)
+        )?;
+        writeln!(
+            f,
+            "  fetch failures: {} ({} decode)",
+            self.fetch_failures, self.decode_failures
+        )?;
+        writeln!(f, "  trains observed: {}", self.trains_observed)?;
+        match &self.busiest_station {
+            Some((name, arrivals)) => {
+                write!(f, "  busiest station: {name} ({arrivals} arrivals)")?
+            }
+            None => write!(f, "  busiest station: none")?,
+        }
+        for (discrepancy, count) in &self.discrepancies {
+            write!(f, "\n  {}: {}", discrepancy.label(), count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::feed::Arrival;
+use crate::headway;
+use crate::ids::RouteId;
+use crate::proto::gtfs::realtime::alert::Effect;
+
+// average delay, in seconds, past which a route is considered delayed
+pub const DELAY_THRESHOLD: i32 = 5 * 60;
+// gap between consecutive trains, in seconds, past which a route is considered delayed
+pub const HEADWAY_GAP_THRESHOLD: i64 = 30 * 60;
+// only predictions within this window are used for headway gaps, in seconds
+const HEADWAY_HORIZON: i64 = 60 * 60;
+
+// ordered by severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum LineStatus {
+    GoodService,
+    ServiceChange,
+    Delays,
+}
+
+impl LineStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::GoodService => "Good Service",
+            Self::ServiceChange => "Service Change",
+            Self::Delays => "Delays",
+        }
+    }
+
+    // sRGB, from the MTA's service status page
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            Self::GoodService => [0x00, 0x93, 0x3c],
+            Self::ServiceChange => [0xfc, 0xcc, 0x0a],
+            Self::Delays => [0xee, 0x35, 0x2e],
+        }
+    }
+}
+
+// signals gathered from the feeds for a single route
+#[derive(Debug, Default)]
+pub struct RouteSignals {
+    pub alert_effects: Vec<Effect>,
+    // reported trip delays, in seconds
+    pub delays: Vec<i32>,
+    // typical largest gap between predicted arrivals at the route's stops, in seconds
+    pub headway_gap: Option<i64>,
+}
+
+impl RouteSignals {
+    pub fn status(&self) -> LineStatus {
+        let average_delay = if self.delays.is_empty() {
+            0
+        } else {
+            self.delays.iter().sum::<i32>() / self.delays.len() as i32
+        };
+
+        if self.alert_effects.contains(&Effect::SignificantDelays)
+            || average_delay > DELAY_THRESHOLD
+            || self.headway_gap.is_some_and(|gap| gap > HEADWAY_GAP_THRESHOLD)
+        {
+            LineStatus::Delays
+        } else if self.alert_effects.iter().any(|effect| {
+            matches!(
+                effect,
+                Effect::NoService
+                    | Effect::ReducedService
+                    | Effect::Detour
+                    | Effect::ModifiedService
+                    | Effect::StopMoved
+            )
+        }) {
+            LineStatus::ServiceChange
+        } else {
+            LineStatus::GoodService
+        }
+    }
+}
+
+// the median across stops of the largest gap between a route's upcoming arrivals, by route_id
+// using the median keeps branch and terminal stops with sparse service from dominating
+pub fn headway_gaps<'a>(
+    arrivals: impl Iterator<Item = &'a Vec<Arrival>>,
+    now: i64,
+) -> HashMap<RouteId, i64> {
+    let mut stop_gaps: HashMap<&RouteId, Vec<i64>> = HashMap::new();
+    for stop_arrivals in arrivals {
+        let mut by_route: HashMap<&RouteId, Vec<i64>> = HashMap::new();
+        for arrival in stop_arrivals {
+            if arrival.time >= now && arrival.time - now <= HEADWAY_HORIZON {
+                by_route.entry(&arrival.route_id).or_default().push(arrival.time);
+            }
+        }
+        for (route_id, mut times) in by_route {
+            if let Some(gap) = headway::gaps(&mut times).max() {
+                stop_gaps.entry(route_id).or_default().push(gap);
+            }
+        }
+    }
+
+    stop_gaps
+        .into_iter()
+        .filter_map(|(route_id, mut gaps)| Some((route_id.to_owned(), headway::median(&mut gaps)?)))
+        .collect()
+}
+
+// line status by route_id, only routes with live data are present
+#[derive(Clone, Default)]
+pub struct LineStatuses(Arc<RwLock<HashMap<RouteId, LineStatus>>>);
+
+impl LineStatuses {
+    pub fn publish(&self, signals: HashMap<RouteId, RouteSignals>) {
+        let statuses = signals
+            .into_iter()
+            .map(|(route_id, signals)| (route_id, signals.status()))
+            .collect();
+        *self.0.write().unwrap() = statuses;
+    }
+
+    pub fn all(&self) -> HashMap<RouteId, LineStatus> {
+        self.0.read().unwrap().clone()
+    }
+}
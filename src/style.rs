@@ -0,0 +1,45 @@
+use serde::Deserialize;
+
+use crate::entities::{self, Properties};
+
+// geometry that style rules can apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StyleTarget {
+    Boros,
+    Parks,
+}
+
+// colors features whose `property` equals `value`, or has any value when `value` is unset
+#[derive(Debug, Clone, Deserialize)]
+pub struct StyleRule {
+    pub target: StyleTarget,
+    pub property: String,
+    pub value: Option<String>,
+    // hex sRGB, e.g. "#2e7d32"
+    #[serde(deserialize_with = "entities::hex_to_srgb")]
+    pub color: [f32; 3],
+}
+
+impl StyleRule {
+    fn matches(&self, target: StyleTarget, properties: &Properties) -> bool {
+        if self.target != target {
+            return false;
+        }
+        match (properties.get(&self.property), &self.value) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(serde_json::Value::String(actual)), Some(value)) => actual == value,
+            (Some(actual), Some(value)) => serde_json::from_str::<serde_json::Value>(value)
+                .is_ok_and(|value| value == *actual),
+        }
+    }
+}
+
+// color of the first rule matching a feature, rules are checked in config order
+pub fn resolve(rules: &[StyleRule], target: StyleTarget, properties: &Properties) -> Option<[f32; 3]> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(target, properties))
+        .map(|rule| rule.color)
+}
@@ -0,0 +1,519 @@
+use anyhow::{Context, Result};
+use geo::{BoundingRect, Coord, CoordsIter, Geometry, LineString, MultiPoint, Rect, Simplify};
+use prost::Message;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::entities::{
+    CollectibleEntity, EntityCollection, Features, Properties, Route, ShapeRoutes, ShapeSeq, Stop,
+};
+use crate::ids::{RouteId, StopId};
+use crate::proto::mvt::tile::{self, GeomType};
+use crate::proto::mvt::Tile;
+
+// tile coordinates run from 0 to EXTENT across a tile, geometry is kept BUFFER past its edges so
+// lines and polygon outlines don't end abruptly at tile seams
+const EXTENT: u32 = 4096;
+const BUFFER: f64 = 64.0;
+// vertices closer than this to the line through their neighbours are dropped, in tile coordinates
+const TOLERANCE: f64 = 1.0;
+
+// MVT geometry commands, section 4.3 of the vector tile spec
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+const STATIONS_TILE_LAYER: &str = "stations";
+const SHAPES_TILE_LAYER: &str = "shapes";
+const BOROUGHS_TILE_LAYER: &str = "boroughs";
+const PARKS_TILE_LAYER: &str = "parks";
+
+// static geometry to export, as loaded, in longitude and latitude
+pub struct TileSources<'a> {
+    pub stops: &'a EntityCollection<BTreeMap<StopId, Stop>>,
+    pub shapes: &'a EntityCollection<BTreeMap<String, Vec<ShapeSeq>>>,
+    pub shape_routes: &'a ShapeRoutes,
+    pub routes: &'a EntityCollection<HashMap<RouteId, Route>>,
+    pub boros: &'a Features,
+    pub parks: &'a Features,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Property {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl Property {
+    fn value(&self) -> tile::Value {
+        match self {
+            Property::String(value) => tile::Value {
+                string_value: Some(value.clone()),
+                ..Default::default()
+            },
+            Property::Number(value) => tile::Value {
+                double_value: Some(*value),
+                ..Default::default()
+            },
+            Property::Bool(value) => tile::Value {
+                bool_value: Some(*value),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+// geometry in Web Mercator, scaled so the whole world is the unit square
+enum Shape {
+    Point(Coord<f64>),
+    Lines(Vec<Vec<Coord<f64>>>),
+    // each polygon's exterior ring and then its holes
+    Polygons(Vec<Vec<Vec<Coord<f64>>>>),
+}
+
+struct SourceFeature {
+    shape: Shape,
+    bounds: Rect<f64>,
+    properties: Vec<(String, Property)>,
+}
+
+impl SourceFeature {
+    fn new(shape: Shape, properties: Vec<(String, Property)>) -> Option<Self> {
+        let coords: Vec<Coord<f64>> = match &shape {
+            Shape::Point(coord) => vec![*coord],
+            Shape::Lines(lines) => lines.iter().flatten().copied().collect(),
+            Shape::Polygons(polygons) => polygons.iter().flatten().flatten().copied().collect(),
+        };
+        let bounds = MultiPoint::from(coords).bounding_rect()?;
+        Some(Self {
+            shape,
+            bounds,
+            properties,
+        })
+    }
+}
+
+// write each layer as Mapbox Vector Tiles to `dir`/{z}/{x}/{y}.mvt, for every zoom level in `zooms`.
+// tiles nothing falls in aren't written, returns how many were
+pub fn export(dir: &Path, zooms: RangeInclusive<u8>, sources: &TileSources) -> Result<usize> {
+    let layers = [
+        (BOROUGHS_TILE_LAYER, features(sources.boros)),
+        (PARKS_TILE_LAYER, features(sources.parks)),
+        (SHAPES_TILE_LAYER, shapes(sources)),
+        (STATIONS_TILE_LAYER, stations(sources)),
+    ];
+    let Some(bounds) = layers
+        .iter()
+        .flat_map(|(_, features)| features.iter().map(|feature| feature.bounds))
+        .reduce(|a, b| {
+            Rect::new(
+                Coord { x: a.min().x.min(b.min().x), y: a.min().y.min(b.min().y) },
+                Coord { x: a.max().x.max(b.max().x), y: a.max().y.max(b.max().y) },
+            )
+        })
+    else {
+        return Ok(0);
+    };
+
+    let mut written = 0;
+    for zoom in zooms {
+        let scale = (1u64 << zoom) as f64;
+        let max_tile = (1u32 << zoom) - 1;
+        let tile_range = |min: f64, max: f64| {
+            ((min * scale).floor().max(0.0) as u32)..=((max * scale).floor() as u32).min(max_tile)
+        };
+        let tiles: Vec<(u32, u32)> = tile_range(bounds.min().x, bounds.max().x)
+            .flat_map(|x| tile_range(bounds.min().y, bounds.max().y).map(move |y| (x, y)))
+            .collect();
+        written += tiles
+            .par_iter()
+            .map(|&(x, y)| -> Result<usize> {
+                let tile = encode_tile(&layers, zoom, x, y);
+                if tile.layers.is_empty() {
+                    return Ok(0);
+                }
+                let path = dir.join(zoom.to_string()).join(x.to_string());
+                fs::create_dir_all(&path).with_context(|| format!("Failed to create '{}'", path.display()))?;
+                let path = path.join(format!("{y}.mvt"));
+                fs::write(&path, tile.encode_to_vec())
+                    .with_context(|| format!("Failed to write '{}'", path.display()))?;
+                Ok(1)
+            })
+            .sum::<Result<usize>>()?;
+        tracing::info!("Wrote zoom level {} tiles", zoom);
+    }
+    Ok(written)
+}
+
+fn encode_tile(layers: &[(&str, Vec<SourceFeature>)], zoom: u8, x: u32, y: u32) -> Tile {
+    let scale = (1u64 << zoom) as f64;
+    // the tile, and its buffer, in the unit square
+    let margin = BUFFER / EXTENT as f64;
+    let covered = Rect::new(
+        Coord { x: (x as f64 - margin) / scale, y: (y as f64 - margin) / scale },
+        Coord { x: (x as f64 + 1.0 + margin) / scale, y: (y as f64 + 1.0 + margin) / scale },
+    );
+    let to_tile = |coord: &Coord<f64>| Coord {
+        x: (coord.x * scale - x as f64) * EXTENT as f64,
+        y: (coord.y * scale - y as f64) * EXTENT as f64,
+    };
+
+    let mut tile = Tile::default();
+    for (name, features) in layers {
+        let mut layer = LayerBuilder::default();
+        for feature in features.iter().filter(|feature| intersects(&feature.bounds, &covered)) {
+            let encoded = match &feature.shape {
+                Shape::Point(coord) => encode_point(to_tile(coord)).map(|geometry| (GeomType::Point, geometry)),
+                Shape::Lines(lines) => {
+                    let clipped: Vec<_> = lines
+                        .iter()
+                        .flat_map(|line| clip_line(&line.iter().map(to_tile).collect::<Vec<_>>()))
+                        .collect();
+                    encode_lines(&clipped).map(|geometry| (GeomType::Linestring, geometry))
+                }
+                Shape::Polygons(polygons) => {
+                    let clipped: Vec<Vec<_>> = polygons
+                        .iter()
+                        .map(|rings| {
+                            rings
+                                .iter()
+                                .map(|ring| clip_ring(&ring.iter().map(to_tile).collect::<Vec<_>>()))
+                                .collect()
+                        })
+                        .collect();
+                    encode_polygons(&clipped).map(|geometry| (GeomType::Polygon, geometry))
+                }
+            };
+            if let Some((kind, geometry)) = encoded {
+                layer.push(kind, geometry, &feature.properties);
+            }
+        }
+        if !layer.features.is_empty() {
+            tile.layers.push(layer.build(name));
+        }
+    }
+    tile
+}
+
+// features of a layer with their keys and values deduplicated, as tags index into them
+#[derive(Default)]
+struct LayerBuilder {
+    features: Vec<tile::Feature>,
+    keys: Vec<String>,
+    key_indices: HashMap<String, u32>,
+    values: Vec<tile::Value>,
+    value_indices: HashMap<String, u32>,
+}
+
+impl LayerBuilder {
+    fn push(&mut self, kind: GeomType, geometry: Vec<u32>, properties: &[(String, Property)]) {
+        let mut tags = Vec::with_capacity(properties.len() * 2);
+        for (key, property) in properties {
+            let key_idx = *self.key_indices.entry(key.clone()).or_insert_with(|| {
+                self.keys.push(key.clone());
+                self.keys.len() as u32 - 1
+            });
+            let value_idx = *self.value_indices.entry(format!("{property:?}")).or_insert_with(|| {
+                self.values.push(property.value());
+                self.values.len() as u32 - 1
+            });
+            tags.extend([key_idx, value_idx]);
+        }
+        self.features.push(tile::Feature {
+            id: None,
+            tags,
+            r#type: Some(kind as i32),
+            geometry,
+        });
+    }
+
+    fn build(self, name: &str) -> tile::Layer {
+        tile::Layer {
+            version: 2,
+            name: name.to_string(),
+            features: self.features,
+            keys: self.keys,
+            values: self.values,
+            extent: Some(EXTENT),
+        }
+    }
+}
+
+fn stations(sources: &TileSources) -> Vec<SourceFeature> {
+    sources
+        .stops
+        .values()
+        .filter(|stop| stop.parent.is_none())
+        .filter_map(|stop| {
+            let properties = vec![
+                ("stop_id".to_string(), Property::String(stop.id.to_string())),
+                ("name".to_string(), Property::String(stop.name.clone())),
+            ];
+            SourceFeature::new(Shape::Point(mercator(stop.coord)), properties)
+        })
+        .collect()
+}
+
+fn shapes(sources: &TileSources) -> Vec<SourceFeature> {
+    sources
+        .shapes
+        .iter()
+        .filter_map(|(shape_id, shape)| {
+            let line: Vec<_> = shape.iter().map(|seq| mercator(seq.coord())).collect();
+            let mut properties = vec![("shape_id".to_string(), Property::String(shape_id.clone()))];
+            if let Some(route_id) = sources.shape_routes.get(shape_id) {
+                properties.push(("route_id".to_string(), Property::String(route_id.to_string())));
+                if let Some(route) = sources.routes.get(route_id) {
+                    let color = srgb::gamma::u8_from_linear(route.color());
+                    properties.push(("route_color".to_string(), Property::String(format!("#{}", hex::encode(color)))));
+                }
+            }
+            SourceFeature::new(Shape::Lines(vec![line]), properties)
+        })
+        .collect()
+}
+
+// polygons from a GeoJSON dataset, with its string, number and boolean properties
+fn features(features: &Features) -> Vec<SourceFeature> {
+    features
+        .iter_features()
+        .filter_map(|(geometry, properties)| {
+            let polygons = match geometry {
+                Geometry::Polygon(polygon) => vec![polygon.clone()],
+                Geometry::MultiPolygon(multi) => multi.0.clone(),
+                _ => return None,
+            };
+            let polygons = polygons
+                .iter()
+                .map(|polygon| {
+                    std::iter::once(polygon.exterior())
+                        .chain(polygon.interiors())
+                        .map(|ring| ring.coords_iter().map(mercator).collect())
+                        .collect()
+                })
+                .collect();
+            SourceFeature::new(Shape::Polygons(polygons), tile_properties(properties))
+        })
+        .collect()
+}
+
+fn tile_properties(properties: &Properties) -> Vec<(String, Property)> {
+    properties
+        .iter()
+        .filter_map(|(key, value)| {
+            let property = match value {
+                serde_json::Value::String(value) => Property::String(value.clone()),
+                serde_json::Value::Number(value) => Property::Number(value.as_f64()?),
+                serde_json::Value::Bool(value) => Property::Bool(*value),
+                _ => return None,
+            };
+            Some((key.clone(), property))
+        })
+        .collect()
+}
+
+// longitude and latitude to Web Mercator, with the world as the unit square and y pointing south
+fn mercator(coord: Coord<f32>) -> Coord<f64> {
+    let lat = (coord.y as f64).to_radians();
+    Coord {
+        x: (coord.x as f64 + 180.0) / 360.0,
+        y: (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0,
+    }
+}
+
+fn intersects(a: &Rect<f64>, b: &Rect<f64>) -> bool {
+    a.min().x <= b.max().x && b.min().x <= a.max().x && a.min().y <= b.max().y && b.min().y <= a.max().y
+}
+
+fn inside(coord: &Coord<f64>) -> bool {
+    (-BUFFER..=EXTENT as f64 + BUFFER).contains(&coord.x) && (-BUFFER..=EXTENT as f64 + BUFFER).contains(&coord.y)
+}
+
+// the runs of a line inside the tile and its buffer, Liang-Barsky on each segment
+fn clip_line(line: &[Coord<f64>]) -> Vec<Vec<Coord<f64>>> {
+    let (min, max) = (-BUFFER, EXTENT as f64 + BUFFER);
+    let mut runs: Vec<Vec<Coord<f64>>> = Vec::new();
+    let mut run: Vec<Coord<f64>> = Vec::new();
+    for segment in line.windows(2) {
+        let (from, to) = (segment[0], segment[1]);
+        let delta = to - from;
+        let (mut enter, mut exit) = (0.0f64, 1.0f64);
+        let bounds = [
+            (-delta.x, from.x - min),
+            (delta.x, max - from.x),
+            (-delta.y, from.y - min),
+            (delta.y, max - from.y),
+        ];
+        let visible = bounds.iter().all(|&(p, q)| {
+            if p == 0.0 {
+                return q >= 0.0;
+            }
+            let t = q / p;
+            if p < 0.0 {
+                enter = enter.max(t);
+            } else {
+                exit = exit.min(t);
+            }
+            enter <= exit
+        });
+        if !visible {
+            if !run.is_empty() {
+                runs.push(std::mem::take(&mut run));
+            }
+            continue;
+        }
+        let (start, end) = (from + delta * enter, from + delta * exit);
+        if run.is_empty() {
+            run.push(start);
+        }
+        run.push(end);
+        // the line left the tile, the next visible segment starts a new run
+        if exit < 1.0 {
+            runs.push(std::mem::take(&mut run));
+        }
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+// an edge of the buffered tile: the coordinate it bounds, where, and whether what's kept is above it
+type Edge = (fn(&Coord<f64>) -> f64, f64, bool);
+
+// a closed ring cut to the tile and its buffer, Sutherland-Hodgman against each edge in turn
+fn clip_ring(ring: &[Coord<f64>]) -> Vec<Coord<f64>> {
+    if ring.iter().all(inside) {
+        return ring.to_vec();
+    }
+    let (min, max) = (-BUFFER, EXTENT as f64 + BUFFER);
+    let edges: [Edge; 4] = [
+        (|coord| coord.x, min, true),
+        (|coord| coord.x, max, false),
+        (|coord| coord.y, min, true),
+        (|coord| coord.y, max, false),
+    ];
+    let mut clipped = ring.to_vec();
+    for (axis, bound, above) in edges {
+        let keep = |coord: &Coord<f64>| if above { axis(coord) >= bound } else { axis(coord) <= bound };
+        let input = std::mem::take(&mut clipped);
+        let Some(mut previous) = input.last().copied() else {
+            break;
+        };
+        for coord in input {
+            if keep(&coord) != keep(&previous) {
+                let t = (bound - axis(&previous)) / (axis(&coord) - axis(&previous));
+                clipped.push(previous + (coord - previous) * t);
+            }
+            if keep(&coord) {
+                clipped.push(coord);
+            }
+            previous = coord;
+        }
+    }
+    clipped
+}
+
+// simplified and rounded to whole tile coordinates, without repeated vertices
+fn quantize(coords: &[Coord<f64>]) -> Vec<(i32, i32)> {
+    let simplified = LineString::from(coords.to_vec()).simplify(&TOLERANCE);
+    let mut quantized: Vec<(i32, i32)> = Vec::with_capacity(simplified.0.len());
+    for coord in simplified.0 {
+        let vertex = (coord.x.round() as i32, coord.y.round() as i32);
+        if quantized.last() != Some(&vertex) {
+            quantized.push(vertex);
+        }
+    }
+    quantized
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    (id & 0x7) | ((count as u32) << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+// vertices are encoded relative to the previous one, across every part of a feature
+#[derive(Default)]
+struct Cursor {
+    geometry: Vec<u32>,
+    at: (i32, i32),
+}
+
+impl Cursor {
+    fn path(&mut self, vertices: &[(i32, i32)], close: bool) {
+        self.geometry.push(command(MOVE_TO, 1));
+        self.step(vertices[0]);
+        self.geometry.push(command(LINE_TO, vertices.len() - 1));
+        for vertex in &vertices[1..] {
+            self.step(*vertex);
+        }
+        if close {
+            self.geometry.push(command(CLOSE_PATH, 1));
+        }
+    }
+
+    fn step(&mut self, (x, y): (i32, i32)) {
+        self.geometry.extend([zigzag(x - self.at.0), zigzag(y - self.at.1)]);
+        self.at = (x, y);
+    }
+}
+
+fn encode_point(coord: Coord<f64>) -> Option<Vec<u32>> {
+    if !inside(&coord) {
+        return None;
+    }
+    let mut cursor = Cursor::default();
+    cursor.geometry.push(command(MOVE_TO, 1));
+    cursor.step((coord.x.round() as i32, coord.y.round() as i32));
+    Some(cursor.geometry)
+}
+
+fn encode_lines(lines: &[Vec<Coord<f64>>]) -> Option<Vec<u32>> {
+    let mut cursor = Cursor::default();
+    for line in lines {
+        let vertices = quantize(line);
+        if vertices.len() >= 2 {
+            cursor.path(&vertices, false);
+        }
+    }
+    Some(cursor.geometry).filter(|geometry| !geometry.is_empty())
+}
+
+// exterior rings wind clockwise on screen (positive area with y pointing down), holes the other way
+fn encode_polygons(polygons: &[Vec<Vec<Coord<f64>>>]) -> Option<Vec<u32>> {
+    let mut cursor = Cursor::default();
+    for rings in polygons {
+        for (idx, ring) in rings.iter().enumerate() {
+            let mut vertices = quantize(ring);
+            // ClosePath returns to the first vertex, it isn't repeated
+            if vertices.len() > 1 && vertices.first() == vertices.last() {
+                vertices.pop();
+            }
+            let area: i64 = vertices
+                .iter()
+                .zip(vertices.iter().cycle().skip(1))
+                .map(|(a, b)| a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64)
+                .sum();
+            if vertices.len() < 3 || area == 0 {
+                // a hole isn't drawn without its exterior
+                if idx == 0 {
+                    break;
+                }
+                continue;
+            }
+            if (idx == 0) != (area > 0) {
+                vertices.reverse();
+            }
+            cursor.path(&vertices, true);
+        }
+    }
+    Some(cursor.geometry).filter(|geometry| !geometry.is_empty())
+}
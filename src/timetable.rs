@@ -0,0 +1,446 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, Timelike};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use crate::ids::{RouteId, StopId, TripId};
+use crate::util;
+
+// GTFS times are relative to the agency's timezone, not the viewer's
+pub const AGENCY_TIMEZONE: Tz = chrono_tz::America::New_York;
+const DAY: u32 = 24 * 60 * 60;
+// how long before a scheduled departure a train is shown stopped at least, in seconds. most of the schedule's
+// arrivals are the same as their departures
+const DWELL: u32 = 30;
+// until this many seconds after midnight the previous day's late night service is still running
+const SERVICE_DAY_OVERLAP: u32 = 4 * 60 * 60;
+
+#[derive(Deserialize)]
+struct CalendarRow {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: u32,
+    end_date: u32,
+}
+
+#[derive(Deserialize)]
+struct CalendarDateRow {
+    service_id: String,
+    date: u32,
+    exception_type: u8,
+}
+
+#[derive(Deserialize)]
+struct TripServiceRow {
+    route_id: RouteId,
+    service_id: String,
+    trip_id: String,
+    shape_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StopTimeRow {
+    trip_id: String,
+    stop_id: StopId,
+    arrival_time: Option<String>,
+    departure_time: Option<String>,
+    stop_sequence: u32,
+}
+
+// the days a service_id runs, dates are YYYYMMDD as in calendar.txt
+#[derive(Default, Serialize, Deserialize)]
+struct Service {
+    // Monday first
+    weekdays: [bool; 7],
+    start_date: u32,
+    end_date: u32,
+    added: HashSet<u32>,
+    removed: HashSet<u32>,
+}
+
+impl Service {
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        let key = date.year() as u32 * 10000 + date.month() * 100 + date.day();
+        if self.removed.contains(&key) {
+            return false;
+        }
+        self.added.contains(&key)
+            || (self.start_date..=self.end_date).contains(&key)
+                && self.weekdays[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScheduledTrip {
+    // in the realtime form, without the static service prefix
+    trip_id: TripId,
+    service_id: String,
+    // arrivals and departures in seconds after midnight of the service day, in stop order
+    stop_times: Vec<(u32, u32, StopId)>,
+}
+
+// when a train is shown stopped at a stop from, at least DWELL before it departs
+fn arrived(&(arrival, departure, _): &(u32, u32, StopId)) -> u32 {
+    arrival.min(departure.saturating_sub(DWELL))
+}
+
+impl ScheduledTrip {
+    // `seconds` into the service day, which is the POSIX timestamp `now`
+    fn position(&self, seconds: u32, now: i64) -> Option<ScheduledPosition<'_>> {
+        let first = self.stop_times.first()?;
+        let (_, last, _) = self.stop_times.last()?;
+        if seconds < arrived(first) || seconds > *last {
+            return None;
+        }
+        // the last stop the train has arrived at
+        let idx = self.stop_times.partition_point(|stop_time| arrived(stop_time) <= seconds) - 1;
+        let (_, departure, stop_id) = &self.stop_times[idx];
+        let next = self.stop_times.get(idx + 1);
+        let midnight = now - seconds as i64;
+        let posix = |time: u32| midnight + time as i64;
+        Some(ScheduledPosition {
+            trip_id: &self.trip_id,
+            stop_id,
+            departed: posix(*departure),
+            next_stop_id: next.map(|(_, _, stop_id)| stop_id),
+            next_arrival: next.map(|next| posix(arrived(next).max(*departure))),
+            stopped: seconds <= *departure,
+            rest: &self.stop_times[idx + 1..],
+            midnight,
+        })
+    }
+}
+
+// where the schedule puts a train, stop_ids are platforms as in stop_times.txt
+pub struct ScheduledPosition<'a> {
+    pub trip_id: &'a TripId,
+    pub stop_id: &'a StopId,
+    // POSIX timestamps of the departure from `stop_id`, still ahead while stopped there, and arrival at `next_stop_id`
+    pub departed: i64,
+    pub next_stop_id: Option<&'a StopId>,
+    pub next_arrival: Option<i64>,
+    pub stopped: bool,
+    // the rest of the trip after `stop_id`, and the service day's midnight as a POSIX timestamp
+    rest: &'a [(u32, u32, StopId)],
+    midnight: i64,
+}
+
+impl ScheduledPosition<'_> {
+    // the stops after `stop_id` with when the train arrives at them, as POSIX timestamps
+    pub fn upcoming(&self) -> impl Iterator<Item = (i64, &StopId)> {
+        self.rest
+            .iter()
+            .map(|stop_time| (self.midnight + arrived(stop_time) as i64, &stop_time.2))
+    }
+}
+
+// a train's hop between consecutive stops of its trip, stop_ids are platforms and times seconds after midnight
+// of the service day it runs in
+pub struct Connection<'a> {
+    // distinct for each trip run in the day
+    pub run: usize,
+    pub trip_id: &'a TripId,
+    pub route_id: &'a RouteId,
+    pub from: &'a StopId,
+    pub to: &'a StopId,
+    pub departure: i64,
+    pub arrival: i64,
+}
+
+// how a train runs past the stations on its way, as the trips of the schedule stop at them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServicePattern {
+    Local,
+    // runs past stations other trips stop at
+    Express,
+}
+
+impl ServicePattern {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Express => "express",
+        }
+    }
+}
+
+// scheduled trips by route, for placing trains when a feed has no realtime positions
+#[derive(Default, Serialize, Deserialize)]
+pub struct Timetable {
+    // by service_id
+    services: HashMap<String, Service>,
+    trips: HashMap<RouteId, Vec<ScheduledTrip>>,
+    // service_ids of the trips run along each shape_id
+    shape_services: HashMap<String, HashSet<String>>,
+    // consecutive stops of a trip that other trips stop between, by the platform stop_id run from
+    express_hops: HashMap<StopId, HashSet<StopId>>,
+    // the express hops made along each shape_id, in the order they're run
+    shape_express_runs: HashMap<String, Vec<(StopId, StopId)>>,
+}
+
+impl Timetable {
+    pub fn load() -> Result<Self> {
+        let find = |file| {
+            util::static_data::gtfs_file(file)
+                .ok_or_else(|| anyhow!("Could not find {} data", file))
+        };
+        let reader = |path: &PathBuf| {
+            csv::Reader::from_path(path).with_context(|| format!("Failed to read '{}'", path.display()))
+        };
+        let malformed = |path: &PathBuf| format!("Malformed row in '{}'", path.display());
+
+        let mut services: HashMap<String, Service> = HashMap::new();
+        let path = find("calendar.txt")?;
+        for rec in reader(&path)?.deserialize() {
+            let row: CalendarRow = rec.with_context(|| malformed(&path))?;
+            let weekdays = [
+                row.monday,
+                row.tuesday,
+                row.wednesday,
+                row.thursday,
+                row.friday,
+                row.saturday,
+                row.sunday,
+            ]
+            .map(|runs| runs == 1);
+            services.insert(
+                row.service_id,
+                Service {
+                    weekdays,
+                    start_date: row.start_date,
+                    end_date: row.end_date,
+                    ..Default::default()
+                },
+            );
+        }
+        // exceptions to calendar.txt are optional
+        if let Some(path) = util::static_data::gtfs_file("calendar_dates.txt") {
+            for rec in reader(&path)?.deserialize() {
+                let row: CalendarDateRow = rec.with_context(|| malformed(&path))?;
+                let service = services.entry(row.service_id).or_default();
+                match row.exception_type {
+                    1 => service.added.insert(row.date),
+                    _ => service.removed.insert(row.date),
+                };
+            }
+        }
+
+        let mut trip_services: HashMap<String, (RouteId, String, Option<String>)> = HashMap::new();
+        let mut shape_services: HashMap<String, HashSet<String>> = HashMap::new();
+        let path = find("trips.txt")?;
+        for rec in reader(&path)?.deserialize() {
+            let row: TripServiceRow = rec.with_context(|| malformed(&path))?;
+            if let Some(shape_id) = &row.shape_id {
+                shape_services
+                    .entry(shape_id.to_owned())
+                    .or_default()
+                    .insert(row.service_id.clone());
+            }
+            trip_services.insert(row.trip_id, (row.route_id, row.service_id, row.shape_id));
+        }
+
+        let mut stop_times: HashMap<String, Vec<(u32, u32, u32, StopId)>> = HashMap::new();
+        let path = find("stop_times.txt")?;
+        for rec in reader(&path)?.deserialize() {
+            let row: StopTimeRow = rec.with_context(|| malformed(&path))?;
+            let arrival = row.arrival_time.as_deref().and_then(parse_time);
+            let departure = row.departure_time.as_deref().and_then(parse_time);
+            if let Some((arrival, departure)) = arrival.or(departure).zip(departure.or(arrival)) {
+                stop_times
+                    .entry(row.trip_id)
+                    .or_default()
+                    .push((row.stop_sequence, arrival, departure, row.stop_id));
+            }
+        }
+
+        let mut trips: HashMap<RouteId, Vec<ScheduledTrip>> = HashMap::new();
+        // the distinct sequences of stops trips make, and the shapes they're run along
+        let mut patterns: HashMap<Vec<StopId>, HashSet<String>> = HashMap::new();
+        for (trip_id, mut times) in stop_times {
+            let Some((route_id, service_id, shape_id)) = trip_services.remove(&trip_id) else {
+                continue;
+            };
+            times.sort_by_key(|(sequence, _, _, _)| *sequence);
+            let pattern = patterns
+                .entry(times.iter().map(|(_, _, _, stop_id)| stop_id.to_owned()).collect())
+                .or_default();
+            pattern.extend(shape_id);
+            // static trip_ids are prefixed with their service, e.g. AFA24GEN-1037-Sunday-00_000600_1..S03R
+            let realtime_id = trip_id.split_once('_').map_or(trip_id.as_str(), |(_, id)| id);
+            trips.entry(route_id).or_default().push(ScheduledTrip {
+                trip_id: realtime_id.into(),
+                service_id,
+                stop_times: times
+                    .into_iter()
+                    .map(|(_, arrival, departure, stop_id)| (arrival, departure, stop_id))
+                    .collect(),
+            });
+        }
+        let (express_hops, shape_express_runs) = express_hops(&patterns);
+        Ok(Self {
+            services,
+            trips,
+            shape_services,
+            express_hops,
+            shape_express_runs,
+        })
+    }
+
+    // the platform stop_ids a route's trips stop at, on any day
+    pub fn route_stops(&self, route_id: &str) -> HashSet<&StopId> {
+        self.trips
+            .get(route_id)
+            .into_iter()
+            .flatten()
+            .flat_map(|trip| trip.stop_times.iter().map(|(_, _, stop_id)| stop_id))
+            .collect()
+    }
+
+    // whether a train runs from one platform to the next past stations other trains stop at
+    pub fn is_express_hop(&self, from: &str, to: &str) -> bool {
+        self.express_hops.get(from).is_some_and(|hops| hops.contains(to))
+    }
+
+    // how a train runs over the stops it has left, None when there's no further stop to tell by
+    pub fn service_pattern<'s>(&self, stop_ids: impl IntoIterator<Item = &'s str>) -> Option<ServicePattern> {
+        let stop_ids: Vec<&str> = stop_ids.into_iter().collect();
+        if stop_ids.len() < 2 {
+            return None;
+        }
+        let express = stop_ids.windows(2).any(|hop| self.is_express_hop(hop[0], hop[1]));
+        Some(if express { ServicePattern::Express } else { ServicePattern::Local })
+    }
+
+    // the platform stop_ids of each express run along the shape
+    pub fn express_runs(&self, shape_id: &str) -> &[(StopId, StopId)] {
+        self.shape_express_runs.get(shape_id).map_or(&[], Vec::as_slice)
+    }
+
+    // whether any trip along the shape runs in the service day at the POSIX timestamp `now`
+    // shapes without trips are kept, there's nothing to say they don't run
+    pub fn shape_active(&self, shape_id: &str, now: i64) -> bool {
+        let Some(services) = self.shape_services.get(shape_id) else {
+            return true;
+        };
+        let Some(local) = DateTime::from_timestamp(now, 0).map(|now| now.with_timezone(&AGENCY_TIMEZONE)) else {
+            return true;
+        };
+        let today = local.date_naive();
+        let mut days = vec![today];
+        if local.num_seconds_from_midnight() < SERVICE_DAY_OVERLAP {
+            days.extend(today.pred_opt());
+        }
+        services.iter().any(|service_id| {
+            self.services
+                .get(service_id)
+                .is_some_and(|service| days.iter().any(|day| service.runs_on(*day)))
+        })
+    }
+
+    // positions of a route's trains at the POSIX timestamp `now`, according to the schedule
+    pub fn positions(&self, route_id: &str, now: i64) -> Vec<ScheduledPosition<'_>> {
+        let (Some(trips), Some(local)) = (
+            self.trips.get(route_id),
+            DateTime::from_timestamp(now, 0).map(|now| now.with_timezone(&AGENCY_TIMEZONE)),
+        ) else {
+            return Vec::new();
+        };
+        let today = local.date_naive();
+        let seconds = local.num_seconds_from_midnight();
+        // trips running past midnight belong to the previous day's service
+        let mut days = vec![(today, seconds)];
+        if let Some(yesterday) = today.pred_opt() {
+            days.push((yesterday, seconds + DAY));
+        }
+
+        trips
+            .iter()
+            .filter_map(|trip| {
+                let service = self.services.get(&trip.service_id)?;
+                days.iter()
+                    .filter(|(date, _)| service.runs_on(*date))
+                    .find_map(|(_, seconds)| trip.position(*seconds, now))
+            })
+            .collect()
+    }
+
+    // every hop of the trips run in a service day, in order of departure. times are seconds after the day's
+    // midnight, past a day for trips running after it
+    pub fn day_connections(&self, date: NaiveDate) -> Vec<Connection<'_>> {
+        let mut connections = Vec::new();
+        let mut run = 0;
+        for (route_id, trips) in &self.trips {
+            for trip in trips {
+                if !self.services.get(&trip.service_id).is_some_and(|service| service.runs_on(date)) {
+                    continue;
+                }
+                connections.extend(trip.stop_times.windows(2).map(|hop| Connection {
+                    run,
+                    trip_id: &trip.trip_id,
+                    route_id,
+                    from: &hop[0].2,
+                    to: &hop[1].2,
+                    departure: hop[0].1 as i64,
+                    arrival: hop[1].0 as i64,
+                }));
+                run += 1;
+            }
+        }
+        connections.sort_by_key(|connection| connection.departure);
+        connections
+    }
+}
+
+// hops between consecutive stops of a pattern that another pattern makes stops between, by the stop run from,
+// and the hops made along each shape
+#[allow(clippy::type_complexity)]
+fn express_hops(
+    patterns: &HashMap<Vec<StopId>, HashSet<String>>,
+) -> (HashMap<StopId, HashSet<StopId>>, HashMap<String, Vec<(StopId, StopId)>>) {
+    let mut stopped_between: HashSet<(&StopId, &StopId)> = HashSet::new();
+    for pattern in patterns.keys() {
+        for (idx, from) in pattern.iter().enumerate() {
+            for to in pattern.iter().skip(idx + 2) {
+                stopped_between.insert((from, to));
+            }
+        }
+    }
+
+    let mut hops: HashMap<StopId, HashSet<StopId>> = HashMap::new();
+    let mut shape_runs: HashMap<String, Vec<(StopId, StopId)>> = HashMap::new();
+    for (pattern, shape_ids) in patterns {
+        let express: Vec<_> = pattern
+            .windows(2)
+            .filter(|hop| stopped_between.contains(&(&hop[0], &hop[1])))
+            .map(|hop| (hop[0].to_owned(), hop[1].to_owned()))
+            .collect();
+        for (from, to) in &express {
+            hops.entry(from.to_owned()).or_default().insert(to.to_owned());
+        }
+        for shape_id in shape_ids {
+            let runs = shape_runs.entry(shape_id.to_owned()).or_default();
+            for hop in &express {
+                if !runs.contains(hop) {
+                    runs.push(hop.clone());
+                }
+            }
+        }
+    }
+    (hops, shape_runs)
+}
+
+// GTFS times are HH:MM:SS after midnight of the service day, hours can go past 24
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().splitn(3, ':').map(|part| part.parse::<u32>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::time::Duration;
+use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+use crate::feed::SharedFeedState;
+use crate::render::color::ColorMode;
+use crate::status::LineStatus;
+
+// how often the icon is refreshed and menu events are handled
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const ICON_SIZE: u32 = 32;
+// shown before any feed has reported
+const NO_STATUS_COLOR: [u8; 3] = [0x80, 0x80, 0x80];
+
+// what the main event loop should do in response to the tray menu
+pub enum TrayAction {
+    Open,
+    Quit,
+}
+
+// tray icon colored by the worst line status, with a menu of quick actions
+// it has to live on the main thread, and on Linux GTK has to be pumped from there too
+pub struct Tray {
+    icon: TrayIcon,
+    shared: SharedFeedState,
+    status: Option<LineStatus>,
+    open: MenuItem,
+    pause: CheckMenuItem,
+    color_modes: Vec<(CheckMenuItem, ColorMode)>,
+    quit: MenuItem,
+}
+
+impl Tray {
+    pub fn new(shared: SharedFeedState) -> Result<Self> {
+        #[cfg(target_os = "linux")]
+        gtk::init()?;
+
+        let menu = Menu::new();
+        let open = MenuItem::new("Open window", true, None);
+        let pause = CheckMenuItem::new("Pause polling", true, false, None);
+        let color_menu = Submenu::new("Station colors", true);
+        let selected = shared.controls.read().unwrap().color_mode;
+        let color_modes: Vec<_> = ColorMode::ALL
+            .into_iter()
+            .map(|mode| {
+                let item = CheckMenuItem::new(mode.label(), true, mode == selected, None);
+                (item, mode)
+            })
+            .collect();
+        for (item, _) in &color_modes {
+            color_menu.append(item)?;
+        }
+        let quit = MenuItem::new("Quit", true, None);
+        menu.append(&open)?;
+        menu.append(&pause)?;
+        menu.append(&color_menu)?;
+        menu.append(&PredefinedMenuItem::separator())?;
+        menu.append(&quit)?;
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("NYC Subway")
+            .with_icon(status_icon(None)?)
+            .build()?;
+
+        Ok(Self {
+            icon,
+            shared,
+            status: None,
+            open,
+            pause,
+            color_modes,
+            quit,
+        })
+    }
+
+    // refresh the icon and apply menu selections, returning actions for the event loop
+    pub fn update(&mut self) -> Vec<TrayAction> {
+        #[cfg(target_os = "linux")]
+        while gtk::events_pending() {
+            gtk::main_iteration_do(false);
+        }
+
+        let status = self.shared.statuses.all().into_values().max();
+        if status != self.status {
+            self.status = status;
+            let tooltip = match status {
+                Some(status) => format!("NYC Subway - {}", status.label()),
+                None => "NYC Subway".to_owned(),
+            };
+            if let Err(err) = status_icon(status).and_then(|icon| Ok(self.icon.set_icon(Some(icon))?)) {
+                tracing::warn!("Failed to update tray icon: {}", err);
+            }
+            let _ = self.icon.set_tooltip(Some(tooltip));
+        }
+
+        let mut actions = Vec::new();
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            if event.id == *self.open.id() {
+                actions.push(TrayAction::Open);
+            } else if event.id == *self.quit.id() {
+                actions.push(TrayAction::Quit);
+            } else if event.id == *self.pause.id() {
+                self.shared.controls.write().unwrap().paused = self.pause.is_checked();
+            } else if let Some(mode) = self
+                .color_modes
+                .iter()
+                .find(|(item, _)| event.id == *item.id())
+                .map(|(_, mode)| *mode)
+            {
+                // the items behave as radio buttons
+                for (item, other) in &self.color_modes {
+                    item.set_checked(*other == mode);
+                }
+                self.shared.controls.write().unwrap().color_mode = mode;
+            }
+        }
+
+        // the overlay panel can change the same controls
+        let controls = self.shared.controls.read().unwrap();
+        if self.pause.is_checked() != controls.paused {
+            self.pause.set_checked(controls.paused);
+        }
+        for (item, mode) in &self.color_modes {
+            if item.is_checked() != (*mode == controls.color_mode) {
+                item.set_checked(*mode == controls.color_mode);
+            }
+        }
+        actions
+    }
+}
+
+// a filled circle in the status color
+fn status_icon(status: Option<LineStatus>) -> Result<Icon> {
+    let [r, g, b] = status.map_or(NO_STATUS_COLOR, |status| status.color());
+    let radius = ICON_SIZE as f32 / 2.0;
+    let rgba = (0..ICON_SIZE * ICON_SIZE)
+        .flat_map(|idx| {
+            let x = (idx % ICON_SIZE) as f32 + 0.5 - radius;
+            let y = (idx / ICON_SIZE) as f32 + 0.5 - radius;
+            let alpha = (radius - x.hypot(y)).clamp(0.0, 1.0);
+            [r, g, b, (alpha * 255.0) as u8]
+        })
+        .collect();
+    Ok(Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE)?)
+}
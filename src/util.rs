@@ -1,31 +1,80 @@
-use anyhow::Result;
-use log::info;
-use reqwest;
+use anyhow::{Context, Result};
+use tracing::info;
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{fs, io};
-use tokio;
-use xdg;
-use zip;
 
+pub mod clock;
+pub mod format;
 pub mod geo;
+pub mod latest;
+pub mod projection;
 
 pub fn get_xdg() -> Result<xdg::BaseDirectories> {
-    let xdg_dirs = xdg::BaseDirectories::with_prefix(env!("CARGO_PKG_NAME"))?;
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(env!("CARGO_PKG_NAME"))
+        .context("Failed to find the XDG base directories, is $HOME set?")?;
     Ok(xdg_dirs)
 }
 
+// when the clock was sped up, the POSIX time then and the speed, see `accelerate`
+static CLOCK: OnceLock<(Instant, i64, f64)> = OnceLock::new();
+
+// set by --plain, terminal output is then only lines of text, without progress bars or colors
+static PLAIN: OnceLock<()> = OnceLock::new();
+
+pub fn set_plain() {
+    let _ = PLAIN.set(());
+}
+
+pub fn plain() -> bool {
+    PLAIN.get().is_some()
+}
+
+// current time as a POSIX timestamp, comparable to GTFS-realtime times
+pub fn posix_now() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    match CLOCK.get() {
+        Some((started, at, speed)) => at + (started.elapsed().as_secs_f64() * speed) as i64,
+        None => now,
+    }
+}
+
+// run `posix_now` `speed` times faster than real time from here on, for simulations
+// the clock can only be sped up once
+pub fn accelerate(speed: f64) {
+    let _ = CLOCK.set((Instant::now(), posix_now(), speed));
+}
+
 pub mod static_data {
     use super::*;
+    use tracing::warn;
+    use indicatif::{ProgressBar, ProgressStyle};
+    use reqwest::header::{self, HeaderValue};
+    use reqwest::StatusCode;
+    use serde::{Deserialize, Serialize};
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    // url, file name and how long a downloaded copy is trusted before it's revalidated
+    pub type StaticDataEndpoint = (&'static str, &'static str, Duration);
 
-    pub type StaticDataEndpoint = (&'static str, &'static str);
+    const WEEK: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+    const MONTH: Duration = Duration::from_secs(60 * 60 * 24 * 30);
 
     pub const GTFS_STATIC: StaticDataEndpoint = (
         "http://web.mta.info/developers/files/google_transit_supplemented.zip",
         "nyc_gtfs_supplemented.zip",
+        WEEK,
     );
     pub const COASTLINE_STATIC: StaticDataEndpoint = (
         "https://data.cityofnewyork.us/resource/59xk-wagz.geojson",
         "nyc_coastline.geojson",
+        MONTH,
     );
     pub const BOROUGH_BOUNDARIES_STATIC: StaticDataEndpoint = (
         // federal GIS data from this dataset has been purged
@@ -33,60 +82,391 @@ pub mod static_data {
         // "https://data.cityofnewyork.us/resource/7t3b-ywvw.geojson",
         "https://bedell.co/static/nyc_boroughs.geojson",
         "nyc_boroughs.geojson",
+        MONTH,
     );
 
     pub const PARKS_STATIC: StaticDataEndpoint = (
         "https://data.cityofnewyork.us/resource/enfh-gkve.geojson?typecategory=Flagship Park",
         "nyc_parks.geojson",
+        MONTH,
     );
 
+    // station complexes, which the GTFS schedule doesn't have
+    pub const STATIONS_STATIC: StaticDataEndpoint = (
+        "https://data.ny.gov/api/views/39hk-dx4f/rows.csv?accessType=DOWNLOAD",
+        "mta_subway_stations.csv",
+        MONTH,
+    );
+
+    // each station complex's ridership by hour of the day over 2024, the hourly dataset's last year, summed upstream.
+    // it has a row for every fare class and payment method of every hour, which are summed rather than averaged
+    pub const RIDERSHIP_STATIC: StaticDataEndpoint = (
+        "https://data.ny.gov/resource/wujg-7c2s.csv?$select=station_complex_id,date_extract_hh(transit_timestamp) AS hour,sum(ridership) AS ridership&$where=transit_timestamp >= '2024-01-01T00:00:00' AND transit_timestamp < '2025-01-01T00:00:00'&$group=station_complex_id,hour&$limit=50000",
+        "mta_ridership_2024.csv",
+        MONTH,
+    );
+    // hours RIDERSHIP_STATIC sums over, 2024 being a leap year
+    pub const RIDERSHIP_HOURS: f32 = 366.0 * 24.0;
+
+    // GTFS schedules are unpacked into a directory of their own under this one, named for when they were unpacked
+    pub const GTFS_DIR: &str = "gtfs";
+    const GTFS_VERSIONS_KEPT: usize = 3;
+
+    // response validators from the last download, stored beside the data as `<file>.validators`
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Validators {
+        etag: Option<String>,
+        last_modified: Option<String>,
+    }
+
+    impl Validators {
+        // what a range request is conditional on, the etag when there is one
+        fn if_range(&self) -> Option<&str> {
+            self.etag.as_deref().or(self.last_modified.as_deref())
+        }
+    }
+
+    fn validators_path(path: &Path) -> PathBuf {
+        let mut file = path.as_os_str().to_owned();
+        file.push(".validators");
+        PathBuf::from(file)
+    }
+
+    // a download in progress, renamed into place once it's complete
+    fn partial_path(path: &Path) -> PathBuf {
+        let mut file = path.as_os_str().to_owned();
+        file.push(".partial");
+        PathBuf::from(file)
+    }
+
+    // how much of a download has arrived, the total is unknown without a Content-Length
+    #[derive(Debug, Clone, Copy)]
+    pub struct Progress {
+        pub downloaded: u64,
+        pub total: Option<u64>,
+    }
+
+    // `fetch_with_progress`, reported with a progress bar on the terminal
     pub async fn fetch(
         endpoint: StaticDataEndpoint,
         base_path: Option<PathBuf>,
-    ) -> Result<PathBuf> {
+        force: bool,
+    ) -> Result<Option<PathBuf>> {
+        let (_, file, _) = endpoint;
+        // indicatif doesn't draw when stderr isn't a terminal, plain output has a line before and after instead
+        let bar = if plain() {
+            eprintln!("Checking {file} for updates");
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::no_length().with_message(file).with_style(
+                ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} {bytes_per_sec}, ETA {eta}")?
+                    .progress_chars("=> "),
+            )
+        };
+        let fetched = fetch_with_progress(endpoint, base_path, force, |progress| {
+            if let Some(total) = progress.total {
+                bar.set_length(total);
+            }
+            bar.set_position(progress.downloaded);
+        })
+        .await;
+        bar.finish_and_clear();
+        if plain() {
+            match &fetched {
+                Ok(Some(_)) => eprintln!("Downloaded {file}, {}", format::bytes(bar.position() as f64)),
+                Ok(None) => eprintln!("{file} is up to date"),
+                Err(_) => {}
+            }
+        }
+        fetched
+    }
+
+    // downloads an endpoint, or revalidates the existing copy with a conditional GET unless forced
+    // the body is streamed to disk, calling `progress` after each chunk. An interrupted download is resumed with a
+    // range request, and one that's cut short or fails its CRCs is fetched once more from scratch
+    // returns the path only when new data was written
+    pub async fn fetch_with_progress(
+        endpoint: StaticDataEndpoint,
+        base_path: Option<PathBuf>,
+        force: bool,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<Option<PathBuf>> {
         let xdg = get_xdg()?;
         let mut outfile_path = if let Some(path) = base_path {
             path
         } else {
             xdg.get_cache_home()
         };
-        fs::create_dir_all(&outfile_path)?;
-        let (static_endpoint, outfile) = endpoint;
+        fs::create_dir_all(&outfile_path)
+            .with_context(|| format!("Failed to create '{}'", outfile_path.display()))?;
+        let (static_endpoint, outfile, _) = endpoint;
         outfile_path.push(outfile);
+        let partial_path = partial_path(&outfile_path);
+        // validators of the response the partial download is from, to resume it only if it hasn't changed since
+        let partial_validators_path = validators_path(&partial_path);
+        let validators_path = validators_path(&outfile_path);
 
-        info!("Fetching: '{}'", static_endpoint);
-        let res = reqwest::get(static_endpoint).await?.bytes().await?;
+        let validators: Validators = if outfile_path.exists() && !force {
+            read_validators(&validators_path)
+        } else {
+            Validators::default()
+        };
+        let mut retried = false;
+        loop {
+            let resumed = fs::metadata(&partial_path)
+                .map(|metadata| metadata.len())
+                .ok()
+                .filter(|len| *len > 0)
+                .zip(
+                    read_validators(&partial_validators_path)
+                        .if_range()
+                        .map(str::to_owned),
+                );
+            let mut request = reqwest::Client::new().get(static_endpoint);
+            match &resumed {
+                Some((offset, if_range)) => {
+                    // the whole body is sent again if it's changed since
+                    request = request
+                        .header(header::RANGE, format!("bytes={offset}-"))
+                        .header(header::IF_RANGE, if_range);
+                }
+                None => {
+                    if let Some(etag) = &validators.etag {
+                        request = request.header(header::IF_NONE_MATCH, etag);
+                    }
+                    if let Some(last_modified) = &validators.last_modified {
+                        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+                    }
+                }
+            }
+
+            info!("Fetching: '{}'", static_endpoint);
+            let mut res = match request.send().await.and_then(|res| res.error_for_status()) {
+                Ok(res) => res,
+                // the partial download is already as long as the file, or longer, start it over
+                Err(err) if resumed.is_some() && err.status() == Some(StatusCode::RANGE_NOT_SATISFIABLE) => {
+                    let _ = fs::remove_file(&partial_path);
+                    continue;
+                }
+                // stale data beats no data
+                Err(err) if outfile_path.exists() => {
+                    warn!("Failed to revalidate '{}', using existing copy: {}", outfile, err);
+                    return Ok(None);
+                }
+                Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to fetch '{static_endpoint}'"))),
+            };
+            if res.status() == StatusCode::NOT_MODIFIED {
+                info!("Unchanged: '{}'", static_endpoint);
+                // bump the modified time so it isn't revalidated again until its max age
+                write_validators(&validators_path, &validators)?;
+                return Ok(None);
+            }
+
+            let header = |name| {
+                res.headers()
+                    .get(name)
+                    .and_then(|value: &HeaderValue| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let fetched = Validators {
+                etag: header(header::ETAG),
+                last_modified: header(header::LAST_MODIFIED),
+            };
+            let (mut partial, downloaded) = match resumed {
+                Some((offset, _)) if res.status() == StatusCode::PARTIAL_CONTENT => {
+                    info!("Resuming '{}' from {}", outfile, format::bytes(offset as f64));
+                    let partial = tokio::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&partial_path)
+                        .await
+                        .with_context(|| format!("Failed to open '{}'", partial_path.display()))?;
+                    (partial, offset)
+                }
+                _ => {
+                    write_validators(&partial_validators_path, &fetched)?;
+                    let partial = tokio::fs::File::create(&partial_path)
+                        .await
+                        .with_context(|| format!("Failed to create '{}'", partial_path.display()))?;
+                    (partial, 0)
+                }
+            };
+            let mut current = Progress {
+                downloaded,
+                total: res.content_length().map(|length| downloaded + length),
+            };
+            progress(current);
+            let write_failed = || format!("Failed to write '{}'", partial_path.display());
+            while let Some(chunk) = res
+                .chunk()
+                .await
+                .with_context(|| format!("Failed to download '{static_endpoint}'"))?
+            {
+                partial.write_all(&chunk).await.with_context(write_failed)?;
+                current.downloaded += chunk.len() as u64;
+                progress(current);
+            }
+            partial.flush().await.with_context(write_failed)?;
+            drop(partial);
+
+            match verify(&partial_path, outfile, current) {
+                Ok(()) => {
+                    tokio::fs::rename(&partial_path, &outfile_path)
+                        .await
+                        .with_context(|| format!("Failed to move the download to '{}'", outfile_path.display()))?;
+                    let _ = fs::remove_file(&partial_validators_path);
+                    write_validators(&validators_path, &fetched)?;
+                    return Ok(Some(outfile_path));
+                }
+                Err(err) => {
+                    let _ = fs::remove_file(&partial_path);
+                    let _ = fs::remove_file(&partial_validators_path);
+                    if retried {
+                        return Err(err.context(format!("'{}' is corrupt", outfile)));
+                    }
+                    warn!("Download of '{}' is corrupt, fetching it again: {}", outfile, err);
+                    retried = true;
+                }
+            }
+        }
+    }
 
-        tokio::fs::write(&outfile_path, res).await?;
-        Ok(outfile_path)
+    fn write_validators(path: &Path, validators: &Validators) -> Result<()> {
+        fs::write(path, serde_json::to_string(validators)?)
+            .with_context(|| format!("Failed to write '{}'", path.display()))
     }
-    pub async fn unzip(path: PathBuf) -> Result<()> {
+
+    fn read_validators(path: &Path) -> Validators {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    // a body shorter than its Content-Length, or a zip with an entry that fails its CRC, is corrupt.
+    // caught here it's fetched again, rather than failing when the schedule is parsed
+    fn verify(path: &Path, outfile: &str, progress: Progress) -> Result<()> {
+        if let Some(total) = progress.total {
+            anyhow::ensure!(
+                progress.downloaded == total,
+                "expected {} bytes, got {}",
+                total,
+                progress.downloaded
+            );
+        }
+        if outfile.ends_with(".zip") {
+            let unreadable = || format!("Failed to read '{}'", path.display());
+            let zipfile = fs::File::open(path).with_context(unreadable)?;
+            let mut archive = zip::ZipArchive::new(zipfile).with_context(unreadable)?;
+            // the zip crate checks an entry's CRC once it's read to the end
+            for i in 0..archive.len() {
+                io::copy(&mut archive.by_index(i)?, &mut io::sink())?;
+            }
+        }
+        Ok(())
+    }
+
+    // unpacks a GTFS schedule into a new version directory, which schedules are loaded from once it's complete.
+    // the running app's files are never overwritten, and versions older than the last GTFS_VERSIONS_KEPT are removed
+    pub async fn unzip(path: PathBuf) -> Result<PathBuf> {
         info!("Unzipping: '{}'", path.display());
         let xdg = get_xdg()?;
+        let versions = xdg.create_data_directory(GTFS_DIR)?;
+        let version = chrono::Utc::now().format("%Y%m%dT%H%M%S").to_string();
+        let partial = versions.join(format!("{version}.partial"));
+        fs::create_dir_all(&partial).with_context(|| format!("Failed to create '{}'", partial.display()))?;
 
-        let zipfile = fs::File::open(path)?;
+        let unreadable = || format!("Failed to read '{}'", path.display());
+        let zipfile = fs::File::open(&path).with_context(unreadable)?;
 
-        let mut archive = zip::ZipArchive::new(zipfile)?;
+        let mut archive = zip::ZipArchive::new(zipfile).with_context(unreadable)?;
 
         for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-
-            if let Some(outpath) = file.enclosed_name() {
-                let filename = outpath.file_name().unwrap();
-                let data_path = xdg.place_data_file(filename)?;
-                let mut outfile = fs::File::create(&data_path)?;
-                io::copy(&mut file, &mut outfile)?;
-            } else {
+            let mut file = archive.by_index(i).with_context(unreadable)?;
+
+            // directories and entries that would land outside of the version directory are skipped
+            let Some(filename) = file.enclosed_name().and_then(|outpath| outpath.file_name().map(PathBuf::from)) else {
                 continue;
+            };
+            let outpath = partial.join(filename);
+            let mut outfile =
+                fs::File::create(&outpath).with_context(|| format!("Failed to create '{}'", outpath.display()))?;
+            io::copy(&mut file, &mut outfile).with_context(|| format!("Failed to unzip '{}'", outpath.display()))?;
+        }
+
+        let version_path = versions.join(version);
+        fs::rename(&partial, &version_path)
+            .with_context(|| format!("Failed to move the schedule to '{}'", version_path.display()))?;
+        prune_gtfs_versions(&versions);
+        Ok(version_path)
+    }
+
+    // complete version directories, oldest first
+    fn gtfs_versions(versions: &Path) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(versions)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir() && path.extension().is_none())
+            .collect();
+        dirs.sort();
+        dirs
+    }
+
+    // unpacking that was interrupted is cleaned up along with the old versions
+    fn prune_gtfs_versions(versions: &Path) {
+        let complete = gtfs_versions(versions);
+        let old = complete.len().saturating_sub(GTFS_VERSIONS_KEPT);
+        let interrupted = fs::read_dir(versions)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "partial"));
+        for dir in complete.into_iter().take(old).chain(interrupted) {
+            match fs::remove_dir_all(&dir) {
+                Ok(()) => info!("Removed old schedule: '{}'", dir.display()),
+                Err(err) => warn!("Failed to remove old schedule '{}': {}", dir.display(), err),
             }
         }
+    }
 
-        Ok(())
+    // the newest unpacked GTFS schedule
+    pub fn gtfs_dir() -> Option<PathBuf> {
+        let versions = get_xdg().ok()?.find_data_file(GTFS_DIR)?;
+        gtfs_versions(&versions).pop()
     }
-    // @todo also check for staleness
+
+    // a file of the newest GTFS schedule, or one unpacked straight into the data directory before schedules were versioned
+    pub fn gtfs_file(file: &str) -> Option<PathBuf> {
+        gtfs_dir()
+            .map(|dir| dir.join(file))
+            .filter(|path| path.exists())
+            .or_else(|| get_xdg().ok()?.find_data_file(file))
+    }
+
+    // missing, or not validated within the endpoint's max age
     pub fn shoud_fetch(endpoint: StaticDataEndpoint) -> bool {
-        let (_, file) = endpoint;
-        let xdg = get_xdg().unwrap();
-        xdg.find_cache_file(file).or_else(|| xdg.find_data_file(file)).is_none()
+        let (_, file, max_age) = endpoint;
+        // with nowhere to look, it's fetched, and fails there with why
+        let Ok(xdg) = get_xdg() else {
+            return true;
+        };
+        xdg.find_cache_file(file)
+            .or_else(|| xdg.find_data_file(file))
+            .and_then(|path| since_validated(&path))
+            .is_none_or(|age| age > max_age)
+    }
+
+    // time since a downloaded file was last fetched or revalidated
+    pub fn since_validated(path: &Path) -> Option<Duration> {
+        let validators_path = validators_path(path);
+        let checked = if validators_path.exists() {
+            &validators_path
+        } else {
+            path
+        };
+        fs::metadata(checked)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
     }
 }
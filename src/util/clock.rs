@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// where feed processing gets the time, both for scheduling fetches and for comparing with feed timestamps
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    // as a POSIX timestamp, comparable to GTFS-realtime times
+    fn posix_now(&self) -> i64;
+}
+
+// the time of day, sped up when the simulation clock is, see `util::accelerate`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn posix_now(&self) -> i64 {
+        super::posix_now()
+    }
+}
+
+// a clock that only moves when it's advanced, for stepping through feed updates deterministically.
+// the map itself always runs on the system clock, this is for tests and embedders of the library
+#[derive(Debug)]
+pub struct ManualClock {
+    started: Instant,
+    posix: i64,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    // stopped at the POSIX timestamp `posix`
+    pub fn new(posix: i64) -> Self {
+        Self {
+            started: Instant::now(),
+            posix,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.started + *self.elapsed.lock().unwrap()
+    }
+
+    fn posix_now(&self) -> i64 {
+        self.posix + self.elapsed.lock().unwrap().as_secs() as i64
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+const FEET_PER_METER: f32 = 3.28084;
+const FEET_PER_MILE: f32 = 5280.0;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockFormat {
+    #[default]
+    #[serde(rename = "12h")]
+    Hours12,
+    #[serde(rename = "24h")]
+    Hours24,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Imperial,
+    Metric,
+}
+
+// locale settings for displayed times and distances, from the `[format]` config section
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Formatter {
+    pub clock: ClockFormat,
+    pub units: Units,
+}
+
+impl Formatter {
+    // POSIX timestamp as a wall clock time in `timezone`, whatever the system timezone
+    pub fn time(&self, timestamp: i64, timezone: Tz) -> String {
+        match self.clock {
+            ClockFormat::Hours12 => local_time(timestamp, timezone, "%-I:%M %p"),
+            ClockFormat::Hours24 => local_time(timestamp, timezone, "%H:%M"),
+        }
+    }
+
+    // as `time`, to the second
+    pub fn clock(&self, timestamp: i64, timezone: Tz) -> String {
+        match self.clock {
+            ClockFormat::Hours12 => local_time(timestamp, timezone, "%-I:%M:%S %p"),
+            ClockFormat::Hours24 => local_time(timestamp, timezone, "%H:%M:%S"),
+        }
+    }
+
+    pub fn distance(&self, meters: f32) -> String {
+        match self.units {
+            Units::Metric if meters < 1000.0 => format!("{:.0} m", meters),
+            Units::Metric => format!("{:.1} km", meters / 1000.0),
+            Units::Imperial => {
+                let feet = meters * FEET_PER_METER;
+                if feet < FEET_PER_MILE / 4.0 {
+                    format!("{:.0} ft", feet)
+                } else {
+                    format!("{:.1} mi", feet / FEET_PER_MILE)
+                }
+            }
+        }
+    }
+}
+
+fn local_time(timestamp: i64, timezone: Tz, format: &str) -> String {
+    match DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => time.with_timezone(&timezone).format(format).to_string(),
+        None => String::from("--:--"),
+    }
+}
+
+// a byte count with a binary unit, e.g. 1.5 MB
+pub fn bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// a number of seconds in its largest whole unit, e.g. 45 s or 3 min
+pub fn age(seconds: i64) -> String {
+    match seconds {
+        ..60 => format!("{seconds} s"),
+        60..3600 => format!("{} min", seconds / 60),
+        _ => format!("{} h", seconds / 3600),
+    }
+}
@@ -1,4 +1,8 @@
-use geo::{Coord, HaversineBearing, HaversineDistance, Point, Polygon, Rect, LineString};
+use geo::{Coord, Polygon, Rect, LineString};
+use std::collections::{BTreeSet, HashMap};
+
+// grid cell size, in meters, used to find routes that share track
+const SHARED_TRACK_CELL: f32 = 30.0;
 
 pub fn combine_bounding_rect(acc: Rect, rect: Rect) -> Rect {
     let Coord { x: min_x, y: min_y } = acc.min();
@@ -22,15 +26,6 @@ pub fn combine_bounding_rect(acc: Rect, rect: Rect) -> Rect {
     Rect::new(nmin, nmax)
 }
 
-pub fn coord_to_xy(coord: Coord<f32>, centroid: &Point<f32>) -> Coord<f32> {
-    let point: Point<f32> = coord.into();
-    let distance = centroid.haversine_distance(&point);
-    let bearing = centroid.haversine_bearing(point).to_radians();
-    let x = distance * bearing.cos();
-    let y = distance * bearing.sin();
-    Coord { x, y }
-}
-
 pub fn circle(coord: Coord, radius: f64) -> Polygon {
     let mut line_coords: Vec<Coord> = Vec::new();
 
@@ -41,7 +36,67 @@ pub fn circle(coord: Coord, radius: f64) -> Polygon {
         line_coords.push(geo::coord!{ x: x, y: y });
     };
     let first = line_coords.first().unwrap();
-    line_coords.push(first.clone());
+    line_coords.push(*first);
 
     Polygon::new(LineString::new(line_coords), vec![])
 }
+
+// offset each line perpendicular to its direction so routes sharing track are drawn side by side
+// `lines` are (route_id, polyline) pairs, lines of the same route are allowed to overlap
+pub fn offset_shared_track(lines: &[(&str, Vec<Coord<f32>>)], spacing: f32) -> Vec<Vec<Coord<f32>>> {
+    let mut grid: HashMap<(i32, i32), BTreeSet<&str>> = HashMap::new();
+    for (route_id, line) in lines {
+        for pair in line.windows(2) {
+            let delta = pair[1] - pair[0];
+            let steps = (delta.x.hypot(delta.y) / SHARED_TRACK_CELL).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let coord = pair[0] + delta * (step as f32 / steps as f32);
+                grid.entry(cell(coord)).or_default().insert(route_id);
+            }
+        }
+    }
+
+    lines
+        .iter()
+        .map(|(route_id, line)| {
+            (0..line.len())
+                .map(|i| {
+                    let coord = line[i];
+                    let direction = line[(i + 1).min(line.len() - 1)] - line[i.saturating_sub(1)];
+                    let length = direction.x.hypot(direction.y);
+                    if length == 0.0 {
+                        return coord;
+                    }
+                    // lines run both ways, keep the normal on the same side regardless of direction
+                    let mut normal = Coord {
+                        x: -direction.y / length,
+                        y: direction.x / length,
+                    };
+                    if normal.x < 0.0 || (normal.x == 0.0 && normal.y < 0.0) {
+                        normal = -normal;
+                    }
+
+                    let (cx, cy) = cell(coord);
+                    let sharing: BTreeSet<&str> = (-1..=1)
+                        .flat_map(|dx| (-1..=1).map(move |dy| (cx + dx, cy + dy)))
+                        .filter_map(|key| grid.get(&key))
+                        .flatten()
+                        .copied()
+                        .collect();
+                    let Some(index) = sharing.iter().position(|id| id == route_id) else {
+                        return coord;
+                    };
+                    let offset = (index as f32 - (sharing.len() - 1) as f32 / 2.0) * spacing;
+                    coord + normal * offset
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn cell(coord: Coord<f32>) -> (i32, i32) {
+    (
+        (coord.x / SHARED_TRACK_CELL).floor() as i32,
+        (coord.y / SHARED_TRACK_CELL).floor() as i32,
+    )
+}
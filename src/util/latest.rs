@@ -0,0 +1,53 @@
+use std::sync::mpsc::{SendError, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+// a channel that only holds the most recent value sent. sending again replaces a value that hasn't been received,
+// so a receiver that falls behind skips to the latest instead of working through a backlog of outdated ones
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        closed: false,
+    }));
+    (Sender(slot.clone()), Receiver(slot))
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    // the sender was dropped
+    closed: bool,
+}
+
+pub struct Sender<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Sender<T> {
+    // fails once the receiver is gone, handing back the value
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if Arc::strong_count(&self.0) == 1 {
+            return Err(SendError(value));
+        }
+        self.0.lock().unwrap().value = Some(value);
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut slot) = self.0.lock() {
+            slot.closed = true;
+        }
+    }
+}
+
+pub struct Receiver<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Receiver<T> {
+    // the last value sent, if it wasn't already received. Disconnected once the sender is gone and nothing is left
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut slot = self.0.lock().unwrap();
+        match slot.value.take() {
+            Some(value) => Ok(value),
+            None if slot.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use geo::{Coord, Point};
+use serde::Deserialize;
+use std::f64::consts::FRAC_PI_4;
+
+// mean earth radius, in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+// GRS80, the ellipsoid of NAD83
+const GRS80_A: f64 = 6_378_137.0;
+const GRS80_F: f64 = 1.0 / 298.257_222_101;
+
+// EPSG:2263, NAD83 / New York Long Island, a Lambert conformal conic with two standard parallels
+const NY_LONG_ISLAND_LAT_1: f64 = 41.0 + 2.0 / 60.0;
+const NY_LONG_ISLAND_LAT_2: f64 = 40.0 + 40.0 / 60.0;
+const NY_LONG_ISLAND_LAT_0: f64 = 40.0 + 10.0 / 60.0;
+const NY_LONG_ISLAND_LON_0: f64 = -74.0;
+
+// how longitude and latitude are flattened onto the map, every projection is in meters from the map origin.
+// projected coords are (north, east), the renderer draws x up the screen and y across it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    // true distances and bearings from the origin
+    #[default]
+    AzimuthalEquidistant,
+    // as web maps are drawn, scaled to true distances at the origin
+    WebMercator,
+    // NY State Plane, Long Island zone (EPSG:2263), in meters rather than its US survey feet
+    StatePlane,
+}
+
+impl Projection {
+    pub fn project(self, coord: Coord<f32>, origin: &Point<f32>) -> Coord<f32> {
+        let (east, north) = self.east_north(
+            coord.x as f64,
+            coord.y as f64,
+            origin.x() as f64,
+            origin.y() as f64,
+        );
+        Coord {
+            x: north as f32,
+            y: east as f32,
+        }
+    }
+
+    // meters east and north of the origin, all in degrees
+    fn east_north(self, lon: f64, lat: f64, lon_0: f64, lat_0: f64) -> (f64, f64) {
+        match self {
+            Self::AzimuthalEquidistant => {
+                let (phi, phi_0) = (lat.to_radians(), lat_0.to_radians());
+                let d_lambda = (lon - lon_0).to_radians();
+                let cos_c = phi_0.sin() * phi.sin() + phi_0.cos() * phi.cos() * d_lambda.cos();
+                let c = cos_c.clamp(-1.0, 1.0).acos();
+                // distance over the sine of the angular distance, 1 at the origin itself
+                let k = if c == 0.0 { 1.0 } else { c / c.sin() };
+                (
+                    EARTH_RADIUS * k * phi.cos() * d_lambda.sin(),
+                    EARTH_RADIUS * k * (phi_0.cos() * phi.sin() - phi_0.sin() * phi.cos() * d_lambda.cos()),
+                )
+            }
+            Self::WebMercator => {
+                let scale = GRS80_A * lat_0.to_radians().cos();
+                let y = |lat: f64| (FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+                (scale * (lon - lon_0).to_radians(), scale * (y(lat) - y(lat_0)))
+            }
+            Self::StatePlane => {
+                let (east, north) = lambert_conformal_conic(lon, lat);
+                let (east_0, north_0) = lambert_conformal_conic(lon_0, lat_0);
+                (east - east_0, north - north_0)
+            }
+        }
+    }
+}
+
+// EPSG:2263 without its false easting, which the offset from the origin cancels out anyway
+fn lambert_conformal_conic(lon: f64, lat: f64) -> (f64, f64) {
+    let e = (2.0 * GRS80_F - GRS80_F * GRS80_F).sqrt();
+    let m = |phi: f64| phi.cos() / (1.0 - (e * phi.sin()).powi(2)).sqrt();
+    let t = |phi: f64| {
+        let e_sin = e * phi.sin();
+        (FRAC_PI_4 - phi / 2.0).tan() / ((1.0 - e_sin) / (1.0 + e_sin)).powf(e / 2.0)
+    };
+
+    let (phi_1, phi_2) = (NY_LONG_ISLAND_LAT_1.to_radians(), NY_LONG_ISLAND_LAT_2.to_radians());
+    let n = (m(phi_1).ln() - m(phi_2).ln()) / (t(phi_1).ln() - t(phi_2).ln());
+    let f = m(phi_1) / (n * t(phi_1).powf(n));
+    let rho = |phi: f64| GRS80_A * f * t(phi).powf(n);
+
+    let rho_0 = rho(NY_LONG_ISLAND_LAT_0.to_radians());
+    let theta = n * (lon - NY_LONG_ISLAND_LON_0).to_radians();
+    let rho = rho(lat.to_radians());
+    (rho * theta.sin(), rho_0 - rho * theta.cos())
+}
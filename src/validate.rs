@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use tracing::warn;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::ids::StopId;
+use crate::proto::gtfs::realtime::TripUpdate;
+use crate::util;
+
+// ways a realtime trip can disagree with the static schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Discrepancy {
+    UnknownTrip,
+    UnscheduledStop,
+    StopOrder,
+    TimeOrder,
+}
+
+impl Discrepancy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::UnknownTrip => "unknown trip",
+            Self::UnscheduledStop => "unscheduled stop",
+            Self::StopOrder => "stops out of order",
+            Self::TimeOrder => "times out of order",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StopTimeRow {
+    trip_id: String,
+    stop_id: StopId,
+    stop_sequence: u32,
+}
+
+// discrepancies already told of, past this many they're forgotten and may be told of again
+const MAX_REPORTED: usize = 50_000;
+
+// scheduled stop patterns, for checking realtime trip updates against
+pub struct ScheduleValidator {
+    // stop_ids in order, by realtime trip_id, a trip_id can run a different pattern on different days
+    patterns: HashMap<String, Vec<Vec<StopId>>>,
+    // each trip's discrepancies already told of, with the stop of those about one. every message repeats them
+    reported: Mutex<HashSet<(String, Discrepancy, Option<String>)>>,
+}
+
+impl ScheduleValidator {
+    pub fn load() -> Result<Self> {
+        let path = util::static_data::gtfs_file("stop_times.txt").context("Could not find stop_times.txt data")?;
+        let mut rdr = csv::Reader::from_path(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut trips: HashMap<String, Vec<(u32, StopId)>> = HashMap::new();
+        for rec in rdr.deserialize() {
+            let row: StopTimeRow = rec?;
+            trips
+                .entry(row.trip_id)
+                .or_default()
+                .push((row.stop_sequence, row.stop_id));
+        }
+
+        let mut patterns: HashMap<String, Vec<Vec<StopId>>> = HashMap::new();
+        for (trip_id, mut stops) in trips {
+            stops.sort_by_key(|(sequence, _)| *sequence);
+            let pattern: Vec<_> = stops.into_iter().map(|(_, stop_id)| stop_id).collect();
+            // static trip_ids are prefixed with their service, e.g. AFA24GEN-1037-Sunday-00_000600_1..S03R
+            let realtime_id = trip_id.split_once('_').map_or(trip_id.as_str(), |(_, id)| id);
+            let trip_patterns = patterns.entry(realtime_id.to_owned()).or_default();
+            if !trip_patterns.contains(&pattern) {
+                trip_patterns.push(pattern);
+            }
+        }
+        Ok(Self {
+            patterns,
+            reported: Mutex::default(),
+        })
+    }
+
+    // discrepancies between a trip update and the schedule not found in an earlier one, each is logged
+    pub fn check(&self, trip_update: &TripUpdate) -> Vec<Discrepancy> {
+        let trip_id = trip_update.trip.trip_id();
+        let mut reported = self.reported.lock().unwrap();
+        if reported.len() >= MAX_REPORTED {
+            reported.clear();
+        }
+        let mut new = |discrepancy, stop_id: Option<&str>| {
+            reported.insert((trip_id.to_owned(), discrepancy, stop_id.map(str::to_owned)))
+        };
+        let Some(patterns) = self.patterns.get(trip_id) else {
+            if !new(Discrepancy::UnknownTrip, None) {
+                return Vec::new();
+            }
+            warn!("Trip {} is not in the schedule", trip_id);
+            return vec![Discrepancy::UnknownTrip];
+        };
+
+        let mut discrepancies = Vec::new();
+        let stop_ids: Vec<_> = trip_update
+            .stop_time_update
+            .iter()
+            .map(|stop_update| stop_update.stop_id())
+            .collect();
+        if let Some(stop_id) = stop_ids.iter().find(|stop_id| {
+            !patterns
+                .iter()
+                .any(|pattern| pattern.iter().any(|scheduled| scheduled == **stop_id))
+        }) {
+            if new(Discrepancy::UnscheduledStop, Some(stop_id)) {
+                warn!("Trip {} stops at {}, which it isn't scheduled to", trip_id, stop_id);
+                discrepancies.push(Discrepancy::UnscheduledStop);
+            }
+        } else if !patterns.iter().any(|pattern| in_order(pattern, &stop_ids)) && new(Discrepancy::StopOrder, None) {
+            warn!("Trip {} visits its stops out of scheduled order", trip_id);
+            discrepancies.push(Discrepancy::StopOrder);
+        }
+
+        let times: Vec<_> = trip_update
+            .stop_time_update
+            .iter()
+            .flat_map(|stop_update| [stop_update.arrival.as_ref(), stop_update.departure.as_ref()])
+            .filter_map(|event| event.and_then(|event| event.time))
+            .collect();
+        if times.windows(2).any(|pair| pair[1] < pair[0]) && new(Discrepancy::TimeOrder, None) {
+            warn!("Trip {} has predicted times that go backwards", trip_id);
+            discrepancies.push(Discrepancy::TimeOrder);
+        }
+        discrepancies
+    }
+}
+
+// whether `stop_ids` appear in `pattern` in the same order
+fn in_order(pattern: &[StopId], stop_ids: &[&str]) -> bool {
+    let mut remaining = pattern.iter();
+    stop_ids
+        .iter()
+        .all(|stop_id| remaining.any(|scheduled| scheduled == *stop_id))
+}
@@ -0,0 +1,154 @@
+// trains from /stream on a Leaflet map, seeded from /geojson and placed at the stations from /stations
+"use strict";
+
+const map = L.map("map", { preferCanvas: true }).setView([40.73, -73.94], 12);
+L.tileLayer("https://{s}.basemaps.cartocdn.com/dark_all/{z}/{x}/{y}{r}.png", {
+  attribution: "&copy; OpenStreetMap contributors &copy; CARTO",
+  maxZoom: 19,
+}).addTo(map);
+
+const status = document.getElementById("status");
+// by stop_id, with their marker
+const stations = new Map();
+// "#rrggbb" by route_id
+const colors = new Map();
+// each route's trains as the stream last sent them
+const vehicles = new Map();
+// by trip_id
+const trains = new Map();
+// trip_ids stopped at each station
+const active = new Map();
+
+// eased linearly between stations while in transit, as /routes/{route_id}/vehicles would place it
+function position(vehicle, now) {
+  const to = stations.get(vehicle.stop_id);
+  if (!to) {
+    return null;
+  }
+  const from = vehicle.from_stop_id && stations.get(vehicle.from_stop_id);
+  if (!from || vehicle.departed == null || vehicle.arrival == null || vehicle.arrival <= vehicle.departed) {
+    return [to.lat, to.lon];
+  }
+  const t = Math.min(Math.max((now - vehicle.departed) / (vehicle.arrival - vehicle.departed), 0), 1);
+  return [from.lat + (to.lat - from.lat) * t, from.lon + (to.lon - from.lon) * t];
+}
+
+function draw() {
+  const now = Date.now() / 1000;
+  const seen = new Set();
+  for (const [routeId, routeVehicles] of vehicles) {
+    for (const vehicle of routeVehicles) {
+      const latlng = position(vehicle, now);
+      if (!latlng) {
+        continue;
+      }
+      seen.add(vehicle.trip_id);
+      let marker = trains.get(vehicle.trip_id);
+      if (!marker) {
+        marker = L.circleMarker(latlng, {
+          radius: 6,
+          weight: 1,
+          color: "#000",
+          fillColor: colors.get(routeId) || "#fff",
+          fillOpacity: vehicle.scheduled ? 0.5 : 1,
+        }).addTo(map);
+        trains.set(vehicle.trip_id, marker);
+      }
+      marker.setLatLng(latlng);
+      marker.bindTooltip(`${routeId} ${vehicle.stopped ? "at" : "to"} ${stations.get(vehicle.stop_id).name}`);
+    }
+  }
+  for (const [tripId, marker] of trains) {
+    if (!seen.has(tripId)) {
+      marker.remove();
+      trains.delete(tripId);
+    }
+  }
+  for (const [stopId, station] of stations) {
+    const lit = (active.get(stopId) || new Set()).size > 0;
+    station.marker.setStyle({ fillColor: lit ? "#fff" : "#555", radius: lit ? 4 : 2 });
+  }
+}
+
+function stopActive(stopId, tripId, arrived) {
+  const trips = active.get(stopId) || new Set();
+  if (arrived) {
+    trips.add(tripId);
+  } else {
+    trips.delete(tripId);
+  }
+  active.set(stopId, trips);
+}
+
+// the snapshot as of now, the stream only sends what changes after
+async function seed() {
+  const collection = await (await fetch("geojson")).json();
+  vehicles.clear();
+  active.clear();
+  for (const feature of collection.features) {
+    const properties = feature.properties;
+    if (properties.kind === "vehicle") {
+      const routeVehicles = vehicles.get(properties.route_id) || [];
+      routeVehicles.push(properties);
+      vehicles.set(properties.route_id, routeVehicles);
+    } else if (properties.kind === "active_stop") {
+      stopActive(properties.stop_id, properties.trip_id, true);
+    }
+  }
+}
+
+function connect() {
+  const scheme = location.protocol === "https:" ? "wss" : "ws";
+  const socket = new WebSocket(`${scheme}://${location.host}/stream`);
+  socket.onopen = () => {
+    status.textContent = "Live";
+    seed().catch((err) => console.error("Failed to load the snapshot", err));
+  };
+  socket.onmessage = (event) => {
+    const update = JSON.parse(event.data);
+    switch (update.type) {
+      case "vehicles":
+        vehicles.set(update.route_id, update.vehicles);
+        break;
+      case "stop_active":
+        stopActive(update.stop_id, update.trip_id, true);
+        break;
+      case "stop_inactive":
+        stopActive(update.stop_id, update.trip_id, false);
+        break;
+    }
+  };
+  // the server restarting shouldn't need a reload
+  socket.onclose = () => {
+    status.textContent = "Reconnecting…";
+    setTimeout(connect, 2000);
+  };
+}
+
+async function start() {
+  const [stationList, routeList] = await Promise.all([
+    fetch("stations").then((response) => response.json()),
+    fetch("routes").then((response) => response.json()),
+  ]);
+  for (const route of routeList) {
+    colors.set(route.route_id, route.route_color);
+  }
+  for (const station of stationList) {
+    const marker = L.circleMarker([station.lat, station.lon], {
+      radius: 2,
+      weight: 0,
+      fillColor: "#555",
+      fillOpacity: 1,
+    })
+      .bindTooltip(station.annotation && station.annotation.note ? `${station.name}: ${station.annotation.note}` : station.name)
+      .addTo(map);
+    stations.set(station.stop_id, { ...station, marker });
+  }
+  connect();
+  setInterval(draw, 1000);
+}
+
+start().catch((err) => {
+  status.textContent = "Failed to load stations";
+  console.error(err);
+});
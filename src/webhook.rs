@@ -0,0 +1,156 @@
+use hmac::{Hmac, Mac};
+use tracing::{info, warn};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::WebhookConfig;
+use crate::ids::{RouteId, StopId, TripId};
+use crate::util;
+
+const MAX_ATTEMPTS: u32 = 4;
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+// an arrival is old news once the train has pulled out, seconds after which it's dropped rather than retried
+const ARRIVAL_STALE_AFTER: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    AlertPosted,
+    StationArrival,
+    FeedOutage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    AlertPosted {
+        id: String,
+        header: String,
+        routes: Vec<RouteId>,
+    },
+    StationArrival {
+        stop_id: StopId,
+        route_id: RouteId,
+        trip_id: TripId,
+        timestamp: u64,
+    },
+    FeedOutage {
+        feed: String,
+        error: String,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::AlertPosted { .. } => EventKind::AlertPosted,
+            Self::StationArrival { .. } => EventKind::StationArrival,
+            Self::FeedOutage { .. } => EventKind::FeedOutage,
+        }
+    }
+
+    // alerts and outages are still worth delivering late, arrivals aren't
+    fn is_stale(&self) -> bool {
+        match self {
+            Self::StationArrival { timestamp, .. } => {
+                util::posix_now() - *timestamp as i64 > ARRIVAL_STALE_AFTER
+            }
+            _ => false,
+        }
+    }
+}
+
+impl WebhookConfig {
+    fn wants(&self, event: &Event) -> bool {
+        if !self.events.contains(&event.kind()) {
+            return false;
+        }
+        match event {
+            Event::StationArrival { stop_id, .. } => self.stations.contains(stop_id),
+            _ => true,
+        }
+    }
+}
+
+// handle for emitting events, each hook posts from its own thread so neither the feed loop
+// nor the other hooks wait on one that's slow or retrying
+#[derive(Clone, Default)]
+pub struct Webhooks {
+    hooks: Vec<(WebhookConfig, Sender<Event>)>,
+}
+
+impl Webhooks {
+    pub fn spawn(hooks: Vec<WebhookConfig>) -> Self {
+        let client = Client::new();
+        let hooks = hooks
+            .into_iter()
+            .map(|hook| {
+                let (tx, rx) = channel::<Event>();
+                let client = client.clone();
+                let worker = hook.clone();
+                thread::spawn(move || {
+                    for event in rx {
+                        post(&client, &worker, &event);
+                    }
+                });
+                (hook, tx)
+            })
+            .collect();
+        Self { hooks }
+    }
+
+    pub fn emit(&self, event: Event) {
+        for (_, tx) in self.hooks.iter().filter(|(hook, _)| hook.wants(&event)) {
+            let _ = tx.send(event.clone());
+        }
+    }
+}
+
+fn post(client: &Client, hook: &WebhookConfig, event: &Event) {
+    let body = serde_json::to_vec(event).unwrap();
+
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            thread::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1));
+        }
+        // queued behind a retrying post, or retried past the point the train left
+        if event.is_stale() {
+            warn!("Webhook {:?} to '{}' dropped, stale", event.kind(), hook.url);
+            return;
+        }
+        let mut request = client
+            .post(&hook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = &hook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+
+        match request.send().and_then(|res| res.error_for_status()) {
+            Ok(_) => {
+                info!("Webhook {:?} delivered to '{}'", event.kind(), hook.url);
+                return;
+            }
+            Err(err) => warn!(
+                "Webhook {:?} to '{}' failed (attempt {}/{}): {}",
+                event.kind(),
+                hook.url,
+                attempt + 1,
+                MAX_ATTEMPTS,
+                err
+            ),
+        }
+    }
+}
+
+// GitHub style signature, `sha256=<hex hmac of the body>`
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
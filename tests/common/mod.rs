@@ -0,0 +1,249 @@
+// each test binary uses its own part of the harness
+#![allow(dead_code)]
+
+use nyc_subway::config::FeedsConfig;
+use nyc_subway::entities::{CollectibleEntity, EntityCollection, Route, ShapeIndex, ShapeRoutes, ShapeSeq, Stop};
+use nyc_subway::feed::{Feed, FeedManager, Frame, SharedFeedState, Transport, FEEDS};
+use nyc_subway::ids::{RouteId, StopId};
+use nyc_subway::proto::gtfs::realtime::FeedMessage;
+use nyc_subway::proto::json;
+use nyc_subway::util::clock::ManualClock;
+use nyc_subway::util::latest::{self, Receiver};
+use nyc_subway::webhook::Webhooks;
+use prost::bytes::Bytes;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// between FeedManager updates while waiting on a frame
+const POLL: Duration = Duration::from_millis(10);
+
+// the fixture schedule, stations are at their longitude and latitude as the map isn't projected
+pub struct Schedule {
+    pub stops: EntityCollection<BTreeMap<StopId, Stop>>,
+    pub routes: EntityCollection<HashMap<RouteId, Route>>,
+    pub shapes: ShapeIndex,
+}
+
+static SCHEDULE: OnceLock<Schedule> = OnceLock::new();
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// static data is read from $XDG_DATA_HOME, which is pointed at the fixtures before anything reads it
+pub fn schedule() -> &'static Schedule {
+    SCHEDULE.get_or_init(|| {
+        std::env::set_var("XDG_DATA_HOME", fixtures().join("data"));
+        // fetched messages are cached, this keeps them out of the real cache
+        let cache = std::env::temp_dir().join(format!("nyc_subway_rs-tests-{}", std::process::id()));
+        std::env::set_var("XDG_CACHE_HOME", cache);
+        // the mock server is local, whatever proxy the environment sets
+        std::env::set_var("NO_PROXY", "127.0.0.1");
+        Schedule {
+            stops: Stop::load_collection().expect("Failed to load the fixture stops.txt"),
+            routes: Route::load_collection().expect("Failed to load the fixture routes.txt"),
+            shapes: ShapeIndex::new(&ShapeSeq::collection(), &ShapeRoutes::default()),
+        }
+    })
+}
+
+// a fixture from tests/fixtures/feeds, written as the proto3 JSON mapping
+pub fn json_fixture(name: &str) -> Vec<u8> {
+    let path = fixtures().join("feeds").join(name);
+    fs::read(&path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err))
+}
+
+// a fixture encoded as protobuf, as the MTA serves feeds. prost drops NYCT's extensions re-encoding it
+pub fn protobuf_fixture(name: &str) -> Vec<u8> {
+    let value = json::parse(&json_fixture(name)).unwrap();
+    FeedMessage::deserialize(&value).unwrap().encode_to_vec()
+}
+
+// serves a message at /{feed} for each feed over plain HTTP/1.1, one connection at a time
+pub struct MockFeedServer {
+    addr: SocketAddr,
+    bodies: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MockFeedServer {
+    // feeds start out with a message without any entities
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bodies: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+        let served = bodies.clone();
+        // the thread lives as long as the test binary, there's nothing to clean up
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                respond(stream, &served);
+            }
+        });
+        let server = Self { addr, bodies };
+        let empty = protobuf_fixture("empty.json");
+        for feed in &FEEDS {
+            server.serve(&format!("{feed:?}"), empty.clone());
+        }
+        server
+    }
+
+    // what the feed named e.g. ACE is served as from now on
+    pub fn serve(&self, feed: &str, body: Vec<u8>) {
+        self.bodies.lock().unwrap().insert(format!("/{feed}"), body);
+    }
+
+    // every feed's endpoint on this server, none are fetched from the MTA
+    pub fn feeds_config(&self) -> FeedsConfig {
+        let endpoints = FEEDS
+            .iter()
+            .map(|feed| (format!("{feed:?}"), format!("http://{}/{feed:?}", self.addr)))
+            .collect();
+        FeedsConfig {
+            endpoints,
+            ..Default::default()
+        }
+    }
+}
+
+fn respond(stream: TcpStream, bodies: &Mutex<HashMap<String, Vec<u8>>>) {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // the rest of a GET is headers, up to the blank line
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|read| read > 2) {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match bodies.lock().unwrap().get(path) {
+        Some(body) => ("200 OK", body.clone()),
+        None => ("404 Not Found", Vec::new()),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let mut stream = &stream;
+    let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(&body));
+}
+
+// serves messages in place of the MTA's endpoints without a server, feeds without one get a message without entities
+#[derive(Default)]
+pub struct FixtureTransport {
+    bodies: Mutex<HashMap<String, Vec<u8>>>,
+    fetches: AtomicUsize,
+}
+
+impl FixtureTransport {
+    pub fn serve(&self, feed: &Feed, body: Vec<u8>) {
+        self.bodies.lock().unwrap().insert(feed.endpoint().to_owned(), body);
+    }
+
+    // how many messages have been fetched, across all feeds
+    pub fn fetches(&self) -> usize {
+        self.fetches.load(Ordering::SeqCst)
+    }
+}
+
+impl Transport for FixtureTransport {
+    fn get(&self, url: &str) -> anyhow::Result<Bytes> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        let body = self.bodies.lock().unwrap().get(url).cloned();
+        Ok(Bytes::from(body.unwrap_or_else(|| protobuf_fixture("empty.json"))))
+    }
+}
+
+// a manager that only sees time pass when `clock` is advanced, fetching every feed from `transport`
+pub fn stepped_manager(
+    transport: Arc<FixtureTransport>,
+    clock: Arc<ManualClock>,
+    shared: SharedFeedState,
+) -> (FeedManager<'static>, Receiver<Frame>) {
+    let schedule = schedule();
+    let (tx, rx) = latest::channel();
+    let manager = FeedManager::new(&schedule.stops, &schedule.routes, &schedule.shapes, shared, Webhooks::default())
+        .with_transport(transport)
+        .with_clock(clock)
+        .with_frames(tx);
+    (manager, rx)
+}
+
+// one update of every feed, fetching those that are due. a stopped clock never runs out the update budget,
+// so each update applies everything its feed has queued
+pub fn update_all(manager: &mut FeedManager) {
+    for _ in 0..FEEDS.len() {
+        manager.update();
+    }
+}
+
+// a manager fetching every feed from `server`, with the frames it sends
+pub fn manager(server: &MockFeedServer, shared: SharedFeedState) -> (FeedManager<'static>, Receiver<Frame>) {
+    let schedule = schedule();
+    // the fixtures' header timestamps are long past, they'd all be drawn as stale
+    shared.controls.write().unwrap().stale_after = Duration::from_secs(u32::MAX as u64);
+    let (tx, rx) = latest::channel();
+    let manager = FeedManager::new(&schedule.stops, &schedule.routes, &schedule.shapes, shared, Webhooks::default())
+        .with_feeds(&server.feeds_config())
+        .with_frames(tx);
+    (manager, rx)
+}
+
+// the stations a frame lights up, by stop_id. frames have an instance for each station, lit ones last,
+// they're told apart by where they're drawn
+pub fn lit_stations(frame: &Frame) -> BTreeSet<&'static str> {
+    schedule()
+        .stops
+        .values()
+        .filter(|stop| stop.parent.is_none())
+        .filter(|stop| {
+            let position = [stop.coord.x, stop.coord.y, 0.0];
+            frame
+                .stops
+                .iter()
+                .any(|instance| instance.scale > 0.0 && instance.position == position)
+        })
+        .map(|stop| stop.id.as_str())
+        .collect()
+}
+
+// updates the manager until it sends a frame lighting up `expected`, then for a few more rounds of every feed,
+// so stations that shouldn't light up have their chance to, and returns the last frame it sent
+pub fn settled_frame(
+    manager: &mut FeedManager,
+    frames: &Receiver<Frame>,
+    timeout: Duration,
+    expected: &[&str],
+) -> Frame {
+    let expected: BTreeSet<&str> = expected.iter().copied().collect();
+    let deadline = Instant::now() + timeout;
+    let mut last = None;
+    while Instant::now() < deadline {
+        manager.update();
+        last = frames.try_recv().ok().or(last);
+        if last.as_ref().is_some_and(|frame| lit_stations(frame) == expected) {
+            for _ in 0..FEEDS.len() * 2 {
+                manager.update();
+            }
+            let frame = frames.try_recv().ok().or(last).unwrap();
+            assert_eq!(lit_stations(&frame), expected, "Stations lit up after the frame expected");
+            return frame;
+        }
+        thread::sleep(POLL);
+    }
+    panic!(
+        "No frame lit up {:?} within {:?}, the last lit up {:?}",
+        expected,
+        timeout,
+        last.as_ref().map(lit_stations)
+    );
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{json_fixture, manager, protobuf_fixture, schedule, settled_frame, MockFeedServer};
+use nyc_subway::feed::SharedFeedState;
+use std::time::Duration;
+
+// long enough to fetch every feed and apply what they queued
+const TIMEOUT: Duration = Duration::from_secs(5);
+// a feed whose header timestamp is long past is fetched again after FETCH_RETRY, plus up to FETCH_JITTER
+const REFETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+// trains STOPPED_AT a platform light up its station in their route's color. ace.json also has a train whose
+// position lags its trip update, one in transit and one stopped at a platform missing from the schedule
+#[test]
+fn stopped_trains_light_their_stations() {
+    let server = MockFeedServer::start();
+    server.serve("ACE", protobuf_fixture("ace.json"));
+    let (mut manager, frames) = manager(&server, SharedFeedState::default());
+
+    let frame = settled_frame(&mut manager, &frames, TIMEOUT, &["A27", "A31"]);
+    assert_eq!(frame.stops.len(), 5);
+    let blue = schedule().routes.get("A").unwrap().color();
+    for instance in frame.stops.iter().filter(|instance| instance.scale > 0.0) {
+        assert_eq!(instance.color, blue);
+    }
+}
+
+#[test]
+fn json_messages_light_the_same_stations() {
+    let server = MockFeedServer::start();
+    server.serve("ACE", json_fixture("ace.json"));
+    let (mut manager, frames) = manager(&server, SharedFeedState::default());
+
+    settled_frame(&mut manager, &frames, TIMEOUT, &["A27", "A31"]);
+}
+
+#[test]
+fn hidden_routes_leave_their_stations_dark() {
+    let server = MockFeedServer::start();
+    server.serve("ACE", protobuf_fixture("ace.json"));
+    let shared = SharedFeedState::default();
+    shared.controls.write().unwrap().hidden_routes.insert("C".into());
+    let (mut manager, frames) = manager(&server, shared);
+
+    settled_frame(&mut manager, &frames, TIMEOUT, &["A27"]);
+}
+
+// the next message has the A train leaving 42 St and the E stopped at W 4 St, while the C stays put at 14 St
+#[test]
+fn stations_go_dark_when_trains_leave() {
+    let server = MockFeedServer::start();
+    server.serve("ACE", protobuf_fixture("ace.json"));
+    let (mut manager, frames) = manager(&server, SharedFeedState::default());
+    settled_frame(&mut manager, &frames, TIMEOUT, &["A27", "A31"]);
+
+    server.serve("ACE", protobuf_fixture("ace-later.json"));
+    settled_frame(&mut manager, &frames, REFETCH_TIMEOUT, &["A31", "A32"]);
+}
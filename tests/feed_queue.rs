@@ -0,0 +1,372 @@
+mod common;
+
+use common::{lit_stations, protobuf_fixture, stepped_manager, update_all, FixtureTransport};
+use nyc_subway::entity_log::{EntityLog, LogFormat};
+use nyc_subway::feed::{
+    Feed, FeedAge, FeedManager, FeedSnapshot, FeedUpdate, Frame, SharedControls, SharedFeedState, TrainEvent,
+    TrainEventReceiver, UpcomingStop, FEEDS,
+};
+use nyc_subway::headway::Direction;
+use nyc_subway::util::clock::ManualClock;
+use nyc_subway::util::latest::Receiver;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+
+// shortly after ace.json was published
+const STARTED_AT: i64 = 1_700_000_040;
+// past FETCH_RETRY and FETCH_JITTER, when every feed is due again
+const REFETCH: Duration = Duration::from_secs(10);
+
+struct Stepped {
+    manager: FeedManager<'static>,
+    snapshot: FeedSnapshot,
+    controls: SharedControls,
+    frames: Receiver<Frame>,
+    updates: broadcast::Receiver<FeedUpdate>,
+    events: TrainEventReceiver,
+    transport: Arc<FixtureTransport>,
+    clock: Arc<ManualClock>,
+}
+
+// the ACE feed serving ace.json, fetched and applied
+fn started() -> Stepped {
+    let transport = Arc::new(FixtureTransport::default());
+    transport.serve(&Feed::ACE, protobuf_fixture("ace.json"));
+    let clock = Arc::new(ManualClock::new(STARTED_AT));
+    let shared = SharedFeedState::default();
+    let updates = shared.updates.subscribe();
+    let events = shared.events.subscribe();
+    let (snapshot, controls) = (shared.snapshot.clone(), shared.controls.clone());
+    let (mut manager, frames) = stepped_manager(transport.clone(), clock.clone(), shared);
+    // fetching applies one queued op, the next update applies the rest
+    update_all(&mut manager);
+    update_all(&mut manager);
+    Stepped {
+        manager,
+        snapshot,
+        controls,
+        frames,
+        updates,
+        events,
+        transport,
+        clock,
+    }
+}
+
+// stations trains were queued to arrive at or leave since the last call, as (trip_id, stop_id, arrived)
+fn stop_updates(updates: &mut broadcast::Receiver<FeedUpdate>) -> Vec<(String, String, bool)> {
+    let mut stops = Vec::new();
+    while let Ok(update) = updates.try_recv() {
+        match update {
+            FeedUpdate::StopActive { stop_id, trip_id, .. } => {
+                stops.push((trip_id.to_string(), stop_id.to_string(), true))
+            }
+            FeedUpdate::StopInactive { stop_id, trip_id } => {
+                stops.push((trip_id.to_string(), stop_id.to_string(), false))
+            }
+            FeedUpdate::Vehicles { .. } | FeedUpdate::Feed(_) => {}
+        }
+    }
+    stops.sort();
+    stops
+}
+
+fn stop(trip_id: &str, stop_id: &str, arrived: bool) -> (String, String, bool) {
+    (trip_id.to_owned(), stop_id.to_owned(), arrived)
+}
+
+// the E's position lags its trip update, which has it past 34 St already, the A bound for 59 St is in transit,
+// and the other A is stopped at a platform missing from the schedule
+#[test]
+fn only_trains_stopped_at_their_latest_stop_are_queued() {
+    let mut stepped = started();
+
+    assert_eq!(
+        stop_updates(&mut stepped.updates),
+        vec![stop("083900_C..N", "A31", true), stop("084250_A..S", "A27", true)]
+    );
+    let frame = stepped.frames.try_recv().unwrap();
+    assert_eq!(lit_stations(&frame), BTreeSet::from(["A27", "A31"]));
+    // there's no depth buffer, lit stations are drawn over the rest by coming last
+    let lit: Vec<bool> = frame.stops.iter().map(|instance| instance.scale > 0.0).collect();
+    assert_eq!(lit, vec![false, false, false, true, true]);
+}
+
+// the A leaves 42 St and the E stops at W 4 St, the C still stopped at 14 St isn't queued again
+#[test]
+fn trains_still_stopped_are_not_queued_again() {
+    let mut stepped = started();
+    stop_updates(&mut stepped.updates);
+
+    stepped.transport.serve(&Feed::ACE, protobuf_fixture("ace-later.json"));
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    update_all(&mut stepped.manager);
+
+    assert_eq!(
+        stop_updates(&mut stepped.updates),
+        vec![stop("084100_E..S", "A32", true), stop("084250_A..S", "A27", false)]
+    );
+    let frame = stepped.frames.try_recv().unwrap();
+    assert_eq!(lit_stations(&frame), BTreeSet::from(["A31", "A32"]));
+}
+
+// the A stopped at 42 St has already been predicted to leave it, which isn't listed
+#[test]
+fn trips_list_the_stations_still_ahead() {
+    let stepped = started();
+
+    let trip = stepped.manager.trip("084250_A..S").unwrap();
+    assert_eq!(trip.route_id.as_str(), "A");
+    assert_eq!(trip.direction, Some(Direction::South));
+    assert_eq!(trip.delay, None);
+    assert_eq!(
+        trip.next_stops,
+        vec![
+            UpcomingStop {
+                stop_id: "A28".into(),
+                time: 1_700_000_110,
+            },
+            UpcomingStop {
+                stop_id: "A31".into(),
+                time: 1_700_000_290,
+            },
+        ]
+    );
+    assert!(stepped.manager.trip("000000_A..N").is_none());
+}
+
+// departures are timestamped with the message the train was first missing from
+#[test]
+fn train_events_follow_the_queue() {
+    let mut stepped = started();
+    while stepped.events.try_recv().is_some() {}
+
+    stepped.transport.serve(&Feed::ACE, protobuf_fixture("ace-later.json"));
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    update_all(&mut stepped.manager);
+
+    let events: Vec<TrainEvent> = std::iter::from_fn(|| stepped.events.try_recv()).collect();
+    assert_eq!(
+        events,
+        vec![
+            TrainEvent::TrainDeparted {
+                trip_id: "084250_A..S".into(),
+                route_id: "A".into(),
+                stop_id: "A27".into(),
+                timestamp: 1_700_000_030,
+            },
+            TrainEvent::TrainArrived {
+                trip_id: "084100_E..S".into(),
+                route_id: "E".into(),
+                stop_id: "A32".into(),
+                timestamp: 1_700_000_028,
+            },
+        ]
+    );
+}
+
+// the next message only deletes the A's position at 42 St, the rest of ace.json still stands
+#[test]
+fn differential_messages_update_the_last_dataset() {
+    let mut stepped = started();
+    stop_updates(&mut stepped.updates);
+
+    stepped.transport.serve(&Feed::ACE, protobuf_fixture("ace-differential.json"));
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    update_all(&mut stepped.manager);
+
+    assert_eq!(stop_updates(&mut stepped.updates), vec![stop("084250_A..S", "A27", false)]);
+    let frame = stepped.frames.try_recv().unwrap();
+    assert_eq!(lit_stations(&frame), BTreeSet::from(["A31"]));
+}
+
+// the BDFM feed serving a later message with the same trips, as shuttles and transfers can appear in two feeds,
+// the ACE feed's reports of them are left out
+#[test]
+fn trips_in_several_feeds_are_kept_from_the_newest() {
+    let transport = Arc::new(FixtureTransport::default());
+    transport.serve(&Feed::ACE, protobuf_fixture("ace.json"));
+    transport.serve(&Feed::BDFM, protobuf_fixture("ace-later.json"));
+    let shared = SharedFeedState::default();
+    let snapshot = shared.snapshot.clone();
+    let (mut manager, frames) = stepped_manager(transport, Arc::new(ManualClock::new(STARTED_AT)), shared);
+    update_all(&mut manager);
+    update_all(&mut manager);
+
+    let trains: Vec<(String, String)> = snapshot
+        .vehicles("A")
+        .into_iter()
+        .map(|vehicle| (vehicle.trip_id.to_string(), vehicle.stop_id.to_string()))
+        .collect();
+    assert_eq!(
+        trains,
+        vec![
+            ("084250_A..S".to_owned(), "A28".to_owned()),
+            ("084300_A..N".to_owned(), "A24".to_owned()),
+        ]
+    );
+    let frame = frames.try_recv().unwrap();
+    assert_eq!(lit_stations(&frame), BTreeSet::from(["A31", "A32"]));
+}
+
+// re-published as one message, the trips both feeds report are only there from the BDFM feed
+#[test]
+fn merged_message_has_each_trip_once() {
+    let transport = Arc::new(FixtureTransport::default());
+    transport.serve(&Feed::ACE, protobuf_fixture("ace.json"));
+    transport.serve(&Feed::BDFM, protobuf_fixture("ace-later.json"));
+    let shared = SharedFeedState::default();
+    let snapshot = shared.snapshot.clone();
+    let (mut manager, _frames) = stepped_manager(transport, Arc::new(ManualClock::new(STARTED_AT)), shared);
+    update_all(&mut manager);
+    update_all(&mut manager);
+
+    let msg = snapshot.feed_message();
+    assert_eq!(msg.header.timestamp(), 1_700_000_030);
+    let mut ids: Vec<&str> = msg.entity.iter().map(|entity| entity.id.as_str()).collect();
+    ids.sort();
+    assert_eq!(
+        ids,
+        vec![
+            "ACE-000007",
+            "ACE-000008",
+            "ACE-000009",
+            "ACE-000010",
+            "BDFM-000001",
+            "BDFM-000002",
+            "BDFM-000003",
+            "BDFM-000004",
+            "BDFM-000005",
+            "BDFM-000006",
+        ]
+    );
+}
+
+#[test]
+fn messages_no_newer_than_the_last_are_ignored() {
+    let mut stepped = started();
+    stop_updates(&mut stepped.updates);
+
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    update_all(&mut stepped.manager);
+
+    assert_eq!(stepped.transport.fetches(), FEEDS.len() * 2);
+    assert!(stop_updates(&mut stepped.updates).is_empty());
+}
+
+#[test]
+fn feeds_are_fetched_once_due() {
+    let mut stepped = started();
+    assert_eq!(stepped.transport.fetches(), FEEDS.len());
+
+    update_all(&mut stepped.manager);
+    assert_eq!(stepped.transport.fetches(), FEEDS.len());
+
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    assert_eq!(stepped.transport.fetches(), FEEDS.len() * 2);
+}
+
+// nothing new is fetched or applied, the feed only goes stale
+#[test]
+fn feeds_going_stale_are_published() {
+    let mut stepped = started();
+    let ace_stale = |feeds: Vec<FeedAge>| feeds.iter().any(|age| age.feed == "ACE" && age.stale);
+    assert!(!ace_stale(stepped.snapshot.feeds()));
+    while stepped.updates.try_recv().is_ok() {}
+
+    stepped.controls.write().unwrap().stale_after = Duration::from_secs(10);
+    update_all(&mut stepped.manager);
+
+    assert_eq!(stepped.transport.fetches(), FEEDS.len());
+    assert!(ace_stale(stepped.snapshot.feeds()));
+    let mut streamed = Vec::new();
+    while let Ok(update) = stepped.updates.try_recv() {
+        if let FeedUpdate::Feed(age) = update {
+            streamed.push(age);
+        }
+    }
+    assert!(ace_stale(streamed));
+}
+
+// the northbound A is cancelled, read as a Stream that ends with the manager
+#[test]
+fn cancelled_trips_are_streamed() {
+    let mut stepped = started();
+    while stepped.events.try_recv().is_some() {}
+    let events = stepped.events.stream();
+
+    stepped.transport.serve(&Feed::ACE, protobuf_fixture("ace-cancelled.json"));
+    stepped.clock.advance(REFETCH);
+    update_all(&mut stepped.manager);
+    update_all(&mut stepped.manager);
+    drop(stepped.manager);
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let events: Vec<TrainEvent> = runtime.block_on(events.collect());
+    assert_eq!(
+        events,
+        vec![TrainEvent::TripCancelled {
+            trip_id: "084300_A..N".into(),
+            route_id: "A".into(),
+        }]
+    );
+}
+
+// the ACE feed has the later message this time, the BDFM feed's stops for the same trips aren't told of
+#[test]
+fn superseded_trips_are_not_queued() {
+    let transport = Arc::new(FixtureTransport::default());
+    transport.serve(&Feed::ACE, protobuf_fixture("ace-later.json"));
+    transport.serve(&Feed::BDFM, protobuf_fixture("ace.json"));
+    let shared = SharedFeedState::default();
+    let mut updates = shared.updates.subscribe();
+    let (mut manager, _frames) = stepped_manager(transport, Arc::new(ManualClock::new(STARTED_AT)), shared);
+    update_all(&mut manager);
+    update_all(&mut manager);
+
+    assert_eq!(
+        stop_updates(&mut updates),
+        vec![stop("083900_C..N", "A31", true), stop("084100_E..S", "A32", true)]
+    );
+}
+
+// every entity of the message is logged, not only the arrivals, and closing writes out the hour in progress
+#[test]
+fn every_entity_is_logged() {
+    let dir = std::env::temp_dir().join(format!("nyc_subway_rs-entities-{}", std::process::id()));
+    let entity_log = EntityLog::spawn(dir.clone(), LogFormat::Csv).unwrap();
+    let transport = Arc::new(FixtureTransport::default());
+    transport.serve(&Feed::ACE, protobuf_fixture("ace.json"));
+    let (manager, _frames) =
+        stepped_manager(transport, Arc::new(ManualClock::new(STARTED_AT)), SharedFeedState::default());
+    let mut manager = manager.with_entity_log(entity_log.clone());
+    update_all(&mut manager);
+    entity_log.close();
+
+    let path = dir.join("date=2023-11-14/hour=22/entities.csv");
+    let mut reader = csv::Reader::from_path(&path).unwrap();
+    let rows: Vec<(String, String, String)> = reader
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            (record[1].to_owned(), record[2].to_owned(), record[3].to_owned())
+        })
+        .collect();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(rows.len(), 10);
+    assert_eq!(
+        rows[..2],
+        [
+            ("000001".to_owned(), "trip_update".to_owned(), "084250_A..S".to_owned()),
+            ("000002".to_owned(), "vehicle".to_owned(), "084250_A..S".to_owned()),
+        ]
+    );
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::schedule;
+use nyc_subway::intern::{Interner, ScheduleIds, TripHandle};
+
+#[test]
+fn platforms_resolve_to_their_station() {
+    let schedule = schedule();
+    let ids = ScheduleIds::new(&schedule.stops, &schedule.routes);
+
+    let station = ids.station("A27S").unwrap();
+    assert_eq!(ids.stops.resolve(station), "A27");
+    assert_eq!(ids.station("A27"), Some(station));
+    assert_eq!(ids.coord(station), schedule.stops.get("A27").unwrap().coord);
+    // not a public station, so missing from the schedule
+    assert_eq!(ids.station("R60S"), None);
+}
+
+// the feeds and everything looking stops up by stop_id agree on a platform's station
+#[test]
+fn stops_and_handles_resolve_to_the_same_station() {
+    let schedule = schedule();
+    let ids = ScheduleIds::new(&schedule.stops, &schedule.routes);
+
+    for stop in schedule.stops.values() {
+        let station = schedule.stops.station(stop.id.as_str()).unwrap();
+        assert!(station.parent.is_none());
+        assert_eq!(ids.station(stop.id.as_str()).map(|station| ids.stops.resolve(station)), Some(&station.id));
+    }
+    assert!(schedule.stops.station("R60S").is_none());
+}
+
+// the renderer draws route bullets in route_id order, 1-based
+#[test]
+fn routes_are_numbered_in_route_id_order() {
+    let schedule = schedule();
+    let ids = ScheduleIds::new(&schedule.stops, &schedule.routes);
+
+    let bullets: Vec<u32> = ["A", "C", "E"]
+        .iter()
+        .map(|route_id| ids.bullet(ids.routes.get(route_id).unwrap()))
+        .collect();
+    assert_eq!(bullets, vec![1, 2, 3]);
+    assert_eq!(ids.routes.get("G"), None);
+}
+
+#[test]
+fn trips_are_interned_once() {
+    let mut trips: Interner<TripHandle> = Interner::default();
+
+    let trip = trips.intern("084250_A..S");
+    assert_ne!(trips.intern("083900_C..N"), trip);
+    assert_eq!(trips.intern("084250_A..S"), trip);
+    assert_eq!(trips.get("084250_A..S"), Some(trip));
+    assert_eq!(trips.resolve(trip), "084250_A..S");
+    assert_eq!(trips.get("084100_E..S"), None);
+}
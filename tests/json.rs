@@ -0,0 +1,53 @@
+use nyc_subway::proto::json::{field_name, is_json, normalize};
+use serde_json::json;
+
+#[test]
+fn keys_are_read_as_proto_field_names() {
+    assert_eq!(field_name("tripId"), "trip_id");
+    assert_eq!(field_name("trip_id"), "trip_id");
+    assert_eq!(field_name("stopTimeUpdate"), "stop_time_update");
+    assert_eq!(field_name("[transit_realtime.nyct_trip_descriptor]"), "nyct_trip_descriptor");
+    assert_eq!(field_name("[nyct_trip_descriptor]"), "nyct_trip_descriptor");
+}
+
+#[test]
+fn nested_objects_and_arrays_are_normalized() {
+    let mut value = json!({
+        "header": { "gtfsRealtimeVersion": "2.0" },
+        "entity": [{
+            "id": "1",
+            "tripUpdate": {
+                "trip": {
+                    "tripId": "048000_A..S",
+                    "[transit_realtime.nyct_trip_descriptor]": { "isAssigned": true },
+                },
+                "stopTimeUpdate": [{ "stopId": "A24S" }],
+            },
+        }],
+    });
+    normalize(&mut value);
+    assert_eq!(
+        value,
+        json!({
+            "header": { "gtfs_realtime_version": "2.0" },
+            "entity": [{
+                "id": "1",
+                "trip_update": {
+                    "trip": {
+                        "trip_id": "048000_A..S",
+                        "nyct_trip_descriptor": { "is_assigned": true },
+                    },
+                    "stop_time_update": [{ "stop_id": "A24S" }],
+                },
+            }],
+        })
+    );
+}
+
+// a FeedMessage starts with its header's tag, a newline, and a 123 byte header's length is {
+#[test]
+fn protobuf_starting_like_json_after_whitespace_is_not_json() {
+    assert!(is_json(br#"{"header": {}}"#));
+    assert!(!is_json(&[0x0A, b'{', 0x0A, 0x03, b'2', b'.', b'0']));
+    assert!(!is_json(&[]));
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::schedule;
+use nyc_subway::feed::Arrivals;
+use nyc_subway::nearby::Nearby;
+
+// Times Square, between the stations at 42 St and 34 St
+const LAT: f64 = 40.7558;
+const LON: f64 = -73.9866;
+
+#[test]
+fn nearest_stations_come_first() {
+    let nearby = Nearby::new(&schedule().stops, Arrivals::default());
+
+    let stops = nearby.nearest_stops(LAT, LON, 2);
+    let stop_ids: Vec<&str> = stops.iter().map(|stop| stop.stop_id.as_str()).collect();
+    assert_eq!(stop_ids, vec!["A27", "A28"]);
+    // about 300 m to Port Authority
+    assert!((250.0..350.0).contains(&stops[0].distance), "{}", stops[0].distance);
+    assert!(stops[0].countdowns.is_empty());
+}
+
+#[test]
+fn stations_within_a_radius_are_measured_along_the_surface() {
+    let nearby = Nearby::new(&schedule().stops, Arrivals::default());
+
+    let stop_ids = |meters| -> Vec<String> {
+        nearby
+            .stops_within_radius(LAT, LON, meters)
+            .into_iter()
+            .map(|stop| stop.stop_id.to_string())
+            .collect()
+    };
+    assert_eq!(stop_ids(1_000.0), vec!["A27", "A28"]);
+    assert_eq!(stop_ids(1_600.0), vec!["A27", "A28", "A24"]);
+    assert!(stop_ids(100.0).is_empty());
+}
@@ -0,0 +1,20 @@
+use nyc_subway::config::Config;
+use nyc_subway::palette::Palette;
+
+#[test]
+fn map_colors_are_read_as_hex() {
+    let config: Config = toml::from_str("[palette]\nroutes = \"monochrome\"\nbackground = \"#000000\"\nparks = \"ffffff\"").unwrap();
+    assert_eq!(config.palette.routes, Palette::Monochrome);
+    assert_eq!(config.palette.background, Some([0.0; 3]));
+    assert_eq!(config.palette.parks, Some([1.0; 3]));
+    assert_eq!(config.palette.water, None);
+}
+
+// rather than drawn white
+#[test]
+fn colors_that_arent_hex_name_their_key() {
+    for water in ["#0a14zz", "#fff", ""] {
+        let err = toml::from_str::<Config>(&format!("[palette]\nwater = \"{water}\"")).unwrap_err();
+        assert!(err.message().contains(&format!("palette.water = \"{water}\"")), "{err}");
+    }
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::schedule;
+use nyc_subway::entities::Transfers;
+use nyc_subway::ids::{StopId, TripId};
+use nyc_subway::planner::{Leg, Planner};
+use nyc_subway::timetable::Timetable;
+use std::collections::HashMap;
+
+// Tuesday 2023-11-14 07:55 in New York, before the fixture's weekday trips run
+const DEPART: i64 = 1_699_966_500;
+// 08:00 that morning, which the fixture's stop times count from
+const EIGHT: i64 = DEPART + 5 * 60;
+
+fn planner() -> (Planner, Timetable) {
+    let schedule = schedule();
+    let transfers = Transfers::load().unwrap();
+    let planner = Planner::new(&schedule.stops, &transfers);
+    (planner, Timetable::load().unwrap())
+}
+
+fn stations(ids: &[&str]) -> Vec<StopId> {
+    ids.iter().map(|id| StopId::from(*id)).collect()
+}
+
+// the E leaving 42 St a minute after the A gets in is too soon to change to, the next one is taken
+#[test]
+fn changes_trains_with_time_to_make_the_connection() {
+    let (planner, timetable) = planner();
+
+    let journey = planner.plan(&timetable, "A24", "A32", DEPART, &HashMap::new()).unwrap();
+    assert_eq!(
+        journey.legs,
+        vec![
+            Leg::Ride {
+                route_id: "A".into(),
+                trip_id: "048000_A..S".into(),
+                stations: stations(&["A24", "A27"]),
+                departure: EIGHT,
+                arrival: EIGHT + 2 * 60,
+            },
+            Leg::Ride {
+                route_id: "E".into(),
+                trip_id: "049000_E..S".into(),
+                stations: stations(&["A27", "A28", "A31", "A32"]),
+                departure: EIGHT + 10 * 60,
+                arrival: EIGHT + 17 * 60,
+            },
+        ]
+    );
+    assert_eq!((journey.departure, journey.arrival), (EIGHT, EIGHT + 17 * 60));
+    assert_eq!(journey.stations(), stations(&["A24", "A27", "A28", "A31", "A32"]).iter().collect::<Vec<_>>());
+
+    // platforms plan from and to their stations
+    let from_platforms = planner.plan(&timetable, "A24S", "A32N", DEPART, &HashMap::new()).unwrap();
+    assert_eq!(from_platforms, journey);
+}
+
+// running two minutes late, the earlier E can be made after all
+#[test]
+fn delayed_trains_are_planned_as_they_run() {
+    let (planner, timetable) = planner();
+    let delays = HashMap::from([(TripId::from("048300_E..S"), 120)]);
+
+    let journey = planner.plan(&timetable, "A24", "A32", DEPART, &delays).unwrap();
+    assert_eq!(journey.arrival, EIGHT + 12 * 60);
+    match &journey.legs[1] {
+        Leg::Ride { trip_id, departure, .. } => {
+            assert_eq!(trip_id, "048300_E..S");
+            assert_eq!(*departure, EIGHT + 5 * 60);
+        }
+        leg => panic!("Expected a ride, got {leg:?}"),
+    }
+}
+
+// no train runs uptown in the fixture, but 14 St and 34 St are a transfer apart
+#[test]
+fn walks_transfers_between_stations() {
+    let (planner, timetable) = planner();
+
+    let journey = planner.plan(&timetable, "A31", "A28", DEPART, &HashMap::new()).unwrap();
+    assert_eq!(
+        journey.legs,
+        vec![Leg::Walk {
+            from: "A31".into(),
+            to: "A28".into(),
+            seconds: 1200,
+        }]
+    );
+    assert_eq!((journey.departure, journey.arrival), (DEPART, DEPART + 1200));
+    assert!(planner.plan(&timetable, "A32", "A24", DEPART, &HashMap::new()).is_none());
+}
+
+// setting off the night before, the first trains of the next service day are taken
+#[test]
+fn plans_into_the_next_service_day() {
+    let (planner, timetable) = planner();
+
+    let tonight = planner.plan(&timetable, "A24", "A32", DEPART - 8 * 60 * 60, &HashMap::new()).unwrap();
+    let morning = planner.plan(&timetable, "A24", "A32", DEPART, &HashMap::new()).unwrap();
+    assert_eq!(tonight, morning);
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use nyc_subway::entities::{Boro, CollectibleEntity, GTFSData, Park, ShapeRoutes, ShapeSeq, TripRow};
+use nyc_subway::proto::mvt::tile::{GeomType, Layer};
+use nyc_subway::proto::mvt::Tile;
+use nyc_subway::tiles::{self, TileSources};
+use prost::Message;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXTENT: f64 = 4096.0;
+const BUFFER: f64 = 64.0;
+const ZOOM: u8 = 14;
+
+// a path of a feature's geometry, in tile coordinates, and whether it was closed
+type DecodedPath = (Vec<(i32, i32)>, bool);
+
+// the fixture schedule and boroughs written as tiles at `zooms`, returns where and how many were written
+fn export(name: &str, zooms: std::ops::RangeInclusive<u8>) -> (PathBuf, usize) {
+    let schedule = common::schedule();
+    let shapes = ShapeSeq::load_collection().expect("Failed to load the fixture shapes.txt");
+    let shape_routes = <ShapeRoutes as GTFSData<TripRow>>::load().expect("Failed to load the fixture trips.txt");
+    let boros = Boro::load_collection().expect("Failed to load the fixture nyc_boroughs.geojson");
+    let parks = Park::collection();
+    let sources = TileSources {
+        stops: &schedule.stops,
+        shapes: &shapes,
+        shape_routes: &shape_routes,
+        routes: &schedule.routes,
+        boros: &boros,
+        parks: &parks,
+    };
+    let dir = std::env::temp_dir().join(format!("nyc_subway_rs-tiles-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let written = tiles::export(&dir, zooms, &sources).unwrap();
+    (dir, written)
+}
+
+// every tile written under `dir`, by its z/x/y
+fn read_tiles(dir: &Path) -> BTreeMap<(u8, u32, u32), Tile> {
+    let mut tiles = BTreeMap::new();
+    for z in fs::read_dir(dir).unwrap() {
+        let z = z.unwrap();
+        for x in fs::read_dir(z.path()).unwrap() {
+            let x = x.unwrap();
+            for y in fs::read_dir(x.path()).unwrap() {
+                let y = y.unwrap().path();
+                let key = (
+                    z.file_name().to_str().unwrap().parse().unwrap(),
+                    x.file_name().to_str().unwrap().parse().unwrap(),
+                    y.file_stem().unwrap().to_str().unwrap().parse().unwrap(),
+                );
+                tiles.insert(key, Tile::decode(fs::read(&y).unwrap().as_slice()).unwrap());
+            }
+        }
+    }
+    fs::remove_dir_all(dir).unwrap();
+    tiles
+}
+
+// the geometry commands of section 4.3 of the vector tile spec, back to absolute paths
+fn decode(geometry: &[u32]) -> Vec<DecodedPath> {
+    let mut paths: Vec<DecodedPath> = Vec::new();
+    let mut at = (0, 0);
+    let mut words = geometry.iter().copied();
+    while let Some(command) = words.next() {
+        let (id, count) = (command & 0x7, command >> 3);
+        if id == 7 {
+            assert_eq!(count, 1);
+            paths.last_mut().expect("ClosePath without a path").1 = true;
+            continue;
+        }
+        for _ in 0..count {
+            let dx = unzigzag(words.next().expect("a vertex is two parameters"));
+            let dy = unzigzag(words.next().expect("a vertex is two parameters"));
+            at = (at.0 + dx, at.1 + dy);
+            match id {
+                1 => paths.push((vec![at], false)),
+                2 => paths.last_mut().expect("LineTo without a MoveTo").0.push(at),
+                _ => panic!("unknown command {id}"),
+            }
+        }
+    }
+    paths
+}
+
+fn unzigzag(n: u32) -> i32 {
+    ((n >> 1) as i32) ^ -((n & 1) as i32)
+}
+
+// twice the ring's area, positive when it winds clockwise with y pointing down
+fn area(ring: &[(i32, i32)]) -> i64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64)
+        .sum()
+}
+
+// a feature's properties, as its tags index them, with numbers written out
+fn properties(layer: &Layer, tags: &[u32]) -> BTreeMap<String, String> {
+    tags.chunks(2)
+        .map(|tag| {
+            let value = &layer.values[tag[1] as usize];
+            let value = value.string_value.clone().unwrap_or_else(|| value.double_value().to_string());
+            (layer.keys[tag[0] as usize].clone(), value)
+        })
+        .collect()
+}
+
+fn layer<'t>(tile: &'t Tile, name: &str) -> Option<&'t Layer> {
+    tile.layers.iter().find(|layer| layer.name == name)
+}
+
+// where a longitude and latitude falls at ZOOM, as the tile and the position in it
+fn tile_position(lon: f64, lat: f64) -> ((u32, u32), (f64, f64)) {
+    let scale = (1u32 << ZOOM) as f64;
+    let lat = lat.to_radians();
+    let x = (lon + 180.0) / 360.0 * scale;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * scale;
+    ((x as u32, y as u32), (x.fract() * EXTENT, y.fract() * EXTENT))
+}
+
+#[test]
+fn every_tile_decodes_within_its_buffer() {
+    let (dir, written) = export("buffer", 12..=ZOOM);
+    let tiles = read_tiles(&dir);
+    assert_eq!(tiles.len(), written);
+    assert!(tiles.keys().any(|(z, ..)| *z == 12) && tiles.keys().any(|(z, ..)| *z == ZOOM));
+
+    for tile in tiles.values() {
+        for layer in &tile.layers {
+            assert_eq!(layer.version, 2);
+            assert_eq!(layer.extent, Some(EXTENT as u32));
+            for feature in &layer.features {
+                let paths = decode(&feature.geometry);
+                assert!(!paths.is_empty());
+                for (vertices, _) in &paths {
+                    for (x, y) in vertices {
+                        let within = -BUFFER..=EXTENT + BUFFER;
+                        assert!(within.contains(&(*x as f64)) && within.contains(&(*y as f64)), "{x},{y}");
+                    }
+                }
+                match feature.r#type() {
+                    GeomType::Point => assert_eq!(paths, vec![(paths[0].0.clone(), false)]),
+                    GeomType::Linestring => assert!(paths.iter().all(|(line, closed)| line.len() >= 2 && !closed)),
+                    GeomType::Polygon => {
+                        assert!(paths.iter().all(|(ring, closed)| ring.len() >= 3 && *closed));
+                        // a polygon starts with its exterior ring
+                        assert!(area(&paths[0].0) > 0);
+                    }
+                    GeomType::Unknown => panic!("feature without a geometry type"),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn stations_are_points_with_their_properties() {
+    let (dir, _) = export("stations", ZOOM..=ZOOM);
+    let tiles = read_tiles(&dir);
+    let ((x, y), (px, py)) = tile_position(-73.989735, 40.757308);
+    let stations = layer(&tiles[&(ZOOM, x, y)], "stations").unwrap();
+    let station = stations
+        .features
+        .iter()
+        .find(|feature| properties(stations, &feature.tags)["stop_id"] == "A27")
+        .unwrap();
+
+    assert_eq!(properties(stations, &station.tags)["name"], "42 St-Port Authority Bus Terminal");
+    let point = decode(&station.geometry)[0].0[0];
+    assert!((point.0 as f64 - px).abs() <= 1.0 && (point.1 as f64 - py).abs() <= 1.0);
+    // platforms are drawn as their station
+    assert!(stations
+        .features
+        .iter()
+        .all(|feature| !properties(stations, &feature.tags)["stop_id"].ends_with(['N', 'S'])));
+}
+
+#[test]
+fn shapes_are_cut_at_tile_edges() {
+    let (dir, _) = export("shapes", ZOOM..=ZOOM);
+    let tiles = read_tiles(&dir);
+    let (start, _) = tile_position(-73.981736, 40.768296);
+    let (end, _) = tile_position(-74.000495, 40.732338);
+    assert_ne!(start, end);
+
+    for (x, y) in [start, end] {
+        let shapes = layer(&tiles[&(ZOOM, x, y)], "shapes").unwrap();
+        let properties = properties(shapes, &shapes.features[0].tags);
+        assert_eq!(properties["shape_id"], "A..S");
+        assert_eq!(properties["route_id"], "A");
+        assert_eq!(properties["route_color"], "#0062cf");
+        // the shape runs on into the next tile, as far as the buffer
+        let lines = decode(&shapes.features[0].geometry);
+        assert!(lines
+            .iter()
+            .flat_map(|(line, _)| line)
+            .any(|(x, y)| [*x, *y].iter().any(|n| *n == -BUFFER as i32 || *n == (EXTENT + BUFFER) as i32)));
+    }
+}
+
+#[test]
+fn boroughs_keep_their_holes_and_properties() {
+    let (dir, _) = export("boroughs", ZOOM..=ZOOM);
+    let tiles = read_tiles(&dir);
+    let ((x, y), _) = tile_position(-73.9925, 40.7475);
+    let boroughs = layer(&tiles[&(ZOOM, x, y)], "boroughs").unwrap();
+    let borough = &boroughs.features[0];
+
+    let properties = properties(boroughs, &borough.tags);
+    assert_eq!(properties["boro_name"], "Manhattan");
+    assert_eq!(properties["boro_code"], "1");
+    let rings = decode(&borough.geometry);
+    assert_eq!(rings.len(), 2);
+    assert!(area(&rings[0].0) > 0);
+    assert!(area(&rings[1].0) < 0);
+    // the borough covers the whole tile, its exterior is cut to the buffer around it
+    let corners = [(-64, -64), (4160, -64), (4160, 4160), (-64, 4160)];
+    assert!(corners.iter().all(|corner| rings[0].0.contains(corner)));
+}
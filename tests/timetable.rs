@@ -0,0 +1,68 @@
+mod common;
+
+use nyc_subway::proto::gtfs::realtime::vehicle_position::VehicleStopStatus;
+use nyc_subway::simulate;
+use nyc_subway::timetable::Timetable;
+
+// 08:00 on Tuesday 2023-11-14 in New York, when the fixture's first A leaves 42 St for 34 St at 08:02
+const EIGHT: i64 = 1_699_966_800;
+
+// where the schedule has the A, as (platform, next platform, stopped)
+fn position(timetable: &Timetable, now: i64) -> Option<(String, Option<String>, bool)> {
+    timetable
+        .positions("A", now)
+        .into_iter()
+        .find(|position| position.trip_id == "048000_A..S")
+        .map(|position| {
+            (
+                position.stop_id.to_string(),
+                position.next_stop_id.map(|stop_id| stop_id.to_string()),
+                position.stopped,
+            )
+        })
+}
+
+fn at(stop_id: &str, next_stop_id: &str, stopped: bool) -> Option<(String, Option<String>, bool)> {
+    Some((stop_id.to_owned(), Some(next_stop_id.to_owned()), stopped))
+}
+
+// trains stop before they depart rather than after, the fixture arrives at each platform as it departs
+#[test]
+fn trains_dwell_before_departing() {
+    common::schedule();
+    let timetable = Timetable::load().unwrap();
+
+    assert_eq!(position(&timetable, EIGHT - 60), None);
+    assert_eq!(position(&timetable, EIGHT - 20), at("A24S", "A27S", true));
+    assert_eq!(position(&timetable, EIGHT), at("A24S", "A27S", true));
+    assert_eq!(position(&timetable, EIGHT + 10), at("A24S", "A27S", false));
+    assert_eq!(position(&timetable, EIGHT + 100), at("A27S", "A28S", true));
+
+    let running = timetable
+        .positions("A", EIGHT + 10)
+        .into_iter()
+        .find(|position| position.trip_id == "048000_A..S")
+        .unwrap();
+    assert_eq!(running.departed, EIGHT);
+    assert_eq!(running.next_arrival, Some(EIGHT + 90));
+}
+
+// a stopped train's trip update starts with its departure from the station it's at
+#[test]
+fn simulated_trains_depart_the_station_they_are_stopped_at() {
+    common::schedule();
+    let timetable = Timetable::load().unwrap();
+
+    let msg = simulate::message(&timetable, &["A"], EIGHT - 20);
+    let ids: Vec<&str> = msg.entity.iter().map(|entity| entity.id.as_str()).collect();
+    assert_eq!(ids, vec!["048000_A..S-tu", "048000_A..S-vp"]);
+    let trip_update = msg.entity[0].trip_update.as_ref().unwrap();
+    let first = &trip_update.stop_time_update[0];
+    assert_eq!(first.stop_id(), "A24S");
+    assert_eq!(first.departure.as_ref().and_then(|event| event.time), Some(EIGHT));
+    assert!(first.arrival.is_none());
+    let next = &trip_update.stop_time_update[1];
+    assert_eq!(next.arrival.as_ref().and_then(|event| event.time), Some(EIGHT + 90));
+    let vehicle = msg.entity[1].vehicle.as_ref().unwrap();
+    assert_eq!(vehicle.current_status(), VehicleStopStatus::StoppedAt);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use nyc_subway::proto::gtfs::realtime::{trip_update::StopTimeUpdate, TripDescriptor, TripUpdate};
+use nyc_subway::validate::{Discrepancy, ScheduleValidator};
+
+fn trip_update(trip_id: &str, stop_ids: &[&str]) -> TripUpdate {
+    TripUpdate {
+        trip: TripDescriptor {
+            trip_id: Some(trip_id.to_owned()),
+            ..Default::default()
+        },
+        stop_time_update: stop_ids
+            .iter()
+            .map(|stop_id| StopTimeUpdate {
+                stop_id: Some((*stop_id).to_owned()),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// every message repeats a trip's discrepancies, they're only found once per trip and stop
+#[test]
+fn discrepancies_are_found_once() {
+    common::schedule();
+    let validator = ScheduleValidator::load().unwrap();
+
+    let detour = trip_update("048000_A..S", &["A24S", "R60S"]);
+    assert_eq!(validator.check(&detour), vec![Discrepancy::UnscheduledStop]);
+    assert_eq!(validator.check(&detour), vec![]);
+    // another stop off its route is found
+    let further = trip_update("048000_A..S", &["A24S", "R61S"]);
+    assert_eq!(validator.check(&further), vec![Discrepancy::UnscheduledStop]);
+
+    let unknown = trip_update("999999_Z..N", &[]);
+    assert_eq!(validator.check(&unknown), vec![Discrepancy::UnknownTrip]);
+    assert_eq!(validator.check(&unknown), vec![]);
+}